@@ -0,0 +1,31 @@
+#![no_main]
+
+// Parse arbitrary data as a pickle, `cargo fuzz run pickle` fails if the parser panics, overflows its stack or runs out of memory.
+
+use std::io::Cursor;
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code, unused_imports)]
+#[path = "../../src/util/mod.rs"]
+mod util;
+
+use util::pickle::{dis::PickleDisassembly, json::pickle_to_json, parser::{PickleLimits, PickleParser}};
+
+fuzz_target!(|data: &[u8]| {
+    // Small limits, so pickles that are slow to load are found as quickly as ones that fail.
+    let limits = PickleLimits {
+        max_allocation: 1 << 20,
+        max_depth: 200,
+        max_memo: 1 << 12,
+        max_objects: 1 << 16,
+        allowed_globals: None,
+    };
+
+    if let Ok(pickle) = PickleParser::new().limits(limits.clone()).load(&mut Cursor::new(data)) {
+        // Printing & converting walk the whole pickle, including shared objects.
+        let _ = pickle.to_string();
+        let _ = pickle_to_json(&pickle);
+    }
+
+    let _ = PickleDisassembly::disassemble(&mut PickleParser::new().limits(limits), &mut Cursor::new(data)).to_string();
+});
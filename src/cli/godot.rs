@@ -2,7 +2,7 @@
 
 use std::{error::Error, fs::File, io::Cursor, path::PathBuf};
 use clap::Parser;
-use crate::{extract::godot::{archive::GodotPck, resource::ResourceContainer, texture::Texture}, util::dir_extract};
+use crate::{extract::godot::{archive::GodotPck, resource::ResourceContainer, texture::Texture}, util::{dir_extract, MappedFiles}};
 
 
 
@@ -27,7 +27,7 @@ pub struct CliGodotPck {
 
 impl CliGodotPck {
 
-    fn mapper(&self, path: String, data: &mut Vec<u8>) -> Result<Option<(String, Vec<u8>)>, Box<dyn Error>> {
+    fn mapper(&self, path: String, data: &mut Vec<u8>) -> Result<Option<MappedFiles>, Box<dyn Error>> {
         println!("File: \"{}\"", path);
 
         let path = path.replace("res://", "");
@@ -36,10 +36,10 @@ impl CliGodotPck {
             match &data[0..4] {
                 b"RSRC" | b"RSCC" => {
                     match ResourceContainer::load(&mut Cursor::new(&data)) {
-                        Ok(extracted_resource) => return Ok(Some((
+                        Ok(extracted_resource) => return Ok(Some(vec![(
                             [path, "extracted-resource".to_owned()].join("."),
                             format!("{:#?}", extracted_resource).into_bytes()
-                        ))),
+                        )])),
                         Err(err) => {
                             println!("Resource parse failed \"{}\" {:#?}", path, err);
                         },
@@ -48,10 +48,10 @@ impl CliGodotPck {
                 [b'G', b'D', _, _] | [b'G', b'S', _, _] => {
                     if let Ok(mut texture) = Texture::load(Cursor::new(&data)) {
                         if let Ok((new_ext, image)) = texture.to_image() {
-                            return Ok(Some((
+                            return Ok(Some(vec![(
                                 [path, new_ext.to_owned()].join("."),
                                 image
-                            )));
+                            )]));
                         }
                     }
                 },
@@ -59,7 +59,7 @@ impl CliGodotPck {
             }
         }
 
-        Ok(Some((path, data.clone())))
+        Ok(Some(vec![(path, data.clone())]))
     }
 
     pub fn extract(&self, output: &PathBuf, overwrite_output: bool) -> Result<(), Box<dyn Error>> {
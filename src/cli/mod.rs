@@ -1,12 +1,13 @@
 
 pub mod godot;
+pub mod pickle;
 pub mod renpy;
 pub mod source_engine;
 
 use std::{error::Error, fs, path::PathBuf};
 use clap::{Parser, Subcommand, ValueEnum};
 
-use self::{godot::CliGodotPck, renpy::CliRenPy, source_engine::CliSource};
+use self::{godot::CliGodotPck, pickle::CliPickle, renpy::{CliRenPy, CliRenPyGame, CliRenPySave, CliRenPyTranslate}, source_engine::{CliImageToVtf, CliKeyValues, CliSource}};
 
 
 
@@ -38,20 +39,46 @@ enum Commands {
         #[arg(short, long, value_enum, default_value_t = ExtractOptions::Overwrite)]
         extract_options: ExtractOptions,
     },
+    /// Generate a translation template from the compiled scripts of a Ren'Py archive.
+    #[command(name = "renpy-translate")]
+    RenPyTranslate(CliRenPyTranslate),
+    /// Unpack Ren'Py saves & persistent data to JSON.
+    #[command(name = "renpy-save")]
+    RenPySave(CliRenPySave),
+    /// Disassemble a pickle, or render it as JSON.
+    Pickle(CliPickle),
+    /// Render Valve KeyValues text, binary VDF & KeyValues3 as JSON.
+    #[command(name = "keyvalues")]
+    KeyValues(CliKeyValues),
+    /// Convert files to formats games load.
+    Convert {
+        #[command(subcommand)]
+        conversion: Conversions,
+    },
 }
 
 #[derive(Subcommand, Debug)]
 enum ExtractionMethods {
     GodotPck(CliGodotPck),
     RenPyArchive(CliRenPy),
+    /// Merge all archives & loose files of a Ren'Py game, in the order Ren'Py loads them.
+    #[command(name = "renpy-game")]
+    RenPyGame(CliRenPyGame),
     SourceEngine(CliSource),
 }
 
+#[derive(Subcommand, Debug)]
+enum Conversions {
+    /// Create a Source engine VTF texture from images.
+    ImageToVtf(CliImageToVtf),
+}
+
 impl ExtractionMethods {
     fn extract(&mut self, output: &PathBuf, overwrite_output: bool) -> Result<(), Box<dyn Error>> {
         match self {
             ExtractionMethods::GodotPck(method) => method.extract(output, overwrite_output)?,
             ExtractionMethods::RenPyArchive(method) => method.extract(output, overwrite_output)?,
+            ExtractionMethods::RenPyGame(method) => method.extract(output, overwrite_output)?,
             ExtractionMethods::SourceEngine(method) => method.extract(output, overwrite_output)?,
         }
         Ok(())
@@ -75,6 +102,11 @@ pub fn execute_cli() -> Result<(), Box<dyn Error>> {
 
             method.extract(&output, extract_options == ExtractOptions::Overwrite)?;
         },
+        Commands::RenPyTranslate(translate) => translate.execute()?,
+        Commands::RenPySave(save) => save.execute()?,
+        Commands::Pickle(pickle) => pickle.execute()?,
+        Commands::KeyValues(keyvalues) => keyvalues.execute()?,
+        Commands::Convert { conversion: Conversions::ImageToVtf(conversion) } => conversion.execute()?,
     }
 
     Ok(())
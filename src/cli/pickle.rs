@@ -0,0 +1,109 @@
+use std::{error::Error, fmt, fs::{self, File}, io::{Cursor, Read}, path::PathBuf};
+use clap::{Parser, ValueEnum};
+use zip::ZipArchive;
+
+use crate::{extract::renpy::{archive::RenPyArchive, script::RenPyCompiledScript}, util::{pickle::{dis::PickleDisassembly, json::pickle_to_json, parser::PickleParser}, virtual_fs::{VirtualDirectory, VirtualFile}}};
+
+
+
+#[derive(Debug)]
+pub enum CliPickleError {
+    MissingEntry(String),
+    MissingSlot(u32),
+}
+
+impl fmt::Display for CliPickleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingEntry(path) => write!(f, "Archive does not contain \"{}\".", path),
+            Self::MissingSlot(slot) => write!(f, "Compiled script does not contain slot {}.", slot),
+        }
+    }
+}
+
+impl Error for CliPickleError { }
+
+
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum CliPickleFormat {
+    /// Operations of the pickle, like `pickletools.dis`.
+    Dis,
+    /// Loaded pickle as JSON.
+    Json,
+}
+
+#[derive(Parser, Debug)]
+pub struct CliPickle {
+    /// Pickle file, or a Ren'Py archive when an entry is given.
+    /// Compiled scripts, bytecode caches, saves & persistent files are unpacked to the pickle they contain.
+    #[arg(index = 1)]
+    file: PathBuf,
+    /// Path of the file inside the Ren'Py archive.
+    #[arg(index = 2)]
+    entry: Option<String>,
+    #[arg(short, long, value_enum, default_value_t = CliPickleFormat::Dis)]
+    format: CliPickleFormat,
+    #[arg(long, default_value_t = 1)]
+    /// Slot of compiled scripts, 1 is the AST.
+    slot: u32,
+}
+
+
+
+impl CliPickle {
+
+    fn read(&self) -> Result<(String, Vec<u8>), Box<dyn Error>> {
+        let Some(entry) = &self.entry else {
+            let name = self.file.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            return Ok((name, fs::read(&self.file)?));
+        };
+
+        let mut archive = RenPyArchive::from_file(File::open(&self.file)?)?;
+        for file in archive.read_files_deep()? {
+            if file.path() == entry {
+                return Ok((entry.clone(), file.read_data()?));
+            }
+        }
+        Err(Box::new(CliPickleError::MissingEntry(entry.clone())))
+    }
+
+    /// Pickle contained in a Ren'Py file, other files are a pickle.
+    fn unpack(&self, path: &str, data: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error>> {
+        let name = path.rsplit('/').next().unwrap_or(path);
+
+        if name.ends_with(".rpyc") || name.ends_with(".rpymc") {
+            let script = RenPyCompiledScript::load(&mut Cursor::new(data))?;
+            Ok(script.chunk(self.slot).ok_or(CliPickleError::MissingSlot(self.slot))?.data)
+        } else if name.ends_with(".rpyb") || name == "persistent" {
+            let mut decompressed = Vec::new();
+            flate2::read::ZlibDecoder::new(Cursor::new(data)).read_to_end(&mut decompressed)?;
+            Ok(decompressed)
+        } else if name.ends_with(".save") {
+            let mut log = Vec::new();
+            ZipArchive::new(Cursor::new(data))?.by_name("log")?.read_to_end(&mut log)?;
+            Ok(log)
+        } else {
+            Ok(data)
+        }
+    }
+
+    pub fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let (path, data) = self.read()?;
+        let data = self.unpack(&path, data)?;
+
+        // Persistent ids are kept as the id.
+        let mut parser = PickleParser::new().persistent_load(Ok);
+
+        match self.format {
+            CliPickleFormat::Dis => print!("{}", PickleDisassembly::disassemble(&mut parser, &mut Cursor::new(data))),
+            CliPickleFormat::Json => {
+                let pickle = parser.load(&mut Cursor::new(data))?;
+                println!("{}", serde_json::to_string_pretty(&pickle_to_json(&pickle))?);
+            },
+        }
+
+        Ok(())
+    }
+
+}
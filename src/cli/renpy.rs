@@ -1,8 +1,103 @@
 
-use std::{error::Error, fs::File, io::Cursor, path::PathBuf};
-use clap::Parser;
+use std::{error::Error, fs::{self, File}, io::Cursor, path::PathBuf};
+use clap::{Args, Parser, ValueEnum};
 
-use crate::{extract::renpy::{archive::RenPyArchive, script::RenPyCompiledScript}, util::dir_extract};
+use crate::{extract::renpy::{archive::RenPyArchive, bytecode::RenPyBytecodeCache, game::RenPyGame, save::{RenPyPersistent, RenPySave}, script::{RenPyCompiledScript, RenPyCompiledScriptChunk, RenPyScriptVersion}, translation::RenPyTranslationTemplate}, util::{dir_extract, pickle::pickle::Pickle, read_ext::ReadExt, MappedFiles, virtual_fs::{VirtualDirectory, VirtualFile}}};
+
+
+
+
+
+#[derive(Args, Debug)]
+pub struct CliRenPyOptions {
+    #[arg(long)]
+    /// Also write the original compiled files alongside decompiled output.
+    keep_original: bool,
+    #[arg(long)]
+    /// Write decompiled scripts as `.rpy` & `.rpym` instead of `.rpyc-decomp` & `.rpymc-decomp`.
+    /// > WARNING: Source files in the archive may be overwritten.
+    rpy: bool,
+    #[arg(long)]
+    /// List the files that failed to convert after extraction.
+    /// Failed files are always written as the original, with the error in a `-error` file.
+    report_failures: bool,
+}
+
+impl CliRenPyOptions {
+
+    /// Output path of decompiled script.
+    fn decompiled_path(&self, path: &str) -> String {
+        match (self.rpy, path.strip_suffix('c')) {
+            (true, Some(source)) => source.to_owned(),
+            _ => format!("{}-decomp", path),
+        }
+    }
+
+    fn mapper(&self, path: &str, data: &[u8]) -> Result<Option<MappedFiles>, Box<dyn Error>> {
+        let mut mapped = Vec::new();
+
+        // .rpymc are compiled .rpym modules, which are the same format as scripts.
+        if path.ends_with(".rpyc") || path.ends_with(".rpymc") {
+            let script = RenPyCompiledScript::load(&mut Cursor::new(data))?;
+            // Without an AST there is nothing to decompile, so the original is written.
+            let Some(mut chunk) = script.ast_chunk() else {
+                return Ok(None);
+            };
+            let pickle = chunk.pickle()?;
+            if let Pickle::Tuple(items) = &pickle {
+                if let [header, ast] = items.as_slice() {
+                    println!("Script: {}", RenPyScriptVersion::from_pickle(chunk.format, header, ast));
+                }
+            }
+            let str = RenPyCompiledScriptChunk::decompile_pickle(chunk.format, pickle)?;
+            mapped.push((self.decompiled_path(path), str.into_bytes()));
+        } else if path.ends_with(".rpyb") {
+            let cache = RenPyBytecodeCache::load(&mut Cursor::new(data), RenPyBytecodeCache::python_version(path))?;
+            mapped.push((format!("{}-listing", path), cache.listing().into_bytes()));
+        } else {
+            return Ok(None);
+        }
+
+        if self.keep_original {
+            mapped.push((path.to_owned(), data.to_vec()));
+        }
+
+        Ok(Some(mapped))
+    }
+
+    fn extract<F, D>(&self, dir: &mut D, output: &PathBuf, overwrite_output: bool) -> Result<(), Box<dyn Error>>
+    where
+        F: VirtualFile,
+        D: VirtualDirectory<F, D>,
+    {
+        let mut failures = Vec::new();
+        dir_extract(dir, output, overwrite_output, |path, data| {
+            println!("File: \"{}\"", path);
+            match self.mapper(&path, data) {
+                // Original file is written with the error, so that nothing is missing from the output.
+                Err(err) => {
+                    println!("Failed to convert \"{}\" {}", path, err);
+                    failures.push((path.clone(), err.to_string()));
+                    Ok(Some(vec![
+                        (format!("{}-error", path), format!("{}\nPlease create a bug report with this file.\n", err).into_bytes()),
+                        (path, data.clone()),
+                    ]))
+                },
+                result => result,
+            }
+        })?;
+
+        if self.report_failures && !failures.is_empty() {
+            println!("{} files failed:", failures.len());
+            for (path, err) in &failures {
+                println!("    \"{}\" {}", path, err);
+            }
+        }
+
+        Ok(())
+    }
+
+}
 
 
 
@@ -12,49 +107,152 @@ use crate::{extract::renpy::{archive::RenPyArchive, script::RenPyCompiledScript}
 pub struct CliRenPy {
     #[arg(index = 1)]
     file: PathBuf,
+    #[command(flatten)]
+    options: CliRenPyOptions,
 }
 
 
 
+impl CliRenPy {
 
+    pub fn extract(&self, output: &PathBuf, overwrite_output: bool) -> Result<(), Box<dyn Error>> {
 
-impl CliRenPy {
+        println!("Loading archive");
+
+        let archive_file = File::open(&self.file)?;
+        let mut archive = RenPyArchive::from_file(archive_file)?;
 
-    fn mapper(path: String, data: &mut Vec<u8>) -> Result<Option<(String, Vec<u8>)>, Box<dyn Error>> {
-        println!("File: \"{}\"", path);
+        println!("Extracting archive");
 
-        if path.ends_with(".rpyc") {
-            let script = RenPyCompiledScript::load(&mut Cursor::new(data))?;
-            if let Some(mut chunk) = script.chunk(1) {
-                if let Ok(str) = chunk.decompile() {
-                    return Ok(Some((
-                        path.replace(".rpyc", ".rpyc-decomp"),
-                        str.as_bytes().to_vec()
-                    )));
-                } else {
-                    println!("Ren'Py script decompilation error could not gracefully handle.");
-                    // So we output pickle instead with message.
-                    return Ok(Some((
-                        path.replace(".rpyc", ".rypc-pickle"),
-                        format!("CATASTROPHIC ERROR\nFile could not decompile\nPlease create a bug report with this file.\n{:#?}", chunk.pickle()?).as_bytes().to_vec()
-                    )))
-                }
+        self.options.extract(&mut archive, output, overwrite_output)?;
+
+        println!("Done");
+
+        Ok(())
+    }
+
+}
+
+
+
+
+
+#[derive(Parser, Debug)]
+pub struct CliRenPyGame {
+    /// Game root directory, or its `game/` directory.
+    #[arg(index = 1)]
+    dir: PathBuf,
+    #[command(flatten)]
+    options: CliRenPyOptions,
+}
+
+
+
+impl CliRenPyGame {
+
+    pub fn extract(&self, output: &PathBuf, overwrite_output: bool) -> Result<(), Box<dyn Error>> {
+
+        println!("Loading game");
+
+        let mut game = RenPyGame::from_dir(&self.dir)?;
+
+        for archive in &game.archives {
+            println!("Archive: \"{}\"", archive.display());
+        }
+        if !game.conflicts.is_empty() {
+            println!("{} files are overridden:", game.conflicts.len());
+            for conflict in &game.conflicts {
+                println!("    \"{}\" \"{}\" overrides \"{}\"", conflict.path, conflict.loaded, conflict.overridden);
             }
         }
 
-        Ok(None)
+        println!("Extracting game");
+
+        self.options.extract(&mut game, output, overwrite_output)?;
+
+        println!("Done");
+
+        Ok(())
     }
 
-    pub fn extract(&self, output: &PathBuf, overwrite_output: bool) -> Result<(), Box<dyn Error>> {
+}
+
+
+
+
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum CliRenPyTranslationFormat {
+    /// Ren'Py `tl/language/` scripts, output is a directory.
+    Tl,
+    /// gettext `.po` file, output is a file.
+    Po,
+}
+
+#[derive(Parser, Debug)]
+pub struct CliRenPyTranslate {
+    #[arg(index = 1)]
+    output: PathBuf,
+    #[arg(index = 2)]
+    file: PathBuf,
+    #[arg(short, long)]
+    /// Language name used for the translation.
+    language: String,
+    #[arg(short, long, value_enum, default_value_t = CliRenPyTranslationFormat::Tl)]
+    format: CliRenPyTranslationFormat,
+}
+
+
+
+impl CliRenPyTranslate {
+
+    pub fn execute(&self) -> Result<(), Box<dyn Error>> {
 
         println!("Loading archive");
 
         let archive_file = File::open(&self.file)?;
         let mut archive = RenPyArchive::from_file(archive_file)?;
 
-        println!("Extracting archive");
+        println!("Collecting translations");
+
+        let mut template = RenPyTranslationTemplate::new(self.language.clone());
+
+        for file in archive.read_files_deep()? {
+            let path = file.path().to_owned();
+            if !path.ends_with(".rpyc") {
+                continue;
+            }
+            println!("File: \"{}\"", path);
+
+            let translatables = RenPyCompiledScript::load(&mut Cursor::new(file.read_data()?))
+                .and_then(|script| match script.ast_chunk() {
+                    Some(mut chunk) => chunk.translatables(),
+                    None => Ok(Vec::new()),
+                });
+            match translatables {
+                Ok(translatables) => template.add(path, translatables),
+                Err(err) => println!("Failed to collect translations \"{}\" {}", path, err),
+            }
+        }
+
+        println!("Writing translations");
 
-        dir_extract(&mut archive, output, overwrite_output, Self::mapper)?;
+        match self.format {
+            CliRenPyTranslationFormat::Tl => {
+                for (path, content) in template.to_tl() {
+                    let mut out_path = self.output.clone();
+                    out_path.push(path);
+                    fs::create_dir_all(out_path.parent().unwrap())?;
+                    fs::write(out_path, content)?;
+                }
+            },
+            CliRenPyTranslationFormat::Po => {
+                if let Some(parent) = self.output.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&self.output, template.to_po())?;
+            },
+        }
 
         println!("Done");
 
@@ -64,3 +262,57 @@ impl CliRenPy {
 }
 
 
+
+
+
+#[derive(Parser, Debug)]
+pub struct CliRenPySave {
+    #[arg(index = 1)]
+    output: PathBuf,
+    /// `.save` files, or `persistent` files.
+    #[arg(index = 2, num_args = 1..)]
+    files: Vec<PathBuf>,
+}
+
+
+
+impl CliRenPySave {
+
+    fn unpack(&self, file: &PathBuf) -> Result<(), Box<dyn Error>> {
+        let name = file.file_stem().and_then(|name| name.to_str()).unwrap_or("save").to_owned();
+        let mut data = Cursor::new(fs::read(file)?);
+
+        // Saves are zip files, anything else is treated as persistent data.
+        if data.check_magic_string("PK\u{3}\u{4}")? {
+            data.set_position(0);
+            let save = RenPySave::load(data)?;
+            fs::write(self.output.join(format!("{}.json", name)), serde_json::to_string_pretty(&save.to_json())?)?;
+            if let Some(screenshot) = &save.screenshot {
+                fs::write(self.output.join(format!("{}.png", name)), screenshot)?;
+            }
+        } else {
+            data.set_position(0);
+            let persistent = RenPyPersistent::load(&mut data)?;
+            fs::write(self.output.join(format!("{}.json", name)), serde_json::to_string_pretty(&persistent.to_json())?)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn execute(&self) -> Result<(), Box<dyn Error>> {
+
+        fs::create_dir_all(&self.output)?;
+
+        for file in &self.files {
+            println!("File: \"{}\"", file.display());
+            if let Err(err) = self.unpack(file) {
+                println!("Failed to unpack \"{}\" {}", file.display(), err);
+            }
+        }
+
+        println!("Done");
+
+        Ok(())
+    }
+
+}
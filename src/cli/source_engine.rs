@@ -1,28 +1,217 @@
+use std::{collections::{HashMap, HashSet}, error::Error, fmt, fs::{self, File}, io::Cursor, path::PathBuf, rc::Rc};
+use clap::{Parser, ValueEnum};
+use image::{codecs::gif::{GifEncoder, Repeat}, Delay, Frame, ImageFormat, RgbaImage};
+use serde_json::Value;
+use crate::{extract::source_engine::{source1::{keyvalues::KeyValuesParser, vmt::{self, VMT}, vtf::{TextureFlags, TextureFormat, VTFError, VTF}, vtf_writer::VTFWriter}, source2::{kv3::{self, KV3Value}, resource::Resource}, vdf::{AppInfo, BinaryKeyValues}, vpk::{SourceEngineVpkArchive, SourceEngineVpkArchiveFiles}}, util::{dir_extract, virtual_fs::{VirtualDirectory, VirtualFile}, MappedFiles}};
 
-use std::{error::Error, fs::File, io::Cursor, path::PathBuf};
-use clap::Parser;
-use crate::{extract::source_engine::{source1::vtf::VTF, vpk::{SourceEngineVpkArchive, SourceEngineVpkArchiveFiles}}, util::dir_extract};
 
 
+#[derive(Debug)]
+pub enum CliSourceError {
+    UnknownFlag(String),
+}
+
+impl fmt::Display for CliSourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownFlag(flag) => write!(f, "Unknown texture flag \"{}\".", flag),
+        }
+    }
+}
+
+impl Error for CliSourceError { }
+
+
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum CliVtfExport {
+    /// First image, as PNG or EXR for HDR formats.
+    Image,
+    /// Every mipmap, frame, face & slice as numbered images in a directory.
+    All,
+    /// Frames as an animated PNG, starting at the first frame.
+    Apng,
+    /// Frames as an animated GIF, starting at the first frame.
+    Gif,
+    /// Cubemap faces as a cross.
+    Cross,
+    /// Cubemap faces as separate images in a directory.
+    Faces,
+    /// Slices of a volume texture as a sheet.
+    Slices,
+    /// Low res thumbnail.
+    Lowres,
+}
 
 #[derive(Parser, Debug)]
 pub struct CliSource {
     #[arg(index = 1)]
     file: PathBuf,
+    #[arg(long)]
+    /// Convert textures in archives, see `--export`.
+    convert: bool,
+    #[arg(long)]
+    /// Also write the original textures alongside converted output.
+    keep_original: bool,
+    #[arg(long, value_enum, default_value_t = CliVtfExport::Image)]
+    /// What part of textures is exported.
+    export: CliVtfExport,
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..))]
+    /// Frame rate of animated textures.
+    fps: u16,
+    #[arg(long)]
+    /// Name textures after the materials that use them & write materials as JSON alongside.
+    materials: bool,
 }
 
 
 
 impl CliSource {
 
-    fn mapper(path: String, data: &mut Vec<u8>) -> Result<Option<(String, Vec<u8>)>, Box<dyn Error>> {
-        println!("File: \"{}\"", path);
+    fn encode_png(image: &RgbaImage) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut encoded = Cursor::new(Vec::new());
+        image.write_to(&mut encoded, ImageFormat::Png)?;
+        Ok(encoded.into_inner())
+    }
+
+    fn encode_apng(frames: &[RgbaImage], fps: u16) -> Result<Vec<u8>, Box<dyn Error>> {
+        let (width, height) = frames.first().map(|frame| frame.dimensions()).ok_or(VTFError::MissingTexture)?;
+        let mut encoded = Vec::new();
+        let mut encoder = png::Encoder::new(&mut encoded, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(frames.len() as u32, 0)?;
+        encoder.set_frame_delay(1, fps)?;
+        let mut writer = encoder.write_header()?;
+        for frame in frames {
+            writer.write_image_data(frame.as_raw())?;
+        }
+        writer.finish()?;
+        Ok(encoded)
+    }
+
+    fn encode_gif(frames: Vec<RgbaImage>, fps: u16) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut encoded = Vec::new();
+        let mut encoder = GifEncoder::new(&mut encoded);
+        encoder.set_repeat(Repeat::Infinite)?;
+        let delay = Delay::from_numer_denom_ms(1000, fps as u32);
+        encoder.encode_frames(frames.into_iter().map(|frame| Frame::from_parts(frame, 0, 0, delay)))?;
+        drop(encoder);
+        Ok(encoded)
+    }
+
+    /// Files of the exported texture, `name` is the output path without extension.
+    fn export(&self, vtf: &VTF, name: &str) -> Result<MappedFiles, Box<dyn Error>> {
+        let texture = |mipmap, frame, face, slice| vtf.texture(mipmap, frame, face, slice).ok_or(VTFError::MissingTexture);
+
+        Ok(match self.export {
+            CliVtfExport::Image => {
+                let (ext, image) = texture(0, 0, 0, 0)?.encode_image()?;
+                vec![(format!("{}.{}", name, ext), image)]
+            },
+            CliVtfExport::All => {
+                let mut mapped = Vec::new();
+                for mipmap in 0..vtf.mipmaps() {
+                    for frame in 0..vtf.frames() {
+                        for face in 0..vtf.faces() {
+                            for slice in 0..vtf.mipmap_depth(mipmap) {
+                                let (ext, image) = texture(mipmap, frame, face, slice)?.encode_image()?;
+                                mapped.push((format!("{}/mip{}_frame{}_face{}_slice{}.{}", name, mipmap, frame, face, slice, ext), image));
+                            }
+                        }
+                    }
+                }
+                mapped
+            },
+            CliVtfExport::Apng => vec![(format!("{}.png", name), Self::encode_apng(&vtf.animation(0)?, self.fps)?)],
+            CliVtfExport::Gif => vec![(format!("{}.gif", name), Self::encode_gif(vtf.animation(0)?, self.fps)?)],
+            CliVtfExport::Cross => vec![(format!("{}.png", name), Self::encode_png(&vtf.cubemap_cross(0, 0)?)?)],
+            CliVtfExport::Faces => {
+                if vtf.faces() < 6 {
+                    return Err(Box::new(VTFError::NotCubemap));
+                }
+                let mut mapped = Vec::new();
+                for face in 0..vtf.faces() {
+                    let (ext, image) = texture(0, 0, face, 0)?.encode_image()?;
+                    mapped.push((format!("{}/{}.{}", name, VTF::CUBEMAP_FACES[face as usize], ext), image));
+                }
+                mapped
+            },
+            CliVtfExport::Slices => vec![(format!("{}.png", name), Self::encode_png(&vtf.slice_sheet(0, 0, 0)?)?)],
+            CliVtfExport::Lowres => {
+                let (ext, image) = vtf.lowres_texture().ok_or(VTFError::MissingResource("low res image"))?.encode_image()?;
+                vec![(format!("{}.{}", name, ext), image)]
+            },
+        })
+    }
+
+    /// Load a material with its patches resolved, `#include` & patches load from the materials of the archive.
+    fn load_material(materials: &Rc<HashMap<String, String>>, text: &str) -> Result<VMT, Box<dyn Error>> {
+        let include = materials.clone();
+        let mut parser = KeyValuesParser::new().include(move |path| Ok(include.get(&vmt::material_path(path)).cloned()));
+        VMT::parse(&mut parser, text)?.resolve(|path| match materials.get(path) {
+            Some(text) => Ok(Some(VMT::parse(&mut parser, text)?)),
+            None => Ok(None),
+        })
+    }
+
+    /// Output names of textures, from the first material that uses them in path order.
+    /// Base textures are named after the material, other textures add the parameter.
+    /// Names that are already used, or are the path of another texture in `textures`, fall back to the texture's own path.
+    fn texture_names(materials: &Rc<HashMap<String, String>>, textures: &HashSet<String>) -> HashMap<String, String> {
+        let mut paths = materials.keys().collect::<Vec<_>>();
+        paths.sort();
 
-        if path.ends_with(".vtf") {
-            let vtf = VTF::load(&mut Cursor::new(data))?;
+        let mut names = HashMap::new();
+        let mut used = HashSet::new();
+        for path in paths {
+            let material = match Self::load_material(materials, &materials[path]) {
+                Ok(material) => material,
+                Err(err) => {
+                    println!("Failed to load material \"{}\" {}", path, err);
+                    continue;
+                },
+            };
+            let name = path.strip_suffix(".vmt").unwrap_or(path);
+            for (param, texture) in material.textures() {
+                if names.contains_key(&texture) {
+                    continue;
+                }
+                let param = param.trim_start_matches(['$', '%']).to_lowercase();
+                let own = texture.strip_suffix(".vtf").unwrap_or(&texture).to_owned();
+                let name = match param.as_str() {
+                    "basetexture" => name.to_owned(),
+                    param => format!("{}_{}", name, param),
+                };
+                let taken = used.contains(&name) || (name != own && textures.contains(&format!("{}.vtf", name)));
+                let name = if taken { own } else { name };
+                used.insert(name.clone());
+                names.insert(texture, name);
+            }
         }
+        names
+    }
 
-        Ok(None)
+    fn mapper(&self, path: &str, data: &[u8], materials: &Rc<HashMap<String, String>>, names: &HashMap<String, String>) -> Result<Option<MappedFiles>, Box<dyn Error>> {
+        if self.materials && path.ends_with(".vmt") {
+            let material = Self::load_material(materials, &String::from_utf8_lossy(data))?;
+            let json = serde_json::to_string_pretty(&material.to_json())?;
+            return Ok(Some(vec![
+                (format!("{}.json", path.strip_suffix(".vmt").unwrap_or(path)), json.into_bytes()),
+                (path.to_owned(), data.to_vec()),
+            ]));
+        }
+        if !self.convert || !path.ends_with(".vtf") {
+            return Ok(None);
+        }
+
+        let vtf = VTF::load(&mut Cursor::new(data))?;
+        let name = names.get(&vmt::texture_path(path)).map(String::as_str).unwrap_or(path.strip_suffix(".vtf").unwrap_or(path));
+        let mut mapped = self.export(&vtf, name)?;
+        if self.keep_original {
+            mapped.push((path.to_owned(), data.to_vec()));
+        }
+        Ok(Some(mapped))
     }
 
     pub fn extract(&self, output: &PathBuf, overwrite_output: bool) -> Result<(), Box<dyn Error>> {
@@ -33,20 +222,61 @@ impl CliSource {
 
                     let archive_files = SourceEngineVpkArchiveFiles::locate(&self.file)?;
                     let mut archive = SourceEngineVpkArchive::from_files(archive_files)?;
+
+                    // Materials are read first, so textures can be named after them.
+                    let mut materials = HashMap::new();
+                    let mut textures = HashSet::new();
+                    if self.materials {
+                        println!("Loading materials");
+                        for file in archive.read_files_deep()? {
+                            if file.path().ends_with(".vmt") {
+                                let path = vmt::material_path(file.path());
+                                materials.insert(path, String::from_utf8_lossy(&file.read_data()?).into_owned());
+                            } else if file.path().ends_with(".vtf") {
+                                textures.insert(vmt::texture_path(file.path()));
+                            }
+                        }
+                    }
+                    let materials = Rc::new(materials);
+                    let names = Self::texture_names(&materials, &textures);
             
                     println!("Extracting archive");
             
-                    dir_extract(&mut archive, output, overwrite_output, Self::mapper)?;
+                    let mut failures = Vec::new();
+                    dir_extract(&mut archive, output, overwrite_output, |path, data| {
+                        println!("File: \"{}\"", path);
+                        match self.mapper(&path, data, &materials, &names) {
+                            // Original file is written so that nothing is missing from the output.
+                            Err(err) => {
+                                println!("Failed to convert \"{}\" {}", path, err);
+                                failures.push((path, err.to_string()));
+                                Ok(None)
+                            },
+                            result => result,
+                        }
+                    })?;
+
+                    if !failures.is_empty() {
+                        println!("{} files failed:", failures.len());
+                        for (path, err) in &failures {
+                            println!("    \"{}\" {}", path, err);
+                        }
+                    }
             
                     println!("Done");
                 },
                 Some("vtf") => {
                     println!("Loading VTF");
                     let vtf = VTF::load(File::open(&self.file)?)?;
-                    if let Some(texture) = vtf.texture(0, 0, 0, 0) {
-                        println!("Converting VTF");
-                        let image = texture.to_image();
-                        image.save(output)?;
+                    println!("Converting VTF");
+                    // Output extension is replaced by the format of the export.
+                    let name = output.with_extension("");
+                    for (path, data) in self.export(&vtf, &name.to_string_lossy())? {
+                        println!("File: \"{}\"", path);
+                        if let Some(parent) = PathBuf::from(&path).parent() {
+                            fs::create_dir_all(parent)?;
+                        }
+                        fs::write(path, data)?;
                     }
                     println!("Done");
                 },
@@ -57,4 +287,182 @@ impl CliSource {
         Ok(())
     }
 
-}
\ No newline at end of file
+}
+
+
+
+
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum CliVtfFormat {
+    Rgba8888,
+    Bgra8888,
+    Rgb888,
+    Bgr888,
+    /// Compressed without alpha.
+    Dxt1,
+    /// Compressed with sharp alpha.
+    Dxt3,
+    /// Compressed with smooth alpha.
+    Dxt5,
+}
+
+impl CliVtfFormat {
+    fn texture_format(&self) -> TextureFormat {
+        match self {
+            Self::Rgba8888 => TextureFormat::IMAGE_FORMAT_RGBA8888,
+            Self::Bgra8888 => TextureFormat::IMAGE_FORMAT_BGRA8888,
+            Self::Rgb888 => TextureFormat::IMAGE_FORMAT_RGB888,
+            Self::Bgr888 => TextureFormat::IMAGE_FORMAT_BGR888,
+            Self::Dxt1 => TextureFormat::IMAGE_FORMAT_DXT1,
+            Self::Dxt3 => TextureFormat::IMAGE_FORMAT_DXT3,
+            Self::Dxt5 => TextureFormat::IMAGE_FORMAT_DXT5,
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct CliImageToVtf {
+    #[arg(index = 1)]
+    output: PathBuf,
+    /// Images of the texture, as frames of an animation.
+    /// Cubemaps have 6 images per frame, in the order right, left, back, front, up & down.
+    #[arg(index = 2, num_args = 1..)]
+    images: Vec<PathBuf>,
+    #[arg(short, long, value_enum, default_value_t = CliVtfFormat::Dxt5)]
+    format: CliVtfFormat,
+    #[arg(long, default_value = "7.2", value_parser = ["7.2", "7.3", "7.4", "7.5"])]
+    vtf_version: String,
+    #[arg(long = "flag")]
+    /// Texture flags by name, such as `CLAMPS`, `ENVMAP` for cubemaps, or `NOMIP` to not generate mipmaps.
+    flags: Vec<String>,
+    #[arg(long)]
+    /// Leave out the low res thumbnail.
+    no_lowres: bool,
+}
+
+
+
+impl CliImageToVtf {
+
+    pub fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let mut flags = TextureFlags::empty();
+        for name in &self.flags {
+            flags.insert(TextureFlags::from_name(&name.to_uppercase()).ok_or_else(|| CliSourceError::UnknownFlag(name.clone()))?);
+        }
+        let minor_version = self.vtf_version.trim_start_matches("7.").parse()?;
+
+        println!("Loading images");
+        let images = self.images.iter().map(|path| Ok(image::open(path)?.to_rgba8())).collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+        println!("Writing VTF");
+        let vtf = VTFWriter::new(self.format.texture_format())
+            .version(minor_version)
+            .flags(flags)
+            .lowres(!self.no_lowres)
+            .write(&images)?;
+        fs::write(&self.output, vtf)?;
+
+        println!("Done");
+        Ok(())
+    }
+
+}
+
+
+
+
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum CliKeyValuesFormat {
+    /// Detect the format from the file's magic & extension.
+    Auto,
+    /// KeyValues text, such as `.vmt`, `.res` or `gameinfo.txt`.
+    Text,
+    /// Binary KeyValues, such as `shortcuts.vdf` or `.vbkv`.
+    Binary,
+    /// Steam's `appinfo.vdf`.
+    Appinfo,
+    /// KeyValues3, text or binary.
+    Kv3,
+    /// Compiled Source 2 resource, such as `.vmat_c`, with its KV3 blocks.
+    Resource,
+}
+
+#[derive(Parser, Debug)]
+pub struct CliKeyValues {
+    #[arg(index = 1)]
+    file: PathBuf,
+    #[arg(short, long, value_enum, default_value_t = CliKeyValuesFormat::Auto)]
+    format: CliKeyValuesFormat,
+    #[arg(long)]
+    /// Read escape sequences in KeyValues text, Source reads them in few files.
+    escapes: bool,
+    #[arg(long = "define", default_values = ["$WIN32", "$WINDOWS"])]
+    /// Conditions that are true in KeyValues text, replacing the defaults, such as `$X360` or `$OSX`.
+    conditions: Vec<String>,
+}
+
+
+
+impl CliKeyValues {
+
+    fn detect(&self, data: &[u8]) -> CliKeyValuesFormat {
+        let magic = data.get(..4).map(|magic| u32::from_le_bytes([magic[0], magic[1], magic[2], magic[3]])).unwrap_or_default();
+        let ext = self.file.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+        if AppInfo::is_appinfo(magic) {
+            CliKeyValuesFormat::Appinfo
+        } else if kv3::is_binary(magic) || data.starts_with(b"<!-- kv3") {
+            CliKeyValuesFormat::Kv3
+        } else if ext.ends_with("_c") {
+            CliKeyValuesFormat::Resource
+        // Binary starts with a type, text with a key or comment.
+        } else if data.starts_with(b"VBKV") || data.first().is_some_and(|byte| *byte <= 0x0B && !byte.is_ascii_whitespace()) {
+            CliKeyValuesFormat::Binary
+        } else {
+            CliKeyValuesFormat::Text
+        }
+    }
+
+    fn text(&self, data: &[u8]) -> Result<Value, Box<dyn Error>> {
+        // Includes are relative to the file.
+        let dir = self.file.parent().map(PathBuf::from).unwrap_or_default();
+        let mut parser = KeyValuesParser::new()
+            .escapes(self.escapes)
+            .conditions(&self.conditions)
+            .include(move |path| match fs::read(dir.join(path)) {
+                Ok(data) => Ok(Some(String::from_utf8_lossy(&data).into_owned())),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(Box::new(err)),
+            });
+        Ok(parser.parse(&String::from_utf8_lossy(data))?.to_json())
+    }
+
+    pub fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let data = fs::read(&self.file)?;
+        let format = match self.format {
+            CliKeyValuesFormat::Auto => self.detect(&data),
+            format => format,
+        };
+
+        let json = match format {
+            CliKeyValuesFormat::Auto | CliKeyValuesFormat::Text => self.text(&data)?,
+            CliKeyValuesFormat::Binary if data.starts_with(b"VBKV") => BinaryKeyValues::load_vbkv(&mut Cursor::new(data))?.to_json(),
+            CliKeyValuesFormat::Binary => BinaryKeyValues::load(&mut Cursor::new(data))?.to_json(),
+            CliKeyValuesFormat::Appinfo => AppInfo::load(Cursor::new(data))?.to_json(),
+            CliKeyValuesFormat::Kv3 => KV3Value::load(&data)?.to_json(),
+            CliKeyValuesFormat::Resource => {
+                // Blocks that are not KV3 are left out.
+                let mut blocks = serde_json::Map::new();
+                for block in Resource::load(&data)?.blocks.iter().filter(|block| block.is_kv3()) {
+                    blocks.insert(block.kind.clone(), block.kv3()?.to_json());
+                }
+                Value::Object(blocks)
+            },
+        };
+        println!("{}", serde_json::to_string_pretty(&json)?);
+
+        Ok(())
+    }
+
+}
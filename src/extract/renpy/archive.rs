@@ -1,6 +1,7 @@
 
 use std::{collections::HashMap, error::Error, fmt, fs::File, io::{Cursor, Read, Seek, Write}};
-use crate::util::{decode_hex, pickle::{parser::PickleParser, pickle::Pickle}, read_ext::ReadExt, virtual_fs::{VirtualDirectory, VirtualEntry, VirtualFile}};
+use serde::de::IgnoredAny;
+use crate::util::{decode_hex, pickle::parser::PickleParser, read_ext::ReadExt, virtual_fs::{VirtualDirectory, VirtualEntry, VirtualFile}};
 
 
 
@@ -106,27 +107,22 @@ impl RenPyArchive {
         let mut decoder = flate2::read::ZlibDecoder::new(Cursor::new(encoded));
         decoder.read_to_end(&mut decoded)?;
 
-        let pickle = PickleParser::parse(&mut Cursor::new(decoded))?;
-        
-        // println!("Pickle: {:#?}", pickle);
-        
+        // Index is `{path: [(offset, length, prefix)]}`.
+        let index: HashMap<String, Vec<(u64, u64, IgnoredAny)>> = PickleParser::parse(&mut Cursor::new(decoded))?.parse()?;
+
         let mut files = Vec::new();
-        
-        for (path, pickle_chunks) in TryInto::<HashMap<String, Pickle>>::try_into(pickle.clone())? {
-            let mut chunks = Vec::new();
-            for chunk in TryInto::<Vec<Pickle>>::try_into(pickle_chunks)? {
-                let chunk = TryInto::<(Pickle, Pickle, Pickle)>::try_into(chunk)?;
-                let offset = TryInto::<u64>::try_into(chunk.0)?;
-                let length = TryInto::<u64>::try_into(chunk.1)?;
-                chunks.push((offset ^ xor, length ^ xor));
-            }
-            
+        for (path, chunks) in index {
+            let chunks = chunks.into_iter().map(|(offset, length, _prefix)| (offset ^ xor, length ^ xor)).collect();
             files.push(RenPyArchiveFile { file: file.try_clone()?, path, chunks });
         }
 
         Ok(RenPyArchive { files })
     }
 
+    pub fn into_files(self) -> Vec<RenPyArchiveFile> {
+        self.files
+    }
+
 }
 
 impl VirtualDirectory<RenPyArchiveFile, RenPyArchive> for RenPyArchive {
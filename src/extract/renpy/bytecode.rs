@@ -0,0 +1,107 @@
+// https://github.com/renpy/renpy/blob/master/renpy/script.py
+
+use std::{error::Error, io::{Cursor, Read}};
+use crate::util::{marshal::{Marshal, MarshalCode, MarshalParser}, pickle::{parser::{PickleParser, PickleStringEncoding}, pickle::Pickle}};
+
+
+
+/// Cache keys are bytes, or `str` when pickled by Python 2 and decoded.
+fn key_bytes(key: Pickle) -> Vec<u8> {
+    match key.into_resolved() {
+        Pickle::Binary(bytes) => bytes,
+        Pickle::String(str) => str.into_bytes(),
+        key => key.to_string().into_bytes(),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn code_listing(code: &MarshalCode, depth: usize) -> String {
+    let indent = "    ".repeat(depth);
+    let mut str = format!("{}code {} \"{}\" line {}\n", indent, code.qualname, code.filename, code.firstlineno);
+    str += &format!("{}    argcount {}, stacksize {}, flags {:#x}, {} bytes of bytecode\n", indent, code.argcount, code.stacksize, code.flags, code.code.len());
+    if !code.names.is_empty() {
+        str += &format!("{}    names: {}\n", indent, code.names.join(", "));
+    }
+    if !code.varnames.is_empty() {
+        str += &format!("{}    varnames: {}\n", indent, code.varnames.join(", "));
+    }
+    str += &format!("{}    consts: {}\n", indent, code.consts.len());
+    for constant in &code.consts {
+        if let Marshal::Code(code) = constant {
+            str += &code_listing(code, depth + 1);
+        }
+    }
+    str
+}
+
+
+
+/// `cache/bytecode-*.rpyb` is a zlib compressed pickle of `(version, {key: marshalled code})`.
+///
+/// The key is a hash of the python source.
+#[derive(Debug)]
+pub struct RenPyBytecodeCache {
+    pub version: Option<i64>,
+    /// Python version that compiled the code, marshalled code objects differ between versions.
+    pub python: (u8, u8),
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl RenPyBytecodeCache {
+
+    /// Ren'Py 8 names the cache with the python version, `bytecode-39.rpyb` is Python 3.9.
+    /// Ren'Py 7 and older use `bytecode.rpyb` and Python 2.7.
+    pub fn python_version(path: &str) -> (u8, u8) {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let version = name.strip_prefix("bytecode-").and_then(|name| name.strip_suffix(".rpyb"));
+        match version.filter(|version| version.len() >= 2) {
+            Some(version) => match (version[..1].parse(), version[1..].parse()) {
+                (Ok(major), Ok(minor)) => (major, minor),
+                _ => (2, 7),
+            },
+            None => (2, 7),
+        }
+    }
+
+    pub fn load(data: &mut impl Read, python: (u8, u8)) -> Result<Self, Box<dyn Error>> {
+        let mut decompressed = Vec::new();
+        flate2::read::ZlibDecoder::new(data).read_to_end(&mut decompressed)?;
+
+        let (version, cache): (Pickle, Pickle) = PickleParser::parse_with_encoding(&mut Cursor::new(decompressed), PickleStringEncoding::Bytes)?.try_into()?;
+
+        let mut entries = match cache {
+            Pickle::Dict(cache) => cache.into_iter()
+                .map(|(key, code)| Ok((key_bytes(key), code.try_into()?)))
+                .collect::<Result<Vec<_>, Box<dyn Error>>>()?,
+            _ => Vec::new(),
+        };
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        Ok(Self { version: version.try_into().ok(), python, entries })
+    }
+
+    /// Listing of cache keys, with the metadata of the code objects they contain.
+    pub fn listing(&self) -> String {
+        let mut str = String::new();
+        str += &format!("# Ren'Py bytecode cache, Python {}.{}", self.python.0, self.python.1);
+        if let Some(version) = self.version {
+            str += &format!(", cache version {}", version);
+        }
+        str += &format!("\n# {} entries\n", self.entries.len());
+
+        for (key, code) in &self.entries {
+            str += &format!("\n{}\n", hex(key));
+            match MarshalParser::parse(&mut Cursor::new(code), self.python) {
+                Ok(Marshal::Code(code)) => str += &code_listing(&code, 1),
+                Ok(value) => str += &format!("    {:?}\n", value),
+                Err(err) => str += &format!("    ***MARSHAL ERROR: {}***\n", err),
+            }
+        }
+
+        str
+    }
+
+}
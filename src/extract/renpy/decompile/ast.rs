@@ -1,125 +1,618 @@
-
 use std::error::Error;
-use crate::util::pickle::pickle::{Pickle, PickleClass};
-use super::{indent, node_extract_data, parse_node};
+use crate::util::pickle::pickle::Pickle;
+use super::{atl::parse_atl_block, block, extract_bool, extract_list, extract_string, indent, map_first_line, mark_line, node_extract_data, node_linenumber, node_name, node_string, node_try_extract_data, parse_node, string_list, string_literal, try_extract_string, DecompileError};
 
 
 
-pub fn parse_node_ast(node: Pickle) -> Result<String, Box<dyn Error>> {
-    let class: PickleClass = node.clone().try_into()?;
+fn same_line(a: &Pickle, b: &Pickle) -> bool {
+    match (node_linenumber(a), node_linenumber(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
 
-    Ok(match class.module.name.as_str() {
-        "Return" => String::new(),
-        "Label" => format!(
-            "label {}:\n{}\n",
-            TryInto::<String>::try_into(node_extract_data(&node, "name")?)?,
-            indent(TryInto::<Vec<Pickle>>::try_into(node_extract_data(&node, "block")?)?
-                .iter()
-                .map(|node| parse_node(node.clone()))
-                .collect::<Result<Vec<_>, _>>()?
-                .join(""))
-        ),
-        "Jump" => format!(
-            "jump {}\n",
-            TryInto::<String>::try_into(node_extract_data(&node, "target")?)?
-        ),
-        "Say" => {
-            let what: String = TryInto::<String>::try_into(node_extract_data(&node, "what")?)?;
-            if let Some(who) = TryInto::<Option<String>>::try_into(node_extract_data(&node, "who")?)? {
-                format!("{} \"{}\"\n", who, what)
-            } else {
-                format!("\"{}\"\n", what)
+fn is_empty_label(node: &Pickle) -> bool {
+    node_name(node) == "Label" && node_try_extract_data(node, "block")
+        .and_then(|block| TryInto::<Vec<Pickle>>::try_into(block).ok())
+        .map(|block| block.is_empty())
+        .unwrap_or(false)
+}
+
+fn is_non_interacting_say(node: &Pickle) -> bool {
+    node_name(node) == "Say" && matches!(node_try_extract_data(node, "interact"), Some(Pickle::Bool(false)))
+}
+
+/// `show image with transition` compiles to `with None`, the statement, then `with transition`.
+fn is_paired_with(node: &Pickle, after: &Pickle) -> bool {
+    let paired = try_extract_string(node, "paired").ok().flatten();
+    let expr = try_extract_string(node, "expr").ok().flatten();
+    node_name(after) == "With"
+        && paired.is_some()
+        && expr.as_deref() == Some("None")
+        && try_extract_string(after, "expr").ok().flatten() == paired
+}
+
+pub fn block_nodes(header: String, nodes: &[Pickle]) -> Result<String, Box<dyn Error>> {
+    Ok(block(header, parse_block(nodes)?.join("")))
+}
+
+/// Scripts variables are always in the `store` namespace, sub stores are prefixed with `store.`.
+fn store_prefix(store: &str) -> String {
+    match store.strip_prefix("store") {
+        Some(name) if !name.is_empty() => format!("{}.", name.trim_start_matches('.')),
+        _ => String::new(),
+    }
+}
+
+
+
+/// `ArgumentInfo` as `(a, b=c, *d, **e)`
+pub fn parse_arguments(arguments: &Pickle) -> Result<String, Box<dyn Error>> {
+    let mut items = Vec::new();
+    for argument in extract_list(arguments, "arguments")? {
+        let (name, value): (Pickle, Pickle) = argument.try_into()?;
+        match name {
+            Pickle::None => items.push(node_string(&value)?),
+            name => items.push(format!("{}={}", node_string(&name)?, node_string(&value)?)),
+        }
+    }
+    if let Some(extrapos) = try_extract_string(arguments, "extrapos")? {
+        items.push(format!("*{}", extrapos));
+    }
+    if let Some(extrakw) = try_extract_string(arguments, "extrakw")? {
+        items.push(format!("**{}", extrakw));
+    }
+    Ok(format!("({})", items.join(", ")))
+}
+
+/// `ParameterInfo` as `(a, b=c, *d, **e)`
+pub fn parse_parameters(parameters: &Pickle) -> Result<String, Box<dyn Error>> {
+    let mut items = Vec::new();
+    match node_try_extract_data(parameters, "parameters") {
+        // Ren'Py 8.1+ stores `Parameter` objects.
+        Some(Pickle::Dict(dict)) => {
+            let mut keyword_only = false;
+            for parameter in dict.values() {
+                let name = extract_string(parameter, "name")?;
+                let default = try_extract_string(parameter, "default")?;
+                let kind: i64 = node_try_extract_data(parameter, "kind").map(|kind| kind.try_into()).transpose()?.unwrap_or(1);
+                match kind {
+                    2 => { keyword_only = true; items.push(format!("*{}", name)); },
+                    3 if !keyword_only => { keyword_only = true; items.push("*".to_owned()); items.push(name); },
+                    4 => items.push(format!("**{}", name)),
+                    _ => items.push(name),
+                }
+                if let (Some(default), Some(last)) = (default, items.last_mut()) {
+                    *last = format!("{}={}", last, default);
+                }
             }
         },
+        Some(Pickle::List(list)) => {
+            let positional = string_list(extract_list(parameters, "positional")?)?;
+            let extrapos = try_extract_string(parameters, "extrapos")?;
+            let mut keyword_only = false;
+            for parameter in list {
+                let (name, default): (Pickle, Pickle) = parameter.try_into()?;
+                let name = node_string(&name)?;
+                if !positional.contains(&name) && !keyword_only {
+                    keyword_only = true;
+                    items.push(match &extrapos {
+                        Some(extrapos) => format!("*{}", extrapos),
+                        None => "*".to_owned(),
+                    });
+                }
+                match default {
+                    Pickle::None => items.push(name),
+                    default => items.push(format!("{}={}", name, node_string(&default)?)),
+                }
+            }
+            if let (Some(extrapos), false) = (&extrapos, keyword_only) {
+                items.push(format!("*{}", extrapos));
+            }
+            if let Some(extrakw) = try_extract_string(parameters, "extrakw")? {
+                items.push(format!("**{}", extrakw));
+            }
+        },
+        _ => { },
+    }
+    Ok(format!("({})", items.join(", ")))
+}
+
+/// Image specifier used by `show`, `scene` & `hide`.
+///
+/// Either `(name, at_list, layer)`, `(name, expression, tag, at_list, layer, zorder)`, or `(name, expression, tag, at_list, layer, zorder, behind)`
+fn parse_imspec(imspec: Pickle) -> Result<String, Box<dyn Error>> {
+    let imspec: Vec<Pickle> = imspec.try_into()?;
+    let (name, expression, tag, at_list, layer, zorder, behind) = match imspec.len() {
+        3 => (&imspec[0], &Pickle::None, &Pickle::None, &imspec[1], &imspec[2], &Pickle::None, &Pickle::None),
+        6 => (&imspec[0], &imspec[1], &imspec[2], &imspec[3], &imspec[4], &imspec[5], &Pickle::None),
+        7 => (&imspec[0], &imspec[1], &imspec[2], &imspec[3], &imspec[4], &imspec[5], &imspec[6]),
+        _ => return Err(Box::new(DecompileError::InvalidNodeData("imspec".to_owned()))),
+    };
+
+    let mut str = match expression {
+        Pickle::None => string_list(name.clone().try_into()?)?.join(" "),
+        expression => format!("expression {}", node_string(expression)?),
+    };
+    if !matches!(tag, Pickle::None) {
+        str += &format!(" as {}", node_string(tag)?);
+    }
+    if let Pickle::List(at_list) = at_list {
+        if !at_list.is_empty() {
+            str += &format!(" at {}", string_list(at_list.clone())?.join(", "));
+        }
+    }
+    if !matches!(layer, Pickle::None) {
+        str += &format!(" onlayer {}", node_string(layer)?);
+    }
+    if !matches!(zorder, Pickle::None) {
+        str += &format!(" zorder {}", node_string(zorder)?);
+    }
+    if let Pickle::List(behind) = behind {
+        if !behind.is_empty() {
+            str += &format!(" behind {}", string_list(behind.clone())?.join(", "));
+        }
+    }
+    Ok(str)
+}
+
+fn parse_at_list(node: &Pickle) -> Result<String, Box<dyn Error>> {
+    let at_list = string_list(extract_list(node, "at_list")?)?;
+    Ok(if at_list.is_empty() { String::new() } else { format!(" at {}", at_list.join(", ")) })
+}
+
+/// Statements that can optionally have an ATL block.
+fn with_atl(header: String, node: &Pickle) -> Result<String, Box<dyn Error>> {
+    Ok(match node_try_extract_data(node, "atl") {
+        Some(atl) => block(header, parse_atl_block(&atl)?),
+        None => format!("{}\n", header),
+    })
+}
+
+fn parse_python(node: &Pickle, prefix: &str) -> Result<String, Box<dyn Error>> {
+    let code = extract_string(node, "code")?;
+    let early = node_name(node) == "EarlyPython";
+    let hide = extract_bool(node, "hide");
+    let store = try_extract_string(node, "store")?.unwrap_or("store".to_owned());
+
+    if prefix.is_empty() && !early && !hide && store == "store" && !code.trim().contains('\n') {
+        return Ok(format!("$ {}\n", code.trim()));
+    }
+
+    let mut header = format!("{}python", prefix);
+    if early {
+        header += " early";
+    }
+    if hide {
+        header += " hide";
+    }
+    if store != "store" {
+        header += &format!(" in {}", store.trim_start_matches("store."));
+    }
+    Ok(block(header, format!("{}\n", code.trim_end())))
+}
+
+fn parse_menu(node: &Pickle, label: Option<String>, say: Option<&Pickle>) -> Result<String, Box<dyn Error>> {
+    let mut header = "menu".to_owned();
+    if let Some(label) = label {
+        header += &format!(" {}", label);
+    }
+    if let Some(arguments) = node_try_extract_data(node, "arguments") {
+        header += &parse_arguments(&arguments)?;
+    }
+
+    let mut body = String::new();
+    if let Some(set) = try_extract_string(node, "set")? {
+        body += &format!("set {}\n", set);
+    }
+    if let Some(with) = try_extract_string(node, "with_")? {
+        body += &format!("with {}\n", with);
+    }
+    if let Some(say) = say {
+        body += &mark_line(say, parse_say(say, true)?);
+    }
+
+    let item_arguments = extract_list(node, "item_arguments")?;
+    for (index, item) in extract_list(node, "items")?.into_iter().enumerate() {
+        let (caption, condition, nodes): (Pickle, Pickle, Pickle) = item.try_into()?;
+        let mut item = string_literal(&node_string(&caption)?);
+        if let Some(arguments) = item_arguments.get(index) {
+            if !matches!(arguments, Pickle::None) {
+                item += &parse_arguments(arguments)?;
+            }
+        }
+        let condition = node_string(&condition)?;
+        if condition != "True" {
+            item += &format!(" if {}", condition);
+        }
+        match nodes {
+            Pickle::None => body += &format!("{}\n", item),
+            nodes => body += &block_nodes(item, &TryInto::<Vec<Pickle>>::try_into(nodes)?)?,
+        }
+    }
+
+    Ok(block(header, body))
+}
+
+/// `who attributes @ temporary_attributes "what" (arguments) nointeract id identifier with with_`
+///
+/// The caption of a menu never interacts, so `nointeract` is not output for it.
+fn parse_say(node: &Pickle, menu: bool) -> Result<String, Box<dyn Error>> {
+    let mut items = Vec::new();
+    if let Some(who) = try_extract_string(node, "who")? {
+        items.push(who);
+    }
+    if let Some(attributes) = node_try_extract_data(node, "attributes") {
+        items.append(&mut string_list(attributes.try_into()?)?);
+    }
+    if let Some(attributes) = node_try_extract_data(node, "temporary_attributes") {
+        items.push("@".to_owned());
+        items.append(&mut string_list(attributes.try_into()?)?);
+    }
+    items.push(string_literal(&extract_string(node, "what")?));
+    if let Some(arguments) = node_try_extract_data(node, "arguments") {
+        items.push(parse_arguments(&arguments)?);
+    }
+    if !menu && matches!(node_try_extract_data(node, "interact"), Some(Pickle::Bool(false))) {
+        items.push("nointeract".to_owned());
+    }
+    if extract_bool(node, "explicit_identifier") {
+        if let Some(identifier) = try_extract_string(node, "identifier")? {
+            items.push(format!("id {}", identifier));
+        }
+    }
+    if let Some(with) = try_extract_string(node, "with_")? {
+        items.push(format!("with {}", with));
+    }
+    Ok(format!("{}\n", items.join(" ")))
+}
+
+fn parse_call(node: &Pickle, from: Option<String>) -> Result<String, Box<dyn Error>> {
+    let mut str = "call ".to_owned();
+    let expression = extract_bool(node, "expression");
+    if expression {
+        str += "expression ";
+    }
+    str += &extract_string(node, "label")?;
+    if let Some(arguments) = node_try_extract_data(node, "arguments") {
+        if expression {
+            str += " pass ";
+        }
+        str += &parse_arguments(&arguments)?;
+    }
+    if let Some(from) = from {
+        str += &format!(" from {}", from);
+    }
+    Ok(format!("{}\n", str))
+}
+
+fn parse_init(node: &Pickle) -> Result<String, Box<dyn Error>> {
+    let priority: i64 = node_extract_data(node, "priority")?.try_into()?;
+    let nodes = extract_list(node, "block")?;
+
+    // Translated strings are grouped in a single init block.
+    if priority == 0 && !nodes.is_empty() && nodes.iter().all(|node| node_name(node) == "TranslateString") {
+        return Ok(parse_block(&nodes)?.join(""));
+    }
+
+    // Some statements are implicitly put in an init block with a default priority.
+    if nodes.len() == 1 {
+        let implicit = match node_name(&nodes[0]) {
+            "Define" | "Default" | "Transform" | "Style" | "Testcase" => priority == 0,
+            "Image" => priority == 500 || priority == 990,
+            "Screen" => priority == -500,
+            "EarlyPython" => true,
+            _ => false,
+        };
+        if implicit {
+            return parse_node(&nodes[0]);
+        }
+    }
+
+    let prefix = if priority == 0 { "init ".to_owned() } else { format!("init {} ", priority) };
+
+    if nodes.len() == 1 && node_name(&nodes[0]) == "Python" {
+        return parse_python(&nodes[0], &prefix);
+    }
+
+    block_nodes(prefix.trim_end().to_owned(), &nodes)
+}
+
+fn parse_style(node: &Pickle) -> Result<String, Box<dyn Error>> {
+    let mut header = format!("style {}", extract_string(node, "style_name")?);
+    if let Some(parent) = try_extract_string(node, "parent")? {
+        header += &format!(" is {}", parent);
+    }
+
+    let mut body = String::new();
+    if let Some(variant) = try_extract_string(node, "variant")? {
+        body += &format!("variant {}\n", variant);
+    }
+    if extract_bool(node, "clear") {
+        body += "clear\n";
+    }
+    if let Some(take) = try_extract_string(node, "take")? {
+        body += &format!("take {}\n", take);
+    }
+    for delattr in string_list(extract_list(node, "delattr")?)? {
+        body += &format!("del {}\n", delattr);
+    }
+    // Dicts keep their insertion order, which is the order in the script.
+    if let Some(Pickle::Dict(properties)) = node_try_extract_data(node, "properties") {
+        for (name, value) in properties {
+            body += &format!("{} {}\n", name.key_string(), node_string(&value)?);
+        }
+    }
+
+    Ok(if body.is_empty() { format!("{}\n", header) } else { block(header, body) })
+}
+
+/// Consecutive `TranslateString` nodes of the same language as a single `translate language strings:` block.
+fn parse_translate_strings(nodes: &[Pickle]) -> Result<String, Box<dyn Error>> {
+    let language = try_extract_string(&nodes[0], "language")?.unwrap_or("None".to_owned());
+    let body = nodes.iter()
+        .map(|node| Ok(format!(
+            "old {}\nnew {}\n",
+            string_literal(&extract_string(node, "old")?),
+            string_literal(&extract_string(node, "new")?),
+        )))
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?
+        .join("\n");
+    Ok(block(format!("translate {} strings", language), body))
+}
+
+/// User statement blocks are stored as lexer output `[(filename, linenumber, text, block)]`
+fn parse_user_statement_block(nodes: Vec<Pickle>) -> Result<String, Box<dyn Error>> {
+    let mut str = String::new();
+    for node in nodes {
+        let (_filename, _linenumber, text, nodes): (Pickle, Pickle, Pickle, Pickle) = node.try_into()?;
+        let text = node_string(&text)?;
+        let nodes: Vec<Pickle> = nodes.try_into()?;
+        if nodes.is_empty() {
+            str += &format!("{}\n", text);
+        } else {
+            str += &format!("{}\n{}", text, indent(parse_user_statement_block(nodes)?));
+        }
+    }
+    Ok(str)
+}
+
+
+
+/// Parse a list of nodes, some statements compile to multiple nodes & are merged back together here.
+pub fn parse_block(nodes: &[Pickle]) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut statements = Vec::new();
+    let mut index = 0;
+
+    while index < nodes.len() {
+        let node = &nodes[index];
+        let next = nodes.get(index + 1);
+        let after = nodes.get(index + 2);
+
+        match (node_name(node), next.map(node_name), after.map(node_name)) {
+            // `menu label:`
+            ("Label", Some("Menu"), _) if is_empty_label(node) && same_line(node, next.unwrap()) => {
+                statements.push(mark_line(node, parse_menu(next.unwrap(), Some(extract_string(node, "name")?), None)?));
+                index += 2;
+            },
+            ("Label", Some("Say"), Some("Menu")) if is_empty_label(node) && same_line(node, after.unwrap()) && is_non_interacting_say(next.unwrap()) => {
+                statements.push(mark_line(node, parse_menu(after.unwrap(), Some(extract_string(node, "name")?), next)?));
+                index += 3;
+            },
+            // Menu caption said by character.
+            ("Say", Some("Menu"), _) if is_non_interacting_say(node) => {
+                statements.push(mark_line(next.unwrap(), parse_menu(next.unwrap(), None, Some(node))?));
+                index += 2;
+            },
+            // `call label from name` is followed by the label, or a pass when no name is given.
+            ("Call", Some("Label"), _) if is_empty_label(next.unwrap()) && same_line(node, next.unwrap()) => {
+                statements.push(mark_line(node, parse_call(node, Some(extract_string(next.unwrap(), "name")?))?));
+                index += 2;
+            },
+            ("Call", Some("Pass"), _) if same_line(node, next.unwrap()) => {
+                statements.push(mark_line(node, parse_call(node, None)?));
+                index += 2;
+            },
+            ("With", Some(_), Some("With")) if is_paired_with(node, after.unwrap()) => {
+                let with = extract_string(after.unwrap(), "expr")?;
+                let statement = parse_node(next.unwrap())?;
+                statements.push(map_first_line(&statement, |line| match line.strip_suffix(':') {
+                    Some(line) => format!("{} with {}:", line, with),
+                    None => format!("{} with {}", line, with),
+                }));
+                index += 3;
+            },
+            ("TranslateString", _, _) => {
+                let language = try_extract_string(node, "language")?;
+                let mut end = index + 1;
+                while end < nodes.len() && node_name(&nodes[end]) == "TranslateString" && try_extract_string(&nodes[end], "language")? == language {
+                    end += 1;
+                }
+                statements.push(mark_line(node, parse_translate_strings(&nodes[index..end])?));
+                index = end;
+            },
+            // A return is added to the end of every script, it does not exist in the source.
+            ("Return", None, _) if index > 0 && node_try_extract_data(node, "expression").is_none() && same_line(node, &nodes[index - 1]) => {
+                index += 1;
+            },
+            _ => {
+                statements.push(parse_node(node)?);
+                index += 1;
+            },
+        }
+    }
+
+    Ok(statements)
+}
+
+
+
+pub fn parse_node_ast(node: &Pickle) -> Result<String, Box<dyn Error>> {
+    Ok(match node_name(node) {
+        "Return" => match try_extract_string(node, "expression")? {
+            Some(expression) => format!("return {}\n", expression),
+            None => "return\n".to_owned(),
+        },
+        "Pass" => "pass\n".to_owned(),
+        "Label" => {
+            let mut header = format!("label {}", extract_string(node, "name")?);
+            if let Some(parameters) = node_try_extract_data(node, "parameters") {
+                header += &parse_parameters(&parameters)?;
+            }
+            if extract_bool(node, "hide") {
+                header += " hide";
+            }
+            block_nodes(header, &extract_list(node, "block")?)?
+        },
+        "Jump" => if extract_bool(node, "expression") {
+            format!("jump expression {}\n", extract_string(node, "target")?)
+        } else {
+            format!("jump {}\n", extract_string(node, "target")?)
+        },
+        "Call" => parse_call(node, None)?,
+        "Menu" => parse_menu(node, None, None)?,
+        "If" => {
+            let mut str = String::new();
+            for (index, entry) in extract_list(node, "entries")?.into_iter().enumerate() {
+                let (condition, nodes): (Pickle, Pickle) = entry.try_into()?;
+                let condition = node_string(&condition)?;
+                let header = match (index, condition.as_str()) {
+                    (0, condition) => format!("if {}", condition),
+                    (_, "True") => "else".to_owned(),
+                    (_, condition) => format!("elif {}", condition),
+                };
+                str += &block_nodes(header, &TryInto::<Vec<Pickle>>::try_into(nodes)?)?;
+            }
+            str
+        },
+        "While" => block_nodes(
+            format!("while {}", extract_string(node, "condition")?),
+            &extract_list(node, "block")?
+        )?,
+        "Say" => parse_say(node, false)?,
         "UserStatement" => {
             // TODO: Use "parsed".
-            format!("{}\n", TryInto::<String>::try_into(node_extract_data(&node, "line")?)?)
+            let line = extract_string(node, "line")?;
+            match extract_list(node, "block")? {
+                nodes if nodes.is_empty() => format!("{}\n", line),
+                nodes => format!("{}\n{}", line, indent(parse_user_statement_block(nodes)?)),
+            }
         },
+        "PostUserStatement" => String::new(),
         "With" => {
-            // FIXME: Often gets put on a newline when should be on the same line.
-            match node_extract_data(&node, "expr")? {
-                Pickle::String(str) => {
-                    if str == "None" {
-                        String::new()
-                    } else {
-                        format!("with {}\n", str)
-                    }
-                },
-                Pickle::Class(class) => format!("with {}\n", parse_node(Pickle::Class(class))?),
-                _ => panic!(),
+            match try_extract_string(node, "expr")? {
+                Some(expr) if expr != "None" => format!("with {}\n", expr),
+                _ => String::new(),
             }
         },
-        "PyExpr" => {
-            let args = TryInto::<(Pickle, Pickle, Pickle, Pickle)>::try_into(*class.args)?;
-            format!("{}", TryInto::<String>::try_into(args.3)?)
+        "Scene" => match node_try_extract_data(node, "imspec") {
+            Some(imspec) => with_atl(format!("scene {}", parse_imspec(imspec)?), node)?,
+            None => match try_extract_string(node, "layer")? {
+                Some(layer) if layer != "master" => with_atl(format!("scene onlayer {}", layer), node)?,
+                _ => with_atl("scene".to_owned(), node)?,
+            },
         },
-        "Scene" => {
-            let imspec = TryInto::<(Pickle, Pickle, Pickle, Pickle, Pickle, Pickle, Pickle)>::try_into(node_extract_data(&node, "imspec")?)?;
-            format!(
-                "scene {}\n",
-                TryInto::<Vec<Pickle>>::try_into(imspec.6)?
-                    .iter()
-                    .map(|item| TryInto::<String>::try_into(item.clone()))
-                    .collect::<Result<Vec<_>, _>>()?
-                    .join(" ")
-            )
+        "Show" => with_atl(format!("show {}", parse_imspec(node_extract_data(node, "imspec")?)?), node)?,
+        "Hide" => format!("hide {}\n", parse_imspec(node_extract_data(node, "imspec")?)?),
+        "ShowLayer" => with_atl(format!("show layer {}{}", extract_string(node, "layer")?, parse_at_list(node)?), node)?,
+        "Camera" => {
+            let mut header = "camera".to_owned();
+            match try_extract_string(node, "layer")? {
+                Some(layer) if layer != "master" => header += &format!(" {}", layer),
+                _ => { },
+            }
+            header += &parse_at_list(node)?;
+            with_atl(header, node)?
         },
-        "Show" => {
-            let imspec = TryInto::<(Pickle, Pickle, Pickle, Pickle, Pickle, Pickle, Pickle)>::try_into(node_extract_data(&node, "imspec")?)?;
-            format!(
-                "show {}\n",
-                TryInto::<Vec<Pickle>>::try_into(imspec.6)?
-                    .iter()
-                    .map(|item| TryInto::<String>::try_into(item.clone()))
-                    .collect::<Result<Vec<_>, _>>()?
-                    .join(" ")
-            )
+        "Image" => {
+            let name = string_list(node_extract_data(node, "imgname")?.try_into()?)?.join(" ");
+            match try_extract_string(node, "code")? {
+                Some(code) => format!("image {} = {}\n", name, code),
+                None => with_atl(format!("image {}", name), node)?,
+            }
         },
-        "Hide" => {
-            let imspec = TryInto::<(Pickle, Pickle, Pickle, Pickle, Pickle, Pickle, Pickle)>::try_into(node_extract_data(&node, "imspec")?)?;
-            format!(
-                "hide {}\n",
-                TryInto::<Vec<Pickle>>::try_into(imspec.6)?
-                    .iter()
-                    .map(|item| TryInto::<String>::try_into(item.clone()))
-                    .collect::<Result<Vec<_>, _>>()?
-                    .join(" ")
-            )
+        "Transform" => {
+            let store = try_extract_string(node, "store")?.unwrap_or("store".to_owned());
+            let mut header = format!("transform {}{}", store_prefix(&store), extract_string(node, "varname")?);
+            if let Some(parameters) = node_try_extract_data(node, "parameters") {
+                header += &parse_parameters(&parameters)?;
+            }
+            with_atl(header, node)?
         },
-        "Init" => format!(
-            "{}\n",
-            TryInto::<Vec<Pickle>>::try_into(node_extract_data(&node, "block")?)?
-                .iter()
-                .map(|node| parse_node(node.clone()))
-                .collect::<Result<Vec<_>, _>>()?
-                .join("\n")
-        ),
+        "Init" => parse_init(node)?,
         "Define" => {
+            let store = try_extract_string(node, "store")?.unwrap_or("store".to_owned());
+            let index = match try_extract_string(node, "index")? {
+                Some(index) => format!("[{}]", index),
+                None => String::new(),
+            };
             format!(
-                "define {}.{} {} {}\n",
-                TryInto::<String>::try_into(node_extract_data(&node, "store")?)?,
-                TryInto::<String>::try_into(node_extract_data(&node, "varname")?)?,
-                TryInto::<String>::try_into(node_extract_data(&node, "operator")?)?,
-                parse_node(node_extract_data(&node, "code")?)?,
+                "define {}{}{} {} {}\n",
+                store_prefix(&store),
+                extract_string(node, "varname")?,
+                index,
+                try_extract_string(node, "operator")?.unwrap_or("=".to_owned()),
+                extract_string(node, "code")?,
             )
         },
         "Default" => {
+            let store = try_extract_string(node, "store")?.unwrap_or("store".to_owned());
             format!(
-                "default {}.{} = {}\n",
-                TryInto::<String>::try_into(node_extract_data(&node, "store")?)?,
-                TryInto::<String>::try_into(node_extract_data(&node, "varname")?)?,
-                parse_node(node_extract_data(&node, "code")?)?,
+                "default {}{} = {}\n",
+                store_prefix(&store),
+                extract_string(node, "varname")?,
+                extract_string(node, "code")?,
             )
         },
-        "Python" => {
-            let code = parse_node(node_extract_data(&node, "code")?)?;
-            
-            if code.split("\n").count() == 1 {
-                format!("$ {}\n", code.replace("\n", ""))
-            } else {
-                format!("init python:\n{}\n", indent(code))
+        "Python" | "EarlyPython" => parse_python(node, "")?,
+        "Style" => parse_style(node)?,
+        "Screen" => parse_node(&node_extract_data(node, "screen")?)?,
+        "Testcase" => block(
+            format!("testcase {}", extract_string(node, "label")?),
+            parse_node(&node_extract_data(node, "test")?)?
+        ),
+        // Dialogue in the source language is also wrapped in translate blocks.
+        "Translate" => match try_extract_string(node, "language")? {
+            Some(language) => block_nodes(
+                format!("translate {} {}", language, extract_string(node, "identifier")?),
+                &extract_list(node, "block")?
+            )?,
+            None => parse_block(&extract_list(node, "block")?)?.join(""),
+        },
+        "TranslateSay" => match try_extract_string(node, "language")? {
+            Some(language) => block(
+                format!("translate {} {}", language, extract_string(node, "identifier")?),
+                parse_say(node, false)?
+            ),
+            None => parse_say(node, false)?,
+        },
+        "EndTranslate" => String::new(),
+        "TranslateString" => parse_translate_strings(std::slice::from_ref(node))?,
+        "TranslatePython" => block(
+            format!("translate {} python", try_extract_string(node, "language")?.unwrap_or("None".to_owned())),
+            format!("{}\n", extract_string(node, "code")?.trim_end())
+        ),
+        // Contains style & python blocks, which are output as `translate language style name:`
+        "TranslateBlock" | "TranslateEarlyBlock" => {
+            let language = try_extract_string(node, "language")?.unwrap_or("None".to_owned());
+            let nodes = extract_list(node, "block")?;
+            // `translate language python:` is an init python block, which is not `translate language init python:`
+            if let [init] = nodes.as_slice() {
+                if node_name(init) == "Init" {
+                    if let [python] = extract_list(init, "block")?.as_slice() {
+                        if node_name(python) == "Python" {
+                            return parse_python(python, &format!("translate {} ", language));
+                        }
+                    }
+                }
             }
+            parse_block(&nodes)?
+                .iter()
+                .map(|statement| map_first_line(statement, |line| format!("translate {} {}", language, line)))
+                .collect::<Vec<_>>()
+                .join("")
         },
-        class => format!("***DECOMPILE ERROR: Unknown class. {}***", class),
+        "RPY" => format!("rpy {}\n", string_list(node_extract_data(node, "rest")?.try_into()?)?.join(" ")),
+        class => format!("***DECOMPILE ERROR: Unknown class. {}***\n", class),
     })
 }
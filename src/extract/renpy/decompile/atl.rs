@@ -0,0 +1,123 @@
+// https://github.com/renpy/renpy/blob/master/renpy/atl.py
+
+use std::error::Error;
+use crate::util::pickle::pickle::Pickle;
+use super::{block, extract_list, node_extract_data, node_name, node_string, node_try_extract_data, parse_node, try_extract_string};
+
+
+
+/// `RawBlock` statements, without the `block:` header.
+pub fn parse_atl_block(node: &Pickle) -> Result<String, Box<dyn Error>> {
+    let mut str = String::new();
+    if matches!(node_try_extract_data(node, "animation"), Some(Pickle::Bool(true))) {
+        str += "animation\n";
+    }
+    for statement in extract_list(node, "statements")? {
+        str += &parse_node(&statement)?;
+    }
+    // Empty blocks are not valid.
+    if str.is_empty() {
+        str += "pass\n";
+    }
+    Ok(str)
+}
+
+/// `RawMultipurpose` is any combination of warper, properties, splines & expressions on a single line.
+fn parse_multipurpose(node: &Pickle) -> Result<String, Box<dyn Error>> {
+    let mut items = Vec::new();
+
+    let duration = try_extract_string(node, "duration")?.unwrap_or("0".to_owned());
+    if let Some(warp_function) = try_extract_string(node, "warp_function")? {
+        items.push(format!("warp {} {}", warp_function, duration));
+    } else if let Some(warper) = try_extract_string(node, "warper")? {
+        items.push(format!("{} {}", warper, duration));
+    } else if duration != "0" {
+        items.push(format!("pause {}", duration));
+    }
+
+    for property in extract_list(node, "properties")? {
+        let (name, value): (Pickle, Pickle) = property.try_into()?;
+        items.push(format!("{} {}", node_string(&name)?, node_string(&value)?));
+    }
+
+    if let Some(revolution) = try_extract_string(node, "revolution")? {
+        items.push(revolution);
+    }
+    if let Some(circles) = try_extract_string(node, "circles")? {
+        if circles != "0" {
+            items.push(format!("circles {}", circles));
+        }
+    }
+
+    // Spline knots are stored with the end value last.
+    for spline in extract_list(node, "splines")? {
+        let (name, knots): (Pickle, Pickle) = spline.try_into()?;
+        let mut knots = TryInto::<Vec<Pickle>>::try_into(knots)?
+            .iter()
+            .map(node_string)
+            .collect::<Result<Vec<_>, _>>()?;
+        let end = knots.pop().unwrap_or_default();
+        let mut spline = format!("{} {}", node_string(&name)?, end);
+        for knot in knots {
+            spline += &format!(" knot {}", knot);
+        }
+        items.push(spline);
+    }
+
+    for expression in extract_list(node, "expressions")? {
+        let (expression, with): (Pickle, Pickle) = expression.try_into()?;
+        match with {
+            Pickle::None => items.push(node_string(&expression)?),
+            with => items.push(format!("{} with {}", node_string(&expression)?, node_string(&with)?)),
+        }
+    }
+
+    Ok(if items.is_empty() { "pass\n".to_owned() } else { format!("{}\n", items.join(" ")) })
+}
+
+
+
+pub fn parse_node_atl(node: &Pickle) -> Result<String, Box<dyn Error>> {
+    Ok(match node_name(node) {
+        "RawBlock" => block("block".to_owned(), parse_atl_block(node)?),
+        "RawMultipurpose" => parse_multipurpose(node)?,
+        "RawContainsExpr" => format!("contains {}\n", node_string(&node_extract_data(node, "expression")?)?),
+        "RawChild" => extract_list(node, "children")?
+            .iter()
+            .map(|child| Ok(block("contains".to_owned(), parse_atl_block(child)?)))
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?
+            .join(""),
+        "RawParallel" => extract_list(node, "blocks")?
+            .iter()
+            .map(|child| Ok(block("parallel".to_owned(), parse_atl_block(child)?)))
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?
+            .join(""),
+        "RawChoice" => {
+            let mut str = String::new();
+            for choice in extract_list(node, "choices")? {
+                let (chance, child): (Pickle, Pickle) = choice.try_into()?;
+                let chance = node_string(&chance)?;
+                let header = if chance == "1.0" { "choice".to_owned() } else { format!("choice {}", chance) };
+                str += &block(header, parse_atl_block(&child)?);
+            }
+            str
+        },
+        "RawOn" => {
+            let mut str = String::new();
+            if let Some(Pickle::Dict(handlers)) = node_try_extract_data(node, "handlers") {
+                for (name, child) in handlers {
+                    str += &block(format!("on {}", name.key_string()), parse_atl_block(&child)?);
+                }
+            }
+            str
+        },
+        "RawRepeat" => match try_extract_string(node, "repeats")? {
+            Some(repeats) => format!("repeat {}\n", repeats),
+            None => "repeat\n".to_owned(),
+        },
+        "RawTime" => format!("time {}\n", node_string(&node_extract_data(node, "time")?)?),
+        "RawFunction" => format!("function {}\n", node_string(&node_extract_data(node, "expr")?)?),
+        "RawEvent" => format!("event {}\n", node_string(&node_extract_data(node, "name")?)?),
+        class => format!("***DECOMPILE ERROR: Unknown class. {}***\n", class),
+    })
+}
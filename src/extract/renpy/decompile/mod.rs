@@ -4,86 +4,326 @@
     We are just taking an abstract syntax tree and putting it back into code.
 */
 
-use std::{collections::HashMap, error::Error};
-use crate::util::pickle::pickle::{Pickle, PickleClass};
-use self::ast::parse_node_ast;
-use super::script::RenPyCompiledScriptChunk;
+use std::{collections::HashSet, error::Error, fmt};
+use crate::util::pickle::pickle::Pickle;
+use self::{ast::parse_node_ast, atl::parse_node_atl, sl2::parse_node_sl2, testcase::parse_node_testcase};
+pub use self::ast::parse_block;
+use super::script::{RenPyCompiledScriptChunk, RenPyScriptFormat, RenPyScriptVersion};
 
 mod ast;
+mod atl;
+mod sl2;
+mod testcase;
 
 
 
+#[derive(Debug)]
+pub enum DecompileError {
+    MissingNodeData(String),
+    InvalidNodeData(String),
+}
+
+impl fmt::Display for DecompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingNodeData(key) => write!(f, "Ren'Py node is missing data \"{}\".", key),
+            Self::InvalidNodeData(key) => write!(f, "Ren'Py node data \"{}\" is not of expected type.", key),
+        }
+    }
+}
+
+impl Error for DecompileError { }
+
+
+
+/// Get the module & class name of node.
+pub fn node_class(node: &Pickle) -> Option<(&str, &str)> {
+    match node.resolve() {
+        Pickle::Class(class) => Some((&class.module.module, &class.module.name)),
+        _ => None,
+    }
+}
+
+pub fn node_name(node: &Pickle) -> &str {
+    node_class(node).map(|(_, name)| name).unwrap_or("")
+}
+
+/// Nodes either store their data as a dict, or as a `(dict, slots)` tuple.
+///
+/// Nodes are shared with the `next` field of the node before them, so they are often references.
+fn node_data<'a>(node: &'a Pickle, key: &str) -> Option<&'a Pickle> {
+    let Pickle::Class(class) = node.resolve() else { return None };
+    if let Some(item) = class.data.get(key) {
+        return Some(item.resolve());
+    }
+    let item = match class.state.as_deref().map(Pickle::resolve) {
+        Some(Pickle::Dict(dict)) => dict.get(key),
+        Some(Pickle::Tuple(tuple)) => tuple.iter().find_map(|item| match item {
+            Pickle::Dict(dict) => dict.get(key),
+            _ => None,
+        }),
+        _ => None,
+    };
+    item.map(Pickle::resolve)
+}
+
 pub fn node_extract_data(node: &Pickle, key: &str) -> Result<Pickle, Box<dyn Error>> {
-    let class: PickleClass = node.clone().try_into()?;
-    let dict: HashMap<String, Pickle> = TryInto::<(Pickle, Pickle)>::try_into(*class.state.unwrap())?.1.try_into()?;
-    if let Some(item) = dict.get(key) {
-        Ok(item.clone())
-    } else {
-        panic!("Unable to extract node pickle data.")
+    match node_data(node, key) {
+        Some(item) => Ok(item.clone()),
+        None => Err(Box::new(DecompileError::MissingNodeData(key.to_owned()))),
+    }
+}
+
+/// Same as [`node_extract_data`], but missing data & `None` are both treated as not existing.
+///
+/// Older Ren'Py versions do not store some of the newer fields.
+pub fn node_try_extract_data(node: &Pickle, key: &str) -> Option<Pickle> {
+    match node_data(node, key) {
+        Some(Pickle::None) | None => None,
+        Some(item) => Some(item.clone()),
+    }
+}
+
+/// Script nodes store `linenumber`, ATL & screen language nodes store a `(filename, linenumber)` location.
+pub fn node_linenumber(node: &Pickle) -> Option<i64> {
+    if let Some(linenumber) = node_try_extract_data(node, "linenumber") {
+        return linenumber.try_into().ok();
+    }
+    match node_try_extract_data(node, "loc").or_else(|| node_try_extract_data(node, "location")) {
+        Some(Pickle::Tuple(location)) if location.len() >= 2 => location[1].clone().try_into().ok(),
+        _ => None,
+    }
+}
+
+pub fn node_filename(node: &Pickle) -> Option<String> {
+    node_try_extract_data(node, "filename").and_then(|filename| filename.try_into().ok())
+}
+
+/// Get the source string of a `str`, `bytes`, `PyExpr`, or `PyCode`.
+pub fn node_string(pickle: &Pickle) -> Result<String, Box<dyn Error>> {
+    match pickle.resolve() {
+        Pickle::String(str) => Ok(str.clone()),
+        // Python 2 str, when not decoded by the parser.
+        Pickle::Binary(bytes) => Ok(String::from_utf8_lossy(bytes).into_owned()),
+        Pickle::Class(class) => match (class.module.name.as_str(), class.args.as_ref(), class.state.as_deref()) {
+            // PyExpr is a subclass of str, the string is the first argument.
+            ("PyExpr", Pickle::Tuple(args), _) if !args.is_empty() => node_string(&args[0]),
+            // PyCode state is (version, source, location, mode, py)
+            ("PyCode", _, Some(Pickle::Tuple(state))) if state.len() >= 2 => node_string(&state[1]),
+            _ => Err(Box::new(DecompileError::InvalidNodeData(class.module.name.clone()))),
+        },
+        _ => Err(Box::new(DecompileError::InvalidNodeData(format!("{:?}", pickle)))),
+    }
+}
+
+pub fn extract_string(node: &Pickle, key: &str) -> Result<String, Box<dyn Error>> {
+    node_string(&node_extract_data(node, key)?)
+}
+
+pub fn try_extract_string(node: &Pickle, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+    node_try_extract_data(node, key).map(|data| node_string(&data)).transpose()
+}
+
+pub fn extract_list(node: &Pickle, key: &str) -> Result<Vec<Pickle>, Box<dyn Error>> {
+    match node_try_extract_data(node, key) {
+        Some(data) => Ok(data.try_into()?),
+        None => Ok(Vec::new()),
     }
 }
 
+pub fn extract_bool(node: &Pickle, key: &str) -> bool {
+    matches!(node_try_extract_data(node, key), Some(Pickle::Bool(true)))
+}
+
+pub fn string_list(list: Vec<Pickle>) -> Result<Vec<String>, Box<dyn Error>> {
+    list.iter().map(node_string).collect()
+}
 
 
-pub fn parse_node(node: Pickle) -> Result<String, Box<dyn Error>> {
-    let class: PickleClass = node.clone().try_into()?;
 
-    Ok(match class.module.module.as_str() {
-        "renpy.ast" => parse_node_ast(node)?,
-        module => format!("***DECOMPILE ERROR: Unknown module. {}***", module),
-    })
+pub fn parse_node(node: &Pickle) -> Result<String, Box<dyn Error>> {
+    let str = match node_class(node) {
+        Some(("renpy.ast", _)) => parse_node_ast(node)?,
+        Some(("renpy.atl", _)) => parse_node_atl(node)?,
+        Some(("renpy.sl2.slast", _)) => parse_node_sl2(node)?,
+        Some(("renpy.test.testast", _)) => parse_node_testcase(node)?,
+        Some((module, _)) => format!("***DECOMPILE ERROR: Unknown module. {}***\n", module),
+        None => format!("***DECOMPILE ERROR: Not a node. {:?}***\n", node),
+    };
+    Ok(mark_line(node, str))
 }
 
 pub fn indent(str: String) -> String {
-    str.split("\n").map(|str| format!("\t{}", str)).collect::<Vec<_>>().join("\n")
+    str.split("\n")
+        .map(|str| if str.is_empty() { String::new() } else { format!("    {}", str) })
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
 
 
+/*
+    Statements are marked with the line number they were originally on,
+    the marker is put at the start of the line after indentation.
+    When all statements are decompiled, the markers are replaced with blank lines to put statements back on their original line.
+*/
 
+const LINE_MARKER: char = '\u{1}';
 
-impl RenPyCompiledScriptChunk {
+fn split_line_marker(line: &str) -> (Option<i64>, &str) {
+    if let Some(rest) = line.strip_prefix(LINE_MARKER) {
+        if let Some((linenumber, rest)) = rest.split_once(LINE_MARKER) {
+            return (linenumber.parse().ok(), rest);
+        }
+    }
+    (None, line)
+}
 
-    pub fn decompile(&mut self) -> Result<String, Box<dyn Error>> {
-        Self::decompile_pickle(self.pickle()?)
+/// Mark the first line of decompiled statement with the line number of node.
+pub fn mark_line(node: &Pickle, str: String) -> String {
+    match node_linenumber(node) {
+        Some(linenumber) if !str.is_empty() && !str.starts_with(LINE_MARKER) => format!("{}{}{}{}", LINE_MARKER, linenumber, LINE_MARKER, str),
+        _ => str,
     }
+}
 
-    pub fn decompile_pickle(pickle: Pickle) -> Result<String, Box<dyn Error>> {
-        let (header, ast): (_, Pickle) = pickle.try_into()?;
-        let header: HashMap<_, _> = header.try_into()?;
+/// Modify the first line of statement, keeping the line marker.
+pub fn map_first_line<F: FnOnce(&str) -> String>(str: &str, map: F) -> String {
+    let (first, rest) = str.split_once('\n').unwrap_or((str, ""));
+    let (linenumber, first) = split_line_marker(first);
+    let first = map(first);
+    let first = match linenumber {
+        Some(linenumber) => format!("{}{}{}{}", LINE_MARKER, linenumber, LINE_MARKER, first),
+        None => first,
+    };
+    if str.contains('\n') { format!("{}\n{}", first, rest) } else { first }
+}
 
-        let version = if let Some(version) = header.get("version") {
-            let version: i64 = version.clone().try_into()?;
-            if version != 5003000 {
-                panic!("Unknown script version.");
-            }
-            version
-        } else {
-            panic!("No script version.");
-        };
-        
-        let key = if let Some(key) = header.get("key") {
-            let key: String = key.clone().try_into()?;
-            if key != "unlocked" {
-                panic!("Unknown script key.");
+pub fn strip_line_markers(str: &str) -> String {
+    str.split('\n')
+        .map(|line| {
+            let content = line.trim_start();
+            format!("{}{}", &line[..line.len() - content.len()], split_line_marker(content).1)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Replace line markers with blank lines, so that statements are on their original line where possible.
+///
+/// `linenumber` is the line number of the first line in str.
+pub fn layout_lines(str: &str, mut linenumber: i64) -> String {
+    let mut output = String::new();
+    for line in str.split_inclusive('\n') {
+        let content = line.trim_start();
+        let (original, content) = split_line_marker(content);
+        if let Some(original) = original {
+            while linenumber < original {
+                output += "\n";
+                linenumber += 1;
             }
-            key
-        } else {
-            panic!("No script key.");
+        }
+        output += &line[..line.len() - line.trim_start().len()];
+        output += content;
+        linenumber += 1;
+    }
+    output
+}
+
+/// Quote string as a Ren'Py/Python string literal.
+pub fn string_literal(str: &str) -> String {
+    let mut literal = String::from("\"");
+    for char in str.chars() {
+        match char {
+            '\\' => literal += "\\\\",
+            '"' => literal += "\\\"",
+            '\n' => literal += "\\n",
+            // Tabs & other control characters would be changed or lost in the source.
+            char if char.is_control() => literal += &format!("\\u{:04x}", char as u32),
+            char => literal.push(char),
+        }
+    }
+    literal + "\""
+}
+
+pub fn block(header: String, body: String) -> String {
+    if body.is_empty() {
+        format!("{}:\n", header)
+    } else {
+        format!("{}:\n{}", header, indent(body))
+    }
+}
+
+
+
+
+
+/// Find the python version from the first `PyExpr`, older versions do not store it.
+///
+/// Shared nodes are only searched the first time they are found.
+fn detect_python(pickle: &Pickle, objects: &mut HashSet<usize>) -> Option<u8> {
+    match pickle {
+        Pickle::Ref(reference) if objects.insert(reference.id()) => detect_python(reference.get(), objects),
+        Pickle::Class(class) if class.module.name == "PyExpr" => match class.args.as_ref() {
+            Pickle::Tuple(args) if args.len() >= 4 => args[3].clone().try_into().ok(),
+            Pickle::Tuple(args) if args.len() == 3 => Some(2),
+            _ => None,
+        },
+        Pickle::Class(class) => detect_python(&class.args, objects)
+            .or_else(|| class.state.as_deref().and_then(|state| detect_python(state, objects)))
+            .or_else(|| class.data.values().find_map(|item| detect_python(item, objects))),
+        Pickle::List(items) | Pickle::Tuple(items) => items.iter().find_map(|item| detect_python(item, objects)),
+        Pickle::Dict(dict) => dict.values().find_map(|item| detect_python(item, objects)),
+        _ => None,
+    }
+}
+
+impl RenPyScriptVersion {
+
+    /// Version from the `(header, ast)` pickle of a script.
+    ///
+    /// Very old scripts do not have a header dict, so everything is optional.
+    pub fn from_pickle(format: RenPyScriptFormat, header: &Pickle, ast: &Pickle) -> Self {
+        let (script_version, key) = match header.resolve() {
+            Pickle::Dict(header) => (
+                header.get("version").and_then(|version| version.clone().try_into().ok()),
+                header.get("key").and_then(|key| key.clone().try_into().ok()),
+            ),
+            Pickle::Number(_) => (header.clone().try_into().ok(), None),
+            _ => (None, None),
         };
+        Self { format, script_version, key, python: detect_python(ast, &mut HashSet::new()) }
+    }
 
-        let mut decompiled = String::new();
-        decompiled += "# Decompiled Ren'Py script.\n";
-        decompiled += "# Decompilation may not be accurate to source code.\n";
+}
+
+
+
+impl RenPyCompiledScriptChunk {
 
+    pub fn version(&mut self) -> Result<RenPyScriptVersion, Box<dyn Error>> {
+        let (header, ast): (Pickle, Pickle) = self.pickle()?.try_into()?;
+        Ok(RenPyScriptVersion::from_pickle(self.format, &header, &ast))
+    }
 
+    pub fn decompile(&mut self) -> Result<String, Box<dyn Error>> {
+        Self::decompile_pickle(self.format, self.pickle()?)
+    }
 
+    pub fn decompile_pickle(format: RenPyScriptFormat, pickle: Pickle) -> Result<String, Box<dyn Error>> {
+        let (header, ast): (Pickle, Pickle) = pickle.try_into()?;
+        let version = RenPyScriptVersion::from_pickle(format, &header, &ast);
         let ast: Vec<Pickle> = ast.try_into()?;
 
-        for node in ast {
-            decompiled += &parse_node(node)?;
-            decompiled += "\n";
+        // Notice is put at the end of the file, so that line numbers match with the original file.
+        let mut decompiled = layout_lines(&parse_block(&ast)?.join(""), 1);
+        decompiled += "\n";
+        decompiled += "# Decompiled Ren'Py script.\n";
+        if let Some(filename) = ast.first().and_then(node_filename) {
+            decompiled += &format!("# Original file \"{}\".\n", filename);
         }
+        decompiled += &format!("# Compiled with {}.\n", version);
+        decompiled += "# Decompilation may not be accurate to source code.\n";
 
 
 
@@ -91,3 +331,265 @@ impl RenPyCompiledScriptChunk {
     }
 
 }
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use crate::util::pickle::pickle::{Pickle, PickleClass, PickleModule};
+    use super::{layout_lines, parse_block, string_literal};
+
+    /// Node with its data stored as state, like nodes using `__slots__`.
+    fn node(module: &str, name: &str, data: Vec<(&str, Pickle)>) -> Pickle {
+        let mut class = PickleClass::new(PickleModule::new(module.to_owned(), name.to_owned()), Pickle::Tuple(Vec::new()));
+        class.state = Some(Box::new(Pickle::Dict(data.into_iter().collect())));
+        Pickle::Class(class)
+    }
+
+    fn ast(name: &str, linenumber: i64, mut data: Vec<(&str, Pickle)>) -> Pickle {
+        data.push(("linenumber", Pickle::from(linenumber)));
+        node("renpy.ast", name, data)
+    }
+
+    fn location(linenumber: i64) -> Pickle {
+        Pickle::Tuple(vec![Pickle::from("game/script.rpy"), Pickle::from(linenumber)])
+    }
+
+    fn strings(strings: &[&str]) -> Pickle {
+        Pickle::List(strings.iter().map(|str| Pickle::from(*str)).collect())
+    }
+
+    fn say(linenumber: i64, who: Option<&str>, what: &str) -> Pickle {
+        ast("Say", linenumber, vec![
+            ("who", who.map(Pickle::from).unwrap_or(Pickle::None)),
+            ("what", Pickle::from(what)),
+        ])
+    }
+
+    fn decompile(nodes: Vec<Pickle>) -> Result<String, Box<dyn Error>> {
+        Ok(layout_lines(&parse_block(&nodes)?.join(""), 1))
+    }
+
+    #[test]
+    fn quote_string_literal() {
+        assert_eq!(string_literal("a \"b\" \\ c\nd"), "\"a \\\"b\\\" \\\\ c\\nd\"");
+        assert_eq!(string_literal("tab\there\r\u{7}é"), "\"tab\\u0009here\\u000d\\u0007é\"");
+    }
+
+    #[test]
+    fn decompile_say() -> Result<(), Box<dyn Error>> {
+        let nodes = vec![
+            ast("Label", 1, vec![
+                ("name", Pickle::from("start")),
+                ("block", Pickle::List(vec![say(2, Some("e"), "Hello \"you\"")])),
+            ]),
+            say(5, None, "World"),
+            // Implicit return at the end of the script.
+            ast("Return", 5, vec![]),
+        ];
+        assert_eq!(decompile(nodes)?, "label start:\n    e \"Hello \\\"you\\\"\"\n\n\n\"World\"\n");
+        Ok(())
+    }
+
+    #[test]
+    fn decompile_menu() -> Result<(), Box<dyn Error>> {
+        let mut caption = say(2, Some("e"), "Pick one.");
+        if let Pickle::Class(class) = &mut caption {
+            if let Some(Pickle::Dict(state)) = class.state.as_deref_mut() {
+                state.insert("interact", Pickle::Bool(false));
+            }
+        }
+        let nodes = vec![
+            ast("Label", 1, vec![("name", Pickle::from("choice")), ("block", Pickle::List(Vec::new()))]),
+            caption,
+            ast("Menu", 1, vec![("items", Pickle::List(vec![
+                Pickle::Tuple(vec![
+                    Pickle::from("Yes"),
+                    Pickle::from("True"),
+                    Pickle::List(vec![ast("Jump", 4, vec![("target", Pickle::from("yes"))])]),
+                ]),
+                Pickle::Tuple(vec![Pickle::from("No"), Pickle::from("flag"), Pickle::None]),
+            ]))]),
+        ];
+        assert_eq!(decompile(nodes)?, "menu choice:\n    e \"Pick one.\"\n    \"Yes\":\n        jump yes\n    \"No\" if flag\n");
+        Ok(())
+    }
+
+    #[test]
+    fn decompile_if_while() -> Result<(), Box<dyn Error>> {
+        let entry = |condition: &str, linenumber: i64| Pickle::Tuple(vec![
+            Pickle::from(condition),
+            Pickle::List(vec![ast("Pass", linenumber, vec![])]),
+        ]);
+        let nodes = vec![
+            ast("If", 1, vec![("entries", Pickle::List(vec![entry("a", 2), entry("b", 4), entry("True", 6)]))]),
+            ast("While", 7, vec![("condition", Pickle::from("x")), ("block", Pickle::List(vec![say(8, None, "Loop")]))]),
+        ];
+        assert_eq!(decompile(nodes)?, "if a:\n    pass\nelif b:\n    pass\nelse:\n    pass\nwhile x:\n    \"Loop\"\n");
+        Ok(())
+    }
+
+    #[test]
+    fn decompile_call_from() -> Result<(), Box<dyn Error>> {
+        let nodes = vec![
+            ast("Call", 1, vec![("label", Pickle::from("chapter"))]),
+            ast("Label", 1, vec![("name", Pickle::from("_call_chapter_1")), ("block", Pickle::List(Vec::new()))]),
+            ast("Call", 2, vec![("label", Pickle::from("other"))]),
+            ast("Pass", 2, vec![]),
+        ];
+        assert_eq!(decompile(nodes)?, "call chapter from _call_chapter_1\ncall other\n");
+        Ok(())
+    }
+
+    #[test]
+    fn decompile_with_pairing() -> Result<(), Box<dyn Error>> {
+        let imspec = Pickle::Tuple(vec![strings(&["bg", "room"]), Pickle::List(Vec::new()), Pickle::None]);
+        let nodes = vec![
+            ast("With", 1, vec![("expr", Pickle::from("None")), ("paired", Pickle::from("dissolve"))]),
+            ast("Show", 1, vec![("imspec", imspec)]),
+            ast("With", 1, vec![("expr", Pickle::from("dissolve"))]),
+            ast("With", 2, vec![("expr", Pickle::from("fade"))]),
+        ];
+        assert_eq!(decompile(nodes)?, "show bg room with dissolve\nwith fade\n");
+        Ok(())
+    }
+
+    #[test]
+    fn decompile_atl() -> Result<(), Box<dyn Error>> {
+        let atl = |name: &str, linenumber: i64, mut data: Vec<(&str, Pickle)>| {
+            data.push(("loc", location(linenumber)));
+            node("renpy.atl", name, data)
+        };
+        let transform = atl("RawBlock", 1, vec![("statements", Pickle::List(vec![
+            atl("RawMultipurpose", 2, vec![
+                ("warper", Pickle::from("linear")),
+                ("duration", Pickle::from("1.0")),
+                ("properties", Pickle::List(vec![Pickle::Tuple(vec![Pickle::from("xalign"), Pickle::from("1.0")])])),
+            ]),
+            atl("RawParallel", 3, vec![("blocks", Pickle::List(vec![
+                atl("RawBlock", 4, vec![("statements", Pickle::List(vec![
+                    atl("RawMultipurpose", 4, vec![("expressions", Pickle::List(vec![Pickle::Tuple(vec![Pickle::from("a"), Pickle::from("dissolve")])]))]),
+                ]))]),
+            ]))]),
+            atl("RawChoice", 5, vec![("choices", Pickle::List(vec![
+                Pickle::Tuple(vec![Pickle::from("0.5"), atl("RawBlock", 6, vec![])]),
+            ]))]),
+            atl("RawRepeat", 7, vec![]),
+        ]))]);
+        let nodes = vec![
+            ast("Transform", 1, vec![("varname", Pickle::from("slide")), ("atl", transform)]),
+        ];
+        assert_eq!(
+            decompile(nodes)?,
+            "transform slide:\n    linear 1.0 xalign 1.0\n    parallel:\n        a with dissolve\n    choice 0.5:\n        pass\n    repeat\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn decompile_screen() -> Result<(), Box<dyn Error>> {
+        let sl2 = |name: &str, linenumber: Option<i64>, mut data: Vec<(&str, Pickle)>| {
+            if let Some(linenumber) = linenumber {
+                data.push(("location", location(linenumber)));
+            }
+            node("renpy.sl2.slast", name, data)
+        };
+        let keyword = |name: &str, value: &str| Pickle::Tuple(vec![Pickle::from(name), Pickle::from(value)]);
+        let screen = sl2("SLScreen", Some(1), vec![
+            ("name", Pickle::from("say")),
+            ("modal", Pickle::from("True")),
+            ("zorder", Pickle::from("0")),
+            ("children", Pickle::List(vec![
+                sl2("SLDisplayable", Some(3), vec![
+                    ("displayable", Pickle::Module(PickleModule::new("renpy.text.text".to_owned(), "Text".to_owned()))),
+                    ("positional", strings(&["what"])),
+                    ("keyword", Pickle::List(vec![keyword("id", "'what'")])),
+                ]),
+                sl2("SLIf", Some(4), vec![("entries", Pickle::List(vec![
+                    Pickle::Tuple(vec![Pickle::from("x"), sl2("SLBlock", None, vec![("children", Pickle::List(vec![
+                        sl2("SLDisplayable", Some(5), vec![
+                            ("displayable", Pickle::Module(PickleModule::new("renpy.display.layout".to_owned(), "Null".to_owned()))),
+                            ("keyword", Pickle::List(vec![keyword("width", "10")])),
+                        ]),
+                    ]))])]),
+                    Pickle::Tuple(vec![Pickle::None, sl2("SLBlock", None, vec![])]),
+                ]))]),
+            ])),
+        ]);
+        let nodes = vec![
+            ast("Init", 1, vec![
+                ("priority", Pickle::from(-500)),
+                ("block", Pickle::List(vec![ast("Screen", 1, vec![("screen", screen)])])),
+            ]),
+        ];
+        assert_eq!(
+            decompile(nodes)?,
+            "screen say:\n    modal True\n    text what id 'what'\n    if x:\n        null width 10\n    else:\n        pass\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn decompile_translate() -> Result<(), Box<dyn Error>> {
+        let translate_string = |linenumber: i64, old: &str, new: &str| ast("TranslateString", linenumber, vec![
+            ("language", Pickle::from("french")),
+            ("old", Pickle::from(old)),
+            ("new", Pickle::from(new)),
+        ]);
+        let nodes = vec![
+            // Dialogue in the source language.
+            ast("Translate", 2, vec![
+                ("identifier", Pickle::from("start_a170b500")),
+                ("block", Pickle::List(vec![say(2, None, "Hi")])),
+            ]),
+            ast("EndTranslate", 2, vec![]),
+            ast("Translate", 4, vec![
+                ("language", Pickle::from("french")),
+                ("identifier", Pickle::from("start_a170b500")),
+                ("block", Pickle::List(vec![say(5, None, "Salut")])),
+            ]),
+            ast("Init", 7, vec![
+                ("priority", Pickle::from(0)),
+                ("block", Pickle::List(vec![translate_string(8, "Yes", "Oui"), translate_string(11, "No", "Non")])),
+            ]),
+            // `translate french python:` is an early block of init python.
+            ast("TranslateEarlyBlock", 14, vec![
+                ("language", Pickle::from("french")),
+                ("block", Pickle::List(vec![ast("Init", 14, vec![
+                    ("priority", Pickle::from(0)),
+                    ("block", Pickle::List(vec![ast("Python", 14, vec![("code", Pickle::from("gui.text_font = \"DejaVuSans.ttf\""))])])),
+                ])])),
+            ]),
+        ];
+        assert_eq!(
+            decompile(nodes)?,
+            "\n\"Hi\"\n\ntranslate french start_a170b500:\n    \"Salut\"\n\n\ntranslate french strings:\n    old \"Yes\"\n    new \"Oui\"\n\n    old \"No\"\n    new \"Non\"\ntranslate french python:\n    gui.text_font = \"DejaVuSans.ttf\"\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn decompile_testcase() -> Result<(), Box<dyn Error>> {
+        let test = |name: &str, linenumber: i64, mut data: Vec<(&str, Pickle)>| {
+            data.push(("linenumber", Pickle::from(linenumber)));
+            node("renpy.test.testast", name, data)
+        };
+        let body = test("Block", 1, vec![("block", Pickle::List(vec![
+            test("Click", 2, vec![("pattern", Pickle::from("Start"))]),
+            test("Until", 3, vec![
+                ("left", test("Click", 3, vec![])),
+                ("right", test("Label", 3, vec![("name", Pickle::from("start"))])),
+            ]),
+            test("Type", 4, vec![("keys", strings(&["h", "i"]))]),
+        ]))]);
+        let nodes = vec![
+            ast("Init", 1, vec![
+                ("priority", Pickle::from(0)),
+                ("block", Pickle::List(vec![ast("Testcase", 1, vec![("label", Pickle::from("smoke")), ("test", body)])])),
+            ]),
+        ];
+        assert_eq!(decompile(nodes)?, "testcase smoke:\n    \"Start\"\n    click until label start\n    type \"hi\"\n");
+        Ok(())
+    }
+}
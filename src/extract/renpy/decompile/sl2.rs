@@ -0,0 +1,241 @@
+// https://github.com/renpy/renpy/blob/master/renpy/sl2/slast.py
+
+use std::error::Error;
+use crate::util::pickle::pickle::Pickle;
+use super::{ast::{parse_arguments, parse_parameters}, atl::parse_atl_block, block, extract_list, extract_string, node_extract_data, node_name, node_string, node_try_extract_data, parse_node, string_list, try_extract_string};
+
+
+
+/// Screen language statement name from the displayable function & style it was compiled to.
+fn displayable_statement(displayable: &Pickle, style: Option<&str>) -> String {
+    let name = match displayable {
+        Pickle::Module(module) => module.name.as_str(),
+        _ => "",
+    };
+    match (name, style) {
+        ("MultiBox" | "Window" | "Bar", Some(style)) => style,
+        ("MultiBox", None) => "fixed",
+        ("Window", None) => "window",
+        ("Bar" | "sl2bar", _) => "bar",
+        ("sl2vbar", _) => "vbar",
+        ("Null", _) => "null",
+        ("Text", _) => "text",
+        ("Grid", _) => "grid",
+        ("Side", _) => "side",
+        ("Button", _) => "button",
+        ("_imagebutton", _) => "imagebutton",
+        ("_textbutton", _) => "textbutton",
+        ("_label", _) => "label",
+        ("Input", _) => "input",
+        ("_key", _) => "key",
+        ("_timer" | "Timer", _) => "timer",
+        ("_viewport" | "sl2viewport" | "Viewport", _) => "viewport",
+        ("sl2vpgrid" | "VPGrid", _) => "vpgrid",
+        ("_imagemap", _) => "imagemap",
+        ("_hotspot", _) => "hotspot",
+        ("_hotbar", _) => "hotbar",
+        ("Transform", _) => "transform",
+        ("_add" | "sl2add", _) => "add",
+        ("Drag", _) => "drag",
+        ("DragGroup", _) => "draggroup",
+        ("_mousearea" | "MouseArea", _) => "mousearea",
+        ("OnEvent", _) => "on",
+        ("AreaPicker", _) => "areapicker",
+        ("NearRect", _) => "nearrect",
+        ("image" | "_image", _) => "image",
+        (name, _) => name.trim_start_matches('_'),
+    }.to_lowercase()
+}
+
+/// Keywords as `name value`
+fn parse_keywords(node: &Pickle) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut keywords = Vec::new();
+    for keyword in extract_list(node, "keyword")? {
+        let (name, value): (Pickle, Pickle) = keyword.try_into()?;
+        match value {
+            Pickle::None => keywords.push(node_string(&name)?),
+            value => keywords.push(format!("{} {}", node_string(&name)?, node_string(&value)?)),
+        }
+    }
+    // `at transform:`
+    if let Some(atl) = node_try_extract_data(node, "atl_transform") {
+        keywords.push(block("at transform".to_owned(), parse_atl_block(&atl)?).trim_end().to_owned());
+    }
+    Ok(keywords)
+}
+
+fn parse_children(node: &Pickle) -> Result<String, Box<dyn Error>> {
+    Ok(extract_list(node, "children")?
+        .iter()
+        .map(parse_node)
+        .collect::<Result<Vec<_>, _>>()?
+        .join(""))
+}
+
+/// `SLBlock` contents, keywords are put each on their own line before children.
+fn parse_sl_block(node: &Pickle) -> Result<String, Box<dyn Error>> {
+    let mut str = String::new();
+    for keyword in parse_keywords(node)? {
+        str += &format!("{}\n", keyword);
+    }
+    str += &parse_children(node)?;
+    Ok(str)
+}
+
+/// Statement with a block, if the block is empty `pass` is used.
+fn sl_block(header: String, body: String) -> String {
+    if body.is_empty() {
+        block(header, "pass\n".to_owned())
+    } else {
+        block(header, body)
+    }
+}
+
+fn parse_displayable(node: &Pickle) -> Result<String, Box<dyn Error>> {
+    let style = try_extract_string(node, "style")?;
+    let mut header = displayable_statement(&node_extract_data(node, "displayable")?, style.as_deref());
+
+    for positional in string_list(extract_list(node, "positional")?)? {
+        header += &format!(" {}", positional);
+    }
+    if let Some(variable) = try_extract_string(node, "variable")? {
+        header += &format!(" as {}", variable);
+    }
+
+    let keywords = parse_keywords(node)?;
+    let children = parse_children(node)?;
+
+    // Short statements are kept on a single line.
+    if children.is_empty() && keywords.iter().all(|keyword| !keyword.contains('\n')) {
+        for keyword in keywords {
+            header += &format!(" {}", keyword);
+        }
+        return Ok(format!("{}\n", header));
+    }
+
+    let mut body = String::new();
+    for keyword in keywords {
+        body += &format!("{}\n", keyword);
+    }
+    body += &children;
+    Ok(block(header, body))
+}
+
+fn parse_if(node: &Pickle, statement: &str) -> Result<String, Box<dyn Error>> {
+    let mut str = String::new();
+    for (index, entry) in extract_list(node, "entries")?.into_iter().enumerate() {
+        let (condition, child): (Pickle, Pickle) = entry.try_into()?;
+        let header = match (index, condition) {
+            (_, Pickle::None) => "else".to_owned(),
+            (0, condition) => format!("{} {}", statement, node_string(&condition)?),
+            (_, condition) => format!("elif {}", node_string(&condition)?),
+        };
+        str += &sl_block(header, parse_sl_block(&child)?);
+    }
+    Ok(str)
+}
+
+fn parse_for(node: &Pickle) -> Result<String, Box<dyn Error>> {
+    let mut variable = extract_string(node, "variable")?;
+    let mut children = extract_list(node, "children")?;
+
+    // Tuple unpacking is compiled to a python statement at the start of the loop.
+    if variable == "_sl2_i" && !children.is_empty() && node_name(&children[0]) == "SLPython" {
+        let code = extract_string(&children.remove(0), "code")?;
+        variable = code.trim().trim_end_matches("_sl2_i").trim().trim_end_matches('=').trim().to_owned();
+    }
+
+    let mut header = format!("for {}", variable);
+    if let Some(index) = try_extract_string(node, "index_expression")? {
+        header += &format!(" index {}", index);
+    }
+    header += &format!(" in {}", extract_string(node, "expression")?);
+
+    let mut body = String::new();
+    for keyword in parse_keywords(node)? {
+        body += &format!("{}\n", keyword);
+    }
+    for child in children {
+        body += &parse_node(&child)?;
+    }
+    Ok(sl_block(header, body))
+}
+
+fn parse_use(node: &Pickle) -> Result<String, Box<dyn Error>> {
+    let mut str = match node_extract_data(node, "target")? {
+        Pickle::String(target) => format!("use {}", target),
+        target => format!("use expression {}", node_string(&target)?),
+    };
+    if let Some(arguments) = node_try_extract_data(node, "args") {
+        if str.starts_with("use expression") {
+            str += " pass ";
+        }
+        str += &parse_arguments(&arguments)?;
+    }
+    if let Some(id) = try_extract_string(node, "id")? {
+        str += &format!(" id {}", id);
+    }
+    Ok(match node_try_extract_data(node, "block") {
+        Some(child) => sl_block(str, parse_sl_block(&child)?),
+        None => format!("{}\n", str),
+    })
+}
+
+fn parse_screen(node: &Pickle) -> Result<String, Box<dyn Error>> {
+    let mut header = format!("screen {}", extract_string(node, "name")?);
+    if let Some(parameters) = node_try_extract_data(node, "parameters") {
+        header += &parse_parameters(&parameters)?;
+    }
+
+    // Screen properties are stored separately from other keywords, only output them when not the default.
+    let mut body = String::new();
+    for (name, default) in [
+        ("modal", Some("False")),
+        ("sensitive", Some("True")),
+        ("tag", None),
+        ("zorder", Some("0")),
+        ("variant", Some("None")),
+        ("predict", Some("None")),
+        ("layer", Some("'screens'")),
+        ("roll_forward", Some("None")),
+    ] {
+        if let Some(value) = try_extract_string(node, name)? {
+            if Some(value.as_str()) != default {
+                body += &format!("{} {}\n", name, value);
+            }
+        }
+    }
+    body += &parse_sl_block(node)?;
+
+    Ok(sl_block(header, body))
+}
+
+fn parse_python(node: &Pickle) -> Result<String, Box<dyn Error>> {
+    let code = extract_string(node, "code")?;
+    if code.trim().contains('\n') {
+        Ok(block("python".to_owned(), format!("{}\n", code.trim_end())))
+    } else {
+        Ok(format!("$ {}\n", code.trim()))
+    }
+}
+
+
+
+pub fn parse_node_sl2(node: &Pickle) -> Result<String, Box<dyn Error>> {
+    Ok(match node_name(node) {
+        "SLScreen" => parse_screen(node)?,
+        "SLDisplayable" => parse_displayable(node)?,
+        "SLBlock" => parse_sl_block(node)?,
+        "SLIf" => parse_if(node, "if")?,
+        "SLShowIf" => parse_if(node, "showif")?,
+        "SLFor" => parse_for(node)?,
+        "SLUse" => parse_use(node)?,
+        "SLPython" => parse_python(node)?,
+        "SLDefault" => format!("default {} = {}\n", extract_string(node, "variable")?, extract_string(node, "expression")?),
+        "SLPass" => "pass\n".to_owned(),
+        "SLBreak" => "break\n".to_owned(),
+        "SLContinue" => "continue\n".to_owned(),
+        "SLTransclude" => "transclude\n".to_owned(),
+        class => format!("***DECOMPILE ERROR: Unknown class. {}***\n", class),
+    })
+}
@@ -0,0 +1,104 @@
+// https://github.com/renpy/renpy/blob/master/renpy/test/testast.py
+
+use std::error::Error;
+use crate::util::pickle::pickle::Pickle;
+use super::{block, extract_list, extract_string, node_extract_data, node_name, node_try_extract_data, parse_node, string_list, string_literal, try_extract_string};
+
+
+
+/// Statements of a `Block`, without a header.
+fn parse_testcase_block(node: &Pickle) -> Result<String, Box<dyn Error>> {
+    let mut str = String::new();
+    for statement in extract_list(node, "block")? {
+        str += &parse_node(&statement)?;
+    }
+    Ok(str)
+}
+
+/// Clause of `until` & `if`, which is a single statement on the same line.
+fn parse_clause(node: &Pickle) -> Result<String, Box<dyn Error>> {
+    Ok(parse_node_testcase(node)?.trim_end_matches('\n').to_owned())
+}
+
+fn pattern(node: &Pickle) -> Result<String, Box<dyn Error>> {
+    Ok(match try_extract_string(node, "pattern")? {
+        Some(pattern) => format!(" pattern {}", string_literal(&pattern)),
+        None => String::new(),
+    })
+}
+
+fn position(node: &Pickle) -> Result<String, Box<dyn Error>> {
+    Ok(match try_extract_string(node, "position")? {
+        Some(position) => format!(" pos {}", position),
+        None => String::new(),
+    })
+}
+
+/// Option that is left out when it is the default.
+fn non_default(node: &Pickle, key: &str, default: i64) -> String {
+    match node_try_extract_data(node, key).and_then(|value| TryInto::<i64>::try_into(value).ok()) {
+        Some(value) if value != default => format!(" {} {}", key, value),
+        _ => String::new(),
+    }
+}
+
+
+
+pub fn parse_node_testcase(node: &Pickle) -> Result<String, Box<dyn Error>> {
+    Ok(match node_name(node) {
+        "Block" => parse_testcase_block(node)?,
+        "Click" => {
+            let mut str = match try_extract_string(node, "pattern")? {
+                Some(pattern) => string_literal(&pattern),
+                None => "click".to_owned(),
+            };
+            str += &non_default(node, "button", 1);
+            str += &position(node)?;
+            if matches!(node_try_extract_data(node, "always"), Some(Pickle::Bool(true))) {
+                str += " always";
+            }
+            format!("{}\n", str)
+        },
+        "Move" => format!("move {}{}\n", extract_string(node, "position")?, pattern(node)?),
+        "Scroll" => format!("scroll {}\n", string_literal(&extract_string(node, "pattern")?)),
+        "Drag" => format!(
+            "drag {}{}{}{}\n",
+            extract_string(node, "points")?,
+            pattern(node)?,
+            non_default(node, "button", 1),
+            non_default(node, "steps", 10),
+        ),
+        "Type" => {
+            let keys = string_list(extract_list(node, "keys")?)?;
+            // Text is split into single characters, anything else is a key name.
+            let keys = match keys.first() {
+                Some(key) if key.chars().count() > 1 => key.clone(),
+                _ => string_literal(&keys.concat()),
+            };
+            format!("type {}{}{}\n", keys, pattern(node)?, position(node)?)
+        },
+        "Action" => format!("run {}\n", extract_string(node, "expr")?),
+        "Pause" => format!("pause {}\n", extract_string(node, "expr")?),
+        "Label" => format!("label {}\n", extract_string(node, "name")?),
+        "Until" => format!(
+            "{} until {}\n",
+            parse_clause(&node_extract_data(node, "left")?)?,
+            parse_clause(&node_extract_data(node, "right")?)?,
+        ),
+        "If" => block(
+            format!("if {}", parse_clause(&node_extract_data(node, "condition")?)?),
+            parse_testcase_block(&node_extract_data(node, "block")?)?
+        ),
+        "Python" => {
+            let code = extract_string(node, "code")?;
+            match code.strip_prefix('\n') {
+                Some(code) => block("python".to_owned(), format!("{}\n", code.trim_end())),
+                None => format!("$ {}\n", code.trim()),
+            }
+        },
+        "Assert" => format!("assert {}\n", extract_string(node, "expr")?),
+        "Jump" => format!("jump {}\n", extract_string(node, "target")?),
+        "Call" => format!("call {}\n", extract_string(node, "target")?),
+        class => format!("***DECOMPILE ERROR: Unknown class. {}***\n", class),
+    })
+}
@@ -0,0 +1,149 @@
+// https://github.com/renpy/renpy/blob/master/renpy/loader.py
+
+use std::{collections::HashMap, error::Error, fs::{self, File}, path::{Path, PathBuf}};
+use crate::util::virtual_fs::{VirtualDirectory, VirtualEntry, VirtualFile};
+use super::archive::{RenPyArchive, RenPyArchiveFile};
+
+
+
+#[derive(Debug)]
+enum RenPyGameFileSource {
+    Loose(PathBuf),
+    Archive(String, RenPyArchiveFile),
+}
+
+#[derive(Debug)]
+pub struct RenPyGameFile {
+    path: String,
+    source: RenPyGameFileSource,
+}
+
+impl RenPyGameFile {
+
+    /// Name of the archive the file is loaded from, or the file path for loose files.
+    pub fn source(&self) -> String {
+        match &self.source {
+            RenPyGameFileSource::Loose(path) => path.display().to_string(),
+            RenPyGameFileSource::Archive(archive, _) => archive.clone(),
+        }
+    }
+
+}
+
+impl VirtualFile for RenPyGameFile {
+    fn path(&mut self) -> &str {
+        &self.path
+    }
+
+    fn read_data(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        match &mut self.source {
+            RenPyGameFileSource::Loose(path) => Ok(fs::read(path)?),
+            RenPyGameFileSource::Archive(_, file) => file.read_data(),
+        }
+    }
+}
+
+
+
+/// File that exists in multiple places, only the one loaded by Ren'Py is used.
+#[derive(Debug)]
+pub struct RenPyGameConflict {
+    pub path: String,
+    pub overridden: String,
+    pub loaded: String,
+}
+
+
+
+/// Merged view of all archives & loose files in a game directory.
+#[derive(Debug)]
+pub struct RenPyGame {
+    files: Vec<RenPyGameFile>,
+    /// Index into files by path.
+    index: HashMap<String, usize>,
+    pub archives: Vec<PathBuf>,
+    pub conflicts: Vec<RenPyGameConflict>,
+}
+
+impl RenPyGame {
+
+    fn loose_files(dir: &Path, prefix: &str, files: &mut Vec<(String, PathBuf)>) -> Result<(), Box<dyn Error>> {
+        let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = format!("{}{}", prefix, name);
+            if entry.file_type()?.is_dir() {
+                // Saves are not part of the game.
+                if path != "saves" {
+                    Self::loose_files(&entry.path(), &format!("{}/", path), files)?;
+                }
+            } else if !name.ends_with(".rpa") {
+                files.push((path, entry.path()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Root is either the game's root directory, or its `game/` directory.
+    ///
+    /// Ren'Py loads loose files first, then archives in reverse alphabetical order.
+    pub fn from_dir(root: &Path) -> Result<Self, Box<dyn Error>> {
+        let game_dir = if root.join("game").is_dir() { root.join("game") } else { root.to_path_buf() };
+
+        let mut archives = fs::read_dir(&game_dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        archives.retain(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "rpa"));
+        archives.sort();
+
+        let mut game = Self { files: Vec::new(), index: HashMap::new(), archives: Vec::new(), conflicts: Vec::new() };
+
+        // Files are added lowest priority first, each one overriding the previous.
+        for archive_path in archives {
+            let name = archive_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            let archive = RenPyArchive::from_file(File::open(&archive_path)?)?;
+            for mut file in archive.into_files() {
+                let path = file.path().to_owned();
+                game.add(RenPyGameFile { path, source: RenPyGameFileSource::Archive(name.clone(), file) });
+            }
+            game.archives.push(archive_path);
+        }
+
+        let mut loose = Vec::new();
+        Self::loose_files(&game_dir, "", &mut loose)?;
+        for (path, file) in loose {
+            game.add(RenPyGameFile { path, source: RenPyGameFileSource::Loose(file) });
+        }
+
+        Ok(game)
+    }
+
+    fn add(&mut self, file: RenPyGameFile) {
+        match self.index.get(&file.path) {
+            Some(&index) => {
+                self.conflicts.push(RenPyGameConflict {
+                    path: file.path.clone(),
+                    overridden: self.files[index].source(),
+                    loaded: file.source(),
+                });
+                self.files[index] = file;
+            },
+            None => {
+                self.index.insert(file.path.clone(), self.files.len());
+                self.files.push(file);
+            },
+        }
+    }
+
+}
+
+impl VirtualDirectory<RenPyGameFile, RenPyGame> for RenPyGame {
+    fn path(&mut self) -> &str {
+        ""
+    }
+
+    fn read_entries(&mut self) -> Result<Vec<VirtualEntry<'_, RenPyGameFile, RenPyGame>>, Box<dyn Error>> {
+        Ok(self.files.iter_mut().map(VirtualEntry::File).collect())
+    }
+}
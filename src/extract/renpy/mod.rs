@@ -1,4 +1,8 @@
 
 pub mod archive;
+pub mod bytecode;
+pub mod game;
+pub mod save;
 pub mod script;
+pub mod translation;
 mod decompile;
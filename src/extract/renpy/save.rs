@@ -0,0 +1,175 @@
+// https://github.com/renpy/renpy/blob/master/renpy/loadsave.py
+// https://github.com/renpy/renpy/blob/master/renpy/persistent.py
+
+use std::{error::Error, fmt, io::{Cursor, Read, Seek}};
+use serde_json::{Map, Value};
+use zip::ZipArchive;
+use crate::util::pickle::{json::pickle_to_json, parser::PickleParser, pickle::{Pickle, PickleDict}};
+
+
+
+#[derive(Debug)]
+pub enum RenPySaveError {
+    MissingEntry(String),
+}
+
+impl fmt::Display for RenPySaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingEntry(name) => write!(f, "Ren'Py save is missing \"{}\".", name),
+        }
+    }
+}
+
+impl Error for RenPySaveError { }
+
+
+
+/// Fields of an object, from its `__dict__` state & items set on it.
+fn object_fields(pickle: &Pickle) -> PickleDict {
+    let mut fields = PickleDict::new();
+    match pickle.resolve() {
+        Pickle::Dict(dict) => fields.extend(dict.clone()),
+        Pickle::Class(class) => {
+            match class.state.as_deref() {
+                Some(Pickle::Dict(dict)) => fields.extend(dict.clone()),
+                Some(Pickle::Tuple(state)) => for item in state {
+                    if let Pickle::Dict(dict) = item {
+                        fields.extend(dict.clone());
+                    }
+                },
+                _ => { },
+            }
+            fields.extend(class.data.clone());
+        },
+        _ => { },
+    }
+    fields
+}
+
+/// Sorted keys of a dict, or a dict subclass.
+fn dict_keys(pickle: Option<&Pickle>) -> Value {
+    let mut keys = pickle.map(object_fields).unwrap_or_default().keys().map(Pickle::key_string).collect::<Vec<_>>();
+    keys.sort();
+    Value::Array(keys.into_iter().map(Value::String).collect())
+}
+
+
+
+/// `persistent` file, a zlib compressed pickle of `renpy.persistent.Persistent`.
+#[derive(Debug)]
+pub struct RenPyPersistent {
+    pub persistent: Pickle,
+}
+
+impl RenPyPersistent {
+
+    pub fn load(data: &mut impl Read) -> Result<Self, Box<dyn Error>> {
+        let mut decompressed = Vec::new();
+        flate2::read::ZlibDecoder::new(data).read_to_end(&mut decompressed)?;
+        Ok(Self { persistent: PickleParser::parse(&mut Cursor::new(decompressed))? })
+    }
+
+    /// Game variables are fields without an underscore, Ren'Py's own data is split into what is useful when debugging.
+    pub fn to_json(&self) -> Value {
+        let fields = object_fields(&self.persistent);
+
+        const EXTRACTED: [&str; 5] = ["_seen_ever", "_seen_images", "_seen_audio", "_chosen", "_preferences"];
+
+        let mut variables = Map::new();
+        let mut internal = Map::new();
+        for (name, value) in &fields {
+            let name = name.key_string();
+            if EXTRACTED.contains(&name.as_str()) {
+                continue;
+            }
+            if name.starts_with('_') {
+                internal.insert(name.clone(), pickle_to_json(value));
+            } else {
+                variables.insert(name.clone(), pickle_to_json(value));
+            }
+        }
+
+        let mut json = Map::new();
+        json.insert("variables".to_owned(), Value::Object(variables));
+        // Statements without a label are seen by their `(filename, time, serial)` name.
+        let seen_labels = match dict_keys(fields.get("_seen_ever")) {
+            Value::Array(keys) => keys.into_iter().filter(|key| !key.as_str().is_some_and(|key| key.starts_with('('))).collect(),
+            keys => keys,
+        };
+        json.insert("seen_labels".to_owned(), seen_labels);
+        json.insert("seen_images".to_owned(), dict_keys(fields.get("_seen_images")));
+        json.insert("seen_audio".to_owned(), dict_keys(fields.get("_seen_audio")));
+        json.insert("chosen".to_owned(), dict_keys(fields.get("_chosen")));
+        json.insert("preferences".to_owned(), fields.get("_preferences").map(pickle_to_json).unwrap_or(Value::Null));
+        json.insert("internal".to_owned(), Value::Object(internal));
+        Value::Object(json)
+    }
+
+}
+
+
+
+/// `.save` file, a zip containing the pickled game state & a screenshot.
+#[derive(Debug)]
+pub struct RenPySave {
+    pub renpy_version: Option<String>,
+    pub extra_info: Option<String>,
+    /// `json` entry, only in saves from Ren'Py 7.0 and later.
+    pub metadata: Option<Value>,
+    /// Store variables, `(roots, log)` is pickled where roots are the variables by their full name.
+    pub roots: Pickle,
+    pub screenshot: Option<Vec<u8>>,
+}
+
+impl RenPySave {
+
+    fn read_entry<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let mut entry = match archive.by_name(name) {
+            Ok(entry) => entry,
+            Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+            Err(err) => return Err(Box::new(err)),
+        };
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        Ok(Some(data))
+    }
+
+    pub fn load(data: impl Read + Seek) -> Result<Self, Box<dyn Error>> {
+        let mut archive = ZipArchive::new(data)?;
+
+        let log = Self::read_entry(&mut archive, "log")?.ok_or(RenPySaveError::MissingEntry("log".to_owned()))?;
+        let (roots, _log): (Pickle, Pickle) = PickleParser::parse(&mut Cursor::new(log))?.try_into()?;
+
+        let metadata = match Self::read_entry(&mut archive, "json")? {
+            Some(json) => Some(serde_json::from_slice(&json)?),
+            None => None,
+        };
+
+        Ok(Self {
+            renpy_version: Self::read_entry(&mut archive, "renpy_version")?.map(|data| String::from_utf8_lossy(&data).into_owned()),
+            extra_info: Self::read_entry(&mut archive, "extra_info")?.map(|data| String::from_utf8_lossy(&data).into_owned()),
+            metadata,
+            roots,
+            screenshot: Self::read_entry(&mut archive, "screenshot.png")?,
+        })
+    }
+
+    /// Store variables are output without the `store.` prefix.
+    pub fn to_json(&self) -> Value {
+        let mut store = Map::new();
+        for (name, value) in object_fields(&self.roots) {
+            let name = name.key_string();
+            let name = name.strip_prefix("store.").map(str::to_owned).unwrap_or(name);
+            store.insert(name, pickle_to_json(&value));
+        }
+
+        let mut json = Map::new();
+        json.insert("renpy_version".to_owned(), self.renpy_version.clone().map(Value::String).unwrap_or(Value::Null));
+        json.insert("extra_info".to_owned(), self.extra_info.clone().map(Value::String).unwrap_or(Value::Null));
+        json.insert("metadata".to_owned(), self.metadata.clone().unwrap_or(Value::Null));
+        json.insert("store".to_owned(), Value::Object(store));
+        Value::Object(json)
+    }
+
+}
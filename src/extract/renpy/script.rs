@@ -1,12 +1,55 @@
-
-use std::{error::Error, io::{Cursor, Read, Seek}};
+use std::{error::Error, fmt, io::{Cursor, Read, Seek, SeekFrom}};
 
 use crate::util::{pickle::{parser::PickleParser, pickle::Pickle}, read_ext::ReadExt};
 
 
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenPyScriptFormat {
+    /// Legacy format, file is a single zlib compressed pickle.
+    RPC1,
+    /// `RENPY RPC2` header followed by a table of zlib compressed slots.
+    RPC2,
+}
+
+
+
+/// Version information stored in the header of compiled scripts.
+#[derive(Debug, Clone)]
+pub struct RenPyScriptVersion {
+    pub format: RenPyScriptFormat,
+    pub script_version: Option<i64>,
+    pub key: Option<String>,
+    /// Python major version the script was compiled for, Ren'Py 7.4 started storing this on every expression.
+    pub python: Option<u8>,
+}
+
+impl fmt::Display for RenPyScriptVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.format, self.python) {
+            (_, Some(3)) => write!(f, "Ren'Py 8")?,
+            (_, Some(2)) => write!(f, "Ren'Py 6 - 7")?,
+            (RenPyScriptFormat::RPC1, _) => write!(f, "Ren'Py 6")?,
+            (RenPyScriptFormat::RPC2, _) => write!(f, "Ren'Py 6 - 8")?,
+        }
+        write!(f, " ({:?}", self.format)?;
+        if let Some(script_version) = self.script_version {
+            write!(f, ", script version {}", script_version)?;
+        }
+        if let Some(key) = &self.key {
+            if key != "unlocked" {
+                write!(f, ", key \"{}\"", key)?;
+            }
+        }
+        write!(f, ")")
+    }
+}
+
+
+
 #[derive(Debug)]
 pub struct RenPyCompiledScriptChunk {
+    pub format: RenPyScriptFormat,
     pub slot: u32,
     pub data: Vec<u8>,
 }
@@ -23,6 +66,7 @@ impl RenPyCompiledScriptChunk {
 
 #[derive(Debug)]
 pub struct RenPyCompiledScript {
+    pub format: RenPyScriptFormat,
     pub chunks: Vec<RenPyCompiledScriptChunk>,
 }
 
@@ -30,33 +74,43 @@ pub struct RenPyCompiledScript {
 
 impl RenPyCompiledScript {
 
+    fn decompress(compressed: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut decompressed = Vec::new();
+        let mut decoder = flate2::read::ZlibDecoder::new(Cursor::new(compressed));
+        decoder.read_to_end(&mut decompressed)?;
+        Ok(decompressed)
+    }
+
     pub fn load(data: &mut (impl Read + Seek)) -> Result<Self, Box<dyn Error>> {
 
-        assert!(data.check_magic_string("RENPY RPC2")?, "Ren'Py script header doesn't match.");
+        if !data.check_magic_string("RENPY RPC2")? {
+            // Legacy scripts only have the AST, which is the same as slot 1.
+            data.seek(SeekFrom::Start(0))?;
+            let mut compressed = Vec::new();
+            data.read_to_end(&mut compressed)?;
+            let format = RenPyScriptFormat::RPC1;
+            return Ok(Self { format, chunks: vec![RenPyCompiledScriptChunk { format, slot: 1, data: Self::decompress(compressed)? }] });
+        }
 
         let mut chunks: Vec<(u32, u32, u32)> = Vec::new();
         loop {
             let slot: u32 = data.read_primitive()?;
             let offset: u32 = data.read_primitive()?;
             let length: u32 = data.read_primitive()?;
-            
+
             if slot == 0 { break }
 
             chunks.push((slot, offset, length));
         }
 
+        let format = RenPyScriptFormat::RPC2;
         let chunks: Vec<RenPyCompiledScriptChunk> = chunks.iter().map(|(slot, offset, length)| {
-            data.seek(std::io::SeekFrom::Start(offset.clone().into()))?;
-
-            let compressed = data.read_to_vec(length.clone() as usize)?;
-            let mut decompressed = Vec::new();
-            let mut decoder = flate2::read::ZlibDecoder::new(Cursor::new(compressed));
-            decoder.read_to_end(&mut decompressed)?;
-
-            Ok(RenPyCompiledScriptChunk { slot: slot.clone(), data: decompressed })
+            data.seek(SeekFrom::Start((*offset).into()))?;
+            let compressed = data.read_to_vec(*length as usize)?;
+            Ok(RenPyCompiledScriptChunk { format, slot: *slot, data: Self::decompress(compressed)? })
         }).collect::<Result<Vec<_>, Box<dyn Error>>>()?;
 
-        Ok(Self { chunks })
+        Ok(Self { format, chunks })
     }
 
     pub fn chunk(self, slot: u32) -> Option<RenPyCompiledScriptChunk> {
@@ -68,4 +122,14 @@ impl RenPyCompiledScript {
         None
     }
 
+    /// Chunk containing the AST, which is slot 1.
+    /// Some scripts only contain the AST in slot 2.
+    pub fn ast_chunk(self) -> Option<RenPyCompiledScriptChunk> {
+        let mut chunks = self.chunks.into_iter()
+            .filter(|chunk| (chunk.slot == 1 || chunk.slot == 2) && !chunk.data.is_empty())
+            .collect::<Vec<_>>();
+        chunks.sort_by_key(|chunk| chunk.slot);
+        if chunks.is_empty() { None } else { Some(chunks.remove(0)) }
+    }
+
 }
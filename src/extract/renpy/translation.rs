@@ -0,0 +1,351 @@
+// https://www.renpy.org/doc/html/translation.html
+
+use std::{collections::HashSet, error::Error};
+use regex::Regex;
+use crate::util::pickle::pickle::Pickle;
+use super::{decompile::{extract_list, extract_string, node_class, node_linenumber, node_name, node_string, node_try_extract_data, parse_block, string_literal, strip_line_markers, try_extract_string}, script::RenPyCompiledScriptChunk};
+
+
+
+#[derive(Debug, Clone)]
+pub enum RenPyTranslatable {
+    /// Dialogue block, keyed by the identifier Ren'Py generates from the label & code.
+    Dialogue {
+        identifier: String,
+        filename: String,
+        linenumber: i64,
+        /// Decompiled code of the block.
+        code: String,
+        who: Option<String>,
+        what: Option<String>,
+    },
+    /// Menu choices & strings marked with `_()`.
+    String {
+        filename: String,
+        linenumber: i64,
+        text: String,
+    },
+}
+
+
+
+struct TranslatableCollector {
+    translatables: Vec<RenPyTranslatable>,
+    strings: HashSet<String>,
+    /// Shared objects are only collected the first time they are found.
+    objects: HashSet<usize>,
+    string_regex: Regex,
+}
+
+impl TranslatableCollector {
+
+    fn new() -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            translatables: Vec::new(),
+            strings: HashSet::new(),
+            objects: HashSet::new(),
+            string_regex: Regex::new(r#"\b__?\(\s*(?:"((?:[^"\\]|\\.)*)"|'((?:[^'\\]|\\.)*)')\s*\)"#)?,
+        })
+    }
+
+    fn push_string(&mut self, location: &(String, i64), text: String) {
+        if !text.is_empty() && self.strings.insert(text.clone()) {
+            self.translatables.push(RenPyTranslatable::String { filename: location.0.clone(), linenumber: location.1, text });
+        }
+    }
+
+    fn push_dialogue(&mut self, node: &Pickle, location: &(String, i64), code: String, say: Option<&Pickle>) -> Result<(), Box<dyn Error>> {
+        let identifier = extract_string(node, "identifier")?;
+        let (who, what) = match say {
+            Some(say) => (try_extract_string(say, "who")?, try_extract_string(say, "what")?),
+            None => (None, None),
+        };
+        self.translatables.push(RenPyTranslatable::Dialogue {
+            identifier,
+            filename: location.0.clone(),
+            linenumber: location.1,
+            code,
+            who,
+            what,
+        });
+        Ok(())
+    }
+
+    fn collect(&mut self, pickle: &Pickle, location: &(String, i64)) -> Result<(), Box<dyn Error>> {
+        if let Pickle::Ref(reference) = pickle {
+            if !self.objects.insert(reference.id()) {
+                return Ok(());
+            }
+            return self.collect(reference.get(), location);
+        }
+
+        let location = &match (try_extract_string(pickle, "filename").ok().flatten(), node_linenumber(pickle)) {
+            (Some(filename), Some(linenumber)) => (filename, linenumber),
+            _ => location.clone(),
+        };
+
+        match (node_class(pickle), pickle) {
+            (Some(("renpy.ast", "Translate")), _) if node_try_extract_data(pickle, "language").is_none() => {
+                let nodes = extract_list(pickle, "block")?;
+                let say = nodes.iter().find(|node| node_name(node) == "Say");
+                self.push_dialogue(pickle, location, strip_line_markers(&parse_block(&nodes)?.join("")), say)?;
+                return Ok(());
+            },
+            (Some(("renpy.ast", "TranslateSay")), _) if node_try_extract_data(pickle, "language").is_none() => {
+                self.push_dialogue(pickle, location, strip_line_markers(&parse_block(std::slice::from_ref(pickle))?.join("")), Some(pickle))?;
+                return Ok(());
+            },
+            (Some(("renpy.ast", "Menu")), _) => {
+                for item in extract_list(pickle, "items")? {
+                    let (caption, _, _): (Pickle, Pickle, Pickle) = item.clone().try_into()?;
+                    self.push_string(location, node_string(&caption)?);
+                }
+            },
+            (_, Pickle::String(str)) => {
+                let strings = self.string_regex.captures_iter(str)
+                    .filter_map(|captures| captures.get(1).or(captures.get(2)))
+                    .map(|text| unescape(text.as_str()))
+                    .collect::<Vec<_>>();
+                for text in strings {
+                    self.push_string(location, text);
+                }
+            },
+            _ => { },
+        }
+
+        match pickle {
+            Pickle::List(items) | Pickle::Tuple(items) | Pickle::Set(items) | Pickle::FrozenSet(items) => {
+                for item in items {
+                    self.collect(item, location)?;
+                }
+            },
+            Pickle::Dict(dict) => {
+                for item in dict.values() {
+                    self.collect(item, location)?;
+                }
+            },
+            Pickle::Class(class) => {
+                self.collect(&class.args, location)?;
+                if let Some(state) = &class.state {
+                    self.collect(state, location)?;
+                }
+                for item in class.data.values() {
+                    self.collect(item, location)?;
+                }
+            },
+            _ => { },
+        }
+
+        Ok(())
+    }
+
+}
+
+/// gettext string, which has C escapes instead of Python's.
+fn po_string(str: &str) -> String {
+    let mut literal = String::from("\"");
+    for char in str.chars() {
+        match char {
+            '\\' => literal += "\\\\",
+            '"' => literal += "\\\"",
+            '\n' => literal += "\\n",
+            '\t' => literal += "\\t",
+            '\r' => literal += "\\r",
+            '\u{07}' => literal += "\\a",
+            '\u{08}' => literal += "\\b",
+            '\u{0C}' => literal += "\\f",
+            '\u{0B}' => literal += "\\v",
+            char if char.is_ascii_control() => literal += &format!("\\{:03o}", char as u32),
+            char => literal.push(char),
+        }
+    }
+    literal + "\""
+}
+
+fn unescape(str: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = str.chars();
+    while let Some(char) = chars.next() {
+        match char {
+            '\\' => match chars.next() {
+                Some('n') => unescaped.push('\n'),
+                Some(char) => unescaped.push(char),
+                None => { },
+            },
+            char => unescaped.push(char),
+        }
+    }
+    unescaped
+}
+
+
+
+impl RenPyCompiledScriptChunk {
+
+    /// Get all dialogue & strings that can be translated.
+    pub fn translatables(&mut self) -> Result<Vec<RenPyTranslatable>, Box<dyn Error>> {
+        let (_header, ast): (Pickle, Pickle) = self.pickle()?.try_into()?;
+        let mut collector = TranslatableCollector::new()?;
+        collector.collect(&ast, &(String::new(), 0))?;
+        Ok(collector.translatables)
+    }
+
+}
+
+
+
+
+
+#[derive(Debug)]
+pub struct RenPyTranslationTemplate {
+    language: String,
+    files: Vec<(String, Vec<RenPyTranslatable>)>,
+    /// Strings are translated once for the whole game, not per file.
+    strings: HashSet<String>,
+}
+
+impl RenPyTranslationTemplate {
+
+    pub fn new(language: String) -> Self {
+        Self { language, files: Vec::new(), strings: HashSet::new() }
+    }
+
+    /// Add translatables of a script, path is the path of the script in the archive.
+    ///
+    /// Strings already added by another script are left out, keeping their first location.
+    pub fn add(&mut self, path: String, translatables: Vec<RenPyTranslatable>) {
+        let translatables = translatables.into_iter()
+            .filter(|translatable| match translatable {
+                RenPyTranslatable::String { text, .. } => self.strings.insert(text.clone()),
+                RenPyTranslatable::Dialogue { .. } => true,
+            })
+            .collect::<Vec<_>>();
+        if !translatables.is_empty() {
+            self.files.push((path, translatables));
+        }
+    }
+
+    /// Ren'Py `tl/language/` layout, as a list of (path, content)
+    pub fn to_tl(&self) -> Vec<(String, String)> {
+        self.files.iter().map(|(path, translatables)| {
+            let path = path.strip_prefix("game/").unwrap_or(path);
+            let path = match path.strip_suffix(".rpyc") {
+                Some(path) => format!("{}.rpy", path),
+                None => path.to_owned(),
+            };
+            let mut dialogue = String::new();
+            let mut strings = String::new();
+
+            for translatable in translatables {
+                match translatable {
+                    RenPyTranslatable::Dialogue { identifier, filename, linenumber, code, .. } => {
+                        dialogue += &format!("# {}:{}\n", filename, linenumber);
+                        dialogue += &format!("translate {} {}:\n\n", self.language, identifier);
+                        for line in code.lines() {
+                            dialogue += &format!("    # {}\n", line);
+                        }
+                        for line in code.lines() {
+                            dialogue += &format!("    {}\n", line);
+                        }
+                        dialogue += "\n";
+                    },
+                    RenPyTranslatable::String { filename, linenumber, text } => {
+                        strings += &format!("    # {}:{}\n", filename, linenumber);
+                        strings += &format!("    old {}\n", string_literal(text));
+                        strings += &format!("    new {}\n\n", string_literal(text));
+                    },
+                }
+            }
+
+            if !strings.is_empty() {
+                dialogue += &format!("translate {} strings:\n\n{}", self.language, strings);
+            }
+
+            (format!("tl/{}/{}", self.language, path), dialogue)
+        }).collect()
+    }
+
+    /// gettext `.po` file, dialogue uses the identifier as the context.
+    pub fn to_po(&self) -> String {
+        let mut po = String::new();
+        po += "msgid \"\"\n";
+        po += "msgstr \"\"\n";
+        po += &format!("\"Language: {}\\n\"\n", self.language);
+        po += "\"MIME-Version: 1.0\\n\"\n";
+        po += "\"Content-Type: text/plain; charset=UTF-8\\n\"\n";
+        po += "\"Content-Transfer-Encoding: 8bit\\n\"\n\n";
+
+        for (_, translatables) in &self.files {
+            for translatable in translatables {
+                match translatable {
+                    RenPyTranslatable::Dialogue { identifier, filename, linenumber, who, what: Some(what), .. } => {
+                        if let Some(who) = who {
+                            po += &format!("#. who: {}\n", who);
+                        }
+                        po += &format!("#: {}:{}\n", filename, linenumber);
+                        po += &format!("msgctxt {}\n", po_string(identifier));
+                        po += &format!("msgid {}\n", po_string(what));
+                        po += "msgstr \"\"\n\n";
+                    },
+                    RenPyTranslatable::Dialogue { .. } => { },
+                    RenPyTranslatable::String { filename, linenumber, text } => {
+                        po += &format!("#: {}:{}\n", filename, linenumber);
+                        po += &format!("msgid {}\n", po_string(text));
+                        po += "msgstr \"\"\n\n";
+                    },
+                }
+            }
+        }
+
+        po
+    }
+
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::{po_string, RenPyTranslatable, RenPyTranslationTemplate};
+
+    fn string(filename: &str, linenumber: i64, text: &str) -> RenPyTranslatable {
+        RenPyTranslatable::String { filename: filename.to_owned(), linenumber, text: text.to_owned() }
+    }
+
+    #[test]
+    fn template_strings_once() {
+        let mut template = RenPyTranslationTemplate::new("french".to_owned());
+        template.add("game/a.rpyc".to_owned(), vec![string("game/a.rpy", 3, "Yes"), string("game/a.rpy", 4, "No")]);
+        template.add("game/b.rpyc".to_owned(), vec![string("game/b.rpy", 8, "Yes")]);
+        template.add("game/c.rpyc".to_owned(), vec![string("game/c.rpy", 1, "No"), string("game/c.rpy", 2, "Maybe")]);
+
+        let tl = template.to_tl();
+        assert_eq!(tl.iter().map(|(path, _)| path.as_str()).collect::<Vec<_>>(), ["tl/french/a.rpy", "tl/french/c.rpy"]);
+        assert!(tl[1].1.contains("game/c.rpy:2") && !tl[1].1.contains("\"No\""));
+
+        // Only the extension is changed.
+        template.add("game/old.rpyc.d/d.rpyc".to_owned(), vec![string("game/old.rpyc.d/d.rpy", 1, "Later")]);
+        assert_eq!(template.to_tl()[2].0, "tl/french/old.rpyc.d/d.rpy");
+
+        let po = template.to_po();
+        assert_eq!(po.matches("msgid \"Yes\"").count(), 1);
+        assert_eq!(po.matches("msgid \"No\"").count(), 1);
+        assert!(po.contains("#: game/a.rpy:3\nmsgid \"Yes\""));
+    }
+
+    #[test]
+    fn template_po_escapes() {
+        assert_eq!(po_string("a\\b \"c\"\n\t\r\u{7}\u{8}\u{c}\u{b}\u{1b}[0m é"), r#""a\\b \"c\"\n\t\r\a\b\f\v\033[0m é""#);
+
+        let mut template = RenPyTranslationTemplate::new("french".to_owned());
+        template.add("game/script.rpyc".to_owned(), vec![RenPyTranslatable::Dialogue {
+            identifier: "start_a170b500".to_owned(),
+            filename: "game/script.rpy".to_owned(),
+            linenumber: 2,
+            code: "e \"Name:\\tValue\"".to_owned(),
+            who: Some("e".to_owned()),
+            what: Some("Name:\tValue".to_owned()),
+        }]);
+        assert!(template.to_po().contains("msgctxt \"start_a170b500\"\nmsgid \"Name:\\tValue\"\nmsgstr \"\"\n"));
+    }
+}
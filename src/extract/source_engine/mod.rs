@@ -1,3 +1,4 @@
-
 pub mod vpk;
+pub mod vdf;
 pub mod source1;
+pub mod source2;
@@ -0,0 +1,293 @@
+// https://learn.microsoft.com/en-us/windows/win32/direct3d10/d3d10-graphics-programming-guide-resources-block-compression
+
+use image::{Rgba, RgbaImage};
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DXTFormat {
+    DXT1,
+    /// DXT1 where the 3 color mode has transparent black.
+    DXT1OneBitAlpha,
+    DXT3,
+    DXT5,
+}
+
+impl DXTFormat {
+
+    pub fn block_size(&self) -> usize {
+        match self {
+            DXTFormat::DXT1 | DXTFormat::DXT1OneBitAlpha => 8,
+            DXTFormat::DXT3 | DXTFormat::DXT5 => 16,
+        }
+    }
+
+    /// Bytes of an image, which is stored in 4x4 blocks.
+    pub fn byte_size(&self, width: u32, height: u32) -> usize {
+        width.div_ceil(4) as usize * height.div_ceil(4) as usize * self.block_size()
+    }
+
+}
+
+
+
+fn rgb565(color: u16) -> [u8; 3] {
+    let r = (color >> 11) & 0x1F;
+    let g = (color >> 5) & 0x3F;
+    let b = color & 0x1F;
+    [((r * 255 + 15) / 31) as u8, ((g * 255 + 31) / 63) as u8, ((b * 255 + 15) / 31) as u8]
+}
+
+fn mix(a: [u8; 3], b: [u8; 3], a_weight: u16, b_weight: u16) -> [u8; 3] {
+    let total = a_weight + b_weight;
+    [0, 1, 2].map(|i| ((a[i] as u16 * a_weight + b[i] as u16 * b_weight + total / 2) / total) as u8)
+}
+
+/// Colors of a block, DXT1 has a 3 color mode when the first color is not greater.
+fn color_block(block: &[u8], format: DXTFormat) -> [Rgba<u8>; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (rgb0, rgb1) = (rgb565(c0), rgb565(c1));
+
+    let four_colors = c0 > c1 || matches!(format, DXTFormat::DXT3 | DXTFormat::DXT5);
+    let palette = if four_colors {
+        [rgb0, rgb1, mix(rgb0, rgb1, 2, 1), mix(rgb0, rgb1, 1, 2)].map(|[r, g, b]| Rgba([r, g, b, 255]))
+    } else {
+        let [r, g, b] = mix(rgb0, rgb1, 1, 1);
+        let black = if format == DXTFormat::DXT1OneBitAlpha { Rgba([0, 0, 0, 0]) } else { Rgba([0, 0, 0, 255]) };
+        [Rgba([rgb0[0], rgb0[1], rgb0[2], 255]), Rgba([rgb1[0], rgb1[1], rgb1[2], 255]), Rgba([r, g, b, 255]), black]
+    };
+
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    std::array::from_fn(|i| palette[((indices >> (i * 2)) & 0x3) as usize])
+}
+
+/// Explicit 4 bit alpha of DXT3.
+fn explicit_alpha(block: &[u8]) -> [u8; 16] {
+    let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+    std::array::from_fn(|i| ((alpha >> (i * 4)) & 0xF) as u8 * 17)
+}
+
+/// Alphas of a DXT5 block, 8 alpha mode when the first alpha is greater.
+fn alpha_palette(a0: u8, a1: u8) -> [u8; 8] {
+    let (a0, a1) = (a0 as u16, a1 as u16);
+    if a0 > a1 {
+        std::array::from_fn(|i| match i {
+            0 => a0 as u8,
+            1 => a1 as u8,
+            i => (((8 - i as u16) * a0 + (i as u16 - 1) * a1 + 3) / 7) as u8,
+        })
+    } else {
+        std::array::from_fn(|i| match i {
+            0 => a0 as u8,
+            1 => a1 as u8,
+            6 => 0,
+            7 => 255,
+            i => (((6 - i as u16) * a0 + (i as u16 - 1) * a1 + 2) / 5) as u8,
+        })
+    }
+}
+
+/// Interpolated alpha of DXT5.
+fn interpolated_alpha(block: &[u8]) -> [u8; 16] {
+    let palette = alpha_palette(block[0], block[1]);
+
+    let mut indices = [0u8; 8];
+    indices[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(indices);
+    std::array::from_fn(|i| palette[((indices >> (i * 3)) & 0x7) as usize])
+}
+
+/// Decode a DXT image, `data` must be at least [`DXTFormat::byte_size`] long.
+pub fn decode(format: DXTFormat, width: u32, height: u32, data: &[u8]) -> RgbaImage {
+    let mut image = RgbaImage::new(width, height);
+    let blocks_x = width.div_ceil(4);
+
+    for (index, block) in data.chunks_exact(format.block_size()).take(format.byte_size(width, height) / format.block_size()).enumerate() {
+        let pixels = match format {
+            DXTFormat::DXT1 | DXTFormat::DXT1OneBitAlpha => color_block(block, format),
+            DXTFormat::DXT3 | DXTFormat::DXT5 => {
+                let alpha = if format == DXTFormat::DXT3 { explicit_alpha(block) } else { interpolated_alpha(block) };
+                let mut pixels = color_block(&block[8..], format);
+                pixels.iter_mut().zip(alpha).for_each(|(pixel, alpha)| pixel[3] = alpha);
+                pixels
+            },
+        };
+
+        let block_x = (index as u32 % blocks_x) * 4;
+        let block_y = (index as u32 / blocks_x) * 4;
+        for (i, pixel) in pixels.into_iter().enumerate() {
+            let (x, y) = (block_x + i as u32 % 4, block_y + i as u32 / 4);
+            // Blocks are padded for sizes that are not a multiple of 4.
+            if x < width && y < height {
+                image.put_pixel(x, y, pixel);
+            }
+        }
+    }
+
+    image
+}
+
+
+
+fn to_rgb565([r, g, b]: [u8; 3]) -> u16 {
+    ((r as u16 * 31 + 127) / 255) << 11 | ((g as u16 * 63 + 127) / 255) << 5 | ((b as u16 * 31 + 127) / 255)
+}
+
+fn distance(a: [u8; 3], b: [u8; 3]) -> u32 {
+    (0..3).map(|i| (a[i] as i32 - b[i] as i32).pow(2) as u32).sum()
+}
+
+/// Indices of a 4 color palette that is closest to each pixel, with the total error.
+fn fit_palette(pixels: &[Rgba<u8>; 16], c0: u16, c1: u16) -> (u32, u32) {
+    let (rgb0, rgb1) = (rgb565(c0), rgb565(c1));
+    let palette = [rgb0, rgb1, mix(rgb0, rgb1, 2, 1), mix(rgb0, rgb1, 1, 2)];
+    pixels.iter().enumerate().fold((0, 0), |(indices, error), (i, pixel)| {
+        let rgb = [pixel[0], pixel[1], pixel[2]];
+        let (index, distance) = palette.iter().map(|color| distance(*color, rgb)).enumerate().min_by_key(|(_, distance)| *distance).unwrap_or((0, 0));
+        (indices | (index as u32) << (i * 2), error + distance)
+    })
+}
+
+/// Color block from the diagonal of the colors' bounding box that fits best, always in 4 color mode.
+fn encode_color_block(pixels: &[Rgba<u8>; 16], block: &mut Vec<u8>) {
+    let min = [0, 1, 2].map(|i| pixels.iter().map(|pixel| pixel[i]).min().unwrap_or(0));
+    let max = [0, 1, 2].map(|i| pixels.iter().map(|pixel| pixel[i]).max().unwrap_or(0));
+
+    // Diagonals where green & blue go the same or opposite way as red.
+    let (c0, c1, indices) = [[false, false], [true, false], [false, true], [true, true]].into_iter()
+        .map(|[flip_g, flip_b]| {
+            let (mut a, mut b) = (max, min);
+            if flip_g { (a[1], b[1]) = (b[1], a[1]); }
+            if flip_b { (a[2], b[2]) = (b[2], a[2]); }
+            let (c0, c1) = (to_rgb565(a), to_rgb565(b));
+            let (c0, c1) = if c0 < c1 { (c1, c0) } else { (c0, c1) };
+            let (indices, error) = if c0 == c1 { (0, 0) } else { fit_palette(pixels, c0, c1) };
+            (c0, c1, indices, error)
+        })
+        .min_by_key(|(_, _, _, error)| *error)
+        .map(|(c0, c1, indices, _)| (c0, c1, indices))
+        .unwrap_or((0, 0, 0));
+
+    block.extend_from_slice(&c0.to_le_bytes());
+    block.extend_from_slice(&c1.to_le_bytes());
+    block.extend_from_slice(&indices.to_le_bytes());
+}
+
+fn encode_explicit_alpha(pixels: &[Rgba<u8>; 16], block: &mut Vec<u8>) {
+    let alpha = pixels.iter().enumerate().fold(0u64, |alpha, (i, pixel)| alpha | ((pixel[3] as u64 * 15 + 127) / 255) << (i * 4));
+    block.extend_from_slice(&alpha.to_le_bytes());
+}
+
+/// Interpolated alpha between the block's extremes, in 8 alpha mode.
+fn encode_interpolated_alpha(pixels: &[Rgba<u8>; 16], block: &mut Vec<u8>) {
+    let a0 = pixels.iter().map(|pixel| pixel[3]).max().unwrap_or(255);
+    let a1 = pixels.iter().map(|pixel| pixel[3]).min().unwrap_or(255);
+
+    let mut header = [a0, a1, 0, 0, 0, 0, 0, 0];
+    let palette = alpha_palette(a0, a1);
+    let indices = pixels.iter().enumerate().fold(0u64, |indices, (i, pixel)| {
+        let index = (0..8).min_by_key(|index| (palette[*index] as i32 - pixel[3] as i32).abs()).unwrap_or(0);
+        indices | (index as u64) << (i * 3)
+    });
+    header[2..].copy_from_slice(&indices.to_le_bytes()[..6]);
+    block.extend_from_slice(&header);
+}
+
+/// Encode an image as DXT, blocks past the edge of the image repeat the edge.
+pub fn encode(format: DXTFormat, image: &RgbaImage) -> Vec<u8> {
+    let (width, height) = image.dimensions();
+    let mut data = Vec::with_capacity(format.byte_size(width, height));
+
+    for block_y in (0..height).step_by(4) {
+        for block_x in (0..width).step_by(4) {
+            let pixels: [Rgba<u8>; 16] = std::array::from_fn(|i| {
+                let x = (block_x + i as u32 % 4).min(width - 1);
+                let y = (block_y + i as u32 / 4).min(height - 1);
+                *image.get_pixel(x, y)
+            });
+            match format {
+                DXTFormat::DXT1 | DXTFormat::DXT1OneBitAlpha => {},
+                DXTFormat::DXT3 => encode_explicit_alpha(&pixels, &mut data),
+                DXTFormat::DXT5 => encode_interpolated_alpha(&pixels, &mut data),
+            }
+            encode_color_block(&pixels, &mut data);
+        }
+    }
+
+    data
+}
+
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+    use super::{decode, encode, DXTFormat};
+
+    /// Color block with indices for the first 4 pixels.
+    fn color_block(c0: u16, c1: u16, indices: [u8; 4]) -> Vec<u8> {
+        let indices = indices.iter().enumerate().fold(0u32, |all, (i, index)| all | (*index as u32) << (i * 2));
+        [c0.to_le_bytes(), c1.to_le_bytes()].concat().into_iter().chain(indices.to_le_bytes()).collect()
+    }
+
+    fn first_pixels(image: &RgbaImage) -> Vec<[u8; 4]> {
+        (0..4).map(|x| image.get_pixel(x, 0).0).collect()
+    }
+
+    #[test]
+    fn dxt1_color_modes() {
+        let four = color_block(0xFFFF, 0x0000, [0, 1, 2, 3]);
+        assert_eq!(first_pixels(&decode(DXTFormat::DXT1, 4, 4, &four)), [[255, 255, 255, 255], [0, 0, 0, 255], [170, 170, 170, 255], [85, 85, 85, 255]]);
+
+        // First color not greater is 3 colors & black, which is transparent with one bit alpha.
+        let three = color_block(0x0000, 0xFFFF, [0, 1, 2, 3]);
+        assert_eq!(first_pixels(&decode(DXTFormat::DXT1, 4, 4, &three)), [[0, 0, 0, 255], [255, 255, 255, 255], [128, 128, 128, 255], [0, 0, 0, 255]]);
+        assert_eq!(decode(DXTFormat::DXT1OneBitAlpha, 4, 4, &three).get_pixel(3, 0).0, [0, 0, 0, 0]);
+        assert_eq!(decode(DXTFormat::DXT1OneBitAlpha, 4, 4, &four).get_pixel(3, 0).0, [85, 85, 85, 255]);
+    }
+
+    #[test]
+    fn dxt3_explicit_alpha() {
+        // 3 color mode does not exist for DXT3 & DXT5.
+        let mut block = vec![0x10, 0xF8, 0, 0, 0, 0, 0, 0];
+        block.extend(color_block(0x0000, 0xFFFF, [3, 3, 3, 3]));
+        let alphas = first_pixels(&decode(DXTFormat::DXT3, 4, 4, &block)).iter().map(|pixel| pixel[3]).collect::<Vec<_>>();
+        assert_eq!(alphas, [0, 17, 136, 255]);
+        assert_eq!(decode(DXTFormat::DXT3, 4, 4, &block).get_pixel(0, 0).0, [170, 170, 170, 0]);
+    }
+
+    #[test]
+    fn dxt5_alpha_palettes() {
+        let alpha_block = |a0: u8, a1: u8, indices: [u64; 4]| {
+            let indices = indices.iter().enumerate().fold(0u64, |all, (i, index)| all | index << (i * 3));
+            let mut block = vec![a0, a1];
+            block.extend_from_slice(&indices.to_le_bytes()[..6]);
+            block.extend(color_block(0xFFFF, 0xFFFF, [0; 4]));
+            block
+        };
+        let alphas = |block: Vec<u8>| first_pixels(&decode(DXTFormat::DXT5, 4, 4, &block)).iter().map(|pixel| pixel[3]).collect::<Vec<_>>();
+
+        // 8 alphas when the first alpha is greater.
+        assert_eq!(alphas(alpha_block(255, 0, [0, 1, 2, 7])), [255, 0, 219, 36]);
+        // Otherwise 6 alphas, with 0 & 255.
+        assert_eq!(alphas(alpha_block(0, 255, [2, 5, 6, 7])), [51, 204, 0, 255]);
+    }
+
+    #[test]
+    fn dxt_partial_blocks() {
+        // 5x3 is 2 blocks, pixels past the edge are dropped.
+        let mut data = color_block(0xF800, 0xF800, [0; 4]);
+        data.extend(color_block(0x001F, 0x001F, [0; 4]));
+        let image = decode(DXTFormat::DXT1, 5, 3, &data);
+        assert_eq!(image.dimensions(), (5, 3));
+        assert_eq!(image.get_pixel(3, 2).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(4, 2).0, [0, 0, 255, 255]);
+        assert_eq!(DXTFormat::DXT5.byte_size(5, 3), 32);
+
+        let image = RgbaImage::from_fn(5, 3, |x, _| if x < 4 { Rgba([255, 0, 0, 255]) } else { Rgba([0, 0, 255, 255]) });
+        assert_eq!(decode(DXTFormat::DXT1, 5, 3, &encode(DXTFormat::DXT1, &image)), image);
+    }
+}
@@ -0,0 +1,503 @@
+// https://developer.valvesoftware.com/wiki/KeyValues
+
+use std::{collections::HashSet, error::Error, fmt, iter::Peekable, str::Chars};
+use serde_json::{Map, Value};
+
+
+
+#[derive(Debug)]
+pub enum KeyValuesError {
+    UnexpectedToken(usize, String),
+    UnexpectedEnd,
+    UnterminatedString(usize),
+    IncludeDepth(String),
+    BlockDepth(usize),
+}
+
+impl fmt::Display for KeyValuesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedToken(line, token) => write!(f, "KeyValues has unexpected \"{}\" on line {}.", token, line),
+            Self::UnexpectedEnd => write!(f, "KeyValues ends before its blocks are closed."),
+            Self::UnterminatedString(line) => write!(f, "KeyValues string on line {} is not terminated.", line),
+            Self::IncludeDepth(path) => write!(f, "KeyValues includes too deep at \"{}\".", path),
+            Self::BlockDepth(line) => write!(f, "KeyValues block on line {} is nested too deep.", line),
+        }
+    }
+}
+
+impl Error for KeyValuesError { }
+
+
+
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyValue {
+    String(String),
+    Block(KeyValues),
+}
+
+impl KeyValue {
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            KeyValue::String(str) => Some(str),
+            KeyValue::Block(_) => None,
+        }
+    }
+
+    pub fn as_block(&self) -> Option<&KeyValues> {
+        match self {
+            KeyValue::String(_) => None,
+            KeyValue::Block(block) => Some(block),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        match self {
+            KeyValue::String(str) => Value::String(str.clone()),
+            KeyValue::Block(block) => block.to_json(),
+        }
+    }
+
+}
+
+/// Keys & values in order, keys may repeat & are compared case insensitively.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct KeyValues {
+    pub entries: Vec<(String, KeyValue)>,
+}
+
+impl KeyValues {
+
+    pub fn new() -> Self {
+        Self { entries: Vec::new() }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(String, KeyValue)> {
+        self.entries.iter()
+    }
+
+    /// First value of the key.
+    pub fn get(&self, key: &str) -> Option<&KeyValue> {
+        self.entries.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)).map(|(_, value)| value)
+    }
+
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.get(key).and_then(KeyValue::as_str)
+    }
+
+    pub fn get_block(&self, key: &str) -> Option<&KeyValues> {
+        self.get(key).and_then(KeyValue::as_block)
+    }
+
+    pub fn push(&mut self, key: String, value: KeyValue) {
+        self.entries.push((key, value));
+    }
+
+    /// Replace the first value of the key, or add it.
+    pub fn set(&mut self, key: String, value: KeyValue) {
+        match self.entries.iter_mut().find(|(k, _)| k.eq_ignore_ascii_case(&key)) {
+            Some((_, v)) => *v = value,
+            None => self.entries.push((key, value)),
+        }
+    }
+
+    /// Add keys of a `#base` file that are missing, blocks in both are merged.
+    pub fn merge_base(&mut self, base: KeyValues) {
+        for (key, value) in base.entries {
+            let existing = self.entries.iter_mut().find(|(k, _)| k.eq_ignore_ascii_case(&key));
+            match (existing, value) {
+                (Some((_, KeyValue::Block(block))), KeyValue::Block(base)) => block.merge_base(base),
+                (Some(_), _) => {},
+                (None, value) => self.entries.push((key, value)),
+            }
+        }
+    }
+
+    /// Convert to a JSON object, repeated keys are an array of their values.
+    pub fn to_json(&self) -> Value {
+        let mut map = Map::new();
+        for (key, value) in &self.entries {
+            let value = value.to_json();
+            match map.get_mut(key) {
+                // Values are never arrays, so an array is from a repeated key.
+                Some(Value::Array(values)) => values.push(value),
+                Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+                None => { map.insert(key.clone(), value); },
+            }
+        }
+        Value::Object(map)
+    }
+
+}
+
+
+
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    String(String),
+    Open,
+    Close,
+    Conditional(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::String(str) => write!(f, "{}", str),
+            Token::Open => write!(f, "{{"),
+            Token::Close => write!(f, "}}"),
+            Token::Conditional(condition) => write!(f, "[{}]", condition),
+        }
+    }
+}
+
+/// Tokens of KeyValues text, with the line of each token.
+struct Tokenizer<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    escapes: bool,
+}
+
+impl<'a> Tokenizer<'a> {
+
+    fn new(text: &'a str, escapes: bool) -> Self {
+        Self { chars: text.trim_start_matches('\u{FEFF}').chars().peekable(), line: 1, escapes }
+    }
+
+    fn skip_whitespace(&mut self) {
+        loop {
+            match self.chars.peek() {
+                Some('\n') => {
+                    self.line += 1;
+                    self.chars.next();
+                },
+                Some(char) if char.is_whitespace() => { self.chars.next(); },
+                Some('/') => {
+                    let mut lookahead = self.chars.clone();
+                    lookahead.next();
+                    if lookahead.peek() != Some(&'/') {
+                        return;
+                    }
+                    while self.chars.peek().is_some_and(|char| *char != '\n') {
+                        self.chars.next();
+                    }
+                },
+                _ => return,
+            }
+        }
+    }
+
+    fn quoted(&mut self) -> Result<String, KeyValuesError> {
+        let line = self.line;
+        let mut str = String::new();
+        loop {
+            match self.chars.next() {
+                None => return Err(KeyValuesError::UnterminatedString(line)),
+                Some('"') => return Ok(str),
+                Some('\\') if self.escapes => match self.chars.next() {
+                    Some('n') => str.push('\n'),
+                    Some('t') => str.push('\t'),
+                    Some('\\') => str.push('\\'),
+                    Some('"') => str.push('"'),
+                    Some(char) => {
+                        str.push('\\');
+                        str.push(char);
+                    },
+                    None => return Err(KeyValuesError::UnterminatedString(line)),
+                },
+                Some(char) => {
+                    if char == '\n' {
+                        self.line += 1;
+                    }
+                    str.push(char);
+                },
+            }
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<(usize, Token)>, KeyValuesError> {
+        self.skip_whitespace();
+        let line = self.line;
+        let token = match self.chars.next() {
+            None => return Ok(None),
+            Some('{') => Token::Open,
+            Some('}') => Token::Close,
+            Some('"') => Token::String(self.quoted()?),
+            Some('[') => {
+                let mut condition = String::new();
+                loop {
+                    match self.chars.next() {
+                        Some(']') => break,
+                        Some('\n') | None => return Err(KeyValuesError::UnexpectedToken(line, format!("[{}", condition))),
+                        Some(char) => condition.push(char),
+                    }
+                }
+                Token::Conditional(condition)
+            },
+            Some(char) => {
+                let mut str = char.to_string();
+                while let Some(char) = self.chars.peek() {
+                    if char.is_whitespace() || matches!(char, '{' | '}' | '"' | '[') {
+                        break;
+                    }
+                    str.push(*char);
+                    self.chars.next();
+                }
+                Token::String(str)
+            },
+        };
+        Ok(Some((line, token)))
+    }
+
+}
+
+
+
+pub type KeyValuesInclude = Box<dyn FnMut(&str) -> Result<Option<String>, Box<dyn Error>>>;
+
+/// Includes deeper than this are assumed to include themselves.
+const MAX_INCLUDE_DEPTH: usize = 32;
+/// Blocks nested deeper than this are an error, instead of overflowing the stack.
+const MAX_BLOCK_DEPTH: usize = 256;
+
+/// Parser for KeyValues text, such as `.vmt`, `.res` & `gameinfo.txt` files.
+pub struct KeyValuesParser {
+    escapes: bool,
+    conditions: HashSet<String>,
+    include: Option<KeyValuesInclude>,
+}
+
+impl fmt::Debug for KeyValuesParser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyValuesParser")
+            .field("escapes", &self.escapes)
+            .field("conditions", &self.conditions)
+            .field("include", &self.include.is_some())
+            .finish()
+    }
+}
+
+impl Default for KeyValuesParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyValuesParser {
+
+    /// Parser for Windows, escape sequences are off like in Source.
+    pub fn new() -> Self {
+        Self {
+            escapes: false,
+            conditions: HashSet::from(["$WIN32".to_owned(), "$WINDOWS".to_owned()]),
+            include: None,
+        }
+    }
+
+    /// Read `\n`, `\t`, `\\` & `\"` in strings, which would otherwise break paths with backslashes.
+    pub fn escapes(mut self, escapes: bool) -> Self {
+        self.escapes = escapes;
+        self
+    }
+
+    /// Conditions that are true in `[$CONDITION]`, such as `$WIN32` or `$X360`.
+    pub fn conditions<S: AsRef<str>>(mut self, conditions: impl IntoIterator<Item = S>) -> Self {
+        self.conditions = conditions.into_iter().map(|condition| condition.as_ref().to_uppercase()).collect();
+        self
+    }
+
+    /// Load files of `#include` & `#base`, missing files are `None` & ignored.
+    ///
+    /// Without it, the directives are kept as keys.
+    pub fn include(mut self, include: impl FnMut(&str) -> Result<Option<String>, Box<dyn Error>> + 'static) -> Self {
+        self.include = Some(Box::new(include));
+        self
+    }
+
+    /// Evaluate `[$A || !$B && $C]`, where `&&` comes before `||`.
+    fn evaluate(&self, condition: &str) -> bool {
+        condition.split("||").any(|all| all.split("&&").all(|term| {
+            let term = term.trim();
+            match term.strip_prefix('!') {
+                Some(term) => !self.conditions.contains(&term.trim().to_uppercase()),
+                None => self.conditions.contains(&term.to_uppercase()),
+            }
+        }))
+    }
+
+    fn expect(tokenizer: &mut Tokenizer) -> Result<(usize, Token), KeyValuesError> {
+        tokenizer.next()?.ok_or(KeyValuesError::UnexpectedEnd)
+    }
+
+    /// Entries until the end of the block, or the end of the text for the root at nesting 0.
+    /// Depth counts includes, which are only allowed in the root.
+    fn parse_block(&mut self, tokenizer: &mut Tokenizer, nesting: usize, depth: usize) -> Result<KeyValues, Box<dyn Error>> {
+        let root = nesting == 0;
+        let mut block = KeyValues::new();
+        let mut bases = Vec::new();
+
+        loop {
+            let key = match tokenizer.next()? {
+                None if root => break,
+                None => return Err(Box::new(KeyValuesError::UnexpectedEnd)),
+                Some((_, Token::Close)) if !root => break,
+                Some((_, Token::String(key))) => key,
+                Some((line, token)) => return Err(Box::new(KeyValuesError::UnexpectedToken(line, token.to_string()))),
+            };
+
+            let (mut line, mut token) = Self::expect(tokenizer)?;
+            let mut enabled = true;
+            if let Token::Conditional(condition) = &token {
+                enabled = self.evaluate(condition);
+                (line, token) = Self::expect(tokenizer)?;
+            }
+
+            let value = match token {
+                Token::Open if nesting >= MAX_BLOCK_DEPTH => return Err(Box::new(KeyValuesError::BlockDepth(line))),
+                Token::Open => KeyValue::Block(self.parse_block(tokenizer, nesting + 1, depth)?),
+                Token::String(value) => KeyValue::String(value),
+                token => return Err(Box::new(KeyValuesError::UnexpectedToken(line, token.to_string()))),
+            };
+
+            // Conditional after the value.
+            let mut lookahead = Tokenizer { chars: tokenizer.chars.clone(), line: tokenizer.line, escapes: tokenizer.escapes };
+            if let Some((_, Token::Conditional(condition))) = lookahead.next()? {
+                enabled &= self.evaluate(&condition);
+                *tokenizer = lookahead;
+            }
+            if !enabled {
+                continue;
+            }
+
+            match (root, key.to_lowercase().as_str(), &value, self.include.is_some()) {
+                (true, directive @ ("#include" | "#base"), KeyValue::String(path), true) => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(Box::new(KeyValuesError::IncludeDepth(path.clone())));
+                    }
+                    let Some(text) = self.include.as_mut().map(|include| include(path)).transpose()?.flatten() else {
+                        continue;
+                    };
+                    let included = self.parse_block(&mut Tokenizer::new(&text, self.escapes), 0, depth + 1)?;
+                    if directive == "#include" {
+                        block.entries.extend(included.entries);
+                    } else {
+                        bases.push(included);
+                    }
+                },
+                _ => block.push(key, value),
+            }
+        }
+
+        for base in bases {
+            block.merge_base(base);
+        }
+        Ok(block)
+    }
+
+    pub fn parse(&mut self, text: &str) -> Result<KeyValues, Box<dyn Error>> {
+        self.parse_block(&mut Tokenizer::new(text, self.escapes), 0, 0)
+    }
+
+}
+
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, error::Error};
+    use serde_json::json;
+    use super::{KeyValuesError, KeyValuesParser};
+
+    fn keys(parser: &mut KeyValuesParser, text: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let keyvalues = parser.parse(text)?;
+        Ok(keyvalues.get_block("root").ok_or("missing root")?.iter().map(|(key, _)| key.clone()).collect())
+    }
+
+    #[test]
+    fn keyvalues_conditions() -> Result<(), Box<dyn Error>> {
+        let text = r#"
+            "root"
+            {
+                "a" "1" [$WIN32]
+                "b" [$X360] "2"
+                "c" "3" [!$X360 && $WIN32]
+                "d" "4" [$X360 || $OSX]
+                "e" [!$WIN32] { "x" "y" }
+                "f" "5" // [$X360]
+            }
+        "#;
+        assert_eq!(keys(&mut KeyValuesParser::new(), text)?, ["a", "c", "f"]);
+        assert_eq!(keys(&mut KeyValuesParser::new().conditions(["$x360"]), text)?, ["b", "d", "e", "f"]);
+        Ok(())
+    }
+
+    #[test]
+    fn keyvalues_includes() -> Result<(), Box<dyn Error>> {
+        let files = HashMap::from([
+            ("included.vmt", r#""included" "1""#),
+            ("base.vmt", r#""root" { "a" "base" "b" "base" } "extra" "1""#),
+        ]);
+        let text = r#"
+            #include "included.vmt"
+            #base "base.vmt"
+            #include "missing.vmt"
+            "root" { "a" "main" }
+        "#;
+
+        // `#include` adds everything, `#base` only adds what is missing.
+        let mut parser = KeyValuesParser::new().include(move |path| Ok(files.get(path).map(|text| text.to_string())));
+        assert_eq!(parser.parse(text)?.to_json(), json!({ "included": "1", "root": { "a": "main", "b": "base" }, "extra": "1" }));
+
+        // Without loading includes they are kept as keys.
+        let keyvalues = KeyValuesParser::new().parse(text)?;
+        assert_eq!(keyvalues.iter().map(|(key, _)| key.as_str()).collect::<Vec<_>>(), ["#include", "#base", "#include", "root"]);
+
+        let mut parser = KeyValuesParser::new().include(|_| Ok(Some(r#"#include "self.vmt""#.to_owned())));
+        let error = parser.parse(r#"#include "self.vmt""#).unwrap_err();
+        assert!(matches!(error.downcast_ref::<KeyValuesError>(), Some(KeyValuesError::IncludeDepth(path)) if path == "self.vmt"));
+        Ok(())
+    }
+
+    #[test]
+    fn keyvalues_escapes() -> Result<(), Box<dyn Error>> {
+        let text = r#""path" "C:\new\table" "quote" "say \"hi\"""#;
+        let keyvalues = KeyValuesParser::new().escapes(true).parse(text)?;
+        assert_eq!(keyvalues.get_str("PATH"), Some("C:\new\table"));
+        assert_eq!(keyvalues.get_str("quote"), Some("say \"hi\""));
+
+        // Backslashes are kept, so the quote after one ends the string.
+        let keyvalues = KeyValuesParser::new().parse(text)?;
+        assert_eq!(keyvalues.get_str("path"), Some(r"C:\new\table"));
+        assert_eq!(keyvalues.get_str("quote"), Some(r"say \"));
+        Ok(())
+    }
+
+    #[test]
+    fn keyvalues_to_json() -> Result<(), Box<dyn Error>> {
+        let keyvalues = KeyValuesParser::new().parse(r#""a" "1" "a" "2" b { c 1 } "a" "3""#)?;
+        assert_eq!(keyvalues.to_json(), json!({ "a": ["1", "2", "3"], "b": { "c": "1" } }));
+        assert_eq!(keyvalues.get_str("a"), Some("1"));
+
+        assert!(matches!(KeyValuesParser::new().parse("\"a\"\n}").unwrap_err().downcast_ref(), Some(KeyValuesError::UnexpectedToken(2, _))));
+        assert!(matches!(KeyValuesParser::new().parse("a { b c").unwrap_err().downcast_ref(), Some(KeyValuesError::UnexpectedEnd)));
+        Ok(())
+    }
+
+    #[test]
+    fn keyvalues_block_depth() -> Result<(), Box<dyn Error>> {
+        let nested = |depth: usize| format!("{}b c{}", "a {\n".repeat(depth), "}".repeat(depth));
+        let keyvalues = KeyValuesParser::new().parse(&nested(256))?;
+        assert!(keyvalues.get_block("a").is_some());
+
+        let error = KeyValuesParser::new().parse(&nested(200_000)).unwrap_err();
+        assert!(matches!(error.downcast_ref::<KeyValuesError>(), Some(KeyValuesError::BlockDepth(257))));
+        Ok(())
+    }
+}
@@ -1,2 +1,6 @@
 
+pub mod keyvalues;
+pub mod vmt;
 pub mod vtf;
+pub mod vtf_writer;
+mod dxt;
@@ -0,0 +1,214 @@
+// https://developer.valvesoftware.com/wiki/Material
+
+use std::{error::Error, fmt};
+use serde_json::{json, Value};
+use super::keyvalues::{KeyValue, KeyValues, KeyValuesParser};
+
+
+
+#[derive(Debug)]
+pub enum VMTError {
+    MissingShader,
+    PatchDepth(String),
+}
+
+impl fmt::Display for VMTError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingShader => write!(f, "VMT has no shader block."),
+            Self::PatchDepth(path) => write!(f, "VMT patches too deep at \"{}\".", path),
+        }
+    }
+}
+
+impl Error for VMTError { }
+
+
+
+/// Patches deeper than this are assumed to include themselves.
+const MAX_PATCH_DEPTH: usize = 32;
+
+/// Material, a shader with its parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VMT {
+    pub shader: String,
+    pub parameters: KeyValues,
+}
+
+impl VMT {
+
+    /// Parameters of shaders that are textures.
+    pub const TEXTURE_PARAMETERS: [&'static str; 16] = [
+        "$basetexture", "$basetexture2", "$bumpmap", "$bumpmap2", "$normalmap", "$detail",
+        "$envmap", "$envmapmask", "$selfillummask", "$phongexponenttexture", "$lightwarptexture",
+        "$blendmodulatetexture", "$iris", "$ambientoccltexture", "$tintmasktexture", "%tooltexture",
+    ];
+
+    /// The first block is the shader, anything after it is ignored like in Source.
+    pub fn from_keyvalues(keyvalues: KeyValues) -> Result<Self, Box<dyn Error>> {
+        let (shader, parameters) = keyvalues.entries.into_iter()
+            .find_map(|(key, value)| match value {
+                KeyValue::Block(block) => Some((key, block)),
+                KeyValue::String(_) => None,
+            })
+            .ok_or(VMTError::MissingShader)?;
+        Ok(Self { shader, parameters })
+    }
+
+    pub fn parse(parser: &mut KeyValuesParser, text: &str) -> Result<Self, Box<dyn Error>> {
+        Self::from_keyvalues(parser.parse(text)?)
+    }
+
+    /// Material that a `patch` material changes.
+    pub fn patch_include(&self) -> Option<&str> {
+        match self.shader.eq_ignore_ascii_case("patch") {
+            true => self.parameters.get_str("include"),
+            false => None,
+        }
+    }
+
+    /// Apply a `patch` material, its `insert` block adds or changes parameters & `replace` only changes existing ones.
+    pub fn patch(&self, mut base: VMT) -> VMT {
+        if let Some(insert) = self.parameters.get_block("insert") {
+            for (key, value) in insert.iter() {
+                base.parameters.set(key.clone(), value.clone());
+            }
+        }
+        if let Some(replace) = self.parameters.get_block("replace") {
+            for (key, value) in replace.iter() {
+                if base.parameters.get(key).is_some() {
+                    base.parameters.set(key.clone(), value.clone());
+                }
+            }
+        }
+        base
+    }
+
+    /// Resolve `patch` materials, `load` returns the material of a path from [`material_path`].
+    pub fn resolve(self, mut load: impl FnMut(&str) -> Result<Option<VMT>, Box<dyn Error>>) -> Result<VMT, Box<dyn Error>> {
+        let mut patches = Vec::new();
+        let mut material = self;
+        while let Some(include) = material.patch_include() {
+            if patches.len() >= MAX_PATCH_DEPTH {
+                return Err(Box::new(VMTError::PatchDepth(include.to_owned())));
+            }
+            let Some(base) = load(&material_path(include))? else {
+                break;
+            };
+            patches.push(material);
+            material = base;
+        }
+        Ok(patches.into_iter().rev().fold(material, |base, patch| patch.patch(base)))
+    }
+
+    /// Texture parameters with the paths of their VTFs, from [`texture_path`].
+    pub fn textures(&self) -> Vec<(&str, String)> {
+        self.parameters.iter()
+            .filter(|(key, _)| Self::TEXTURE_PARAMETERS.iter().any(|param| key.eq_ignore_ascii_case(param)))
+            .filter_map(|(key, value)| Some((key.as_str(), value.as_str()?)))
+            // Cubemaps of the map, not a texture.
+            .filter(|(_, value)| !value.eq_ignore_ascii_case("env_cubemap"))
+            .map(|(key, value)| (key, texture_path(value)))
+            .collect()
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "shader": self.shader,
+            "parameters": self.parameters.to_json(),
+            "textures": self.textures().into_iter().map(|(key, path)| (key.to_owned(), Value::String(path))).collect::<serde_json::Map<_, _>>(),
+        })
+    }
+
+}
+
+
+
+fn normalize_path(path: &str) -> String {
+    let path = path.trim().to_lowercase().replace('\\', "/");
+    let path = path.trim_start_matches('/');
+    match path.starts_with("materials/") {
+        true => path.to_owned(),
+        false => format!("materials/{}", path),
+    }
+}
+
+/// Path of a texture in the game files, textures are relative to `materials/` & may leave out `.vtf`.
+pub fn texture_path(texture: &str) -> String {
+    let path = normalize_path(texture);
+    match path.ends_with(".vtf") {
+        true => path,
+        false => format!("{}.vtf", path),
+    }
+}
+
+/// Path of a material in the game files, like [`texture_path`] for `.vmt`.
+pub fn material_path(material: &str) -> String {
+    let path = normalize_path(material);
+    match path.ends_with(".vmt") {
+        true => path,
+        false => format!("{}.vmt", path),
+    }
+}
+
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use super::{super::keyvalues::KeyValuesParser, material_path, texture_path, VMTError, VMT};
+
+    fn parse(text: &str) -> Result<VMT, Box<dyn Error>> {
+        VMT::parse(&mut KeyValuesParser::new(), text)
+    }
+
+    #[test]
+    fn vmt_resolve_patch() -> Result<(), Box<dyn Error>> {
+        let patch = parse(r#"
+            "Patch"
+            {
+                "include" "Materials\Base"
+                "insert" { "$detail" "detail/noise" "$basetexture" "inserted" }
+                "replace" { "$bumpmap" "replaced" "$missing" "x" }
+            }
+        "#)?;
+        let mut loaded = Vec::new();
+        let material = patch.resolve(|path| {
+            loaded.push(path.to_owned());
+            parse(r#""LightmappedGeneric" { "$basetexture" "old" "$envmap" "env_cubemap" "$bumpmap" "Models\Bump.vtf" }"#).map(Some)
+        })?;
+        assert_eq!(loaded, ["materials/base.vmt"]);
+        assert_eq!(material.shader, "LightmappedGeneric");
+        assert_eq!(material.parameters.get_str("$basetexture"), Some("inserted"));
+        assert_eq!(material.parameters.get_str("$bumpmap"), Some("replaced"));
+        assert_eq!(material.parameters.get("$missing"), None);
+
+        // Cubemaps of the map are not textures.
+        assert_eq!(material.textures(), [
+            ("$basetexture", "materials/inserted.vtf".to_owned()),
+            ("$bumpmap", "materials/replaced.vtf".to_owned()),
+            ("$detail", "materials/detail/noise.vtf".to_owned()),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn vmt_patch_depth() -> Result<(), Box<dyn Error>> {
+        let patch = || parse(r#""patch" { "include" "materials/self.vmt" }"#);
+        let error = patch()?.resolve(|_| patch().map(Some)).unwrap_err();
+        assert!(matches!(error.downcast_ref::<VMTError>(), Some(VMTError::PatchDepth(_))));
+
+        // Missing base materials leave the patch as it is.
+        assert_eq!(patch()?.resolve(|_| Ok(None))?, patch()?);
+        assert!(matches!(parse(r#""$basetexture" "a""#).unwrap_err().downcast_ref::<VMTError>(), Some(VMTError::MissingShader)));
+        Ok(())
+    }
+
+    #[test]
+    fn vmt_paths() {
+        assert_eq!(material_path("/Materials/Dev/Dev_Measure.vmt"), "materials/dev/dev_measure.vmt");
+        assert_eq!(texture_path(r"dev\dev_measure"), "materials/dev/dev_measure.vtf");
+    }
+}
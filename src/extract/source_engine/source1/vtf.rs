@@ -1,17 +1,58 @@
 
 // https://developer.valvesoftware.com/wiki/VTF_(Valve_Texture_Format)
 
-use std::{error::Error, io::{Read, Seek, SeekFrom}};
+use std::{error::Error, fmt, io::{Cursor, Read, Seek, SeekFrom}};
 use crate::util::read_ext::ReadExt;
 use bitflags::bitflags;
-use image::{Rgba, RgbaImage};
+use half::f16;
+use image::{DynamicImage, ImageFormat, Rgba, Rgba32FImage, RgbaImage};
+use super::dxt::{self, DXTFormat};
+
+
+
+
+
+#[derive(Debug)]
+pub enum VTFError {
+    InvalidMagic,
+    UnsupportedVersion(u32, u32),
+    MissingResource(&'static str),
+    UnknownFormat(i32),
+    TextureTooShort,
+    MissingTexture,
+    NotCubemap,
+    CannotEncode(TextureFormat),
+    ImageCount(usize),
+    ImageSize,
+    MipmapCount(u8),
+}
+
+impl fmt::Display for VTFError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidMagic => write!(f, "File is not a VTF."),
+            Self::UnsupportedVersion(major, minor) => write!(f, "Unsupported VTF version {}.{}.", major, minor),
+            Self::MissingResource(resource) => write!(f, "VTF is missing the {} resource.", resource),
+            Self::UnknownFormat(format) => write!(f, "Unknown VTF texture format {}.", format),
+            Self::TextureTooShort => write!(f, "VTF texture data is shorter than its size."),
+            Self::MissingTexture => write!(f, "VTF does not contain the texture."),
+            Self::NotCubemap => write!(f, "VTF is not a cubemap."),
+            Self::CannotEncode(format) => write!(f, "Cannot encode VTF texture format {:?}.", format),
+            Self::ImageCount(count) => write!(f, "Cannot write VTF from {} images, cubemaps need 6 images per frame.", count),
+            Self::ImageSize => write!(f, "Images of a VTF must be the same size, at most 65535 pixels."),
+            Self::MipmapCount(mipmaps) => write!(f, "VTF has {} mipmaps, more than its size can be halved.", mipmaps),
+        }
+    }
+}
+
+impl Error for VTFError { }
 
 
 
 
 
 bitflags! {
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy)]
     pub struct TextureFlags: u32 {
         // Flags from the *.txt config file
         const POINTSAMPLE = 0x00000001;
@@ -100,7 +141,6 @@ impl TextureFormat {
 
     pub fn new(value: i32) -> Result<TextureFormat, Box<dyn Error>> {
         Ok(match value {
-            -1 => panic!("Texture format NONE."),
             0 => TextureFormat::IMAGE_FORMAT_RGBA8888,
             1 => TextureFormat::IMAGE_FORMAT_ABGR8888,
             2 => TextureFormat::IMAGE_FORMAT_RGB888,
@@ -128,15 +168,27 @@ impl TextureFormat {
             24 => TextureFormat::IMAGE_FORMAT_RGBA16161616F,
             25 => TextureFormat::IMAGE_FORMAT_RGBA16161616,
             26 => TextureFormat::IMAGE_FORMAT_UVLX8888,
-            _ => panic!("Unknown texture format."),
+            format => return Err(Box::new(VTFError::UnknownFormat(format))),
         })
     }
 
-    pub fn byte_size(&self, width: u32, height: u32) -> u32 {
+    /// Block compressed format, which is stored in 4x4 blocks.
+    pub(super) fn dxt_format(&self) -> Option<DXTFormat> {
+        match self {
+            TextureFormat::IMAGE_FORMAT_DXT1 => Some(DXTFormat::DXT1),
+            TextureFormat::IMAGE_FORMAT_DXT1_ONEBITALPHA => Some(DXTFormat::DXT1OneBitAlpha),
+            TextureFormat::IMAGE_FORMAT_DXT3 => Some(DXTFormat::DXT3),
+            TextureFormat::IMAGE_FORMAT_DXT5 => Some(DXTFormat::DXT5),
+            _ => None,
+        }
+    }
+
+    /// Bytes per pixel of uncompressed formats.
+    fn pixel_size(&self) -> usize {
         match self {
             TextureFormat::IMAGE_FORMAT_RGBA16161616F |
-            TextureFormat::IMAGE_FORMAT_RGBA16161616 
-                => width * height * 8,
+            TextureFormat::IMAGE_FORMAT_RGBA16161616
+                => 8,
             TextureFormat::IMAGE_FORMAT_RGBA8888 |
             TextureFormat::IMAGE_FORMAT_ABGR8888 |
             TextureFormat::IMAGE_FORMAT_ARGB8888 |
@@ -144,12 +196,12 @@ impl TextureFormat {
             TextureFormat::IMAGE_FORMAT_BGRX8888 |
             TextureFormat::IMAGE_FORMAT_UVWQ8888 |
             TextureFormat::IMAGE_FORMAT_UVLX8888
-                => width * height * 4,
+                => 4,
             TextureFormat::IMAGE_FORMAT_RGB888 |
             TextureFormat::IMAGE_FORMAT_BGR888 |
             TextureFormat::IMAGE_FORMAT_RGB888_BLUESCREEN |
             TextureFormat::IMAGE_FORMAT_BGR888_BLUESCREEN
-                => width * height * 3,
+                => 3,
             TextureFormat::IMAGE_FORMAT_RGB565 |
             TextureFormat::IMAGE_FORMAT_IA88 |
             TextureFormat::IMAGE_FORMAT_BGR565 |
@@ -157,32 +209,47 @@ impl TextureFormat {
             TextureFormat::IMAGE_FORMAT_BGRA4444 |
             TextureFormat::IMAGE_FORMAT_BGRA5551 |
             TextureFormat::IMAGE_FORMAT_UV88
-                => width * height * 2,
+                => 2,
             TextureFormat::IMAGE_FORMAT_I8 |
             TextureFormat::IMAGE_FORMAT_P8 |
             TextureFormat::IMAGE_FORMAT_A8
-                => width * height,
+                => 1,
             TextureFormat::IMAGE_FORMAT_DXT1 |
             TextureFormat::IMAGE_FORMAT_DXT1_ONEBITALPHA |
             TextureFormat::IMAGE_FORMAT_DXT3 |
             TextureFormat::IMAGE_FORMAT_DXT5
-                => {
-                    // Texture is encoded into 4x4 blocks, size must be multiple of 4.
-                    let width = width + (width % 4);
-                    let height = height + (height % 4);
-                    match self {
-                        TextureFormat::IMAGE_FORMAT_DXT1 |
-                        TextureFormat::IMAGE_FORMAT_DXT1_ONEBITALPHA
-                            => (width * height) / 2,
-                        TextureFormat::IMAGE_FORMAT_DXT3 |
-                        TextureFormat::IMAGE_FORMAT_DXT5
-                            => width * height,
-                        _ => panic!("Error"),
-                    }
-                },
+                => 0,
         }
     }
 
+    /// Formats with a higher range than 8 bit.
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, TextureFormat::IMAGE_FORMAT_RGBA16161616F | TextureFormat::IMAGE_FORMAT_RGBA16161616)
+    }
+
+    pub fn byte_size(&self, width: u32, height: u32) -> usize {
+        match self.dxt_format() {
+            Some(format) => format.byte_size(width, height),
+            None => width as usize * height as usize * self.pixel_size(),
+        }
+    }
+
+}
+
+
+
+/// Expand a channel of `bits` to 8 bits.
+fn expand_bits(value: u16, bits: u32) -> u8 {
+    let max = (1u32 << bits) - 1;
+    ((value as u32 & max) * 255 / max) as u8
+}
+
+/// Tone map a linear HDR channel with Reinhard, then encode as sRGB.
+fn tone_map(value: f32) -> u8 {
+    let value = value.max(0.0);
+    let value = value / (1.0 + value);
+    let value = if value <= 0.0031308 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 };
+    (value * 255.0).round().clamp(0.0, 255.0) as u8
 }
 
 
@@ -200,44 +267,116 @@ pub struct Texture {
 impl Texture {
 
     pub fn new(width: u32, height: u32, format: TextureFormat, data: Vec<u8>) -> Self {
-        if (data.len() as u32) != format.byte_size(width, height) {
-            println!("VTF TEXTURE SIZE DOES NOT MATCH.");
-        }
         Self { width, height, format, data }
     }
 
-    fn convert_pixels<F>(&self, image: &mut RgbaImage, pixel_bytes: usize, mut convert: F)
-    where
-        F: FnMut(&Vec<u8>) -> Rgba<u8>
-    {
-        let mut offset: usize = 0;
-        let mut bytes: Vec<u8> = Vec::new();
+    pub fn width(&self) -> u32 { self.width }
+    pub fn height(&self) -> u32 { self.height }
+    pub fn format(&self) -> TextureFormat { self.format }
 
-        for y in 0..image.height() {
-            for x in 0..image.width() {
-                bytes.resize(pixel_bytes, 0);
-                if offset + pixel_bytes > self.data.len() {
-                    panic!("Convert pixels out of bounds.");
-                }
-                bytes.clone_from_slice(&self.data[offset..(offset + pixel_bytes)]);
-                offset += pixel_bytes;
+    fn convert_pixels(&self, convert: impl Fn(&[u8]) -> Rgba<u8>) -> Result<RgbaImage, Box<dyn Error>> {
+        let pixel_size = self.format.pixel_size();
+        let mut pixels = self.data.chunks_exact(pixel_size);
+        let mut image = RgbaImage::new(self.width, self.height);
+        for pixel in image.pixels_mut() {
+            *pixel = convert(pixels.next().ok_or(VTFError::TextureTooShort)?);
+        }
+        Ok(image)
+    }
 
-                let color = convert(&bytes);
-                image.put_pixel(x, y, color);
+    /// Convert to an 8 bit image.
+    ///
+    /// * Bluescreen formats are transparent where the color is pure blue.
+    /// * P8 has no palette, the index is converted as luminance.
+    /// * Normal map formats are converted as their channels.
+    /// * Floating point HDR is tone mapped.
+    pub fn to_image(&self) -> Result<RgbaImage, Box<dyn Error>> {
+        if let Some(format) = self.format.dxt_format() {
+            if self.data.len() < format.byte_size(self.width, self.height) {
+                return Err(Box::new(VTFError::TextureTooShort));
             }
+            return Ok(dxt::decode(format, self.width, self.height, &self.data));
         }
+
+        let packed = |pixel: &[u8]| u16::from_le_bytes([pixel[0], pixel[1]]);
+        let bluescreen = |r: u8, g: u8, b: u8| Rgba([r, g, b, if (r, g, b) == (0, 0, 255) { 0 } else { 255 }]);
+
+        self.convert_pixels(|pixel| match self.format {
+            TextureFormat::IMAGE_FORMAT_RGBA8888 => Rgba([pixel[0], pixel[1], pixel[2], pixel[3]]),
+            TextureFormat::IMAGE_FORMAT_ABGR8888 => Rgba([pixel[3], pixel[2], pixel[1], pixel[0]]),
+            TextureFormat::IMAGE_FORMAT_ARGB8888 => Rgba([pixel[1], pixel[2], pixel[3], pixel[0]]),
+            TextureFormat::IMAGE_FORMAT_BGRA8888 => Rgba([pixel[2], pixel[1], pixel[0], pixel[3]]),
+            TextureFormat::IMAGE_FORMAT_BGRX8888 => Rgba([pixel[2], pixel[1], pixel[0], 255]),
+            TextureFormat::IMAGE_FORMAT_RGB888 => Rgba([pixel[0], pixel[1], pixel[2], 255]),
+            TextureFormat::IMAGE_FORMAT_BGR888 => Rgba([pixel[2], pixel[1], pixel[0], 255]),
+            TextureFormat::IMAGE_FORMAT_RGB888_BLUESCREEN => bluescreen(pixel[0], pixel[1], pixel[2]),
+            TextureFormat::IMAGE_FORMAT_BGR888_BLUESCREEN => bluescreen(pixel[2], pixel[1], pixel[0]),
+            TextureFormat::IMAGE_FORMAT_RGB565 => {
+                let v = packed(pixel);
+                Rgba([expand_bits(v, 5), expand_bits(v >> 5, 6), expand_bits(v >> 11, 5), 255])
+            },
+            TextureFormat::IMAGE_FORMAT_BGR565 => {
+                let v = packed(pixel);
+                Rgba([expand_bits(v >> 11, 5), expand_bits(v >> 5, 6), expand_bits(v, 5), 255])
+            },
+            TextureFormat::IMAGE_FORMAT_BGRX5551 => {
+                let v = packed(pixel);
+                Rgba([expand_bits(v >> 10, 5), expand_bits(v >> 5, 5), expand_bits(v, 5), 255])
+            },
+            TextureFormat::IMAGE_FORMAT_BGRA5551 => {
+                let v = packed(pixel);
+                Rgba([expand_bits(v >> 10, 5), expand_bits(v >> 5, 5), expand_bits(v, 5), expand_bits(v >> 15, 1)])
+            },
+            TextureFormat::IMAGE_FORMAT_BGRA4444 => {
+                let v = packed(pixel);
+                Rgba([expand_bits(v >> 8, 4), expand_bits(v >> 4, 4), expand_bits(v, 4), expand_bits(v >> 12, 4)])
+            },
+            TextureFormat::IMAGE_FORMAT_I8 | TextureFormat::IMAGE_FORMAT_P8 => Rgba([pixel[0], pixel[0], pixel[0], 255]),
+            TextureFormat::IMAGE_FORMAT_IA88 => Rgba([pixel[0], pixel[0], pixel[0], pixel[1]]),
+            TextureFormat::IMAGE_FORMAT_A8 => Rgba([0, 0, 0, pixel[0]]),
+            TextureFormat::IMAGE_FORMAT_UV88 => Rgba([pixel[0], pixel[1], 0, 255]),
+            TextureFormat::IMAGE_FORMAT_UVWQ8888 | TextureFormat::IMAGE_FORMAT_UVLX8888 => Rgba([pixel[0], pixel[1], pixel[2], pixel[3]]),
+            TextureFormat::IMAGE_FORMAT_RGBA16161616 => Rgba([pixel[1], pixel[3], pixel[5], pixel[7]]),
+            TextureFormat::IMAGE_FORMAT_RGBA16161616F => {
+                let channel = |i: usize| f16::from_le_bytes([pixel[i * 2], pixel[i * 2 + 1]]).to_f32();
+                Rgba([tone_map(channel(0)), tone_map(channel(1)), tone_map(channel(2)), (channel(3).clamp(0.0, 1.0) * 255.0).round() as u8])
+            },
+            TextureFormat::IMAGE_FORMAT_DXT1 |
+            TextureFormat::IMAGE_FORMAT_DXT1_ONEBITALPHA |
+            TextureFormat::IMAGE_FORMAT_DXT3 |
+            TextureFormat::IMAGE_FORMAT_DXT5
+                => unreachable!(),
+        })
     }
 
-    pub fn to_image(&self) -> RgbaImage {
-        let mut image = RgbaImage::new(self.width, self.height);
+    /// Convert to a floating point image, HDR formats keep their range.
+    pub fn to_hdr_image(&self) -> Result<Rgba32FImage, Box<dyn Error>> {
+        let channel: fn([u8; 2]) -> f32 = match self.format {
+            TextureFormat::IMAGE_FORMAT_RGBA16161616F => |bytes| f16::from_le_bytes(bytes).to_f32(),
+            TextureFormat::IMAGE_FORMAT_RGBA16161616 => |bytes| u16::from_le_bytes(bytes) as f32 / 65535.0,
+            _ => return Ok(DynamicImage::ImageRgba8(self.to_image()?).into_rgba32f()),
+        };
+
+        let mut pixels = self.data.chunks_exact(8);
+        let mut image = Rgba32FImage::new(self.width, self.height);
+        for pixel in image.pixels_mut() {
+            let bytes = pixels.next().ok_or(VTFError::TextureTooShort)?;
+            *pixel = Rgba([0, 1, 2, 3].map(|i| channel([bytes[i * 2], bytes[i * 2 + 1]])));
+        }
+        Ok(image)
+    }
 
-        match self.format {
-            TextureFormat::IMAGE_FORMAT_RGBA8888 => self.convert_pixels(&mut image, 4, |pixel| {
-                Rgba([pixel[0], pixel[1], pixel[2], pixel[3]])
-            }),
-            format => panic!("Cannot convert image format. {:?}", format),
+    /// Encode as PNG, or EXR for HDR formats.
+    /// Returns the file extension & encoded image.
+    pub fn encode_image(&self) -> Result<(&'static str, Vec<u8>), Box<dyn Error>> {
+        let mut encoded = Cursor::new(Vec::new());
+        if self.format.is_hdr() {
+            DynamicImage::ImageRgba32F(self.to_hdr_image()?).write_to(&mut encoded, ImageFormat::OpenExr)?;
+            Ok(("exr", encoded.into_inner()))
+        } else {
+            self.to_image()?.write_to(&mut encoded, ImageFormat::Png)?;
+            Ok(("png", encoded.into_inner()))
         }
-        image
     }
 
 }
@@ -278,8 +417,123 @@ impl PartialOrd for VTFVersion {
 
 
 
+/// Sequence of an animated particle sheet.
+#[derive(Debug, Clone)]
+pub struct VTFSheetSequence {
+    pub index: u32,
+    /// Sequence stops at the last frame instead of looping.
+    pub clamp: bool,
+    pub total_time: f32,
+    pub frames: Vec<VTFSheetFrame>,
+}
+
+#[derive(Debug, Clone)]
+pub struct VTFSheetFrame {
+    pub duration: f32,
+    /// Texture coordinates as `[left, top, right, bottom]`, one per image of the frame.
+    pub coords: Vec<[f32; 4]>,
+}
+
+impl VTFSheetSequence {
+
+    /// Sequences of the sheet resource, sheets after version 0 have 4 images per frame.
+    fn load_sheet(mut data: impl Read) -> Result<Vec<Self>, Box<dyn Error>> {
+        let version: u32 = data.read_primitive()?;
+        let images = if version == 0 { 1 } else { 4 };
+
+        let count: u32 = data.read_primitive()?;
+        let mut sequences = Vec::new();
+        for _ in 0..count {
+            let index: u32 = data.read_primitive()?;
+            let clamp = data.read_primitive::<u32>()? != 0;
+            let frame_count: u32 = data.read_primitive()?;
+            let total_time: f32 = data.read_primitive()?;
+
+            let mut frames = Vec::new();
+            for _ in 0..frame_count {
+                let duration: f32 = data.read_primitive()?;
+                let mut coords = Vec::new();
+                for _ in 0..images {
+                    coords.push([data.read_primitive()?, data.read_primitive()?, data.read_primitive()?, data.read_primitive()?]);
+                }
+                frames.push(VTFSheetFrame { duration, coords });
+            }
+            sequences.push(Self { index, clamp, total_time, frames });
+        }
+        Ok(sequences)
+    }
+
+}
+
+
+
+/// Resource of a 7.3+ VTF, from the resource directory after the header.
+#[derive(Debug, Clone)]
+pub enum VTFResource {
+    LowResImage,
+    HighResImage,
+    /// Animated particle sheet.
+    Sheet(Vec<VTFSheetSequence>),
+    /// CRC32 of the source image.
+    Crc(u32),
+    /// Largest mipmap used, as a power of 2 for each axis.
+    LODControl { clamp_u: u8, clamp_v: u8 },
+    /// Extended flags from the texture settings.
+    TextureSettings(u32),
+    /// KeyValues text, such as the texture's compile settings.
+    KeyValues(String),
+    /// Resource with an unknown tag, with the inline value or the data it points to.
+    Unknown { tag: [u8; 3], flags: u8, value: u32, data: Option<Vec<u8>> },
+}
+
+impl VTFResource {
+
+    const LOWRES_IMAGE: [u8; 3] = [0x01, 0x00, 0x00];
+    const HIGHRES_IMAGE: [u8; 3] = [0x30, 0x00, 0x00];
+    const SHEET: [u8; 3] = [0x10, 0x00, 0x00];
+    const CRC: [u8; 3] = *b"CRC";
+    const LOD_CONTROL: [u8; 3] = *b"LOD";
+    const TEXTURE_SETTINGS: [u8; 3] = *b"TSO";
+    const KEY_VALUES: [u8; 3] = *b"KVD";
+
+    /// Resource stores its value in the directory entry instead of pointing to data.
+    const NO_DATA_CHUNK: u8 = 0x02;
+
+    /// Data of a resource, which is prefixed by its length.
+    fn read_chunk(data: &mut (impl Read + Seek), offset: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+        data.seek(SeekFrom::Start(offset as u64))?;
+        let length: u32 = data.read_primitive()?;
+        let mut chunk = Vec::new();
+        data.take(length as u64).read_to_end(&mut chunk)?;
+        Ok(chunk)
+    }
+
+    fn load(data: &mut (impl Read + Seek), tag: [u8; 3], flags: u8, value: u32) -> Result<Self, Box<dyn Error>> {
+        Ok(match tag {
+            Self::LOWRES_IMAGE => Self::LowResImage,
+            Self::HIGHRES_IMAGE => Self::HighResImage,
+            Self::SHEET => Self::Sheet(VTFSheetSequence::load_sheet(Self::read_chunk(data, value)?.as_slice())?),
+            Self::CRC => Self::Crc(value),
+            Self::LOD_CONTROL => Self::LODControl { clamp_u: value as u8, clamp_v: (value >> 8) as u8 },
+            Self::TEXTURE_SETTINGS => Self::TextureSettings(value),
+            Self::KEY_VALUES => Self::KeyValues(String::from_utf8_lossy(&Self::read_chunk(data, value)?).into_owned()),
+            tag => {
+                let chunk = if flags & Self::NO_DATA_CHUNK == 0 { Some(Self::read_chunk(data, value)?) } else { None };
+                Self::Unknown { tag, flags, value, data: chunk }
+            },
+        })
+    }
+
+}
+
+
+
+
+
 #[derive(Debug)]
 pub struct VTF {
+    version: VTFVersion,
+    resources: Vec<VTFResource>,
     lowres_texture: Option<Texture>,
     mipmaps: u8,
     frames: u16,
@@ -291,10 +545,22 @@ pub struct VTF {
 
 impl VTF {
 
+    /// Texture data, which is short if the file ends early.
+    fn read_texture(data: &mut impl Read, size: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut tex_data = Vec::new();
+        data.take(size as u64).read_to_end(&mut tex_data)?;
+        Ok(tex_data)
+    }
+
     pub fn load(mut data: impl Read + Seek) -> Result<Self, Box<dyn Error>> {
-        assert!(data.check_magic_string("VTF\0")?);
+        if !data.check_magic_string("VTF\0")? {
+            return Err(Box::new(VTFError::InvalidMagic));
+        }
 
         let version = VTFVersion::new(data.read_primitive()?, data.read_primitive()?);
+        if version.major != 7 || version.minor > 5 {
+            return Err(Box::new(VTFError::UnsupportedVersion(version.major, version.minor)));
+        }
 
         let header_size: u32 = data.read_primitive()?;
 
@@ -315,18 +581,21 @@ impl VTF {
         let highres_format = TextureFormat::new(data.read_primitive()?)?;
 
         let mipmaps: u8 = data.read_primitive()?;
+        // Cubemaps before 7.5 have a 7th sphere map face, unless the first frame is -1.
         let faces = if flags.intersects(TextureFlags::ENVMAP) {
-            if version < VTFVersion::new(7, 5) && first_frame == 0xFFFF {
-                first_frame = 0;
-                7
-            } else {
-                6
-            }
+            if version < VTFVersion::new(7, 5) && first_frame != 0xFFFF { 7 } else { 6 }
         } else {
             1
         };
+        if first_frame == 0xFFFF {
+            first_frame = 0;
+        }
 
-        let lowres_format = TextureFormat::new(data.read_primitive()?)?;
+        // Low res image may be missing, which is format NONE.
+        let lowres_format = match data.read_primitive::<i32>()? {
+            -1 => None,
+            format => Some(TextureFormat::new(format)?),
+        };
         let lowres_width: u8 = data.read_primitive()?;
         let lowres_height: u8 = data.read_primitive()?;
 
@@ -336,43 +605,90 @@ impl VTF {
             depth = data.read_primitive()?;
         }
 
+        // Mipmaps halve the size down to 1x1x1, so there is at most one per bit of the largest axis.
+        let max_mipmaps = u16::BITS - width.max(height).max(depth).leading_zeros();
+        if mipmaps as u32 > max_mipmaps.max(1) {
+            return Err(Box::new(VTFError::MipmapCount(mipmaps)));
+        }
+
+        // Resource directory, images are found by their offset.
+        let mut entries = Vec::new();
         if version >= VTFVersion::new(7, 3) {
-            panic!("Unsupported VTF version. {:?}", version);
+            data.seek(SeekFrom::Current(3))?;
+            let resource_count: u32 = data.read_primitive()?;
+            data.seek(SeekFrom::Current(8))?;
+            for _ in 0..resource_count {
+                let tag: [u8; 3] = [data.read_primitive()?, data.read_primitive()?, data.read_primitive()?];
+                let flags: u8 = data.read_primitive()?;
+                let value: u32 = data.read_primitive()?;
+                entries.push((tag, flags, value));
+            }
         }
 
-        // Read texture data
-        data.seek(SeekFrom::Start(header_size as u64))?;
+        let mut resources = Vec::new();
+        let mut lowres_offset = None;
+        let mut highres_offset = None;
+        for (tag, flags, value) in entries {
+            let resource = VTFResource::load(&mut data, tag, flags, value)?;
+            match resource {
+                VTFResource::LowResImage => lowres_offset = Some(value as u64),
+                VTFResource::HighResImage => highres_offset = Some(value as u64),
+                _ => {},
+            }
+            resources.push(resource);
+        }
+
+        // Before 7.3 the images follow the header, the low res image first.
+        if version < VTFVersion::new(7, 3) {
+            let lowres_size = lowres_format.map_or(0, |format| format.byte_size(lowres_width as u32, lowres_height as u32) as u64);
+            lowres_offset = Some(header_size as u64);
+            highres_offset = Some(header_size as u64 + lowres_size);
+        }
 
         // Low res texture
-        let lowres_texture = {
-            let size = lowres_format.byte_size(lowres_width as u32, lowres_height as u32);
-            if size > 0 {
-                let tex_data = data.read_to_vec(size as usize)?;
-                Some(Texture::new(lowres_width as u32, lowres_height as u32, lowres_format, tex_data))
-            } else {
-                None
-            }
+        let lowres_texture = match (lowres_format, lowres_offset) {
+            (Some(lowres_format), Some(offset)) => {
+                data.seek(SeekFrom::Start(offset))?;
+                let size = lowres_format.byte_size(lowres_width as u32, lowres_height as u32);
+                if size > 0 {
+                    let tex_data = Self::read_texture(&mut data, size)?;
+                    Some(Texture::new(lowres_width as u32, lowres_height as u32, lowres_format, tex_data))
+                } else {
+                    None
+                }
+            },
+            _ => None,
         };
 
-        // High res texture
+        let highres_offset = highres_offset.ok_or(VTFError::MissingResource("high res image"))?;
+        data.seek(SeekFrom::Start(highres_offset))?;
+
+        // High res texture, from the smallest mipmap to the largest.
+        // Textures after the end of the file are missing, frame & depth counts may be far larger than the file.
         let mut textures = Vec::new();
-        for mipmap in 0..mipmaps {
-            for frame in 0..frames {
-                for face in 0..faces {
-                    for slice in 0..depth {
-                        let inverse_mip = mipmaps - mipmap - 1;
-                        let mip_width = width >> inverse_mip;
-                        let mip_height = height >> inverse_mip;
-                        let size = highres_format.byte_size(mip_width as u32, mip_height as u32);
-                        let tex_data = data.read_to_vec(size as usize)?;
-                        let texture = Texture::new(mip_width as u32, mip_height as u32, highres_format, tex_data);
+        'textures: for mipmap in (0..mipmaps).rev() {
+            let mip_width = (width as u32 >> mipmap).max(1);
+            let mip_height = (height as u32 >> mipmap).max(1);
+            let mip_depth = (depth >> mipmap).max(1);
+            for _frame in 0..frames {
+                for _face in 0..faces {
+                    for _slice in 0..mip_depth {
+                        let size = highres_format.byte_size(mip_width, mip_height);
+                        let tex_data = Self::read_texture(&mut data, size)?;
+                        let short = tex_data.len() < size;
+                        let texture = Texture::new(mip_width, mip_height, highres_format, tex_data);
                         textures.push(texture);
+                        if short {
+                            break 'textures;
+                        }
                     }
                 }
             }
         }
 
         Ok(Self {
+            version,
+            resources,
             lowres_texture,
             mipmaps,
             frames,
@@ -385,41 +701,208 @@ impl VTF {
 
 
 
+    /// Version as `(major, minor)`.
+    pub fn version(&self) -> (u32, u32) { (self.version.major, self.version.minor) }
     pub fn mipmaps(&self) -> u8 { self.mipmaps }
     pub fn frames(&self) -> u16 { self.frames }
     pub fn faces(&self) -> u8 { self.faces }
     pub fn depth(&self) -> u16 { self.depth }
+    pub fn lowres_texture(&self) -> Option<&Texture> { self.lowres_texture.as_ref() }
+
+    /// Resources of 7.3+ VTFs, older versions have none.
+    pub fn resources(&self) -> &[VTFResource] { &self.resources }
+
+    pub fn sheet(&self) -> Option<&[VTFSheetSequence]> {
+        self.resources.iter().find_map(|resource| match resource {
+            VTFResource::Sheet(sequences) => Some(sequences.as_slice()),
+            _ => None,
+        })
+    }
+
+    pub fn crc(&self) -> Option<u32> {
+        self.resources.iter().find_map(|resource| match resource {
+            VTFResource::Crc(crc) => Some(*crc),
+            _ => None,
+        })
+    }
+
+    /// LOD clamp as `(u, v)`.
+    pub fn lod_control(&self) -> Option<(u8, u8)> {
+        self.resources.iter().find_map(|resource| match resource {
+            VTFResource::LODControl { clamp_u, clamp_v } => Some((*clamp_u, *clamp_v)),
+            _ => None,
+        })
+    }
+
+    pub fn texture_settings(&self) -> Option<u32> {
+        self.resources.iter().find_map(|resource| match resource {
+            VTFResource::TextureSettings(flags) => Some(*flags),
+            _ => None,
+        })
+    }
+
+    pub fn key_values(&self) -> Option<&str> {
+        self.resources.iter().find_map(|resource| match resource {
+            VTFResource::KeyValues(text) => Some(text.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Frame shown first when animated.
+    pub fn first_frame(&self) -> u16 { self.first_frame }
+
+    /// Slices of a mipmap, volume textures halve their depth with each mipmap.
+    pub fn mipmap_depth(&self, mipmap: u8) -> u16 {
+        self.depth.checked_shr(mipmap as u32).unwrap_or(0).max(1)
+    }
 
     fn texture_index(&self, mipmap: u8, frame: u16, face: u8, slice: u16) -> Option<usize> {
-        if mipmap >= self.mipmaps || frame >= self.frames || face >= self.faces || slice >= self.depth {
+        if mipmap >= self.mipmaps || frame >= self.frames || face >= self.faces || slice >= self.mipmap_depth(mipmap) {
             return None;
         }
 
-        let mipmaps = self.mipmaps as usize;
-        let frames = self.frames as usize;
-        let faces = self.faces as usize;
-        let depth = self.depth as usize;
+        // Mipmaps are stored from the smallest, each with all frames, faces & slices.
+        let images = |mipmap: u8| self.frames as usize * self.faces as usize * self.mipmap_depth(mipmap) as usize;
+        let offset = (mipmap + 1..self.mipmaps).map(images).sum::<usize>();
 
-        let mipmap = (self.mipmaps - mipmap - 1) as usize;
         let frame = frame as usize;
         let face = face as usize;
         let slice = slice as usize;
 
-        Some(
-            mipmap +
-            frame * mipmaps +
-            face * frames * mipmaps +
-            slice * faces * frames * mipmaps
-        )
+        Some(offset + (frame * self.faces as usize + face) * self.mipmap_depth(mipmap) as usize + slice)
     }
+
+    /// Texture of a mipmap, where mipmap 0 is the full size.
     pub fn texture(&self, mipmap: u8, frame: u16, face: u8, slice: u16) -> Option<&Texture> {
-        if let Some(index) = self.texture_index(mipmap, frame, face, slice) {
-            self.textures.get(index)
-        } else {
-            None
+        self.textures.get(self.texture_index(mipmap, frame, face, slice)?)
+    }
+
+    fn texture_image(&self, mipmap: u8, frame: u16, face: u8, slice: u16) -> Result<RgbaImage, Box<dyn Error>> {
+        self.texture(mipmap, frame, face, slice).ok_or(VTFError::MissingTexture)?.to_image()
+    }
+
+    /// Frames of an animated texture, starting at the first frame.
+    pub fn animation(&self, mipmap: u8) -> Result<Vec<RgbaImage>, Box<dyn Error>> {
+        let first_frame = if self.first_frame < self.frames { self.first_frame } else { 0 };
+        (first_frame..self.frames).chain(0..first_frame)
+            .map(|frame| self.texture_image(mipmap, frame, 0, 0))
+            .collect()
+    }
+
+    /// Name of cubemap faces, in the order they are stored.
+    pub const CUBEMAP_FACES: [&'static str; 7] = ["right", "left", "back", "front", "up", "down", "sphere"];
+
+    /// Cubemap faces as a horizontal cross, with up & down above & below the front face.
+    /// Faces are placed as stored, without rotation.
+    pub fn cubemap_cross(&self, mipmap: u8, frame: u16) -> Result<RgbaImage, Box<dyn Error>> {
+        if self.faces < 6 {
+            return Err(Box::new(VTFError::NotCubemap));
+        }
+
+        // Cells of the cross as (face, column, row).
+        const CROSS: [(u8, u32, u32); 6] = [(4, 1, 0), (1, 0, 1), (3, 1, 1), (0, 2, 1), (2, 3, 1), (5, 1, 2)];
+
+        let texture = self.texture(mipmap, frame, 0, 0).ok_or(VTFError::MissingTexture)?;
+        let (width, height) = (texture.width(), texture.height());
+        let mut image = RgbaImage::new(width * 4, height * 3);
+        for (face, column, row) in CROSS {
+            let face = self.texture_image(mipmap, frame, face, 0)?;
+            image::imageops::replace(&mut image, &face, (column * width) as i64, (row * height) as i64);
         }
+        Ok(image)
     }
 
+    /// Slices of a volume texture as a grid, from left to right & top to bottom.
+    pub fn slice_sheet(&self, mipmap: u8, frame: u16, face: u8) -> Result<RgbaImage, Box<dyn Error>> {
+        let texture = self.texture(mipmap, frame, face, 0).ok_or(VTFError::MissingTexture)?;
+        let (width, height) = (texture.width(), texture.height());
+
+        let slices = self.mipmap_depth(mipmap) as u32;
+        let columns = (slices as f64).sqrt().ceil() as u32;
+        let rows = slices.div_ceil(columns);
+        let mut image = RgbaImage::new(width * columns, height * rows);
+        for slice in 0..slices {
+            let slice_image = self.texture_image(mipmap, frame, face, slice as u16)?;
+            image::imageops::replace(&mut image, &slice_image, ((slice % columns) * width) as i64, ((slice / columns) * height) as i64);
+        }
+        Ok(image)
+    }
 }
 
 
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::{error::Error, io::Cursor};
+    use image::RgbaImage;
+    use super::{super::vtf_writer::VTFWriter, Texture, TextureFormat, VTF};
+
+    fn pixels(format: TextureFormat, data: &[u8]) -> Result<Vec<[u8; 4]>, Box<dyn Error>> {
+        let width = (data.len() / format.byte_size(1, 1)) as u32;
+        Ok(Texture::new(width, 1, format, data.to_vec()).to_image()?.pixels().map(|pixel| pixel.0).collect())
+    }
+
+    #[test]
+    fn texture_packed_bits() -> Result<(), Box<dyn Error>> {
+        let packed = |values: &[u16]| values.iter().flat_map(|value| value.to_le_bytes()).collect::<Vec<_>>();
+        assert_eq!(pixels(TextureFormat::IMAGE_FORMAT_RGB565, &packed(&[0x001F, 0x07E0, 0x0410]))?, [[255, 0, 0, 255], [0, 255, 0, 255], [131, 129, 0, 255]]);
+        assert_eq!(pixels(TextureFormat::IMAGE_FORMAT_BGR565, &packed(&[0xF800, 0x001F]))?, [[255, 0, 0, 255], [0, 0, 255, 255]]);
+        assert_eq!(pixels(TextureFormat::IMAGE_FORMAT_BGRX5551, &packed(&[0xFC00, 0x03E0]))?, [[255, 0, 0, 255], [0, 255, 0, 255]]);
+        assert_eq!(pixels(TextureFormat::IMAGE_FORMAT_BGRA5551, &packed(&[0x8000, 0x001F]))?, [[0, 0, 0, 255], [0, 0, 255, 0]]);
+        assert_eq!(pixels(TextureFormat::IMAGE_FORMAT_BGRA4444, &packed(&[0x1234]))?, [[34, 51, 68, 17]]);
+        Ok(())
+    }
+
+    #[test]
+    fn texture_bluescreen() -> Result<(), Box<dyn Error>> {
+        assert_eq!(pixels(TextureFormat::IMAGE_FORMAT_RGB888_BLUESCREEN, &[0, 0, 255, 0, 0, 254])?, [[0, 0, 255, 0], [0, 0, 254, 255]]);
+        assert_eq!(pixels(TextureFormat::IMAGE_FORMAT_BGR888_BLUESCREEN, &[255, 0, 0, 0, 0, 255])?, [[0, 0, 255, 0], [255, 0, 0, 255]]);
+        Ok(())
+    }
+
+    #[test]
+    fn texture_hdr_tone_map() -> Result<(), Box<dyn Error>> {
+        // Half floats 0, 1, 1000 & -1, alpha 0.5.
+        let pixel = |value: u16| [value, value, value, 0x3800].iter().flat_map(|value| value.to_le_bytes()).collect::<Vec<_>>();
+        let data = [pixel(0x0000), pixel(0x3C00), pixel(0x63D0), pixel(0xBC00)].concat();
+        assert_eq!(
+            pixels(TextureFormat::IMAGE_FORMAT_RGBA16161616F, &data)?,
+            [[0, 0, 0, 128], [188, 188, 188, 128], [255, 255, 255, 128], [0, 0, 0, 128]]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn texture_too_short() {
+        assert!(Texture::new(2, 2, TextureFormat::IMAGE_FORMAT_RGBA8888, vec![0; 15]).to_image().is_err());
+        assert!(Texture::new(5, 4, TextureFormat::IMAGE_FORMAT_DXT1, vec![0; 8]).to_image().is_err());
+    }
+
+    #[test]
+    fn vtf_mipmap_count() -> Result<(), Box<dyn Error>> {
+        let mut data = VTFWriter::new(TextureFormat::IMAGE_FORMAT_RGBA8888).write(&[RgbaImage::new(8, 4)])?;
+        assert_eq!(VTF::load(Cursor::new(&data))?.mipmaps(), 4);
+        // Mipmap count is right after the high res format.
+        for mipmaps in [5, 16, 32, 255] {
+            data[56] = mipmaps;
+            assert!(VTF::load(Cursor::new(&data)).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn vtf_frames_past_end() -> Result<(), Box<dyn Error>> {
+        let mut data = VTFWriter::new(TextureFormat::IMAGE_FORMAT_RGBA8888).write(&[RgbaImage::new(1, 1)])?;
+        // Frames & depth of 65535 in a file with 1 texture.
+        data[24..26].copy_from_slice(&u16::MAX.to_le_bytes());
+        data[63..65].copy_from_slice(&u16::MAX.to_le_bytes());
+        let vtf = VTF::load(Cursor::new(&data))?;
+        assert_eq!((vtf.frames(), vtf.mipmap_depth(0)), (u16::MAX, u16::MAX));
+        assert!(vtf.texture(0, 0, 0, 0).is_some_and(|texture| texture.to_image().is_ok()));
+        assert!(vtf.texture(0, 0, 0, 2).is_none());
+        assert!(vtf.animation(0).is_err());
+        Ok(())
+    }
+}
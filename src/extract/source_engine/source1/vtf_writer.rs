@@ -0,0 +1,301 @@
+// https://developer.valvesoftware.com/wiki/VTF_(Valve_Texture_Format)
+
+use std::error::Error;
+use image::{imageops::{self, FilterType}, RgbaImage};
+use super::{dxt, vtf::{TextureFlags, TextureFormat, VTFError}};
+
+
+
+/// Largest size of the low res thumbnail.
+const LOWRES_SIZE: u32 = 16;
+
+/// Header size of 7.2, later versions add the resource directory.
+const HEADER_SIZE: u32 = 80;
+
+/// Writes VTF 7.2 to 7.5 from images.
+pub struct VTFWriter {
+    minor_version: u32,
+    format: TextureFormat,
+    flags: TextureFlags,
+    lowres: bool,
+}
+
+impl VTFWriter {
+
+    /// Writer for VTF 7.2 with a low res thumbnail.
+    pub fn new(format: TextureFormat) -> Self {
+        Self { minor_version: 2, format, flags: TextureFlags::empty(), lowres: true }
+    }
+
+    /// Minor version of VTF 7, from 2 to 5.
+    pub fn version(mut self, minor_version: u32) -> Self {
+        self.minor_version = minor_version;
+        self
+    }
+
+    /// Flags of the texture, `NOMIP` writes only the full size image.
+    pub fn flags(mut self, flags: TextureFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn lowres(mut self, lowres: bool) -> Self {
+        self.lowres = lowres;
+        self
+    }
+
+    fn encode(format: TextureFormat, image: &RgbaImage) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(match format {
+            TextureFormat::IMAGE_FORMAT_RGBA8888 => image.as_raw().clone(),
+            TextureFormat::IMAGE_FORMAT_BGRA8888 => image.pixels().flat_map(|pixel| [pixel[2], pixel[1], pixel[0], pixel[3]]).collect(),
+            TextureFormat::IMAGE_FORMAT_RGB888 => image.pixels().flat_map(|pixel| [pixel[0], pixel[1], pixel[2]]).collect(),
+            TextureFormat::IMAGE_FORMAT_BGR888 => image.pixels().flat_map(|pixel| [pixel[2], pixel[1], pixel[0]]).collect(),
+            TextureFormat::IMAGE_FORMAT_DXT1 |
+            TextureFormat::IMAGE_FORMAT_DXT3 |
+            TextureFormat::IMAGE_FORMAT_DXT5
+                => dxt::encode(format.dxt_format().ok_or(VTFError::CannotEncode(format))?, image),
+            format => return Err(Box::new(VTFError::CannotEncode(format))),
+        })
+    }
+
+    fn has_alpha(format: TextureFormat) -> bool {
+        matches!(format,
+            TextureFormat::IMAGE_FORMAT_RGBA8888 |
+            TextureFormat::IMAGE_FORMAT_BGRA8888 |
+            TextureFormat::IMAGE_FORMAT_DXT3 |
+            TextureFormat::IMAGE_FORMAT_DXT5
+        )
+    }
+
+    /// Image resized to a mipmap, mipmap 0 is the full size.
+    fn mipmap(image: &RgbaImage, mipmap: u8) -> RgbaImage {
+        if mipmap == 0 {
+            return image.clone();
+        }
+        let width = (image.width() >> mipmap).max(1);
+        let height = (image.height() >> mipmap).max(1);
+        imageops::resize(image, width, height, FilterType::Triangle)
+    }
+
+    /// Average color in linear space, which Source uses for radiosity.
+    fn reflectivity(image: &RgbaImage) -> [f32; 3] {
+        let mut sum = [0.0f64; 3];
+        for pixel in image.pixels() {
+            for (i, sum) in sum.iter_mut().enumerate() {
+                *sum += (pixel[i] as f64 / 255.0).powf(2.2);
+            }
+        }
+        let count = (image.width() as f64 * image.height() as f64).max(1.0);
+        sum.map(|sum| (sum / count) as f32)
+    }
+
+    /// Write a VTF, images are frames of an animation.
+    /// Cubemaps have 6 images per frame, in the order right, left, back, front, up & down.
+    pub fn write(&self, images: &[RgbaImage]) -> Result<Vec<u8>, Box<dyn Error>> {
+        if !(2..=5).contains(&self.minor_version) {
+            return Err(Box::new(VTFError::UnsupportedVersion(7, self.minor_version)));
+        }
+
+        let faces = if self.flags.contains(TextureFlags::ENVMAP) { 6 } else { 1 };
+        let Some(first) = images.first() else {
+            return Err(Box::new(VTFError::ImageCount(0)));
+        };
+        if !images.len().is_multiple_of(faces) || images.len() / faces > u16::MAX as usize {
+            return Err(Box::new(VTFError::ImageCount(images.len())));
+        }
+        let frames = (images.len() / faces) as u16;
+        let (width, height) = first.dimensions();
+        if images.iter().any(|image| image.dimensions() != (width, height)) || width > u16::MAX as u32 || height > u16::MAX as u32 || width == 0 || height == 0 {
+            return Err(Box::new(VTFError::ImageSize));
+        }
+
+        let mipmaps = if self.flags.contains(TextureFlags::NOMIP) { 1 } else { width.max(height).ilog2() as u8 + 1 };
+
+        let mut flags = self.flags;
+        if Self::has_alpha(self.format) && images.iter().any(|image| image.pixels().any(|pixel| pixel[3] < 255)) {
+            flags.insert(TextureFlags::EIGHTBITALPHA);
+        }
+
+        // Low res thumbnail is halved until it fits.
+        let lowres = if self.lowres {
+            let (mut lowres_width, mut lowres_height) = (width, height);
+            while lowres_width > LOWRES_SIZE || lowres_height > LOWRES_SIZE {
+                lowres_width = (lowres_width / 2).max(1);
+                lowres_height = (lowres_height / 2).max(1);
+            }
+            let image = imageops::resize(first, lowres_width, lowres_height, FilterType::Triangle);
+            Some((image.dimensions(), Self::encode(TextureFormat::IMAGE_FORMAT_DXT1, &image)?))
+        } else {
+            None
+        };
+
+        // High res image, from the smallest mipmap to the largest.
+        let mut highres = Vec::new();
+        for mipmap in (0..mipmaps).rev() {
+            for image in images {
+                highres.extend_from_slice(&Self::encode(self.format, &Self::mipmap(image, mipmap))?);
+            }
+        }
+
+        // Resource directory of 7.3+, which points to the images.
+        let resource_count = if self.minor_version >= 3 { if lowres.is_some() { 2 } else { 1 } } else { 0 };
+        let header_size = HEADER_SIZE + resource_count * 8;
+        let lowres_offset = header_size;
+        let highres_offset = lowres_offset + lowres.as_ref().map_or(0, |(_, data)| data.len() as u32);
+
+        let mut data = Vec::new();
+        data.extend_from_slice(b"VTF\0");
+        data.extend_from_slice(&7u32.to_le_bytes());
+        data.extend_from_slice(&self.minor_version.to_le_bytes());
+        data.extend_from_slice(&header_size.to_le_bytes());
+        data.extend_from_slice(&(width as u16).to_le_bytes());
+        data.extend_from_slice(&(height as u16).to_le_bytes());
+        data.extend_from_slice(&flags.bits().to_le_bytes());
+        data.extend_from_slice(&frames.to_le_bytes());
+        // First frame of -1 marks cubemaps before 7.5 as not having a sphere map face.
+        let first_frame: u16 = if faces == 6 && self.minor_version < 5 { 0xFFFF } else { 0 };
+        data.extend_from_slice(&first_frame.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        for reflectivity in Self::reflectivity(first) {
+            data.extend_from_slice(&reflectivity.to_le_bytes());
+        }
+        data.extend_from_slice(&[0; 4]);
+        // Bumpmap scale.
+        data.extend_from_slice(&1.0f32.to_le_bytes());
+        data.extend_from_slice(&(self.format as i32).to_le_bytes());
+        data.push(mipmaps);
+        match &lowres {
+            Some(((lowres_width, lowres_height), _)) => {
+                data.extend_from_slice(&(TextureFormat::IMAGE_FORMAT_DXT1 as i32).to_le_bytes());
+                data.push(*lowres_width as u8);
+                data.push(*lowres_height as u8);
+            },
+            None => {
+                data.extend_from_slice(&(-1i32).to_le_bytes());
+                data.extend_from_slice(&[0, 0]);
+            },
+        }
+        // Depth.
+        data.extend_from_slice(&1u16.to_le_bytes());
+
+        if self.minor_version >= 3 {
+            data.extend_from_slice(&[0; 3]);
+            data.extend_from_slice(&resource_count.to_le_bytes());
+            data.extend_from_slice(&[0; 8]);
+            if lowres.is_some() {
+                data.extend_from_slice(&[0x01, 0x00, 0x00, 0x00]);
+                data.extend_from_slice(&lowres_offset.to_le_bytes());
+            }
+            data.extend_from_slice(&[0x30, 0x00, 0x00, 0x00]);
+            data.extend_from_slice(&highres_offset.to_le_bytes());
+        } else {
+            data.resize(HEADER_SIZE as usize, 0);
+        }
+
+        if let Some((_, lowres)) = &lowres {
+            data.extend_from_slice(lowres);
+        }
+        data.extend_from_slice(&highres);
+        Ok(data)
+    }
+
+}
+
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::{error::Error, io::Cursor};
+    use image::{Rgba, RgbaImage};
+    use super::{super::vtf::{TextureFlags, TextureFormat, VTF}, VTFWriter};
+
+    #[test]
+    fn write_cubemap() -> Result<(), Box<dyn Error>> {
+        let faces = (0..6).map(|face| RgbaImage::from_pixel(4, 4, Rgba([face * 40, 0, 0, 255]))).collect::<Vec<_>>();
+        for minor_version in 2..=5 {
+            let mut data = VTFWriter::new(TextureFormat::IMAGE_FORMAT_RGBA8888).version(minor_version).flags(TextureFlags::ENVMAP).write(&faces)?;
+            let first_frame = u16::from_le_bytes([data[26], data[27]]);
+            assert_eq!(first_frame, if minor_version < 5 { 0xFFFF } else { 0 });
+
+            let vtf = VTF::load(Cursor::new(&data))?;
+            assert_eq!((vtf.faces(), vtf.first_frame()), (6, 0));
+            for face in 0..6 {
+                assert_eq!(vtf.texture(0, 0, face, 0).unwrap().to_image()?, faces[face as usize]);
+            }
+
+            // Without -1 older cubemaps have a sphere map, which is missing here.
+            if minor_version < 5 {
+                data[26..28].copy_from_slice(&0u16.to_le_bytes());
+                let vtf = VTF::load(Cursor::new(&data))?;
+                assert_eq!(vtf.faces(), 7);
+                assert!(vtf.texture(0, 0, 6, 0).is_none());
+            }
+        }
+        Ok(())
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Largest difference of a channel, alpha is only compared when the format has it.
+    fn max_error(a: &RgbaImage, b: &RgbaImage, alpha: bool) -> u8 {
+        a.pixels().zip(b.pixels())
+            .flat_map(|(a, b)| (0..if alpha { 4 } else { 3 }).map(move |i| a[i].abs_diff(b[i])))
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn write_round_trip() -> Result<(), Box<dyn Error>> {
+        let frames = (0..2u32).map(|frame| RgbaImage::from_fn(8, 4, |x, y| Rgba([(x * 32) as u8, (x * 32) as u8, (frame * 128) as u8, (255 - y * 64) as u8]))).collect::<Vec<_>>();
+        let formats = [
+            (TextureFormat::IMAGE_FORMAT_RGBA8888, 0, true),
+            (TextureFormat::IMAGE_FORMAT_BGRA8888, 0, true),
+            (TextureFormat::IMAGE_FORMAT_RGB888, 0, false),
+            (TextureFormat::IMAGE_FORMAT_BGR888, 0, false),
+            (TextureFormat::IMAGE_FORMAT_DXT1, 24, false),
+            (TextureFormat::IMAGE_FORMAT_DXT3, 24, true),
+            (TextureFormat::IMAGE_FORMAT_DXT5, 24, true),
+        ];
+
+        for minor_version in 2..=5 {
+            for (format, tolerance, alpha) in formats {
+                let data = VTFWriter::new(format).version(minor_version).write(&frames)?;
+
+                // 7.3+ point to the images from the resource directory, 7.2 images follow the header.
+                let header_size = u32_at(&data, 12);
+                let lowres_size = TextureFormat::IMAGE_FORMAT_DXT1.byte_size(8, 4) as u32;
+                if minor_version >= 3 {
+                    assert_eq!((header_size, u32_at(&data, 68)), (96, 2));
+                    assert_eq!((&data[80..84], u32_at(&data, 84)), (&[0x01, 0, 0, 0][..], header_size));
+                    assert_eq!((&data[88..92], u32_at(&data, 92)), (&[0x30, 0, 0, 0][..], header_size + lowres_size));
+                } else {
+                    assert_eq!(header_size, 80);
+                }
+                let highres_size = (0..4).map(|mipmap| format.byte_size(8 >> mipmap, (4 >> mipmap).max(1)) as u32 * 2).sum::<u32>();
+                assert_eq!(data.len() as u32, header_size + lowres_size + highres_size);
+
+                let vtf = VTF::load(Cursor::new(&data))?;
+                assert_eq!((vtf.version(), vtf.mipmaps(), vtf.frames(), vtf.faces()), ((7, minor_version), 4, 2, 1));
+                let lowres = vtf.lowres_texture().unwrap();
+                assert!(max_error(&lowres.to_image()?, &frames[0], false) <= 24);
+
+                for mipmap in 0..4 {
+                    let texture = vtf.texture(mipmap, 1, 0, 0).unwrap();
+                    assert_eq!((texture.width(), texture.height()), (8 >> mipmap, (4 >> mipmap).max(1)));
+                }
+                for (index, frame) in frames.iter().enumerate() {
+                    let image = vtf.texture(0, index as u16, 0, 0).unwrap().to_image()?;
+                    assert!(max_error(&image, frame, alpha) <= tolerance, "{:?} 7.{}", format, minor_version);
+                    if !alpha {
+                        assert!(image.pixels().all(|pixel| pixel[3] == 255));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,896 @@
+// https://developer.valvesoftware.com/wiki/KeyValues3
+// https://github.com/ValveResourceFormat/ValveResourceFormat
+
+use std::{error::Error, fmt, iter::Peekable, str::Chars};
+use serde_json::{json, Map, Value};
+
+
+
+#[derive(Debug)]
+pub enum KV3Error {
+    InvalidMagic,
+    UnsupportedVersion(u32),
+    UnknownEncoding([u8; 16]),
+    UnknownCompression(u32),
+    UnsupportedBlocks(u32),
+    UnknownType(u8),
+    UnknownFlag(String),
+    StringIndex(i32),
+    DataTooShort,
+    UnexpectedChar(usize, char),
+    UnexpectedEnd,
+    Depth(usize),
+}
+
+impl fmt::Display for KV3Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidMagic => write!(f, "KV3 has invalid magic."),
+            Self::UnsupportedVersion(magic) => write!(f, "KV3 binary version {:#010x} is not supported.", magic),
+            Self::UnknownEncoding(guid) => write!(f, "KV3 has unknown encoding {:02x?}.", guid),
+            Self::UnknownCompression(method) => write!(f, "KV3 has unknown compression method {}.", method),
+            Self::UnsupportedBlocks(count) => write!(f, "KV3 has {} binary blob blocks, which are not supported.", count),
+            Self::UnknownType(kind) => write!(f, "KV3 has unknown type {}.", kind),
+            Self::UnknownFlag(flag) => write!(f, "KV3 has unknown flag \"{}\".", flag),
+            Self::StringIndex(index) => write!(f, "KV3 refers to missing string {}.", index),
+            Self::DataTooShort => write!(f, "KV3 data is too short."),
+            Self::UnexpectedChar(line, char) => write!(f, "KV3 has unexpected '{}' on line {}.", char, line),
+            Self::UnexpectedEnd => write!(f, "KV3 ends before its value is complete."),
+            Self::Depth(max) => write!(f, "KV3 is nested more than {} deep.", max),
+        }
+    }
+}
+
+impl Error for KV3Error { }
+
+/// Objects & arrays nested deeper than this are an error, instead of overflowing the stack.
+const MAX_DEPTH: usize = 256;
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KV3Flag {
+    Resource,
+    ResourceName,
+    Panorama,
+    SoundEvent,
+    SubClass,
+}
+
+impl KV3Flag {
+
+    pub fn new(flag: u8) -> Result<Self, KV3Error> {
+        Ok(match flag {
+            1 => KV3Flag::Resource,
+            2 => KV3Flag::ResourceName,
+            3 => KV3Flag::Panorama,
+            4 => KV3Flag::SoundEvent,
+            5 => KV3Flag::SubClass,
+            flag => return Err(KV3Error::UnknownFlag(flag.to_string())),
+        })
+    }
+
+    pub fn from_name(name: &str) -> Result<Self, KV3Error> {
+        Ok(match name {
+            "resource" => KV3Flag::Resource,
+            "resource_name" => KV3Flag::ResourceName,
+            "panorama" => KV3Flag::Panorama,
+            "soundevent" => KV3Flag::SoundEvent,
+            "subclass" => KV3Flag::SubClass,
+            name => return Err(KV3Error::UnknownFlag(name.to_owned())),
+        })
+    }
+
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KV3Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Double(f64),
+    String(String),
+    Blob(Vec<u8>),
+    Array(Vec<KV3Value>),
+    Object(Vec<(String, KV3Value)>),
+    /// Value with a hint of what it is, such as a resource path.
+    Flagged(KV3Flag, Box<KV3Value>),
+}
+
+impl KV3Value {
+
+    pub fn get(&self, key: &str) -> Option<&KV3Value> {
+        match self {
+            KV3Value::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, value)| value),
+            KV3Value::Flagged(_, value) => value.get(key),
+            _ => None,
+        }
+    }
+
+    /// Convert to JSON, flags are left out & blobs are hex strings.
+    pub fn to_json(&self) -> Value {
+        match self {
+            KV3Value::Null => Value::Null,
+            KV3Value::Bool(bool) => json!(bool),
+            KV3Value::Int(int) => json!(int),
+            KV3Value::UInt(int) => json!(int),
+            KV3Value::Double(double) => json!(double),
+            KV3Value::String(str) => Value::String(str.clone()),
+            KV3Value::Blob(blob) => Value::String(blob.iter().map(|byte| format!("{:02x}", byte)).collect()),
+            KV3Value::Array(values) => Value::Array(values.iter().map(KV3Value::to_json).collect()),
+            KV3Value::Object(entries) => Value::Object(entries.iter().map(|(key, value)| (key.clone(), value.to_json())).collect::<Map<_, _>>()),
+            KV3Value::Flagged(_, value) => value.to_json(),
+        }
+    }
+
+    /// Load KV3 binary, or KV3 text when it does not start with a binary magic.
+    pub fn load(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        match data.get(..4).map(|magic| u32::from_le_bytes([magic[0], magic[1], magic[2], magic[3]])) {
+            Some(magic) if is_binary(magic) => parse_binary(data),
+            _ => parse_text(&String::from_utf8_lossy(data)),
+        }
+    }
+
+}
+
+
+
+
+
+struct TextParser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    depth: usize,
+}
+
+impl TextParser<'_> {
+
+    fn next(&mut self) -> Result<char, KV3Error> {
+        let char = self.chars.next().ok_or(KV3Error::UnexpectedEnd)?;
+        if char == '\n' {
+            self.line += 1;
+        }
+        Ok(char)
+    }
+
+    fn starts_with(&self, str: &str) -> bool {
+        let mut chars = self.chars.clone();
+        str.chars().all(|char| chars.next() == Some(char))
+    }
+
+    fn skip(&mut self, count: usize) -> Result<(), KV3Error> {
+        for _ in 0..count {
+            self.next()?;
+        }
+        Ok(())
+    }
+
+    /// Skip whitespace, `//` & `/* */` comments, and the `<!-- kv3 -->` header.
+    fn skip_whitespace(&mut self) -> Result<(), KV3Error> {
+        loop {
+            if self.chars.peek().is_some_and(|char| char.is_whitespace()) {
+                self.next()?;
+            } else if self.starts_with("//") {
+                while self.chars.peek().is_some_and(|char| *char != '\n') {
+                    self.next()?;
+                }
+            } else if self.starts_with("/*") || self.starts_with("<!--") {
+                let end = if self.starts_with("/*") { "*/" } else { "-->" };
+                while !self.starts_with(end) {
+                    self.next()?;
+                }
+                self.skip(end.len())?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn unexpected(&mut self) -> KV3Error {
+        match self.chars.peek() {
+            Some(char) => KV3Error::UnexpectedChar(self.line, *char),
+            None => KV3Error::UnexpectedEnd,
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), KV3Error> {
+        self.skip_whitespace()?;
+        match self.chars.peek() {
+            Some(char) if *char == expected => self.skip(1),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn identifier(&mut self) -> String {
+        let mut str = String::new();
+        while let Some(char) = self.chars.peek() {
+            if !(char.is_alphanumeric() || matches!(char, '_' | '.' | '$' | '-' | '+')) {
+                break;
+            }
+            str.push(*char);
+            self.chars.next();
+        }
+        str
+    }
+
+    fn string(&mut self) -> Result<String, KV3Error> {
+        // Multi line strings leave out the line breaks after & before the quotes.
+        if self.starts_with("\"\"\"") {
+            self.skip(3)?;
+            let mut str = String::new();
+            while !self.starts_with("\"\"\"") {
+                str.push(self.next()?);
+            }
+            self.skip(3)?;
+            let str = str.strip_prefix("\r\n").or_else(|| str.strip_prefix('\n')).unwrap_or(&str);
+            let str = str.strip_suffix("\r\n").or_else(|| str.strip_suffix('\n')).unwrap_or(str);
+            return Ok(str.to_owned());
+        }
+
+        self.skip(1)?;
+        let mut str = String::new();
+        loop {
+            match self.next()? {
+                '"' => return Ok(str),
+                '\\' => match self.next()? {
+                    'n' => str.push('\n'),
+                    't' => str.push('\t'),
+                    char => str.push(char),
+                },
+                char => str.push(char),
+            }
+        }
+    }
+
+    fn number(&mut self, str: &str) -> Option<KV3Value> {
+        if let Ok(int) = str.parse::<i64>() {
+            Some(KV3Value::Int(int))
+        } else if let Ok(int) = str.parse::<u64>() {
+            Some(KV3Value::UInt(int))
+        } else {
+            str.parse::<f64>().ok().map(KV3Value::Double)
+        }
+    }
+
+    fn value(&mut self) -> Result<KV3Value, KV3Error> {
+        if self.depth >= MAX_DEPTH {
+            return Err(KV3Error::Depth(MAX_DEPTH));
+        }
+        self.depth += 1;
+        let value = self.nested_value();
+        self.depth -= 1;
+        value
+    }
+
+    fn nested_value(&mut self) -> Result<KV3Value, KV3Error> {
+        self.skip_whitespace()?;
+        match self.chars.peek() {
+            None => Err(KV3Error::UnexpectedEnd),
+            Some('{') => {
+                self.skip(1)?;
+                let mut entries = Vec::new();
+                loop {
+                    self.skip_whitespace()?;
+                    let key = match self.chars.peek() {
+                        Some('}') => {
+                            self.skip(1)?;
+                            break;
+                        },
+                        Some('"') => self.string()?,
+                        _ => self.identifier(),
+                    };
+                    if key.is_empty() {
+                        return Err(self.unexpected());
+                    }
+                    self.expect('=')?;
+                    entries.push((key, self.value()?));
+                    self.skip_whitespace()?;
+                    if self.chars.peek() == Some(&',') {
+                        self.skip(1)?;
+                    }
+                }
+                Ok(KV3Value::Object(entries))
+            },
+            Some('[') => {
+                self.skip(1)?;
+                let mut values = Vec::new();
+                loop {
+                    self.skip_whitespace()?;
+                    if self.chars.peek() == Some(&']') {
+                        self.skip(1)?;
+                        break;
+                    }
+                    values.push(self.value()?);
+                    self.skip_whitespace()?;
+                    match self.chars.peek() {
+                        Some(',') => self.skip(1)?,
+                        Some(']') => {},
+                        _ => return Err(self.unexpected()),
+                    }
+                }
+                Ok(KV3Value::Array(values))
+            },
+            Some('#') => {
+                self.skip(1)?;
+                self.expect('[')?;
+                let mut blob = Vec::new();
+                loop {
+                    self.skip_whitespace()?;
+                    if self.chars.peek() == Some(&']') {
+                        self.skip(1)?;
+                        break;
+                    }
+                    let hex = self.identifier();
+                    blob.push(u8::from_str_radix(&hex, 16).map_err(|_| self.unexpected())?);
+                }
+                Ok(KV3Value::Blob(blob))
+            },
+            Some('"') => Ok(KV3Value::String(self.string()?)),
+            Some(_) => {
+                let word = self.identifier();
+                match word.as_str() {
+                    "" => Err(self.unexpected()),
+                    "null" => Ok(KV3Value::Null),
+                    "true" => Ok(KV3Value::Bool(true)),
+                    "false" => Ok(KV3Value::Bool(false)),
+                    // Flag before a value, such as `resource:"path"`.
+                    _ if self.chars.peek() == Some(&':') => {
+                        self.skip(1)?;
+                        Ok(KV3Value::Flagged(KV3Flag::from_name(&word)?, Box::new(self.value()?)))
+                    },
+                    word => self.number(word).ok_or_else(|| KV3Error::UnexpectedChar(self.line, word.chars().next().unwrap_or(' '))),
+                }
+            },
+        }
+    }
+
+}
+
+/// Parse KV3 text, including its `<!-- kv3 -->` header.
+pub fn parse_text(text: &str) -> Result<KV3Value, Box<dyn Error>> {
+    let mut parser = TextParser { chars: text.trim_start_matches('\u{FEFF}').chars().peekable(), line: 1, depth: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace()?;
+    if parser.chars.peek().is_some() {
+        return Err(Box::new(parser.unexpected()));
+    }
+    Ok(value)
+}
+
+
+
+
+
+/// `VKV\x03`, which has an encoding GUID.
+const MAGIC_LEGACY: u32 = 0x03564B56;
+/// `\x01KV3`, with separate buffers of each value size.
+const MAGIC_V1: u32 = 0x4B563301;
+/// `\x02KV3`, which adds zstd.
+const MAGIC_V2: u32 = 0x4B563302;
+
+const ENCODING_BLOCK_COMPRESSED: [u8; 16] = [0x46, 0x1A, 0x79, 0x95, 0xBC, 0x95, 0x6C, 0x4F, 0xA7, 0x0B, 0x05, 0xBC, 0xA1, 0xB7, 0xDF, 0xD2];
+const ENCODING_BLOCK_LZ4: [u8; 16] = [0x8A, 0x34, 0x47, 0x68, 0xA1, 0x63, 0x5C, 0x4F, 0xA1, 0x97, 0x53, 0x80, 0x6F, 0xD9, 0xB1, 0x19];
+const ENCODING_UNCOMPRESSED: [u8; 16] = [0x00, 0x05, 0x86, 0x1B, 0xD8, 0xF7, 0xC1, 0x40, 0xAD, 0x82, 0x75, 0xA4, 0x82, 0x67, 0xE7, 0x14];
+
+/// If the magic is of a KV3 binary version, which may not be supported.
+pub fn is_binary(magic: u32) -> bool {
+    magic == MAGIC_LEGACY || magic & 0xFFFFFF00 == 0x4B563300
+}
+
+/// Reads values of KV3 binary, legacy versions store everything in one buffer.
+struct BinaryReader<'a> {
+    data: &'a [u8],
+    strings: Vec<String>,
+    /// Position of values that aren't in another buffer, which is everything for legacy.
+    position: usize,
+    /// Positions of 1 byte values, 8 byte values & types, for versions that separate them.
+    buffers: Option<[usize; 3]>,
+    depth: usize,
+}
+
+impl<'a> BinaryReader<'a> {
+
+    fn take(position: &mut usize, data: &'a [u8], len: usize) -> Result<&'a [u8], KV3Error> {
+        let bytes = data.get(*position..*position + len).ok_or(KV3Error::DataTooShort)?;
+        *position += len;
+        Ok(bytes)
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], KV3Error> {
+        match &mut self.buffers {
+            Some([bytes, _, _]) => Self::take(bytes, self.data, len),
+            None => Self::take(&mut self.position, self.data, len),
+        }
+    }
+
+    fn read_byte(&mut self) -> Result<u8, KV3Error> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_int(&mut self) -> Result<[u8; 4], KV3Error> {
+        Ok(Self::take(&mut self.position, self.data, 4)?.try_into().unwrap_or_default())
+    }
+
+    fn read_eight(&mut self) -> Result<[u8; 8], KV3Error> {
+        let bytes = match &mut self.buffers {
+            Some([_, eights, _]) => Self::take(eights, self.data, 8)?,
+            None => Self::take(&mut self.position, self.data, 8)?,
+        };
+        Ok(bytes.try_into().unwrap_or_default())
+    }
+
+    fn read_type_byte(&mut self) -> Result<u8, KV3Error> {
+        match &mut self.buffers {
+            Some([_, _, types]) => Ok(Self::take(types, self.data, 1)?[0]),
+            None => Ok(Self::take(&mut self.position, self.data, 1)?[0]),
+        }
+    }
+
+    /// Type & flag of a value, the high bit means the flag follows.
+    fn read_type(&mut self) -> Result<(u8, Option<KV3Flag>), KV3Error> {
+        let kind = self.read_type_byte()?;
+        if kind & 0x80 != 0 {
+            let flag = self.read_type_byte()?;
+            Ok((kind & 0x3F, if flag == 0 { None } else { Some(KV3Flag::new(flag)?) }))
+        } else {
+            Ok((kind, None))
+        }
+    }
+
+    fn read_string(&mut self) -> Result<String, KV3Error> {
+        let index = i32::from_le_bytes(self.read_int()?);
+        match index {
+            -1 => Ok(String::new()),
+            index => self.strings.get(index as usize).cloned().ok_or(KV3Error::StringIndex(index)),
+        }
+    }
+
+    /// Values of arrays may not take any data, so counts are limited by the size of the data instead.
+    fn read_count(&mut self) -> Result<usize, KV3Error> {
+        let count = u32::from_le_bytes(self.read_int()?) as usize;
+        if count > self.data.len() {
+            return Err(KV3Error::DataTooShort);
+        }
+        Ok(count)
+    }
+
+    fn value(&mut self) -> Result<KV3Value, KV3Error> {
+        let (kind, flag) = self.read_type()?;
+        self.typed_value(kind, flag)
+    }
+
+    fn typed_value(&mut self, kind: u8, flag: Option<KV3Flag>) -> Result<KV3Value, KV3Error> {
+        if self.depth >= MAX_DEPTH {
+            return Err(KV3Error::Depth(MAX_DEPTH));
+        }
+        self.depth += 1;
+        let value = self.nested_typed_value(kind, flag);
+        self.depth -= 1;
+        value
+    }
+
+    fn nested_typed_value(&mut self, kind: u8, flag: Option<KV3Flag>) -> Result<KV3Value, KV3Error> {
+        let value = match kind {
+            1 => KV3Value::Null,
+            2 => KV3Value::Bool(self.read_byte()? != 0),
+            3 => KV3Value::Int(i64::from_le_bytes(self.read_eight()?)),
+            4 => KV3Value::UInt(u64::from_le_bytes(self.read_eight()?)),
+            5 => KV3Value::Double(f64::from_le_bytes(self.read_eight()?)),
+            0 | 6 => KV3Value::String(self.read_string()?),
+            7 => {
+                let len = self.read_count()?;
+                KV3Value::Blob(self.read_bytes(len)?.to_vec())
+            },
+            8 => {
+                let count = self.read_count()?;
+                KV3Value::Array((0..count).map(|_| self.value()).collect::<Result<_, _>>()?)
+            },
+            9 => {
+                let count = self.read_count()?;
+                let mut entries = Vec::with_capacity(count.min(self.data.len()));
+                for _ in 0..count {
+                    let key = self.read_string()?;
+                    entries.push((key, self.value()?));
+                }
+                KV3Value::Object(entries)
+            },
+            // Arrays where every value is the same type, 24 has a 1 byte count.
+            10 | 24 => {
+                let count = if kind == 10 { self.read_count()? } else { self.read_byte()? as usize };
+                let (kind, flag) = self.read_type()?;
+                KV3Value::Array((0..count).map(|_| self.typed_value(kind, flag)).collect::<Result<_, _>>()?)
+            },
+            11 => KV3Value::Int(i32::from_le_bytes(self.read_int()?) as i64),
+            12 => KV3Value::UInt(u32::from_le_bytes(self.read_int()?) as u64),
+            13 => KV3Value::Bool(true),
+            14 => KV3Value::Bool(false),
+            15 => KV3Value::Int(0),
+            16 => KV3Value::Int(1),
+            17 => KV3Value::Double(0.0),
+            18 => KV3Value::Double(1.0),
+            19 => KV3Value::Double(f32::from_le_bytes(self.read_int()?) as f64),
+            23 => KV3Value::Int(self.read_byte()? as i64),
+            kind => return Err(KV3Error::UnknownType(kind)),
+        };
+        Ok(match flag {
+            Some(flag) => KV3Value::Flagged(flag, Box::new(value)),
+            None => value,
+        })
+    }
+
+}
+
+/// Null terminated strings from the position.
+fn read_strings(data: &[u8], position: &mut usize, count: usize) -> Result<Vec<String>, KV3Error> {
+    let mut strings = Vec::with_capacity(count.min(data.len()));
+    for _ in 0..count {
+        let rest = data.get(*position..).ok_or(KV3Error::DataTooShort)?;
+        let len = rest.iter().position(|byte| *byte == 0).ok_or(KV3Error::DataTooShort)?;
+        strings.push(String::from_utf8_lossy(&rest[..len]).into_owned());
+        *position += len + 1;
+    }
+    Ok(strings)
+}
+
+fn read_u32(data: &[u8], position: usize) -> Result<u32, KV3Error> {
+    data.get(position..position + 4).map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).ok_or(KV3Error::DataTooShort)
+}
+
+/// Valve's LZ77 of the legacy block compressed encoding.
+fn block_decompress(data: &[u8]) -> Result<Vec<u8>, KV3Error> {
+    let flags = data.get(..4).ok_or(KV3Error::DataTooShort)?;
+    let size = u32::from_le_bytes([flags[0], flags[1], flags[2], 0]) as usize;
+    if flags[3] & 0x80 != 0 {
+        return Ok(data[4..].to_vec());
+    }
+
+    let mut output = Vec::with_capacity(size);
+    let mut position = 4;
+    while output.len() < size {
+        let mask = u16::from_le_bytes(data.get(position..position + 2).ok_or(KV3Error::DataTooShort)?.try_into().unwrap_or_default());
+        position += 2;
+        for bit in 0..16 {
+            if output.len() >= size {
+                break;
+            }
+            if mask & (1 << bit) != 0 {
+                let copy = u16::from_le_bytes(data.get(position..position + 2).ok_or(KV3Error::DataTooShort)?.try_into().unwrap_or_default());
+                position += 2;
+                let offset = (copy >> 4) as usize + 1;
+                let len = (copy & 0xF) as usize + 3;
+                let start = output.len().checked_sub(offset).ok_or(KV3Error::DataTooShort)?;
+                // Copies may overlap what they write.
+                for i in 0..len {
+                    output.push(output[start + i]);
+                }
+            } else {
+                output.push(*data.get(position).ok_or(KV3Error::DataTooShort)?);
+                position += 1;
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// Size is checked before it is allocated, LZ4 can't expand data more than 255 times.
+fn lz4_decompress(data: &[u8], size: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    if size > data.len().saturating_mul(255) {
+        return Err(Box::new(KV3Error::DataTooShort));
+    }
+    Ok(lz4_flex::block::decompress(data, size)?)
+}
+
+fn zstd_decompress(data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut output = Vec::new();
+    std::io::Read::read_to_end(&mut ruzstd::StreamingDecoder::new(data)?, &mut output)?;
+    Ok(output)
+}
+
+fn parse_legacy(data: &[u8]) -> Result<KV3Value, Box<dyn Error>> {
+    let encoding: [u8; 16] = data.get(4..20).ok_or(KV3Error::DataTooShort)?.try_into()?;
+    // Format GUID is 20..36.
+    let body = data.get(36..).ok_or(KV3Error::DataTooShort)?;
+    let buffer = match encoding {
+        ENCODING_UNCOMPRESSED => body.to_vec(),
+        ENCODING_BLOCK_COMPRESSED => block_decompress(body)?,
+        ENCODING_BLOCK_LZ4 => lz4_decompress(body.get(4..).ok_or(KV3Error::DataTooShort)?, read_u32(body, 0)? as usize)?,
+        encoding => return Err(Box::new(KV3Error::UnknownEncoding(encoding))),
+    };
+
+    let mut position = 4;
+    let strings = read_strings(&buffer, &mut position, read_u32(&buffer, 0)? as usize)?;
+    let mut reader = BinaryReader { data: &buffer, strings, position, buffers: None, depth: 0 };
+    Ok(reader.value()?)
+}
+
+/// Buffer of `\x01KV3` & `\x02KV3`, which is 1 byte values, 4 byte values starting with the string count,
+/// 8 byte values, strings, types & a trailer.
+fn parse_buffers(buffer: &[u8], bytes_count: usize, ints_count: usize, eights_count: usize) -> Result<KV3Value, Box<dyn Error>> {
+    let ints = bytes_count.next_multiple_of(4);
+    let strings_count = read_u32(buffer, ints)? as usize;
+    let eights = (ints + ints_count * 4).next_multiple_of(8);
+    let mut position = eights + eights_count * 8;
+    let strings = read_strings(buffer, &mut position, strings_count)?;
+    let mut reader = BinaryReader { data: buffer, strings, position: ints + 4, buffers: Some([0, eights, position]), depth: 0 };
+    Ok(reader.value()?)
+}
+
+/// Parse KV3 binary, which is `VKV\x03`, `\x01KV3` or `\x02KV3` without binary blob blocks.
+pub fn parse_binary(data: &[u8]) -> Result<KV3Value, Box<dyn Error>> {
+    let magic = read_u32(data, 0)?;
+    let header = |index: usize| read_u32(data, 20 + index * 4);
+
+    match magic {
+        MAGIC_LEGACY => parse_legacy(data),
+        MAGIC_V1 => {
+            let (method, bytes_count, ints_count, eights_count) = (header(0)?, header(1)?, header(2)?, header(3)?);
+            let body = data.get(36..).ok_or(KV3Error::DataTooShort)?;
+            let size = read_u32(body, 0)? as usize;
+            let buffer = match method {
+                0 => body.get(4..4 + size).ok_or(KV3Error::DataTooShort)?.to_vec(),
+                1 => lz4_decompress(&body[4..], size)?,
+                method => return Err(Box::new(KV3Error::UnknownCompression(method))),
+            };
+            parse_buffers(&buffer, bytes_count as usize, ints_count as usize, eights_count as usize)
+        },
+        MAGIC_V2 => {
+            // Dictionary id & frame size share the second field.
+            let (method, bytes_count, ints_count, eights_count) = (header(0)?, header(2)?, header(3)?, header(4)?);
+            let (size, compressed_size, block_count) = (header(7)? as usize, header(8)? as usize, header(9)?);
+            if block_count != 0 {
+                return Err(Box::new(KV3Error::UnsupportedBlocks(block_count)));
+            }
+            let body = data.get(64..64 + compressed_size).ok_or(KV3Error::DataTooShort)?;
+            let buffer = match method {
+                0 => body.to_vec(),
+                1 => lz4_decompress(body, size)?,
+                2 => zstd_decompress(body)?,
+                method => return Err(Box::new(KV3Error::UnknownCompression(method))),
+            };
+            parse_buffers(&buffer, bytes_count as usize, ints_count as usize, eights_count as usize)
+        },
+        magic if is_binary(magic) => Err(Box::new(KV3Error::UnsupportedVersion(magic))),
+        _ => Err(Box::new(KV3Error::InvalidMagic)),
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use super::{parse_binary, parse_text, KV3Error, KV3Flag, KV3Value, ENCODING_BLOCK_COMPRESSED, ENCODING_BLOCK_LZ4, ENCODING_UNCOMPRESSED, MAGIC_LEGACY, MAGIC_V1, MAGIC_V2, MAX_DEPTH};
+
+    /// Legacy uncompressed KV3 binary of strings & values.
+    fn legacy(strings: &[&str], values: &[u8]) -> Vec<u8> {
+        let mut data = [MAGIC_LEGACY.to_le_bytes().as_slice(), &ENCODING_UNCOMPRESSED, &[0; 16]].concat();
+        data.extend((strings.len() as u32).to_le_bytes());
+        for str in strings {
+            data.extend(str.as_bytes());
+            data.push(0);
+        }
+        data.extend(values);
+        data
+    }
+
+    const STRINGS: [&str; 8] = ["name", "size", "id", "on", "icon", "scale", "crate", "materials/crate.vmat"];
+
+    /// `{ name = "crate", size = -3, id = u64::MAX, on = true, icon = resource:"materials/crate.vmat", scale = [0.5, 2.0] }`
+    fn expected() -> KV3Value {
+        KV3Value::Object(vec![
+            ("name".to_owned(), KV3Value::String("crate".to_owned())),
+            ("size".to_owned(), KV3Value::Int(-3)),
+            ("id".to_owned(), KV3Value::UInt(u64::MAX)),
+            ("on".to_owned(), KV3Value::Bool(true)),
+            ("icon".to_owned(), KV3Value::Flagged(KV3Flag::Resource, Box::new(KV3Value::String("materials/crate.vmat".to_owned())))),
+            ("scale".to_owned(), KV3Value::Array(vec![KV3Value::Double(0.5), KV3Value::Double(2.0)])),
+        ])
+    }
+
+    fn ints(ints: &[i32]) -> Vec<u8> {
+        ints.iter().flat_map(|int| int.to_le_bytes()).collect()
+    }
+
+    /// Values of `expected` in one buffer, with types before their data.
+    fn legacy_values() -> Vec<u8> {
+        [
+            vec![9], ints(&[6]),
+            ints(&[0]), vec![6], ints(&[6]),
+            ints(&[1]), vec![11], ints(&[-3]),
+            ints(&[2]), vec![4], u64::MAX.to_le_bytes().to_vec(),
+            ints(&[3]), vec![2, 1],
+            ints(&[4]), vec![0x86, 1], ints(&[7]),
+            ints(&[5]), vec![10], ints(&[2]), vec![5], 0.5f64.to_le_bytes().to_vec(), 2.0f64.to_le_bytes().to_vec(),
+        ].concat()
+    }
+
+    /// Values of `expected` in separate buffers, with the counts of bytes, ints & eights.
+    fn buffers() -> (Vec<u8>, [u32; 3]) {
+        let bytes = [1, 0, 0, 0];
+        let ints = ints(&[STRINGS.len() as i32, 6, 0, 6, 1, -3, 2, 3, 4, 7, 5, 2]);
+        let eights = [u64::MAX.to_le_bytes(), 0.5f64.to_le_bytes(), 2.0f64.to_le_bytes()].concat();
+        let strings = STRINGS.iter().flat_map(|str| [str.as_bytes(), &[0]].concat()).collect::<Vec<_>>();
+        let types = [9, 6, 11, 4, 2, 0x86, 1, 10, 5];
+        // 4 bytes of padding before the eights.
+        let buffer = [&bytes[..], &ints, &[0; 4], &eights, &strings, &types, &[0xFF, 0xEE, 0xDD, 0xCC]].concat();
+        (buffer, [1, 12, 3])
+    }
+
+    /// Valve's LZ77, copying the longest earlier match of 3 to 18 bytes.
+    fn block_compress(data: &[u8]) -> Vec<u8> {
+        let mut output = (data.len() as u32).to_le_bytes().to_vec();
+        let mut position = 0;
+        while position < data.len() {
+            let mask_position = output.len();
+            output.extend([0, 0]);
+            let mut mask = 0u16;
+            for bit in 0..16 {
+                if position >= data.len() {
+                    break;
+                }
+                let (offset, len) = (1..=position.min(4096))
+                    .map(|offset| (offset, (0..18.min(data.len() - position)).take_while(|i| data[position - offset + i] == data[position + i]).count()))
+                    .max_by_key(|(_, len)| *len)
+                    .unwrap_or((0, 0));
+                if len >= 3 {
+                    mask |= 1 << bit;
+                    output.extend((((offset - 1) << 4 | (len - 3)) as u16).to_le_bytes());
+                    position += len;
+                } else {
+                    output.push(data[position]);
+                    position += 1;
+                }
+            }
+            output[mask_position..mask_position + 2].copy_from_slice(&mask.to_le_bytes());
+        }
+        output
+    }
+
+    fn is_error(err: Box<dyn Error>, check: impl Fn(&KV3Error) -> bool) -> bool {
+        err.downcast_ref::<KV3Error>().is_some_and(check)
+    }
+
+    #[test]
+    fn kv3_depth() -> Result<(), Box<dyn Error>> {
+        let text = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(parse_text(&text(MAX_DEPTH)).is_ok());
+        assert!(is_error(parse_text(&text(100_000)).unwrap_err(), |err| matches!(err, KV3Error::Depth(_))));
+        assert!(is_error(parse_text(&"resource:".repeat(100_000)).unwrap_err(), |err| matches!(err, KV3Error::Depth(_))));
+
+        // Arrays of 1 array, ending with an empty array.
+        let binary = |depth: usize| legacy(&[], &[[8, 1, 0, 0, 0].repeat(depth - 1), vec![8, 0, 0, 0, 0]].concat());
+        assert!(parse_binary(&binary(MAX_DEPTH)).is_ok());
+        assert!(is_error(parse_binary(&binary(100_000)).unwrap_err(), |err| matches!(err, KV3Error::Depth(_))));
+
+        // Typed arrays of typed arrays.
+        let typed = legacy(&[], &[[10, 1, 0, 0, 0].repeat(100_000), vec![13]].concat());
+        assert!(is_error(parse_binary(&typed).unwrap_err(), |err| matches!(err, KV3Error::Depth(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn kv3_typed_array_count() -> Result<(), Box<dyn Error>> {
+        let value = parse_binary(&legacy(&[], &[10, 3, 0, 0, 0, 13]))?;
+        assert_eq!(value, KV3Value::Array(vec![KV3Value::Bool(true); 3]));
+
+        // Values of true take no data, so a huge count must not be looped over.
+        let err = parse_binary(&legacy(&[], &[10, 0xFF, 0xFF, 0xFF, 0xFF, 13])).unwrap_err();
+        assert!(is_error(err, |err| matches!(err, KV3Error::DataTooShort)));
+        let err = parse_binary(&legacy(&[], &[9, 0xFF, 0xFF, 0xFF, 0xFF])).unwrap_err();
+        assert!(is_error(err, |err| matches!(err, KV3Error::DataTooShort)));
+        Ok(())
+    }
+
+    #[test]
+    fn kv3_text() -> Result<(), Box<dyn Error>> {
+        let text = concat!(
+            "\u{FEFF}<!-- kv3 encoding:text:version{e21c7f3c-8a33-41c5-9977-a76d3a32aa0d} format:generic:version{7412167c-06e9-4698-aff2-e63eb59037e7} -->\n",
+            "{\n",
+            "    // Comment\n",
+            "    name = \"crate\"\n",
+            "    \"quoted key\" = \"tab\\tquote\\\"\"\n",
+            "    /* Block\n comment */\n",
+            "    description = \"\"\"\n",
+            "First line\n",
+            "  \"Second\" line\n",
+            "\"\"\"\n",
+            "    icon = resource:\"materials/crate.vmat\"\n",
+            "    sound = soundevent:resource_name:\"crate.break\"\n",
+            "    data = #[ 00 ff 1A ]\n",
+            "    values = [ -3, 18446744073709551615, 0.5, 1e3, true, false, null, ],\n",
+            "    empty = {}\n",
+            "}\n",
+        );
+        let value = parse_text(text)?;
+        assert_eq!(value, KV3Value::Object(vec![
+            ("name".to_owned(), KV3Value::String("crate".to_owned())),
+            ("quoted key".to_owned(), KV3Value::String("tab\tquote\"".to_owned())),
+            ("description".to_owned(), KV3Value::String("First line\n  \"Second\" line".to_owned())),
+            ("icon".to_owned(), KV3Value::Flagged(KV3Flag::Resource, Box::new(KV3Value::String("materials/crate.vmat".to_owned())))),
+            ("sound".to_owned(), KV3Value::Flagged(KV3Flag::SoundEvent, Box::new(KV3Value::Flagged(KV3Flag::ResourceName, Box::new(KV3Value::String("crate.break".to_owned())))))),
+            ("data".to_owned(), KV3Value::Blob(vec![0x00, 0xFF, 0x1A])),
+            ("values".to_owned(), KV3Value::Array(vec![
+                KV3Value::Int(-3), KV3Value::UInt(u64::MAX), KV3Value::Double(0.5), KV3Value::Double(1000.0),
+                KV3Value::Bool(true), KV3Value::Bool(false), KV3Value::Null,
+            ])),
+            ("empty".to_owned(), KV3Value::Object(vec![])),
+        ]));
+        assert_eq!(value.to_json()["data"], "00ff1a");
+        assert_eq!(KV3Value::load(text.as_bytes())?, value);
+
+        assert!(is_error(parse_text("{ a = unknown:1 }").unwrap_err(), |err| matches!(err, KV3Error::UnknownFlag(_))));
+        assert!(is_error(parse_text("{\n a = 1\n b = ] }").unwrap_err(), |err| matches!(err, KV3Error::UnexpectedChar(3, ']'))));
+        assert!(is_error(parse_text("{ a = \"\"\"never ends").unwrap_err(), |err| matches!(err, KV3Error::UnexpectedEnd)));
+        Ok(())
+    }
+
+    #[test]
+    fn kv3_binary_legacy() -> Result<(), Box<dyn Error>> {
+        let buffer = [ints(&[STRINGS.len() as i32]), STRINGS.iter().flat_map(|str| [str.as_bytes(), &[0]].concat()).collect(), legacy_values()].concat();
+        let header = |encoding: &[u8; 16]| [MAGIC_LEGACY.to_le_bytes().as_slice(), encoding, &[0; 16]].concat();
+
+        assert_eq!(KV3Value::load(&legacy(&STRINGS, &legacy_values()))?, expected());
+
+        let compressed = block_compress(&buffer);
+        assert!(compressed.len() < buffer.len() + 4);
+        assert_eq!(parse_binary(&[header(&ENCODING_BLOCK_COMPRESSED), compressed].concat())?, expected());
+        // High bit of the flags means the buffer is stored as is.
+        let raw = [&(buffer.len() as u32 | 0x8000_0000).to_le_bytes()[..], &buffer].concat();
+        assert_eq!(parse_binary(&[header(&ENCODING_BLOCK_COMPRESSED), raw].concat())?, expected());
+
+        let lz4 = [&(buffer.len() as u32).to_le_bytes()[..], &lz4_flex::block::compress(&buffer)].concat();
+        assert_eq!(parse_binary(&[header(&ENCODING_BLOCK_LZ4), lz4].concat())?, expected());
+        let huge = [&u32::MAX.to_le_bytes()[..], &lz4_flex::block::compress(&buffer)].concat();
+        assert!(is_error(parse_binary(&[header(&ENCODING_BLOCK_LZ4), huge].concat()).unwrap_err(), |err| matches!(err, KV3Error::DataTooShort)));
+
+        assert!(is_error(parse_binary(&[header(&[0xAB; 16]), buffer].concat()).unwrap_err(), |err| matches!(err, KV3Error::UnknownEncoding(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn kv3_binary_v1() -> Result<(), Box<dyn Error>> {
+        let (buffer, [bytes, ints, eights]) = buffers();
+        let v1 = |method: u32, body: &[u8]| [
+            &MAGIC_V1.to_le_bytes()[..], &[0; 16],
+            &method.to_le_bytes(), &bytes.to_le_bytes(), &ints.to_le_bytes(), &eights.to_le_bytes(),
+            &(buffer.len() as u32).to_le_bytes(), body,
+        ].concat();
+
+        assert_eq!(KV3Value::load(&v1(0, &buffer))?, expected());
+        assert_eq!(parse_binary(&v1(1, &lz4_flex::block::compress(&buffer)))?, expected());
+        assert!(is_error(parse_binary(&v1(2, &buffer)).unwrap_err(), |err| matches!(err, KV3Error::UnknownCompression(2))));
+
+        let mut huge = v1(1, &lz4_flex::block::compress(&buffer));
+        huge[36..40].copy_from_slice(&0xFFFFFFF0u32.to_le_bytes());
+        assert!(is_error(parse_binary(&huge).unwrap_err(), |err| matches!(err, KV3Error::DataTooShort)));
+        Ok(())
+    }
+
+    #[test]
+    fn kv3_binary_v2() -> Result<(), Box<dyn Error>> {
+        let (buffer, [bytes, ints, eights]) = buffers();
+        let v2 = |method: u32, body: &[u8], blocks: u32| {
+            let header = [method, 0, bytes, ints, eights, 0, 0, buffer.len() as u32, body.len() as u32, blocks, 0];
+            [&MAGIC_V2.to_le_bytes()[..], &[0; 16], &header.iter().flat_map(|field| field.to_le_bytes()).collect::<Vec<_>>(), body].concat()
+        };
+
+        assert_eq!(v2(0, &[], 0).len(), 64);
+        assert_eq!(KV3Value::load(&v2(0, &buffer, 0))?, expected());
+        assert_eq!(parse_binary(&v2(1, &lz4_flex::block::compress(&buffer), 0))?, expected());
+        assert_eq!(parse_binary(&v2(2, &zstd::bulk::compress(&buffer, 0)?, 0))?, expected());
+        assert!(is_error(parse_binary(&v2(0, &buffer, 1)).unwrap_err(), |err| matches!(err, KV3Error::UnsupportedBlocks(1))));
+
+        // Uncompressed size is checked against what LZ4 can expand to, before it is allocated.
+        let mut huge = v2(1, &lz4_flex::block::compress(&buffer), 0);
+        huge[20 + 7 * 4..20 + 8 * 4].copy_from_slice(&0xFFFFFFF0u32.to_le_bytes());
+        assert!(is_error(parse_binary(&huge).unwrap_err(), |err| matches!(err, KV3Error::DataTooShort)));
+
+        let mut bad_string = v2(0, &buffer, 0);
+        let index = 64 + 4 + 4 * 3;
+        bad_string[index..index + 4].copy_from_slice(&100i32.to_le_bytes());
+        assert!(is_error(parse_binary(&bad_string).unwrap_err(), |err| matches!(err, KV3Error::StringIndex(100))));
+        assert!(is_error(parse_binary(&0x4B563305u32.to_le_bytes()).unwrap_err(), |err| matches!(err, KV3Error::UnsupportedVersion(_))));
+        Ok(())
+    }
+}
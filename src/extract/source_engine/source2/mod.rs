@@ -0,0 +1,2 @@
+pub mod kv3;
+pub mod resource;
@@ -0,0 +1,86 @@
+// https://developer.valvesoftware.com/wiki/Resource_Compiler
+// https://github.com/ValveResourceFormat/ValveResourceFormat
+
+use std::{error::Error, fmt, io::{Cursor, Seek, SeekFrom}};
+use crate::util::read_ext::ReadExt;
+use super::kv3::{self, KV3Value};
+
+
+
+#[derive(Debug)]
+pub enum ResourceError {
+    UnsupportedHeaderVersion(u16),
+    BlockOutOfBounds(String),
+}
+
+impl fmt::Display for ResourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedHeaderVersion(version) => write!(f, "Resource header version {} is not supported.", version),
+            Self::BlockOutOfBounds(kind) => write!(f, "Resource block \"{}\" is out of bounds.", kind),
+        }
+    }
+}
+
+impl Error for ResourceError { }
+
+
+
+#[derive(Debug, Clone)]
+pub struct ResourceBlock {
+    /// Type of the block, such as `DATA`, `RED2` or `MDAT`.
+    pub kind: String,
+    pub data: Vec<u8>,
+}
+
+impl ResourceBlock {
+
+    /// If the block is KV3 binary, other blocks may be KV3 text.
+    pub fn is_kv3(&self) -> bool {
+        self.data.get(..4).is_some_and(|magic| kv3::is_binary(u32::from_le_bytes([magic[0], magic[1], magic[2], magic[3]])))
+    }
+
+    pub fn kv3(&self) -> Result<KV3Value, Box<dyn Error>> {
+        KV3Value::load(&self.data)
+    }
+
+}
+
+/// Compiled Source 2 resource, such as `.vmat_c` or `.vmdl_c`, which is a list of blocks.
+#[derive(Debug, Clone)]
+pub struct Resource {
+    pub version: u16,
+    pub blocks: Vec<ResourceBlock>,
+}
+
+impl Resource {
+
+    const HEADER_VERSION: u16 = 12;
+
+    pub fn load(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = Cursor::new(data);
+        let _file_size: u32 = reader.read_primitive()?;
+        let header_version: u16 = reader.read_primitive()?;
+        if header_version != Self::HEADER_VERSION {
+            return Err(Box::new(ResourceError::UnsupportedHeaderVersion(header_version)));
+        }
+        let version: u16 = reader.read_primitive()?;
+
+        // Offsets are relative to where they are stored.
+        let blocks_offset = reader.position() + reader.read_primitive::<u32>()? as u64;
+        let block_count: u32 = reader.read_primitive()?;
+        reader.seek(SeekFrom::Start(blocks_offset))?;
+
+        let mut blocks = Vec::new();
+        for _ in 0..block_count {
+            let kind = String::from_utf8_lossy(&reader.read_primitive::<[u8; 4]>()?).into_owned();
+            let offset = reader.position() as usize + reader.read_primitive::<u32>()? as usize;
+            let size: u32 = reader.read_primitive()?;
+            let data = data.get(offset..offset + size as usize).ok_or_else(|| ResourceError::BlockOutOfBounds(kind.clone()))?;
+            blocks.push(ResourceBlock { kind, data: data.to_vec() });
+        }
+
+        Ok(Self { version, blocks })
+    }
+
+}
@@ -0,0 +1,444 @@
+// https://developer.valvesoftware.com/wiki/Binary_VDF
+// https://github.com/SteamDatabase/SteamAppInfo
+
+use std::{error::Error, fmt, io::{Cursor, Read, Seek, SeekFrom}};
+use serde_json::{json, Map, Value};
+use crate::util::read_ext::ReadExt;
+
+
+
+#[derive(Debug)]
+pub enum VDFError {
+    InvalidMagic,
+    UnknownType(u8),
+    StringIndex(i32),
+    Depth(usize),
+}
+
+impl fmt::Display for VDFError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidMagic => write!(f, "VDF has invalid magic."),
+            Self::UnknownType(kind) => write!(f, "VDF has unknown type {}.", kind),
+            Self::StringIndex(index) => write!(f, "VDF key refers to missing string {}.", index),
+            Self::Depth(max) => write!(f, "VDF is nested more than {} deep.", max),
+        }
+    }
+}
+
+impl Error for VDFError { }
+
+/// Blocks nested deeper than this are an error, instead of overflowing the stack.
+const MAX_DEPTH: usize = 256;
+
+
+
+/// Null terminated string, which may not be UTF-8.
+fn read_cstring(data: &mut impl Read) -> Result<String, Box<dyn Error>> {
+    let mut str = Vec::new();
+    loop {
+        match data.read_primitive::<u8>()? {
+            0 => return Ok(String::from_utf8_lossy(&str).into_owned()),
+            byte => str.push(byte),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BinaryKeyValue {
+    Block(BinaryKeyValues),
+    String(String),
+    Int(i32),
+    Float(f32),
+    Pointer(u32),
+    Color([u8; 4]),
+    UInt64(u64),
+    Int64(i64),
+}
+
+impl BinaryKeyValue {
+
+    pub fn to_json(&self) -> Value {
+        match self {
+            BinaryKeyValue::Block(block) => block.to_json(),
+            BinaryKeyValue::String(str) => Value::String(str.clone()),
+            BinaryKeyValue::Int(int) => json!(int),
+            BinaryKeyValue::Float(float) => json!(float),
+            BinaryKeyValue::Pointer(pointer) => json!(pointer),
+            BinaryKeyValue::Color(color) => json!(color),
+            BinaryKeyValue::UInt64(int) => json!(int),
+            BinaryKeyValue::Int64(int) => json!(int),
+        }
+    }
+
+}
+
+/// Binary KeyValues, which are typed unlike KeyValues text.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BinaryKeyValues {
+    pub entries: Vec<(String, BinaryKeyValue)>,
+}
+
+impl BinaryKeyValues {
+
+    pub fn get(&self, key: &str) -> Option<&BinaryKeyValue> {
+        self.entries.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)).map(|(_, value)| value)
+    }
+
+    /// Entries until the end of the block, the root at depth 0 may also end at the end of the data.
+    /// Keys are an index into `keys` when given, like in `appinfo.vdf` 0x29.
+    fn load_block(data: &mut impl Read, keys: Option<&[String]>, depth: usize) -> Result<Self, Box<dyn Error>> {
+        let root = depth == 0;
+        let mut entries = Vec::new();
+        loop {
+            let mut kind = [0u8];
+            if data.read(&mut kind)? == 0 && root {
+                break;
+            }
+            let kind = match kind[0] {
+                // Alternate end is written by some Steam files.
+                0x08 | 0x0B => break,
+                kind => kind,
+            };
+
+            let key = match keys {
+                Some(keys) => {
+                    let index: i32 = data.read_primitive()?;
+                    keys.get(index as usize).ok_or(VDFError::StringIndex(index))?.clone()
+                },
+                None => read_cstring(data)?,
+            };
+
+            let value = match kind {
+                0x00 if depth >= MAX_DEPTH => return Err(Box::new(VDFError::Depth(MAX_DEPTH))),
+                0x00 => BinaryKeyValue::Block(Self::load_block(data, keys, depth + 1)?),
+                0x01 => BinaryKeyValue::String(read_cstring(data)?),
+                0x02 => BinaryKeyValue::Int(data.read_primitive()?),
+                0x03 => BinaryKeyValue::Float(data.read_primitive()?),
+                0x04 => BinaryKeyValue::Pointer(data.read_primitive()?),
+                0x06 => BinaryKeyValue::Color([data.read_primitive()?, data.read_primitive()?, data.read_primitive()?, data.read_primitive()?]),
+                0x07 => BinaryKeyValue::UInt64(data.read_primitive()?),
+                0x0A => BinaryKeyValue::Int64(data.read_primitive()?),
+                kind => return Err(Box::new(VDFError::UnknownType(kind))),
+            };
+            entries.push((key, value));
+        }
+        Ok(Self { entries })
+    }
+
+    /// Binary KeyValues, such as `shortcuts.vdf`.
+    pub fn load(data: &mut impl Read) -> Result<Self, Box<dyn Error>> {
+        Self::load_block(data, None, 0)
+    }
+
+    /// `.vbkv` file, which is binary KeyValues after a checksum.
+    pub fn load_vbkv(data: &mut impl Read) -> Result<Self, Box<dyn Error>> {
+        if !data.check_magic_string("VBKV")? {
+            return Err(Box::new(VDFError::InvalidMagic));
+        }
+        let _crc: u32 = data.read_primitive()?;
+        Self::load(data)
+    }
+
+    /// Convert to a JSON object, repeated keys are an array of their values.
+    pub fn to_json(&self) -> Value {
+        let mut map = Map::new();
+        for (key, value) in &self.entries {
+            let value = value.to_json();
+            match map.get_mut(key) {
+                Some(Value::Array(values)) => values.push(value),
+                Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+                None => { map.insert(key.clone(), value); },
+            }
+        }
+        Value::Object(map)
+    }
+
+}
+
+
+
+
+
+#[derive(Debug, Clone)]
+pub struct AppInfoApp {
+    pub id: u32,
+    pub info_state: u32,
+    pub last_updated: u32,
+    pub pics_token: u64,
+    pub sha1: [u8; 20],
+    pub change_number: u32,
+    /// SHA-1 of the binary KeyValues, since 0x28.
+    pub binary_sha1: Option<[u8; 20]>,
+    pub data: BinaryKeyValues,
+}
+
+impl AppInfoApp {
+
+    pub fn to_json(&self) -> Value {
+        let hex = |hash: &[u8; 20]| hash.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        json!({
+            "id": self.id,
+            "info_state": self.info_state,
+            "last_updated": self.last_updated,
+            "pics_token": self.pics_token,
+            "sha1": hex(&self.sha1),
+            "change_number": self.change_number,
+            "binary_sha1": self.binary_sha1.as_ref().map(hex),
+            "data": self.data.to_json(),
+        })
+    }
+
+}
+
+/// Steam's `appinfo.vdf` cache of app metadata.
+#[derive(Debug, Clone)]
+pub struct AppInfo {
+    /// Version from 0x27 to 0x29, 0x29 stores keys in a string table.
+    pub version: u8,
+    pub universe: u32,
+    pub apps: Vec<AppInfoApp>,
+}
+
+impl AppInfo {
+
+    pub const MAGIC: u32 = 0x07564400;
+
+    pub fn is_appinfo(magic: u32) -> bool {
+        magic & 0xFFFFFF00 == Self::MAGIC && (0x27..=0x29).contains(&(magic & 0xFF))
+    }
+
+    pub fn load(mut data: impl Read + Seek) -> Result<Self, Box<dyn Error>> {
+        let magic: u32 = data.read_primitive()?;
+        if !Self::is_appinfo(magic) {
+            return Err(Box::new(VDFError::InvalidMagic));
+        }
+        let version = (magic & 0xFF) as u8;
+        let universe: u32 = data.read_primitive()?;
+
+        let keys = if version >= 0x29 {
+            let offset: u64 = data.read_primitive()?;
+            let apps_offset = data.stream_position()?;
+            data.seek(SeekFrom::Start(offset))?;
+            let count: u32 = data.read_primitive()?;
+            let keys = (0..count).map(|_| read_cstring(&mut data)).collect::<Result<Vec<_>, _>>()?;
+            data.seek(SeekFrom::Start(apps_offset))?;
+            Some(keys)
+        } else {
+            None
+        };
+
+        let mut apps = Vec::new();
+        loop {
+            let id: u32 = data.read_primitive()?;
+            if id == 0 {
+                break;
+            }
+            let size: u32 = data.read_primitive()?;
+            // Size is only allocated as it is read, so a bad size fails on the data after it instead.
+            let mut app = Vec::new();
+            (&mut data).take(size as u64).read_to_end(&mut app)?;
+            let mut app = Cursor::new(app);
+            apps.push(AppInfoApp {
+                id,
+                info_state: app.read_primitive()?,
+                last_updated: app.read_primitive()?,
+                pics_token: app.read_primitive()?,
+                sha1: app.read_primitive()?,
+                change_number: app.read_primitive()?,
+                binary_sha1: if version >= 0x28 { Some(app.read_primitive()?) } else { None },
+                data: BinaryKeyValues::load_block(&mut app, keys.as_deref(), 0)?,
+            });
+        }
+
+        Ok(Self { version, universe, apps })
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "version": self.version,
+            "universe": self.universe,
+            "apps": self.apps.iter().map(AppInfoApp::to_json).collect::<Vec<_>>(),
+        })
+    }
+
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::{error::Error, io::Cursor};
+    use serde_json::json;
+    use super::{AppInfo, BinaryKeyValue, BinaryKeyValues, VDFError, MAX_DEPTH};
+
+    /// Entry of binary KeyValues with a null terminated key.
+    fn entry(kind: u8, key: &str, value: &[u8]) -> Vec<u8> {
+        [&[kind], key.as_bytes(), &[0], value].concat()
+    }
+
+    fn cstring(str: &str) -> Vec<u8> {
+        [str.as_bytes(), &[0]].concat()
+    }
+
+    /// `shortcuts.vdf` with a shortcut that has a tag.
+    fn shortcuts() -> Vec<u8> {
+        let tags = [entry(0x01, "0", &cstring("Favorite")), vec![0x08]].concat();
+        let shortcut = [
+            entry(0x02, "appid", &(-123456789i32).to_le_bytes()),
+            entry(0x01, "AppName", &cstring("Game")),
+            entry(0x01, "Exe", &cstring("\"C:\\Game\\game.exe\"")),
+            entry(0x02, "LastPlayTime", &1700000000i32.to_le_bytes()),
+            entry(0x00, "tags", &tags),
+            vec![0x08],
+        ].concat();
+        [entry(0x00, "shortcuts", &[entry(0x00, "0", &shortcut), vec![0x08]].concat()), vec![0x08, 0x08]].concat()
+    }
+
+    #[test]
+    fn vdf_depth() -> Result<(), Box<dyn Error>> {
+        let nested = |depth: usize| [[0x00, b'a', 0x00].repeat(depth), [0x08].repeat(depth)].concat();
+
+        let block = BinaryKeyValues::load(&mut Cursor::new(nested(MAX_DEPTH)))?;
+        assert_eq!(block.entries.len(), 1);
+
+        let err = BinaryKeyValues::load(&mut Cursor::new(nested(100_000))).unwrap_err();
+        assert!(matches!(err.downcast_ref::<VDFError>(), Some(VDFError::Depth(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn vdf_shortcuts() -> Result<(), Box<dyn Error>> {
+        let vdf = BinaryKeyValues::load(&mut Cursor::new(shortcuts()))?;
+        let Some(BinaryKeyValue::Block(shortcut)) = vdf.get("shortcuts").and_then(|shortcuts| match shortcuts {
+            BinaryKeyValue::Block(shortcuts) => shortcuts.get("0"),
+            _ => None,
+        }) else {
+            panic!("Shortcut is missing");
+        };
+        // Keys are case insensitive.
+        assert_eq!(shortcut.get("appname"), Some(&BinaryKeyValue::String("Game".to_owned())));
+        assert_eq!(shortcut.get("appid"), Some(&BinaryKeyValue::Int(-123456789)));
+        assert_eq!(vdf.to_json(), json!({
+            "shortcuts": {
+                "0": {
+                    "appid": -123456789,
+                    "AppName": "Game",
+                    "Exe": "\"C:\\Game\\game.exe\"",
+                    "LastPlayTime": 1700000000,
+                    "tags": { "0": "Favorite" },
+                },
+            },
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn vdf_types() -> Result<(), Box<dyn Error>> {
+        let data = [
+            entry(0x03, "float", &1.5f32.to_le_bytes()),
+            entry(0x04, "pointer", &7u32.to_le_bytes()),
+            entry(0x06, "color", &[1, 2, 3, 4]),
+            entry(0x07, "uint64", &u64::MAX.to_le_bytes()),
+            entry(0x0A, "int64", &i64::MIN.to_le_bytes()),
+            entry(0x01, "repeated", &cstring("a")),
+            entry(0x01, "repeated", &cstring("b")),
+            entry(0x01, "repeated", &cstring("c")),
+            // Alternate end.
+            vec![0x0B],
+        ].concat();
+        let vdf = BinaryKeyValues::load(&mut Cursor::new(data))?;
+        assert_eq!(vdf.entries.len(), 8);
+        assert_eq!(vdf.to_json(), json!({
+            "float": 1.5,
+            "pointer": 7,
+            "color": [1, 2, 3, 4],
+            "uint64": u64::MAX,
+            "int64": i64::MIN,
+            "repeated": ["a", "b", "c"],
+        }));
+
+        let err = BinaryKeyValues::load(&mut Cursor::new(entry(0x05, "wide", &[]))).unwrap_err();
+        assert!(matches!(err.downcast_ref::<VDFError>(), Some(VDFError::UnknownType(0x05))));
+        // Only the root may end at the end of the data.
+        assert!(BinaryKeyValues::load(&mut Cursor::new(entry(0x00, "block", &entry(0x02, "int", &[1, 0, 0, 0])))).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn vdf_vbkv() -> Result<(), Box<dyn Error>> {
+        let data = [&b"VBKV"[..], &0x12345678u32.to_le_bytes(), &shortcuts()].concat();
+        let vdf = BinaryKeyValues::load_vbkv(&mut Cursor::new(data))?;
+        assert_eq!(vdf, BinaryKeyValues::load(&mut Cursor::new(shortcuts()))?);
+
+        let err = BinaryKeyValues::load_vbkv(&mut Cursor::new(shortcuts())).unwrap_err();
+        assert!(matches!(err.downcast_ref::<VDFError>(), Some(VDFError::InvalidMagic)));
+        Ok(())
+    }
+
+    #[test]
+    fn vdf_appinfo() -> Result<(), Box<dyn Error>> {
+        // Keys of 0x29 are indexes into the string table at the end.
+        let keys = ["appinfo", "appid", "common", "name", "type"];
+        let kv = |version: u8| {
+            let key = |index: i32| match version {
+                0x29 => index.to_le_bytes().to_vec(),
+                _ => cstring(keys[index as usize]),
+            };
+            [
+                vec![0x00], key(0),
+                vec![0x02], key(1), 440u32.to_le_bytes().to_vec(),
+                vec![0x00], key(2),
+                vec![0x01], key(3), cstring("Team Fortress 2"),
+                vec![0x01], key(4), cstring("Game"),
+                vec![0x08, 0x08, 0x08],
+            ].concat()
+        };
+        let appinfo = |version: u8| {
+            let mut app = [1u32.to_le_bytes(), 1700000000u32.to_le_bytes()].concat();
+            app.extend(0xABCDu64.to_le_bytes());
+            app.extend([0x11; 20]);
+            app.extend(9000u32.to_le_bytes());
+            if version >= 0x28 {
+                app.extend([0x22; 20]);
+            }
+            app.extend(kv(version));
+
+            let mut data = [(AppInfo::MAGIC | version as u32).to_le_bytes(), 1u32.to_le_bytes()].concat();
+            let table_offset = data.len() + if version == 0x29 { 8 } else { 0 };
+            let apps = [&440u32.to_le_bytes()[..], &(app.len() as u32).to_le_bytes(), &app, &0u32.to_le_bytes()].concat();
+            if version == 0x29 {
+                data.extend(((table_offset + apps.len()) as u64).to_le_bytes());
+            }
+            data.extend(apps);
+            if version == 0x29 {
+                data.extend((keys.len() as u32).to_le_bytes());
+                data.extend(keys.iter().flat_map(|key| cstring(key)));
+            }
+            data
+        };
+
+        for version in 0x27..=0x29 {
+            let data = appinfo(version);
+            assert!(AppInfo::is_appinfo(u32::from_le_bytes(data[..4].try_into()?)));
+            let info = AppInfo::load(Cursor::new(data))?;
+            assert_eq!((info.version, info.universe, info.apps.len()), (version, 1, 1));
+            let app = &info.apps[0];
+            assert_eq!((app.id, app.info_state, app.last_updated, app.pics_token, app.change_number), (440, 1, 1700000000, 0xABCD, 9000));
+            assert_eq!(app.sha1, [0x11; 20]);
+            assert_eq!(app.binary_sha1, if version >= 0x28 { Some([0x22; 20]) } else { None });
+            assert_eq!(app.data.to_json(), json!({ "appinfo": { "appid": 440, "common": { "name": "Team Fortress 2", "type": "Game" } } }));
+        }
+
+        // Key index outside of the string table.
+        let mut data = appinfo(0x29);
+        let index = 16 + 8 + 4 + 4 + 8 + 20 + 4 + 20 + 1;
+        data[index..index + 4].copy_from_slice(&7i32.to_le_bytes());
+        let err = AppInfo::load(Cursor::new(data)).unwrap_err();
+        assert!(matches!(err.downcast_ref::<VDFError>(), Some(VDFError::StringIndex(7))));
+
+        assert!(!AppInfo::is_appinfo(AppInfo::MAGIC | 0x26));
+        assert!(AppInfo::load(Cursor::new((AppInfo::MAGIC | 0x2A).to_le_bytes())).is_err());
+        Ok(())
+    }
+}
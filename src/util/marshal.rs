@@ -0,0 +1,325 @@
+// https://github.com/python/cpython/blob/main/Python/marshal.c
+
+use std::{error::Error, fmt, io::{self, Read}};
+use super::read_ext::ReadExt;
+
+
+
+#[derive(Debug)]
+pub enum MarshalError {
+    InvalidType(u8),
+    InvalidReference(usize),
+    InvalidString,
+}
+
+impl fmt::Display for MarshalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidType(kind) => write!(f, "Marshal invalid type {:#x}.", kind),
+            Self::InvalidReference(index) => write!(f, "Marshal reference {} does not exist.", index),
+            Self::InvalidString => write!(f, "Marshal string is not valid UTF-8."),
+        }
+    }
+}
+
+impl Error for MarshalError { }
+
+
+
+#[derive(Debug, Clone)]
+pub enum Marshal {
+    Null,
+    None,
+    Bool(bool),
+    StopIteration,
+    Ellipsis,
+    Int(i64),
+    /// Digits are base 2^15, least significant first.
+    Long { negative: bool, digits: Vec<u16> },
+    Float(f64),
+    Complex(f64, f64),
+    Bytes(Vec<u8>),
+    String(String),
+    Tuple(Vec<Marshal>),
+    List(Vec<Marshal>),
+    Dict(Vec<(Marshal, Marshal)>),
+    Set(Vec<Marshal>),
+    FrozenSet(Vec<Marshal>),
+    Code(Box<MarshalCode>),
+}
+
+/// Code object, fields that do not exist in the Python version are left empty.
+#[derive(Debug, Clone, Default)]
+pub struct MarshalCode {
+    pub argcount: i32,
+    pub posonlyargcount: i32,
+    pub kwonlyargcount: i32,
+    pub nlocals: i32,
+    pub stacksize: i32,
+    pub flags: i32,
+    pub code: Vec<u8>,
+    pub consts: Vec<Marshal>,
+    pub names: Vec<String>,
+    /// `co_varnames` before Python 3.11, `co_localsplusnames` after.
+    pub varnames: Vec<String>,
+    pub freevars: Vec<String>,
+    pub cellvars: Vec<String>,
+    pub filename: String,
+    pub name: String,
+    pub qualname: String,
+    pub firstlineno: i32,
+}
+
+impl Marshal {
+
+    fn into_items(self) -> Vec<Marshal> {
+        match self {
+            Marshal::Tuple(items) | Marshal::List(items) | Marshal::Set(items) | Marshal::FrozenSet(items) => items,
+            _ => Vec::new(),
+        }
+    }
+
+    fn into_string(self) -> String {
+        match self {
+            Marshal::String(str) => str,
+            Marshal::Bytes(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            _ => String::new(),
+        }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Marshal::Bytes(bytes) => bytes,
+            Marshal::String(str) => str.into_bytes(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn into_strings(self) -> Vec<String> {
+        self.into_items().into_iter().map(Marshal::into_string).collect()
+    }
+
+}
+
+
+
+const FLAG_REF: u8 = 0x80;
+
+/// Python marshal reader, the format of code objects depends on the Python version that wrote them.
+#[derive(Debug)]
+pub struct MarshalParser {
+    version: (u8, u8),
+    references: Vec<Option<Marshal>>,
+    /// Python 2 interned strings.
+    interned: Vec<Marshal>,
+}
+
+impl MarshalParser {
+
+    pub fn new(version: (u8, u8)) -> Self {
+        Self { version, references: Vec::new(), interned: Vec::new() }
+    }
+
+    /// Lengths are not trusted, only as much as exists in data is allocated.
+    fn read_bytes(data: &mut impl Read, len: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut bytes = Vec::new();
+        data.take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() < len {
+            return Err(Box::new(io::Error::from(io::ErrorKind::UnexpectedEof)));
+        }
+        Ok(bytes)
+    }
+
+    fn read_string_len(data: &mut impl Read, len: usize) -> Result<String, Box<dyn Error>> {
+        String::from_utf8(Self::read_bytes(data, len)?).map_err(|_| Box::new(MarshalError::InvalidString) as Box<dyn Error>)
+    }
+
+    fn read_items(&mut self, data: &mut impl Read, len: usize) -> Result<Vec<Marshal>, Box<dyn Error>> {
+        (0..len).map(|_| self.read(data)).collect()
+    }
+
+    fn read_code(&mut self, data: &mut impl Read) -> Result<MarshalCode, Box<dyn Error>> {
+        let mut code = MarshalCode::default();
+        let (major, minor) = self.version;
+
+        code.argcount = data.read_primitive()?;
+        if (major, minor) >= (3, 8) {
+            code.posonlyargcount = data.read_primitive()?;
+        }
+        if major >= 3 {
+            code.kwonlyargcount = data.read_primitive()?;
+        }
+        if (major, minor) < (3, 11) {
+            code.nlocals = data.read_primitive()?;
+        }
+        code.stacksize = data.read_primitive()?;
+        code.flags = data.read_primitive()?;
+        code.code = self.read(data)?.into_bytes();
+        code.consts = self.read(data)?.into_items();
+        code.names = self.read(data)?.into_strings();
+        code.varnames = self.read(data)?.into_strings();
+        if (major, minor) < (3, 11) {
+            code.freevars = self.read(data)?.into_strings();
+            code.cellvars = self.read(data)?.into_strings();
+        } else {
+            self.read(data)?; // co_localspluskinds
+        }
+        code.filename = self.read(data)?.into_string();
+        code.name = self.read(data)?.into_string();
+        if (major, minor) >= (3, 11) {
+            code.qualname = self.read(data)?.into_string();
+        } else {
+            code.qualname = code.name.clone();
+        }
+        code.firstlineno = data.read_primitive()?;
+        self.read(data)?; // co_lnotab or co_linetable
+        if (major, minor) >= (3, 11) {
+            self.read(data)?; // co_exceptiontable
+        }
+
+        Ok(code)
+    }
+
+    pub fn read(&mut self, data: &mut impl Read) -> Result<Marshal, Box<dyn Error>> {
+        let kind: u8 = data.read_primitive()?;
+
+        // Python 2 does not have references, so the flag is never set.
+        let reference = if kind & FLAG_REF != 0 && self.version.0 >= 3 {
+            self.references.push(None);
+            Some(self.references.len() - 1)
+        } else {
+            None
+        };
+
+        let value = match kind & !FLAG_REF {
+            b'0' => Marshal::Null,
+            b'N' => Marshal::None,
+            b'F' => Marshal::Bool(false),
+            b'T' => Marshal::Bool(true),
+            b'S' => Marshal::StopIteration,
+            b'.' => Marshal::Ellipsis,
+            b'i' => Marshal::Int(data.read_primitive::<i32>()?.into()),
+            b'I' => Marshal::Int(data.read_primitive::<i64>()?),
+            b'l' => {
+                let len: i32 = data.read_primitive()?;
+                let digits = (0..len.unsigned_abs()).map(|_| data.read_primitive::<u16>()).collect::<Result<Vec<_>, _>>()?;
+                Marshal::Long { negative: len < 0, digits }
+            },
+            b'f' => {
+                let len: u8 = data.read_primitive()?;
+                Marshal::Float(Self::read_string_len(data, len as usize)?.parse().unwrap_or(f64::NAN))
+            },
+            b'g' => Marshal::Float(data.read_primitive()?),
+            b'x' => {
+                let len: u8 = data.read_primitive()?;
+                let real = Self::read_string_len(data, len as usize)?.parse().unwrap_or(f64::NAN);
+                let len: u8 = data.read_primitive()?;
+                let imag = Self::read_string_len(data, len as usize)?.parse().unwrap_or(f64::NAN);
+                Marshal::Complex(real, imag)
+            },
+            b'y' => Marshal::Complex(data.read_primitive()?, data.read_primitive()?),
+            b's' => {
+                let len: u32 = data.read_primitive()?;
+                Marshal::Bytes(Self::read_bytes(data, len as usize)?)
+            },
+            // Python 2 interned str, Python 3 interned unicode.
+            b't' if self.version.0 < 3 => {
+                let len: u32 = data.read_primitive()?;
+                let value = Marshal::Bytes(Self::read_bytes(data, len as usize)?);
+                self.interned.push(value.clone());
+                value
+            },
+            b'R' if self.version.0 < 3 => {
+                let index: u32 = data.read_primitive()?;
+                self.interned.get(index as usize).cloned().ok_or(MarshalError::InvalidReference(index as usize))?
+            },
+            b'u' | b't' => {
+                let len: u32 = data.read_primitive()?;
+                Marshal::String(String::from_utf8_lossy(&Self::read_bytes(data, len as usize)?).into_owned())
+            },
+            b'a' | b'A' => {
+                let len: u32 = data.read_primitive()?;
+                Marshal::String(Self::read_string_len(data, len as usize)?)
+            },
+            b'z' | b'Z' => {
+                let len: u8 = data.read_primitive()?;
+                Marshal::String(Self::read_string_len(data, len as usize)?)
+            },
+            b'(' => {
+                let len: u32 = data.read_primitive()?;
+                Marshal::Tuple(self.read_items(data, len as usize)?)
+            },
+            b')' => {
+                let len: u8 = data.read_primitive()?;
+                Marshal::Tuple(self.read_items(data, len as usize)?)
+            },
+            b'[' => {
+                let len: u32 = data.read_primitive()?;
+                Marshal::List(self.read_items(data, len as usize)?)
+            },
+            b'<' => {
+                let len: u32 = data.read_primitive()?;
+                Marshal::Set(self.read_items(data, len as usize)?)
+            },
+            b'>' => {
+                let len: u32 = data.read_primitive()?;
+                Marshal::FrozenSet(self.read_items(data, len as usize)?)
+            },
+            b'{' => {
+                let mut items = Vec::new();
+                loop {
+                    let key = self.read(data)?;
+                    if matches!(key, Marshal::Null) { break }
+                    items.push((key, self.read(data)?));
+                }
+                Marshal::Dict(items)
+            },
+            b'c' => Marshal::Code(Box::new(self.read_code(data)?)),
+            b'r' => {
+                let index: u32 = data.read_primitive()?;
+                match self.references.get(index as usize) {
+                    Some(Some(value)) => value.clone(),
+                    _ => return Err(Box::new(MarshalError::InvalidReference(index as usize))),
+                }
+            },
+            kind => return Err(Box::new(MarshalError::InvalidType(kind))),
+        };
+
+        if let Some(index) = reference {
+            self.references[index] = Some(value.clone());
+        }
+
+        Ok(value)
+    }
+
+    pub fn parse(data: &mut impl Read, version: (u8, u8)) -> Result<Marshal, Box<dyn Error>> {
+        MarshalParser::new(version).read(data)
+    }
+
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::{error::Error, io::Cursor};
+    use super::{Marshal, MarshalParser};
+
+    #[test]
+    fn marshal_strings() -> Result<(), Box<dyn Error>> {
+        // `("hi", <reference to "hi">)`
+        let data = b"(\x02\x00\x00\x00\xf5\x02\x00\x00\x00hir\x00\x00\x00\x00";
+        let Marshal::Tuple(items) = MarshalParser::parse(&mut Cursor::new(data), (3, 8))? else { panic!("Not a tuple.") };
+        assert!(items.iter().all(|item| matches!(item, Marshal::String(str) if str == "hi")));
+        assert_eq!(items.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn marshal_truncated() {
+        // Lengths larger than the data are an error, not an allocation or padding.
+        for data in [&b"s\xff\xff\xff\xff"[..], b"u\x04\x00\x00\x00abc", b"a\x02\x00\x00\x00h", b"(\xff\xff\xff\xff"] {
+            assert!(MarshalParser::parse(&mut Cursor::new(data), (3, 8)).is_err());
+        }
+    }
+}
@@ -1,5 +1,6 @@
 
 pub mod read_ext;
+pub mod marshal;
 pub mod pickle;
 pub mod virtual_fs;
 
@@ -39,35 +40,42 @@ macro_rules! hashmap {
 
 
 
+/// Files output by a mapper, as (path, data).
+pub type MappedFiles = Vec<(String, Vec<u8>)>;
+
+/// Extract all files of dir to output.
+///
+/// Mapper may convert a file into any number of output files, `None` writes the original file.
 pub fn dir_extract<F, D, G>(dir: &mut D, output: &PathBuf, overwrite_output: bool, mut mapper: G) -> Result<(), Box<dyn Error>>
 where
     F: VirtualFile,
     D: VirtualDirectory<F, D>,
-    G: FnMut(String, &mut Vec<u8>) -> Result<Option<(String, Vec<u8>)>, Box<dyn Error>>,
+    G: FnMut(String, &mut Vec<u8>) -> Result<Option<MappedFiles>, Box<dyn Error>>,
 {
     for file in dir.read_files_deep()? {
         // Load & map data.
         let mut data = file.read_data()?;
-        // TODO: Probably want an option to ignore mapper error.
         let mapped = mapper(file.path().to_owned(), &mut data)?;
-        let (path, mut data) = if let Some(mapped) = mapped { mapped } else { (file.path().to_owned(), data) };
-
-        // Output path.
-        let mut out_path = PathBuf::from(output);
-        out_path.push(path);
-
-        // Skip existing file.
-        if let Ok(meta) = fs::metadata(&out_path) {
-            if meta.is_file() && !overwrite_output {
-                continue;
+        let mapped = if let Some(mapped) = mapped { mapped } else { vec![(file.path().to_owned(), data)] };
+
+        for (path, mut data) in mapped {
+            // Output path.
+            let mut out_path = PathBuf::from(output);
+            out_path.push(path);
+
+            // Skip existing file.
+            if let Ok(meta) = fs::metadata(&out_path) {
+                if meta.is_file() && !overwrite_output {
+                    continue;
+                }
             }
-        }
 
-        // Write file.
-        fs::create_dir_all(out_path.parent().unwrap())?;
-        let mut output_file = File::create(out_path)?;
-        output_file.write_all(&mut data)?;
-        output_file.flush()?;
+            // Write file.
+            fs::create_dir_all(out_path.parent().unwrap())?;
+            let mut output_file = File::create(out_path)?;
+            output_file.write_all(&mut data)?;
+            output_file.flush()?;
+        }
     }
 
     Ok(())
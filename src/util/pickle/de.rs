@@ -0,0 +1,393 @@
+// https://serde.rs/impl-deserializer.html
+
+use serde::{de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor, EnumAccess, VariantAccess}, forward_to_deserialize_any};
+use super::{error::PickleError, pickle::{Pickle, PickleClass, PickleDict, PickleNumber, PickleRef}};
+
+
+
+/// Fields of an object, from its `__dict__` state, `__slots__` state & items set on it.
+fn class_fields(class: &PickleClass) -> PickleDict {
+    let mut fields = PickleDict::new();
+    match class.state.as_deref() {
+        Some(Pickle::Dict(dict)) => fields.extend(dict.clone()),
+        Some(Pickle::Tuple(state)) => for item in state {
+            if let Pickle::Dict(dict) = item {
+                fields.extend(dict.clone());
+            }
+        },
+        _ => { },
+    }
+    fields.extend(class.data.clone());
+    fields
+}
+
+/// What a class deserializes as.
+///
+/// List subclasses are their items, objects are their fields,
+/// and objects without state are their constructor arguments, so `set([1, 2])` is `[1, 2]`.
+fn class_value(class: PickleClass) -> Pickle {
+    if !class.items.is_empty() {
+        let mut items = match *class.args {
+            Pickle::Tuple(mut args) if !args.is_empty() => match args.remove(0).into_resolved() {
+                Pickle::List(items) => items,
+                _ => Vec::new(),
+            },
+            _ => Vec::new(),
+        };
+        items.extend(class.items);
+        return Pickle::List(items);
+    }
+
+    let fields = class_fields(&class);
+    if !fields.is_empty() {
+        return Pickle::Dict(fields);
+    }
+
+    match (class.state, *class.args) {
+        (Some(state), _) if !matches!(*state, Pickle::Dict(_) | Pickle::Tuple(_)) => *state,
+        (_, Pickle::Tuple(mut args)) if args.len() == 1 => args.remove(0),
+        (_, Pickle::Tuple(args)) if args.is_empty() => Pickle::Dict(PickleDict::new()),
+        (_, args) => args,
+    }
+}
+
+
+
+/// Deserialize the object of a reference, which may contain itself.
+fn resolve<T>(reference: &PickleRef, f: impl FnOnce(PickleDeserializer) -> Result<T, PickleError>) -> Result<T, PickleError> {
+    reference.visit(|pickle| match pickle {
+        Some(pickle) => f(PickleDeserializer::new(pickle.clone())),
+        None => Err(de::Error::custom("object contains itself")),
+    })
+}
+
+/// Deserialize a type from a pickle.
+///
+/// Classes are deserialized as their fields, see [`PickleDeserializer`].
+pub fn from_pickle<T: DeserializeOwned>(pickle: Pickle) -> Result<T, PickleError> {
+    T::deserialize(PickleDeserializer::new(pickle))
+}
+
+/// Serde deserializer for [`Pickle`].
+///
+/// * Lists, tuples & sets are sequences, dicts are maps.
+/// * Shared objects are cloned, objects that contain themselves are an error.
+/// * Classes are maps of their fields, or their items & arguments for builtin containers.
+/// * Enums are strings for unit variants, single entry dicts, or classes where the class name is the variant.
+pub struct PickleDeserializer {
+    pickle: Pickle,
+}
+
+impl PickleDeserializer {
+    pub fn new(pickle: Pickle) -> Self {
+        Self { pickle }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for PickleDeserializer {
+    type Error = PickleError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.pickle {
+            Pickle::None => visitor.visit_unit(),
+            Pickle::Bool(bool) => visitor.visit_bool(bool),
+            Pickle::Number(PickleNumber::Int(v)) => visitor.visit_i64(v),
+            Pickle::Number(PickleNumber::Uint(v)) => visitor.visit_u64(v),
+            Pickle::Number(PickleNumber::Float(v)) => visitor.visit_f64(v),
+            Pickle::Number(number @ PickleNumber::BigInt(_)) => match (number.to_i128(), number.to_u128()) {
+                (Some(v), _) => visitor.visit_i128(v),
+                (None, Some(v)) => visitor.visit_u128(v),
+                (None, None) => Err(de::Error::custom("integer is too large")),
+            },
+            Pickle::String(str) => visitor.visit_string(str),
+            Pickle::Binary(bytes) => visitor.visit_byte_buf(bytes),
+            Pickle::List(items) | Pickle::Tuple(items) | Pickle::Set(items) | Pickle::FrozenSet(items) => visitor.visit_seq(PickleSeqAccess::new(items)),
+            Pickle::Dict(dict) => visitor.visit_map(PickleMapAccess::new(dict)),
+            Pickle::Module(module) => visitor.visit_string(format!("{}.{}", module.module, module.name)),
+            Pickle::Class(class) => PickleDeserializer::new(class_value(class)).deserialize_any(visitor),
+            Pickle::Ref(reference) => resolve(&reference, |deserializer| deserializer.deserialize_any(visitor)),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.pickle {
+            Pickle::None => visitor.visit_none(),
+            Pickle::Ref(reference) => resolve(&reference, |deserializer| deserializer.deserialize_option(visitor)),
+            pickle => visitor.visit_some(PickleDeserializer::new(pickle)),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, name: &'static str, variants: &'static [&'static str], visitor: V) -> Result<V::Value, Self::Error> {
+        match self.pickle {
+            Pickle::String(variant) => visitor.visit_enum(PickleEnumAccess { variant, value: None }),
+            Pickle::Dict(dict) if dict.len() == 1 => match dict.into_iter().next() {
+                Some((Pickle::String(variant), value)) => visitor.visit_enum(PickleEnumAccess { variant, value: Some(value) }),
+                _ => Err(de::Error::custom("enum variant must be a string")),
+            },
+            Pickle::Ref(reference) => resolve(&reference, |deserializer| deserializer.deserialize_enum(name, variants, visitor)),
+            Pickle::Class(class) => {
+                let variant = class.module.name.clone();
+                visitor.visit_enum(PickleEnumAccess { variant, value: Some(class_value(class)) })
+            },
+            _ => Err(de::Error::custom("expected enum, a string, a single entry dict, or a class")),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
+    }
+}
+
+
+
+struct PickleSeqAccess {
+    items: std::vec::IntoIter<Pickle>,
+    index: usize,
+}
+
+impl PickleSeqAccess {
+    fn new(items: Vec<Pickle>) -> Self {
+        Self { items: items.into_iter(), index: 0 }
+    }
+}
+
+impl<'de> SeqAccess<'de> for PickleSeqAccess {
+    type Error = PickleError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error> {
+        let item = match self.items.next() {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+        let index = self.index;
+        self.index += 1;
+        seed.deserialize(PickleDeserializer::new(item)).map(Some).map_err(|err| err.at(&format!("[{}]", index)))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+
+
+struct PickleMapAccess {
+    items: std::vec::IntoIter<(Pickle, Pickle)>,
+    /// Path segment & value of the last key.
+    value: Option<(String, Pickle)>,
+}
+
+impl PickleMapAccess {
+    fn new(dict: PickleDict) -> Self {
+        Self { items: dict.into_iter(), value: None }
+    }
+}
+
+impl<'de> MapAccess<'de> for PickleMapAccess {
+    type Error = PickleError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error> {
+        let (key, value) = match self.items.next() {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+        let (segment, result) = match key {
+            Pickle::String(key) => (format!(".{}", key), seed.deserialize(PickleKeyDeserializer(key))),
+            key => (format!("[{}]", key), seed.deserialize(PickleDeserializer::new(key))),
+        };
+        let result = result.map(Some).map_err(|err| err.at(&segment));
+        self.value = Some((segment, value));
+        result
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Self::Error> {
+        let (segment, value) = self.value.take().ok_or_else(|| de::Error::custom("value is missing"))?;
+        seed.deserialize(PickleDeserializer::new(value)).map_err(|err| err.at(&segment))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+
+
+/// String dict keys, keys of other types are parsed from them, such as keys of a JSON like dict.
+struct PickleKeyDeserializer(String);
+
+macro_rules! deserialize_key_parse {
+    ($($method:ident => $visit:ident),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                match self.0.parse() {
+                    Ok(v) => visitor.$visit(v),
+                    Err(_) => Err(de::Error::invalid_value(de::Unexpected::Str(&self.0), &visitor)),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for PickleKeyDeserializer {
+    type Error = PickleError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_string(self.0)
+    }
+
+    deserialize_key_parse! {
+        deserialize_i8 => visit_i8, deserialize_i16 => visit_i16, deserialize_i32 => visit_i32, deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8, deserialize_u16 => visit_u16, deserialize_u32 => visit_u32, deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32, deserialize_f64 => visit_f64, deserialize_bool => visit_bool
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_enum(PickleEnumAccess { variant: self.0, value: None })
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf option unit unit_struct
+        seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+
+
+struct PickleEnumAccess {
+    variant: String,
+    value: Option<Pickle>,
+}
+
+impl<'de> EnumAccess<'de> for PickleEnumAccess {
+    type Error = PickleError;
+    type Variant = PickleVariantAccess;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error> {
+        let variant = seed.deserialize(self.variant.clone().into_deserializer())?;
+        Ok((variant, PickleVariantAccess { variant: self.variant, value: self.value }))
+    }
+}
+
+struct PickleVariantAccess {
+    variant: String,
+    value: Option<Pickle>,
+}
+
+impl PickleVariantAccess {
+    fn value(self) -> Result<(String, Pickle), PickleError> {
+        match self.value {
+            Some(value) => Ok((self.variant, value)),
+            None => Err(de::Error::invalid_type(de::Unexpected::UnitVariant, &"variant with a value")),
+        }
+    }
+}
+
+impl<'de> VariantAccess<'de> for PickleVariantAccess {
+    type Error = PickleError;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        match self.value {
+            None | Some(Pickle::None) => Ok(()),
+            Some(_) => Err(de::Error::invalid_type(de::Unexpected::NewtypeVariant, &"unit variant")),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Self::Error> {
+        let (variant, value) = self.value()?;
+        seed.deserialize(PickleDeserializer::new(value)).map_err(|err| err.at(&format!("::{}", variant)))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error> {
+        let (variant, value) = self.value()?;
+        de::Deserializer::deserialize_seq(PickleDeserializer::new(value), visitor).map_err(|err| err.at(&format!("::{}", variant)))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, Self::Error> {
+        let (variant, value) = self.value()?;
+        de::Deserializer::deserialize_map(PickleDeserializer::new(value), visitor).map_err(|err| err.at(&format!("::{}", variant)))
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use serde::{Deserialize, Serialize};
+    use crate::util::pickle::{pickle::{Pickle, PickleClass, PickleDict, PickleModule}, ser::to_pickle};
+    use super::from_pickle;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Empty,
+        Circle(f64),
+        Rect { w: u32, h: u32 },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Scene {
+        name: String,
+        shapes: Vec<Shape>,
+        origin: (i32, i32),
+        layer: Option<u8>,
+        ids: HashMap<u32, String>,
+        big: u128,
+    }
+
+    #[test]
+    fn round_trip() {
+        let scene = Scene {
+            name: "scene".to_owned(),
+            shapes: vec![Shape::Empty, Shape::Circle(1.5), Shape::Rect { w: 2, h: 3 }],
+            origin: (-1, 1),
+            layer: None,
+            ids: HashMap::from([(1, "one".to_owned()), (2, "two".to_owned())]),
+            big: u128::MAX,
+        };
+        let pickle = to_pickle(&scene).unwrap();
+        assert_eq!(from_pickle::<Scene>(pickle).unwrap(), scene);
+    }
+
+    #[test]
+    fn class() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Point { x: i64, y: i64 }
+
+        let mut class = PickleClass::new(PickleModule::new("game".to_owned(), "Point".to_owned()), Pickle::Tuple(Vec::new()));
+        class.state = Some(Box::new(Pickle::Dict(PickleDict::from_iter([("x".to_owned(), Pickle::from(1)), ("y".to_owned(), Pickle::from(2))]))));
+        assert_eq!(from_pickle::<Point>(Pickle::Class(class)).unwrap(), Point { x: 1, y: 2 });
+
+        // Enum variants by class name.
+        let class = PickleClass::new(PickleModule::new("game".to_owned(), "Circle".to_owned()), Pickle::Tuple(vec![Pickle::from(2.0)]));
+        assert_eq!(from_pickle::<Shape>(Pickle::Class(class)).unwrap(), Shape::Circle(2.0));
+    }
+
+    #[test]
+    fn error_path() {
+        let pickle = Pickle::Dict(PickleDict::from_iter([
+            ("name".to_owned(), Pickle::from("scene")),
+            ("shapes".to_owned(), Pickle::List(vec![
+                Pickle::from("Empty"),
+                Pickle::Dict(PickleDict::from_iter([("Rect".to_owned(), Pickle::Dict(PickleDict::from_iter([
+                    ("w".to_owned(), Pickle::from(1)),
+                    ("h".to_owned(), Pickle::from("tall")),
+                ])))])),
+            ])),
+        ]));
+        let err = from_pickle::<Scene>(pickle).unwrap_err().to_string();
+        assert!(err.starts_with("Pickle at .shapes[1]::Rect.h: "), "{}", err);
+    }
+}
@@ -0,0 +1,169 @@
+// https://github.com/python/cpython/blob/main/Lib/pickletools.py
+
+use std::{fmt, io::{self, Read}};
+use super::parser::{PickleOpcode, PickleParser};
+
+
+
+/// Reader that keeps the bytes of the operation being read.
+struct PickleRecorder<'a, R: Read> {
+    data: &'a mut R,
+    offset: u64,
+    bytes: Vec<u8>,
+}
+
+impl<R: Read> Read for PickleRecorder<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = self.data.read(buf)?;
+        self.offset += length as u64;
+        self.bytes.extend_from_slice(&buf[..length]);
+        Ok(length)
+    }
+}
+
+
+
+/// Operation of a pickle, same as a line of `pickletools.dis`.
+#[derive(Debug, Clone)]
+pub struct PickleInstruction {
+    pub offset: u64,
+    pub opcode: PickleOpcode,
+    /// Stack depth after the operation, marks are counted.
+    pub depth: usize,
+    /// Memo index the operation stores to or loads from.
+    pub memo: Option<usize>,
+    pub argument: Option<String>,
+}
+
+impl PickleInstruction {
+
+    fn new(offset: u64, opcode: PickleOpcode, bytes: &[u8], memo_index: usize, parser: &PickleParser) -> Self {
+        // Argument bytes follow the opcode.
+        let argument = &bytes[1.min(bytes.len())..];
+        let line = || String::from_utf8_lossy(argument).trim_end().to_owned();
+
+        let memo = match opcode {
+            PickleOpcode::PUT | PickleOpcode::GET => line().parse().ok(),
+            PickleOpcode::BINPUT | PickleOpcode::BINGET => argument.first().map(|index| *index as usize),
+            PickleOpcode::LONG_BINPUT | PickleOpcode::LONG_BINGET => argument.try_into().ok().map(|index| u32::from_le_bytes(index) as usize),
+            PickleOpcode::MEMOIZE => Some(memo_index),
+            _ => None,
+        };
+
+        let argument = match opcode {
+            PickleOpcode::PROTO => argument.first().map(u8::to_string),
+            PickleOpcode::FRAME => argument.try_into().ok().map(|length| u64::from_le_bytes(length).to_string()),
+            PickleOpcode::GLOBAL | PickleOpcode::INST => Some(line().replace('\n', " ")),
+            PickleOpcode::PERSID => Some(line()),
+            PickleOpcode::INT | PickleOpcode::BININT | PickleOpcode::BININT1 | PickleOpcode::BININT2
+            | PickleOpcode::LONG | PickleOpcode::LONG1 | PickleOpcode::LONG4
+            | PickleOpcode::FLOAT | PickleOpcode::BINFLOAT
+            | PickleOpcode::STRING | PickleOpcode::BINSTRING | PickleOpcode::SHORT_BINSTRING
+            | PickleOpcode::UNICODE | PickleOpcode::BINUNICODE | PickleOpcode::SHORT_BINUNICODE | PickleOpcode::BINUNICODE8
+            | PickleOpcode::BINBYTES | PickleOpcode::SHORT_BINBYTES | PickleOpcode::BINBYTES8 | PickleOpcode::BYTEARRAY8
+            | PickleOpcode::EXT1 | PickleOpcode::EXT2 | PickleOpcode::EXT4 => parser.stack_top().map(ToString::to_string),
+            _ => None,
+        };
+
+        Self { offset, opcode, depth: parser.stack_depth(), memo, argument }
+    }
+
+}
+
+impl fmt::Display for PickleInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let memo = self.memo.map(|index| format!("[{}]", index)).unwrap_or_default();
+        let opcode = format!("{:?}", self.opcode);
+        let line = format!("{:>8} {:<16} {:>5} {:>6} {}", self.offset, opcode, self.depth, memo, self.argument.as_deref().unwrap_or_default());
+        write!(f, "{}", line.trim_end())
+    }
+}
+
+
+
+/// Operations of a pickle, read until `STOP` or the first error.
+#[derive(Debug, Clone)]
+pub struct PickleDisassembly {
+    pub instructions: Vec<PickleInstruction>,
+    /// Error that stopped the pickle from being read, the operations before it are kept.
+    pub error: Option<String>,
+}
+
+impl PickleDisassembly {
+
+    /// Operations are read by the parser, so memo & stack are the same as when loading.
+    pub fn disassemble(parser: &mut PickleParser, data: &mut impl Read) -> Self {
+        let mut recorder = PickleRecorder { data, offset: 0, bytes: Vec::new() };
+        let mut instructions = Vec::new();
+
+        parser.reset();
+        loop {
+            let offset = recorder.offset;
+            let memo_index = parser.next_memo_index();
+            recorder.bytes.clear();
+
+            match parser.read_operation(&mut recorder) {
+                Ok(opcode) => {
+                    instructions.push(PickleInstruction::new(offset, opcode, &recorder.bytes, memo_index, parser));
+                    if opcode == PickleOpcode::STOP {
+                        return Self { instructions, error: None };
+                    }
+                },
+                Err(err) => return Self { instructions, error: Some(format!("{} at offset {}", err, offset)) },
+            }
+        }
+    }
+
+}
+
+impl fmt::Display for PickleDisassembly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:>8} {:<16} {:>5} {:>6} argument", "offset", "opcode", "stack", "memo")?;
+        for instruction in &self.instructions {
+            writeln!(f, "{}", instruction)?;
+        }
+        if let Some(err) = &self.error {
+            writeln!(f, "error: {}", err)?;
+        }
+        Ok(())
+    }
+}
+
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use crate::util::pickle::parser::{PickleOpcode, PickleParser};
+    use super::PickleDisassembly;
+
+    #[test]
+    fn disassemble() {
+        // `l = []; l.append(l); pickle.dumps(l, 2)`
+        let dis = PickleDisassembly::disassemble(&mut PickleParser::new(), &mut Cursor::new(b"\x80\x02]q\x00h\x00a."));
+        assert!(dis.error.is_none());
+
+        let opcodes = dis.instructions.iter().map(|instruction| instruction.opcode).collect::<Vec<_>>();
+        assert_eq!(opcodes, vec![PickleOpcode::PROTO, PickleOpcode::EMPTY_LIST, PickleOpcode::BINPUT, PickleOpcode::BINGET, PickleOpcode::APPEND, PickleOpcode::STOP]);
+        assert_eq!(dis.instructions.iter().map(|instruction| instruction.offset).collect::<Vec<_>>(), vec![0, 2, 3, 5, 7, 8]);
+        assert_eq!(dis.instructions.iter().map(|instruction| instruction.depth).collect::<Vec<_>>(), vec![0, 1, 1, 2, 1, 1]);
+        assert_eq!(dis.instructions[2].memo, Some(0));
+        assert_eq!(dis.instructions[3].memo, Some(0));
+        assert_eq!(dis.instructions[0].argument.as_deref(), Some("2"));
+        assert_eq!(dis.to_string().lines().nth(3).map(str::trim), Some("3 BINPUT               1    [0]"));
+    }
+
+    #[test]
+    fn error() {
+        // Truncated `pickle.dumps(("a", 1), 4)`.
+        let dis = PickleDisassembly::disassemble(&mut PickleParser::new(), &mut Cursor::new(b"\x80\x04\x95\x0b\x00\x00\x00\x00\x00\x00\x00\x8c\x01a\x94K\x01"));
+        assert_eq!(dis.instructions.len(), 5);
+        assert_eq!(dis.instructions[2].argument.as_deref(), Some("'a'"));
+        assert_eq!(dis.instructions[3].memo, Some(0));
+        assert_eq!(dis.instructions[4].argument.as_deref(), Some("1"));
+        assert!(dis.error.is_some_and(|err| err.ends_with("at offset 17")));
+    }
+
+}
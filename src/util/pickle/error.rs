@@ -1,6 +1,6 @@
 
 use std::{error::Error, fmt};
-use super::parser::{PickleOpcode, PickleProtocol};
+use super::parser::{PickleLimit, PickleOpcode, PickleProtocol};
 
 
 
@@ -19,6 +19,15 @@ pub enum PickleError {
     InvalidReferencePickleType,
     CannotTryInto,
     CannotExtract,
+    InvalidString,
+    InvalidNumber(String),
+    MissingPersistentLoad,
+    UnknownExtension(u32),
+    MissingBuffer,
+    TooLarge,
+    LimitExceeded(PickleLimit),
+    /// Serde error, with the path to the field that failed.
+    Serde { path: String, message: String },
 }
 
 impl fmt::Display for PickleError {
@@ -37,10 +46,43 @@ impl fmt::Display for PickleError {
             Self::InvalidReferencePickleType => write!(f, "Pickle tried to reference pickle that is not correct type."),
             Self::CannotTryInto => write!(f, "Pickle cannot try into."),
             Self::CannotExtract => write!(f, "Pickle cannot extract."),
+            Self::InvalidString => write!(f, "Pickle string argument is not correctly escaped."),
+            Self::InvalidNumber(number) => write!(f, "Pickle number argument \"{}\" is invalid.", number),
+            Self::MissingPersistentLoad => write!(f, "Pickle has a persistent id, but no persistent load was specified."),
+            Self::UnknownExtension(code) => write!(f, "Pickle extension code {} is not registered.", code),
+            Self::MissingBuffer => write!(f, "Pickle out-of-band buffer was not supplied."),
+            Self::TooLarge => write!(f, "Pickle value is too large for protocol."),
+            Self::LimitExceeded(limit) => write!(f, "Pickle exceeds limit, {}.", limit),
+            Self::Serde { path, message } if path.is_empty() => write!(f, "Pickle {}", message),
+            Self::Serde { path, message } => write!(f, "Pickle at {}: {}", path, message),
         }
     }
 }
 
 impl Error for PickleError { }
 
+impl PickleError {
+
+    /// Prefix the serde path of the error, errors from other variants become serde errors.
+    pub fn at(self, segment: &str) -> Self {
+        match self {
+            Self::Serde { path, message } => Self::Serde { path: format!("{}{}", segment, path), message },
+            err => Self::Serde { path: segment.to_owned(), message: err.to_string() },
+        }
+    }
+
+}
+
+impl serde::de::Error for PickleError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self::Serde { path: String::new(), message: msg.to_string() }
+    }
+}
+
+impl serde::ser::Error for PickleError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self::Serde { path: String::new(), message: msg.to_string() }
+    }
+}
+
 
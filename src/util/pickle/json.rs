@@ -0,0 +1,99 @@
+use serde_json::{Map, Number, Value};
+use super::pickle::{Pickle, PickleClass, PickleDict, PickleNumber};
+
+
+
+/// Convert pickle to JSON.
+///
+/// Types without a JSON equivalent are converted to their `repr()`, so are dict keys that are not strings.
+/// Classes are converted to objects with the class name in `__class__`,
+/// shared objects are repeated and objects that contain themselves are cut off with their `repr()`.
+pub fn pickle_to_json(pickle: &Pickle) -> Value {
+    match pickle {
+        Pickle::None => Value::Null,
+        Pickle::Bool(bool) => Value::Bool(*bool),
+        Pickle::Number(PickleNumber::Int(v)) => Value::from(*v),
+        Pickle::Number(PickleNumber::Uint(v)) => Value::from(*v),
+        Pickle::Number(PickleNumber::Float(v)) => match Number::from_f64(*v) {
+            Some(number) => Value::Number(number),
+            None => Value::String(pickle.to_string()),
+        },
+        // Longs that fit are numbers, others are decimal strings as JSON parsers may lose precision.
+        Pickle::Number(number @ PickleNumber::BigInt(_)) => match number.to_i128() {
+            Some(v) if i64::try_from(v).is_ok() => Value::from(v as i64),
+            Some(v) if u64::try_from(v).is_ok() => Value::from(v as u64),
+            _ => Value::String(pickle.to_string()),
+        },
+        Pickle::Binary(_) | Pickle::Module(_) => Value::String(pickle.to_string()),
+        Pickle::String(str) => Value::String(str.clone()),
+        Pickle::List(items) | Pickle::Tuple(items) | Pickle::Set(items) | Pickle::FrozenSet(items) => Value::Array(items.iter().map(pickle_to_json).collect()),
+        Pickle::Dict(dict) => Value::Object(dict_to_json(dict)),
+        Pickle::Class(class) => class_to_json(class),
+        Pickle::Ref(reference) => reference.visit(|object| match object {
+            Some(object) => pickle_to_json(object),
+            None => Value::String(pickle.to_string()),
+        }),
+    }
+}
+
+fn dict_to_json(dict: &PickleDict) -> Map<String, Value> {
+    dict.iter().map(|(key, value)| (key.key_string(), pickle_to_json(value))).collect()
+}
+
+fn class_to_json(class: &PickleClass) -> Value {
+    let args = match class.args.as_ref() {
+        Pickle::Tuple(args) => args.as_slice(),
+        _ => &[],
+    };
+
+    // Builtin containers & their subclasses, such as Ren'Py's revertable types.
+    match class.module.name.as_str() {
+        "list" | "RevertableList" | "deque" if class.data.is_empty() => {
+            let mut items = match args.first().map(Pickle::resolve) {
+                Some(Pickle::List(items)) => items.clone(),
+                _ => Vec::new(),
+            };
+            items.extend(class.items.iter().cloned());
+            return Value::Array(items.iter().map(pickle_to_json).collect());
+        },
+        "dict" | "RevertableDict" | "OrderedDict" | "defaultdict" if class.items.is_empty() => {
+            return Value::Object(dict_to_json(&class.data));
+        },
+        "set" | "frozenset" | "RevertableSet" => {
+            if let Some(items @ (Pickle::List(_) | Pickle::Tuple(_) | Pickle::Set(_))) = args.first().map(Pickle::resolve) {
+                return pickle_to_json(items);
+            }
+        },
+        _ => { },
+    }
+
+    let mut object = Map::new();
+    object.insert("__class__".to_owned(), Value::String(format!("{}.{}", class.module.module, class.module.name)));
+    if !args.is_empty() {
+        object.insert("__args__".to_owned(), pickle_to_json(&class.args));
+    }
+    if let Some(kwargs) = &class.kwargs {
+        object.insert("__kwargs__".to_owned(), pickle_to_json(kwargs));
+    }
+
+    // State is either `__dict__`, or a `(__dict__, slots)` tuple.
+    match class.state.as_deref() {
+        Some(Pickle::Dict(dict)) => object.extend(dict_to_json(dict)),
+        Some(Pickle::Tuple(state)) if state.len() == 2 && state.iter().all(|item| matches!(item, Pickle::Dict(_) | Pickle::None)) => {
+            for item in state {
+                if let Pickle::Dict(dict) = item {
+                    object.extend(dict_to_json(dict));
+                }
+            }
+        },
+        Some(state) => { object.insert("__state__".to_owned(), pickle_to_json(state)); },
+        None => { },
+    }
+
+    object.extend(dict_to_json(&class.data));
+    if !class.items.is_empty() {
+        object.insert("__items__".to_owned(), Value::Array(class.items.iter().map(pickle_to_json).collect()));
+    }
+
+    Value::Object(object)
+}
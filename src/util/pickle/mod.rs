@@ -2,3 +2,8 @@
 pub mod parser;
 pub mod pickle;
 pub mod error;
+pub mod json;
+pub mod de;
+pub mod ser;
+pub mod writer;
+pub mod dis;
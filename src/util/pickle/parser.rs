@@ -1,15 +1,15 @@
 
-// TODO: Implement all opcodes.
+// https://github.com/python/cpython/blob/main/Lib/pickle.py
 
-use std::{collections::HashMap, error::Error, io::Read};
+use std::{collections::{HashMap, HashSet, VecDeque}, error::Error, fmt, io::{self, Read}};
 use crate::util::read_ext::ReadExt;
 
-use super::{error::PickleError, pickle::{Pickle, PickleClass, PickleModule, PickleNumber}};
+use super::{error::PickleError, pickle::{negate_long, Pickle, PickleConstructor, PickleDict, PickleModule, PickleNumber, PickleRef, MAX_DIGITS}};
 
 
 
 #[allow(non_camel_case_types)]
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PickleOpcode {
     MARK = 0x28, // push special markobject on stack
     STOP = 0x2E, // every pickle ends with STOP
@@ -163,66 +163,97 @@ impl PickleOpcode {
 
 
 
+/// Items are kept with how deeply nested they are, so nesting is known without walking them.
 #[derive(Debug)]
 struct PickleStack {
     stack: Vec<PickleStackItem>,
+    /// Number of values pushed, copies count every value they contain.
+    pushed: usize,
 }
 
 impl PickleStack {
 
     pub fn new() -> Self {
-        Self { stack: Vec::new() }
+        Self { stack: Vec::new(), pushed: 0 }
     }
 
+    /// Push a value that does not contain other values, or a reference.
     pub fn push(&mut self, item: Pickle) {
-        self.stack.push(PickleStackItem::Pickle(item));
+        self.push_nested(item, 0);
+    }
+
+    pub fn push_nested(&mut self, item: Pickle, depth: usize) {
+        self.push_values(item, depth, 1);
+    }
+
+    /// Push a value that counts as more than one value, such as a copy.
+    pub fn push_values(&mut self, item: Pickle, depth: usize, values: usize) {
+        self.pushed += values;
+        self.stack.push(PickleStackItem::Pickle(item, depth));
     }
 
     pub fn pop(&mut self) -> Result<Pickle, Box<dyn Error>> {
+        Ok(self.pop_nested()?.0)
+    }
+
+    pub fn pop_nested(&mut self) -> Result<(Pickle, usize), Box<dyn Error>> {
         match self.stack.pop() {
-            Some(PickleStackItem::Pickle(item)) => Ok(item),
+            Some(PickleStackItem::Pickle(item, depth)) => Ok((item, depth)),
             Some(PickleStackItem::Mark) => Err(Box::new(PickleError::StackMark)),
             None => Err(Box::new(PickleError::StackEmpty)),
         }
     }
 
-    pub fn last(&mut self) -> Result<&Pickle, Box<dyn Error>> {
+    pub fn last(&self) -> Result<&Pickle, Box<dyn Error>> {
+        Ok(self.last_nested()?.0)
+    }
+
+    pub fn last_nested(&self) -> Result<(&Pickle, usize), Box<dyn Error>> {
         match self.stack.last() {
-            Some(PickleStackItem::Pickle(item)) => Ok(item),
+            Some(PickleStackItem::Pickle(item, depth)) => Ok((item, *depth)),
             Some(PickleStackItem::Mark) => Err(Box::new(PickleError::StackMark)),
             None => Err(Box::new(PickleError::StackEmpty)),
         }
     }
 
-    pub fn set_last(&mut self, item: Pickle) -> Result<Pickle, Box<dyn Error>> {
-        let removed_item = self.pop()?;
-        self.push(item);
-        Ok(removed_item)
-    }
-
     pub fn push_mark(&mut self) {
         self.stack.push(PickleStackItem::Mark);
     }
 
     pub fn pop_mark(&mut self) -> Result<Vec<Pickle>, Box<dyn Error>> {
-        let mut items: Vec<Pickle> = Vec::new();
+        Ok(self.pop_mark_nested()?.0)
+    }
 
-        loop {
-            match self.stack.pop() {
-                Some(PickleStackItem::Pickle(item)) => items.insert(0, item),
-                Some(PickleStackItem::Mark) => break,
-                None => return Err(Box::new(PickleError::StackEmpty)),
-            }
-        }
+    /// Items after the topmost mark, with the depth of the most nested item.
+    pub fn pop_mark_nested(&mut self) -> Result<(Vec<Pickle>, usize), Box<dyn Error>> {
+        let mark = self.stack.iter().rposition(|item| matches!(item, PickleStackItem::Mark)).ok_or(PickleError::StackEmpty)?;
+        let items = self.stack.split_off(mark + 1);
+        self.stack.pop();
+
+        let mut depth = 0;
+        let items = items.into_iter().map(|item| match item {
+            PickleStackItem::Pickle(item, item_depth) => {
+                depth = depth.max(item_depth);
+                item
+            },
+            PickleStackItem::Mark => unreachable!(),
+        }).collect();
+        Ok((items, depth))
+    }
 
-        Ok(items)
+    /// Discard the stack top, or the topmost mark if there are no items after it.
+    pub fn discard(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.stack.pop() {
+            Some(_) => Ok(()),
+            None => Err(Box::new(PickleError::StackEmpty)),
+        }
     }
 
 }
 
 #[derive(Debug, Clone)]
 enum PickleStackItem {
-    Pickle(Pickle),
+    Pickle(Pickle, usize),
     Mark,
 }
 
@@ -230,62 +261,45 @@ enum PickleStackItem {
 
 #[derive(Debug)]
 struct PickleMemo {
-    items: Vec<PickleMemoItem>,
+    items: HashMap<usize, (Pickle, usize)>,
 }
 
 impl PickleMemo {
 
     pub fn new() -> Self {
-        Self { items: Vec::new() }
+        Self { items: HashMap::new() }
     }
 
-    pub fn get(&mut self, index: usize) -> Result<&Pickle, Box<dyn Error>> {
-        match self.items.get(index) {
-            Some(PickleMemoItem::Pickle(item)) => Ok(item),
-            Some(PickleMemoItem::Empty) => Err(Box::new(PickleError::MemoIndexEmpty)),
-            None => Err(Box::new(PickleError::MemoIndexOutOfBounds)),
+    pub fn get(&self, index: usize) -> Result<(&Pickle, usize), Box<dyn Error>> {
+        match self.items.get(&index) {
+            Some((item, depth)) => Ok((item, *depth)),
+            None => Err(Box::new(PickleError::MemoIndexEmpty)),
         }
     }
 
-    pub fn set(&mut self, index: usize, value: Pickle) -> Result<(), Box<dyn Error>> {
-        while index >= self.items.len() {
-            self.items.push(PickleMemoItem::Empty);
-        }
-
-        self.items[index] = PickleMemoItem::Pickle(value);
-
-        Ok(())
+    pub fn set(&mut self, index: usize, value: Pickle, depth: usize) {
+        self.items.insert(index, (value, depth));
     }
 
-    pub fn push(&mut self, value: Pickle) -> usize {
-        self.items.push(PickleMemoItem::Pickle(value));
-        self.items.len()
+    pub fn contains(&self, index: usize) -> bool {
+        self.items.contains_key(&index)
     }
 
-    pub fn last(&mut self) -> Result<&Pickle, Box<dyn Error>> {
-        match self.items.last() {
-            // Last item should NEVER be empty.
-            Some(PickleMemoItem::Pickle(item)) => Ok(item),
-            Some(PickleMemoItem::Empty) => Err(Box::new(PickleError::MemoEmpty)),
-            None => Err(Box::new(PickleError::MemoEmpty)),
-        }
+    /// Index `MEMOIZE` stores at, which is the number of items in the memo.
+    pub fn next_index(&self) -> usize {
+        self.items.len()
     }
 
 }
 
-#[derive(Debug, Clone)]
-enum PickleMemoItem {
-    Pickle(Pickle),
-    Empty,
-}
-
 
 
 
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum PickleProtocol {
     Unknown,
+    Protocol0,
     Protocol1,
     Protocol2,
     Protocol3,
@@ -297,7 +311,8 @@ impl PickleProtocol {
 
     pub fn from(value: u8) -> Result<Self, Box<dyn Error>> {
         match value {
-            1 => Err(Box::new(PickleError::UnsupportedProtocol(PickleProtocol::Protocol1))),
+            0 => Ok(PickleProtocol::Protocol0),
+            1 => Ok(PickleProtocol::Protocol1),
             2 => Ok(PickleProtocol::Protocol2),
             3 => Ok(PickleProtocol::Protocol3),
             4 => Ok(PickleProtocol::Protocol4),
@@ -306,171 +321,1402 @@ impl PickleProtocol {
         }
     }
 
+    pub fn version(&self) -> Option<u8> {
+        match self {
+            PickleProtocol::Unknown => None,
+            PickleProtocol::Protocol0 => Some(0),
+            PickleProtocol::Protocol1 => Some(1),
+            PickleProtocol::Protocol2 => Some(2),
+            PickleProtocol::Protocol3 => Some(3),
+            PickleProtocol::Protocol4 => Some(4),
+            PickleProtocol::Protocol5 => Some(5),
+        }
+    }
+
 }
 
 
 
 
 
-#[derive(Debug)]
+/// How Python 2 `str` byte strings are decoded, same as the `encoding` argument of Python's `pickle.load`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PickleStringEncoding {
+    /// Decode as UTF-8, falling back to Latin-1 when not valid UTF-8.
+    Utf8,
+    Latin1,
+    /// Keep as [`Pickle::Binary`].
+    Bytes,
+}
+
+impl PickleStringEncoding {
+
+    fn decode(&self, bytes: Vec<u8>) -> Pickle {
+        match self {
+            Self::Utf8 => match String::from_utf8(bytes) {
+                Ok(str) => Pickle::String(str),
+                Err(err) => Pickle::String(latin1(err.as_bytes())),
+            },
+            Self::Latin1 => Pickle::String(latin1(&bytes)),
+            Self::Bytes => Pickle::Binary(bytes),
+        }
+    }
+
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| *byte as char).collect()
+}
+
+fn latin1_bytes(str: &str) -> Vec<u8> {
+    str.chars().map(|char| char as u8).collect()
+}
+
+/// Python 2 module & class names that were renamed in Python 3, from `_compat_pickle`.
+fn python2_global(module: String, name: String) -> PickleModule {
+    let (module, name) = match (module.as_str(), name.as_str()) {
+        ("__builtin__", "unicode") => ("builtins", "str"),
+        ("__builtin__", "long") => ("builtins", "int"),
+        ("__builtin__", "xrange") => ("builtins", "range"),
+        ("__builtin__", "unichr") => ("builtins", "chr"),
+        ("__builtin__", "basestring") => ("builtins", "str"),
+        ("__builtin__", "reduce") => ("functools", "reduce"),
+        ("__builtin__", "intern") => ("sys", "intern"),
+        ("__builtin__" | "exceptions", name) => ("builtins", name),
+        ("copy_reg", name) => ("copyreg", name),
+        ("UserDict", "UserDict" | "IterableUserDict") => ("collections", "UserDict"),
+        ("UserList", "UserList") => ("collections", "UserList"),
+        ("UserString", "UserString") => ("collections", "UserString"),
+        ("Queue", name) => ("queue", name),
+        ("cPickle", name) => ("pickle", name),
+        ("cStringIO" | "StringIO", "StringIO") => ("io", "StringIO"),
+        ("itertools", "izip") => ("builtins", "zip"),
+        ("itertools", "imap") => ("builtins", "map"),
+        ("itertools", "ifilter") => ("builtins", "filter"),
+        _ => return PickleModule::new(module, name),
+    };
+    PickleModule::new(module.to_owned(), name.to_owned())
+}
+
+/// Decode a Python 2 `repr()` quoted string, used by the `STRING` opcode.
+pub fn unescape_string(line: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let quoted = match line {
+        [b'"', inner @ .., b'"'] | [b'\'', inner @ .., b'\''] => inner,
+        _ => return Err(Box::new(PickleError::InvalidString)),
+    };
+
+    let mut bytes = Vec::new();
+    let mut iter = quoted.iter().copied().peekable();
+    while let Some(byte) = iter.next() {
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        match iter.next().ok_or(PickleError::InvalidString)? {
+            b'n' => bytes.push(b'\n'),
+            b'r' => bytes.push(b'\r'),
+            b't' => bytes.push(b'\t'),
+            b'a' => bytes.push(0x07),
+            b'b' => bytes.push(0x08),
+            b'f' => bytes.push(0x0C),
+            b'v' => bytes.push(0x0B),
+            b'x' => {
+                let hex = [iter.next().ok_or(PickleError::InvalidString)?, iter.next().ok_or(PickleError::InvalidString)?];
+                let hex = std::str::from_utf8(&hex).map_err(|_| PickleError::InvalidString)?;
+                bytes.push(u8::from_str_radix(hex, 16).map_err(|_| PickleError::InvalidString)?);
+            },
+            digit @ b'0'..=b'7' => {
+                let mut value = (digit - b'0') as u32;
+                for _ in 0..2 {
+                    match iter.peek() {
+                        Some(digit @ b'0'..=b'7') => {
+                            value = value * 8 + (digit - b'0') as u32;
+                            iter.next();
+                        },
+                        _ => break,
+                    }
+                }
+                bytes.push(value as u8);
+            },
+            byte => bytes.push(byte),
+        }
+    }
+    Ok(bytes)
+}
+
+/// Decode a `raw-unicode-escape` string, used by the `UNICODE` opcode.
+fn unescape_raw_unicode(line: &[u8]) -> Result<String, Box<dyn Error>> {
+    let mut str = String::new();
+    let mut index = 0;
+    while index < line.len() {
+        let length = match &line[index..] {
+            [b'\\', b'u', ..] => 4,
+            [b'\\', b'U', ..] => 8,
+            _ => {
+                str.push(line[index] as char);
+                index += 1;
+                continue;
+            },
+        };
+        let hex = line.get(index + 2..index + 2 + length).ok_or(PickleError::InvalidString)?;
+        let hex = std::str::from_utf8(hex).map_err(|_| PickleError::InvalidString)?;
+        let char = u32::from_str_radix(hex, 16).ok().and_then(char::from_u32).ok_or(PickleError::InvalidString)?;
+        str.push(char);
+        index += 2 + length;
+    }
+    Ok(str)
+}
+
+
+
+
+/// Parse a decimal string argument, used by the `INT` & `LONG` opcodes.
+fn parse_int(line: &[u8]) -> Result<PickleNumber, Box<dyn Error>> {
+    let str = String::from_utf8_lossy(line);
+    let number = str.trim().trim_end_matches('L');
+    if let Ok(v) = number.parse::<i64>() {
+        Ok(PickleNumber::Int(v))
+    } else if let Ok(v) = number.parse::<u64>() {
+        Ok(PickleNumber::Uint(v))
+    } else if let Some(bytes) = parse_decimal_long(number).or_else(|| parse_hex_long(number)) {
+        Ok(PickleNumber::BigInt(bytes))
+    } else {
+        Err(Box::new(PickleError::InvalidNumber(str.into_owned())))
+    }
+}
+
+/// Parse a decimal integer to two's complement bytes, limited to as many digits as Python.
+fn parse_decimal_long(number: &str) -> Option<Vec<u8>> {
+    let (negative, digits) = match number.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, number.strip_prefix('+').unwrap_or(number)),
+    };
+    if digits.is_empty() || digits.len() > MAX_DIGITS || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut bytes = Vec::new();
+    for digit in digits.bytes() {
+        let mut carry = (digit - b'0') as u32;
+        for byte in bytes.iter_mut() {
+            let value = *byte as u32 * 10 + carry;
+            *byte = value as u8;
+            carry = value >> 8;
+        }
+        if carry > 0 {
+            bytes.push(carry as u8);
+        }
+    }
+    // Sign byte.
+    bytes.push(0x00);
+    if negative {
+        negate_long(&mut bytes);
+    }
+    PickleNumber::BigInt(bytes).long_bytes()
+}
+
+/// Parse a `0x` prefixed hex integer to two's complement bytes, Python reads `LONG` with `int(value, 0)`.
+fn parse_hex_long(number: &str) -> Option<Vec<u8>> {
+    let (negative, number) = match number.strip_prefix('-') {
+        Some(number) => (true, number),
+        None => (false, number.strip_prefix('+').unwrap_or(number)),
+    };
+    let hex = number.strip_prefix("0x").or_else(|| number.strip_prefix("0X"))?;
+    if hex.is_empty() || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let hex = if hex.len() % 2 == 1 { format!("0{}", hex) } else { hex.to_owned() };
+    let mut bytes = (0..hex.len()).step_by(2).rev().map(|index| u8::from_str_radix(&hex[index..index + 2], 16)).collect::<Result<Vec<_>, _>>().ok()?;
+    // Sign byte.
+    bytes.push(0x00);
+    if negative {
+        negate_long(&mut bytes);
+    }
+    PickleNumber::BigInt(bytes).long_bytes()
+}
+
+fn parse_index(line: &[u8]) -> Result<usize, Box<dyn Error>> {
+    let str = String::from_utf8_lossy(line);
+    str.trim().parse().map_err(|_| Box::new(PickleError::InvalidNumber(str.into_owned())) as Box<dyn Error>)
+}
+
+fn parse_float(line: &[u8]) -> Result<f64, Box<dyn Error>> {
+    let str = String::from_utf8_lossy(line);
+    str.trim().parse().map_err(|_| Box::new(PickleError::InvalidNumber(str.into_owned())) as Box<dyn Error>)
+}
+
+/// Objects that can change after they are created, they are shared when memoized.
+fn is_object(pickle: &Pickle) -> bool {
+    matches!(pickle, Pickle::List(_) | Pickle::Dict(_) | Pickle::Set(_) | Pickle::Class(_))
+}
+
+/// Replace references that are only used once with their object.
+///
+/// Objects that are used once are referenced from `objects` & from one place in the pickle.
+fn inline_objects(pickle: &mut Pickle, objects: &mut HashMap<usize, (PickleRef, Pickle, usize)>) {
+    match pickle {
+        Pickle::Ref(reference) if reference.count() == 2 => {
+            if let Some((_, mut object, _)) = objects.remove(&reference.id()) {
+                inline_objects(&mut object, objects);
+                *pickle = object;
+            }
+        },
+        Pickle::List(items) | Pickle::Tuple(items) | Pickle::Set(items) | Pickle::FrozenSet(items) => {
+            items.iter_mut().for_each(|item| inline_objects(item, objects));
+        },
+        Pickle::Dict(dict) => dict.modify(|items| for (key, value) in items {
+            inline_objects(key, objects);
+            inline_objects(value, objects);
+        }),
+        Pickle::Class(class) => {
+            inline_objects(&mut class.args, objects);
+            if let Some(kwargs) = &mut class.kwargs {
+                inline_objects(kwargs, objects);
+            }
+            if let Some(state) = &mut class.state {
+                inline_objects(state, objects);
+            }
+            class.data.modify(|items| for (key, value) in items {
+                inline_objects(key, objects);
+                inline_objects(value, objects);
+            });
+            class.items.iter_mut().for_each(|item| inline_objects(item, objects));
+        },
+        _ => { },
+    }
+}
+
+/// Call `f` with each value directly in a value, references are not followed.
+fn for_each_child(pickle: &Pickle, mut f: impl FnMut(&Pickle)) {
+    match pickle {
+        Pickle::List(items) | Pickle::Tuple(items) | Pickle::Set(items) | Pickle::FrozenSet(items) => items.iter().for_each(f),
+        Pickle::Dict(dict) => dict.iter().for_each(|(key, value)| {
+            f(key);
+            f(value);
+        }),
+        Pickle::Class(class) => {
+            f(&class.args);
+            class.kwargs.iter().chain(&class.state).for_each(|item| f(item));
+            class.data.iter().for_each(|(key, value)| {
+                f(key);
+                f(value);
+            });
+            class.items.iter().for_each(f);
+        },
+        _ => { },
+    }
+}
+
+/// Number of values & bytes in a value.
+fn value_size(pickle: &Pickle) -> (usize, usize) {
+    let bytes = match pickle {
+        Pickle::String(str) => str.len(),
+        Pickle::Binary(bytes) | Pickle::Number(PickleNumber::BigInt(bytes)) => bytes.len(),
+        _ => 0,
+    };
+    let mut size = (1, bytes);
+    for_each_child(pickle, |item| {
+        let (values, bytes) = value_size(item);
+        size = (size.0 + values, size.1 + bytes);
+    });
+    size
+}
+
+/// Depth of a value without following references, references are collected with the depth they are at.
+fn nested_references(pickle: &Pickle, depth: usize, references: &mut Vec<(usize, usize)>) -> usize {
+    if let Pickle::Ref(reference) = pickle {
+        references.push((reference.id(), depth));
+        return depth;
+    }
+    let mut deepest = depth;
+    for_each_child(pickle, |item| deepest = deepest.max(nested_references(item, depth + 1, references)));
+    deepest
+}
+
+
+
+/// Limit of [`PickleLimits`] that a pickle exceeded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PickleLimit {
+    Allocation(usize),
+    Depth(usize),
+    Memo(usize),
+    Objects(usize),
+    /// Global that is not allowed, as `module.name`.
+    Global(String),
+}
+
+impl fmt::Display for PickleLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Allocation(max) => write!(f, "more than {} bytes allocated", max),
+            Self::Depth(max) => write!(f, "nested more than {} deep", max),
+            Self::Memo(max) => write!(f, "more than {} memo items", max),
+            Self::Objects(max) => write!(f, "more than {} values", max),
+            Self::Global(global) => write!(f, "global \"{}\" is not allowed", global),
+        }
+    }
+}
+
+/// Limits on what a pickle may allocate, pickles from game files are untrusted.
+#[derive(Debug, Clone)]
+pub struct PickleLimits {
+    /// Bytes of strings, bytes & longs, including copies from the memo.
+    pub max_allocation: usize,
+    /// Nesting of lists, dicts, tuples, sets & objects, including through shared objects.
+    pub max_depth: usize,
+    /// Items in the memo.
+    pub max_memo: usize,
+    /// Values pushed to the stack, copies from the memo count every value they contain.
+    pub max_objects: usize,
+    /// Globals that may be loaded, all globals are allowed if `None`.
+    /// Entries are either `module.name`, or a module which allows its globals & submodules.
+    pub allowed_globals: Option<Vec<String>>,
+}
+
+impl Default for PickleLimits {
+    fn default() -> Self {
+        Self {
+            max_allocation: 1 << 30,
+            max_depth: 1000,
+            max_memo: 1 << 24,
+            max_objects: 1 << 24,
+            allowed_globals: None,
+        }
+    }
+}
+
+impl PickleLimits {
+
+    /// Add to the allowed globals, only allowed globals may be loaded after this.
+    pub fn allow_global(mut self, global: impl Into<String>) -> Self {
+        self.allowed_globals.get_or_insert_with(Vec::new).push(global.into());
+        self
+    }
+
+    fn allows_global(&self, module: &PickleModule) -> bool {
+        let Some(allowed_globals) = &self.allowed_globals else {
+            return true;
+        };
+        let global = format!("{}.{}", module.module, module.name);
+        allowed_globals.iter().any(|allowed| global == *allowed || global.strip_prefix(allowed.as_str()).is_some_and(|rest| rest.starts_with('.')))
+    }
+
+}
+
+
+
+pub type PicklePersistentLoad = Box<dyn FnMut(Pickle) -> Result<Pickle, Box<dyn Error>>>;
+
 pub struct PickleParser {
     protocol: PickleProtocol,
+    encoding: PickleStringEncoding,
     stack: PickleStack,
     memo: PickleMemo,
+    persistent_load: Option<PicklePersistentLoad>,
+    buffers: Option<VecDeque<Vec<u8>>>,
+    extensions: HashMap<u32, PickleModule>,
+    /// Memoized objects by their reference id with their depth, they are changed here until the pickle is loaded.
+    objects: HashMap<usize, (PickleRef, Pickle, usize)>,
+    limits: PickleLimits,
+    /// Bytes read for strings, bytes & longs, and copied from the memo.
+    allocated: usize,
+}
+
+impl fmt::Debug for PickleParser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PickleParser")
+            .field("protocol", &self.protocol)
+            .field("encoding", &self.encoding)
+            .field("stack", &self.stack)
+            .field("memo", &self.memo)
+            .field("persistent_load", &self.persistent_load.is_some())
+            .field("buffers", &self.buffers)
+            .field("extensions", &self.extensions)
+            .field("objects", &self.objects.len())
+            .field("limits", &self.limits)
+            .field("allocated", &self.allocated)
+            .finish()
+    }
 }
 
 impl PickleParser {
 
     pub fn new() -> Self {
+        Self::with_encoding(PickleStringEncoding::Utf8)
+    }
+
+    pub fn with_encoding(encoding: PickleStringEncoding) -> Self {
         Self {
             protocol: PickleProtocol::Unknown,
+            encoding,
             stack: PickleStack::new(),
             memo: PickleMemo::new(),
+            persistent_load: None,
+            buffers: None,
+            extensions: HashMap::new(),
+            objects: HashMap::new(),
+            limits: PickleLimits::default(),
+            allocated: 0,
         }
     }
 
-    fn read_operation(&mut self, data: &mut impl Read) -> Result<PickleOpcode, Box<dyn Error>> {
-        let opcode: PickleOpcode = PickleOpcode::new(data.read_primitive()?)?;
+    /// Resolve persistent ids, same as `Unpickler.persistent_load`.
+    ///
+    /// Pickles with persistent ids fail to load without it.
+    pub fn persistent_load(mut self, persistent_load: impl FnMut(Pickle) -> Result<Pickle, Box<dyn Error>> + 'static) -> Self {
+        self.persistent_load = Some(Box::new(persistent_load));
+        self
+    }
 
-        // println!("Opcode: {:#?}", opcode);
+    /// Out-of-band buffers for protocol 5 `NEXT_BUFFER`, in the order they were given to `buffer_callback`.
+    pub fn buffers(mut self, buffers: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        self.buffers = Some(buffers.into_iter().collect());
+        self
+    }
 
-        // First opcode MUST be protocol.
-        if self.protocol == PickleProtocol::Unknown {
-            if opcode == PickleOpcode::PROTO {
-                self.protocol = PickleProtocol::from(data.read_primitive()?)?;
-                return Ok(opcode);
-            } else {
-                return Err(Box::new(PickleError::PickleInvalidProtocolOperation));
+    /// Register an extension code, same as `copyreg.add_extension`.
+    pub fn extension(mut self, code: u32, module: PickleModule) -> Self {
+        self.extensions.insert(code, module);
+        self
+    }
+
+    /// Limits checked while reading, [`PickleLimits::default`] is used otherwise.
+    pub fn limits(mut self, limits: PickleLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Python 2 pickles use Python 2 module names.
+    fn global(&self, module: String, name: String) -> Result<PickleModule, Box<dyn Error>> {
+        let module = match self.protocol {
+            PickleProtocol::Protocol0 | PickleProtocol::Protocol1 | PickleProtocol::Protocol2 => python2_global(module, name),
+            _ => PickleModule::new(module, name),
+        };
+        if !self.limits.allows_global(&module) {
+            return Err(Box::new(PickleError::LimitExceeded(PickleLimit::Global(format!("{}.{}", module.module, module.name)))));
+        }
+        Ok(module)
+    }
+
+    fn limit(limit: PickleLimit) -> Box<dyn Error> {
+        Box::new(PickleError::LimitExceeded(limit))
+    }
+
+    fn allocate(&mut self, bytes: usize) -> Result<(), Box<dyn Error>> {
+        self.allocated = self.allocated.saturating_add(bytes);
+        if self.allocated > self.limits.max_allocation {
+            return Err(Self::limit(PickleLimit::Allocation(self.limits.max_allocation)));
+        }
+        Ok(())
+    }
+
+    /// Read a newline terminated argument, without the newline.
+    ///
+    /// Lines are charged as they grow, so a missing newline can not read past the allocation limit.
+    fn read_line(&mut self, data: &mut impl Read) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut line = Vec::new();
+        loop {
+            match data.read_primitive::<u8>()? {
+                0x0A => return Ok(line),
+                byte => {
+                    self.allocate(1)?;
+                    line.push(byte);
+                },
             }
         }
+    }
 
-        // TODO: Sort these.
-        match opcode {
-            PickleOpcode::PROTO => return Err(Box::new(PickleError::PickleInvalidProtocolOperation)),
-            PickleOpcode::FRAME => { data.read_primitive::<u64>()?; }, // Used to indicate how many bytes to preload.
-            PickleOpcode::EMPTY_DICT => { self.stack.push(Pickle::Dict(HashMap::new())); }
-            // TODO: This may break as they need to be the same item, not cloned.
-            PickleOpcode::MEMOIZE => { self.memo.push(self.stack.last()?.clone()); }
-            PickleOpcode::SHORT_BINUNICODE => { self.stack.push(Pickle::String(data.read_string::<u8>()?)); },
-            PickleOpcode::EMPTY_LIST => { self.stack.push(Pickle::List(Vec::new())); },
-            PickleOpcode::BININT => { self.stack.push(Pickle::Number(PickleNumber::Int(data.read_primitive::<i32>()?.into()))); },
-            PickleOpcode::SHORT_BINBYTES => {
-                let len: u8 = data.read_primitive()?;
-                self.stack.push(Pickle::Binary(data.read_to_vec(len as usize)?));
+    fn read_line_string(&mut self, data: &mut impl Read) -> Result<String, Box<dyn Error>> {
+        Ok(String::from_utf8(self.read_line(data)?)?)
+    }
+
+    /// Read a counted argument, the length is checked before anything is allocated.
+    fn read_bytes(&mut self, data: &mut impl Read, length: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.allocate(usize::try_from(length).unwrap_or(usize::MAX))?;
+        // Read as the data arrives, so a length longer than the data does not allocate it.
+        let mut bytes = Vec::new();
+        data.take(length).read_to_end(&mut bytes)?;
+        if (bytes.len() as u64) < length {
+            return Err(Box::new(io::Error::from(io::ErrorKind::UnexpectedEof)));
+        }
+        Ok(bytes)
+    }
+
+    fn read_string(&mut self, data: &mut impl Read, length: u64) -> Result<String, Box<dyn Error>> {
+        Ok(String::from_utf8(self.read_bytes(data, length)?)?)
+    }
+
+    /// Push a copy of a stack or memo item, everything in it counts towards the limits.
+    fn push_copy(&mut self, item: Pickle, depth: usize) -> Result<(), Box<dyn Error>> {
+        let (values, bytes) = value_size(&item);
+        self.allocate(bytes)?;
+        self.stack.push_values(item, depth, values);
+        Ok(())
+    }
+
+    fn get(&mut self, index: usize) -> Result<(), Box<dyn Error>> {
+        let (item, depth) = self.memo.get(index)?;
+        self.push_copy(item.clone(), depth)
+    }
+
+    fn put(&mut self, index: usize) -> Result<(), Box<dyn Error>> {
+        if !self.memo.contains(index) && self.memo.next_index() >= self.limits.max_memo {
+            return Err(Self::limit(PickleLimit::Memo(self.limits.max_memo)));
+        }
+        let (item, depth) = self.memoize()?;
+        self.memo.set(index, item, depth);
+        Ok(())
+    }
+
+    fn persistent(&mut self, pid: Pickle) -> Result<Pickle, Box<dyn Error>> {
+        match &mut self.persistent_load {
+            Some(persistent_load) => persistent_load(pid),
+            None => Err(Box::new(PickleError::MissingPersistentLoad)),
+        }
+    }
+
+    fn extension_module(&self, code: u32) -> Result<PickleModule, Box<dyn Error>> {
+        match self.extensions.get(&code) {
+            Some(module) => self.global(module.module.clone(), module.name.clone()),
+            None => Err(Box::new(PickleError::UnknownExtension(code))),
+        }
+    }
+
+    /// Memoize the stack top, objects are replaced with a reference so changes to them are shared.
+    fn memoize(&mut self) -> Result<(Pickle, usize), Box<dyn Error>> {
+        let (item, depth) = self.stack.last_nested()?;
+        if !is_object(item) {
+            return Ok((item.clone(), depth));
+        }
+        let (item, depth) = self.stack.pop_nested()?;
+        let reference = PickleRef::empty();
+        self.objects.insert(reference.id(), (reference.clone(), item, depth));
+        self.stack.push(Pickle::Ref(reference.clone()));
+        Ok((Pickle::Ref(reference), 0))
+    }
+
+    /// Object a reference points to, while it is being loaded.
+    fn object<'a>(&'a self, pickle: &'a Pickle) -> &'a Pickle {
+        match pickle {
+            Pickle::Ref(reference) => match self.objects.get(&reference.id()) {
+                Some((_, object, _)) => object,
+                None => reference.get(),
             },
-            PickleOpcode::TUPLE3 => {
-                let items = (self.stack.pop()?, self.stack.pop()?, self.stack.pop()?);
-                self.stack.push(Pickle::Tuple(vec![items.2, items.1, items.0]));
+            pickle => pickle,
+        }
+    }
+
+    /// Change the stack top below `items` & push it, it may be a reference to the object to change.
+    ///
+    /// Items are added inside the object, so its depth is at least one more than theirs.
+    fn modify(&mut self, items_depth: usize, f: impl FnOnce(Pickle) -> Result<Pickle, Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+        let (pickle, depth) = self.stack.pop_nested()?;
+        match pickle {
+            Pickle::Ref(reference) => {
+                let (_, object, depth) = self.objects.get_mut(&reference.id()).ok_or(PickleError::InvalidReferencePickleType)?;
+                *object = f(std::mem::replace(object, Pickle::None))?;
+                *depth = (*depth).max(items_depth + 1);
+                if *depth > self.limits.max_depth {
+                    return Err(Self::limit(PickleLimit::Depth(self.limits.max_depth)));
+                }
+                self.stack.push(Pickle::Ref(reference));
             },
-            PickleOpcode::APPEND => {
-                let item = self.stack.pop()?;
-                let mut list = TryInto::<Vec<Pickle>>::try_into(self.stack.pop()?)?;
-                list.push(item);
-                self.stack.push(Pickle::List(list));
+            pickle => {
+                let pickle = f(pickle)?;
+                self.stack.push_nested(pickle, depth.max(items_depth + 1));
             },
-            PickleOpcode::SETITEM => {
-                let item = self.stack.pop()?;
-                let key = TryInto::<String>::try_into(self.stack.pop()?)?;
-                let mut dict = self.stack.pop()?;
-                match dict {
-                    Pickle::Dict(ref mut dict) => dict.insert(key, item),
-                    Pickle::Class(ref mut class) => class.data.insert(key, item),
-                    _ => return Err(Box::new(PickleError::CannotTryInto)),
+        }
+        Ok(())
+    }
+
+    /// Depth of the pickle once its references are resolved.
+    ///
+    /// Objects are nested through references when they are loaded, which is checked before they are resolved.
+    fn check_object_depth(&self, pickle: &Pickle) -> Result<(), Box<dyn Error>> {
+        let references = |pickle: &Pickle| {
+            let mut references = Vec::new();
+            let depth = nested_references(pickle, 0, &mut references);
+            (references, depth)
+        };
+
+        // Objects that reference an object being visited are part of a cycle, which does not add depth.
+        let mut depths: HashMap<usize, usize> = HashMap::new();
+        let mut visiting: HashSet<usize> = HashSet::new();
+        for id in self.objects.keys().copied() {
+            if depths.contains_key(&id) {
+                continue;
+            }
+            // Objects being visited with their references, the next reference to visit & their depth.
+            let mut path = vec![(id, references(&self.objects[&id].1), 0)];
+            visiting.insert(id);
+            while let Some((id, (object_references, depth), index)) = path.last_mut() {
+                let Some((reference, reference_depth)) = object_references.get(*index).copied() else {
+                    let (id, depth) = (*id, *depth);
+                    if depth > self.limits.max_depth {
+                        return Err(Self::limit(PickleLimit::Depth(self.limits.max_depth)));
+                    }
+                    visiting.remove(&id);
+                    depths.insert(id, depth);
+                    path.pop();
+                    continue;
                 };
-                self.stack.push(dict);
+                match depths.get(&reference) {
+                    Some(object_depth) => {
+                        *depth = (*depth).max(reference_depth + object_depth);
+                        *index += 1;
+                    },
+                    None if visiting.contains(&reference) || !self.objects.contains_key(&reference) => *index += 1,
+                    None => {
+                        visiting.insert(reference);
+                        path.push((reference, references(&self.objects[&reference].1), 0));
+                    },
+                }
+            }
+        }
+
+        let (pickle_references, mut depth) = references(pickle);
+        for (reference, reference_depth) in pickle_references {
+            depth = depth.max(reference_depth + depths.get(&reference).copied().unwrap_or(0));
+        }
+        if depth > self.limits.max_depth {
+            return Err(Self::limit(PickleLimit::Depth(self.limits.max_depth)));
+        }
+        Ok(())
+    }
+
+    /// Set the objects of references, references that are only used once are replaced with their object.
+    fn resolve_objects(&mut self, mut pickle: Pickle) -> Pickle {
+        let mut objects = std::mem::take(&mut self.objects);
+        inline_objects(&mut pickle, &mut objects);
+        while let Some(id) = objects.keys().next().copied() {
+            if let Some((reference, mut object, _)) = objects.remove(&id) {
+                inline_objects(&mut object, &mut objects);
+                reference.set(object);
+            }
+        }
+        pickle
+    }
+
+    /// Set items of a dict, or a dict subclass.
+    fn set_items(mut dict: Pickle, items: Vec<Pickle>) -> Result<Pickle, Box<dyn Error>> {
+        let mut items = items.into_iter();
+        while let (Some(key), Some(value)) = (items.next(), items.next()) {
+            match dict {
+                Pickle::Dict(ref mut dict) => dict.insert(key, value),
+                Pickle::Class(ref mut class) => class.data.insert(key, value),
+                _ => return Err(Box::new(PickleError::CannotTryInto)),
+            };
+        }
+        Ok(dict)
+    }
+
+    /// Append items to a list, or a list subclass.
+    fn extend(list: Pickle, mut items: Vec<Pickle>) -> Result<Pickle, Box<dyn Error>> {
+        Ok(match list {
+            Pickle::List(mut list) => {
+                list.append(&mut items);
+                Pickle::List(list)
             },
-            PickleOpcode::STOP => { },
-            PickleOpcode::MARK => {
-                self.stack.push_mark();
+            Pickle::Class(mut class) => {
+                class.items.append(&mut items);
+                Pickle::Class(class)
             },
-            PickleOpcode::BINGET => {
-                let index: u8 = data.read_primitive()?;
-                let item = self.memo.get(index as usize)?;
-                self.stack.push(item.clone());
+            _ => return Err(Box::new(PickleError::CannotTryInto)),
+        })
+    }
+
+    /// Add items to a set created by `EMPTY_SET`.
+    fn add_items(set: Pickle, mut items: Vec<Pickle>) -> Result<Pickle, Box<dyn Error>> {
+        match set {
+            Pickle::Set(mut set) => {
+                set.append(&mut items);
+                Ok(Pickle::Set(set))
             },
-            PickleOpcode::SETITEMS => {
-                let mut items = self.stack.pop_mark()?;
-                let mut dict = self.stack.pop()?;
-                while items.len() > 0 {
-                    let value = items.pop().unwrap();
-                    let key = TryInto::<String>::try_into(items.pop().unwrap())?;
-                    match dict {
-                        Pickle::Dict(ref mut dict) => dict.insert(key, value),
-                        Pickle::Class(ref mut class) => class.data.insert(key, value),
-                        _ => return Err(Box::new(PickleError::CannotTryInto)),
+            _ => Err(Box::new(PickleError::CannotTryInto)),
+        }
+    }
+
+    /// Class created by `cls.__new__`.
+    fn new_class(mut module: PickleModule, args: Pickle, kwargs: Option<Pickle>) -> Pickle {
+        let mut class = module.class(args);
+        class.constructor = PickleConstructor::New;
+        class.kwargs = kwargs.map(Box::new);
+        Pickle::Class(class)
+    }
+
+    /// Apply callable to arguments, some helper functions are replaced with the object they create.
+    fn reduce(&self, mut module: PickleModule, args: Pickle) -> Pickle {
+        match (module.module.as_str(), module.name.as_str(), &args) {
+            // copy_reg._reconstructor(cls, base, state) is how protocol 0 & 1 create objects.
+            ("copyreg", "_reconstructor", Pickle::Tuple(args)) if args.len() == 3 => {
+                if let Pickle::Module(class) = &args[0] {
+                    let new_args = match &args[1] {
+                        Pickle::Module(base) if base.module == "builtins" && base.name == "object" => Pickle::Tuple(Vec::new()),
+                        _ => Pickle::Tuple(vec![args[2].clone()]),
                     };
+                    return Self::new_class(class.clone(), new_args, None);
                 }
-                self.stack.push(dict);
             },
-            // TODO: Don't clone, Refactor PickleStack & PickleMemo to use pointers to pickle.
-            PickleOpcode::BINPUT => { self.memo.set(data.read_primitive::<u8>()? as usize, self.stack.last()?.clone())?; },
-            PickleOpcode::BINUNICODE => { self.stack.push(Pickle::String(data.read_string::<u32>()?)); }
-            PickleOpcode::GLOBAL => { self.stack.push(Pickle::Module(PickleModule::new(data.read_terminated_string(0x0A)?, data.read_terminated_string(0x0A)?))) }
-            PickleOpcode::TUPLE1 => {
-                let item = self.stack.pop()?;
-                self.stack.push(Pickle::Tuple(vec![item]));
-            },
-            // TODO: These aren't the same are they?
-            PickleOpcode::REDUCE | PickleOpcode::NEWOBJ => {
-                let args = self.stack.pop()?;
-                let mut module = TryInto::<PickleModule>::try_into(self.stack.pop()?)?;
-                let class = module.class(args);
-                self.stack.push(Pickle::Class(class));
-            },
-            PickleOpcode::EMPTY_TUPLE => { self.stack.push(Pickle::Tuple(Vec::new())); },
-            PickleOpcode::NONE => { self.stack.push(Pickle::None); }
-            PickleOpcode::TUPLE2 => {
-                let items = (self.stack.pop()?, self.stack.pop()?);
-                self.stack.push(Pickle::Tuple(vec![items.1, items.0]));
+            // copy_reg.__newobj__(cls, *args) is the same as NEWOBJ.
+            ("copyreg", "__newobj__", Pickle::Tuple(args)) if !args.is_empty() => {
+                if let Pickle::Module(class) = &args[0] {
+                    return Self::new_class(class.clone(), Pickle::Tuple(args[1..].to_vec()), None);
+                }
+            },
+            // copyreg.__newobj_ex__(cls, args, kwargs) is the same as NEWOBJ_EX.
+            ("copyreg", "__newobj_ex__", Pickle::Tuple(args)) if args.len() == 3 => {
+                if let Pickle::Module(class) = &args[0] {
+                    return Self::new_class(class.clone(), args[1].clone(), Some(args[2].clone()));
+                }
+            },
+            // Sets pickled with protocol 3 and below, `set([items])`.
+            ("builtins", "set" | "frozenset", Pickle::Tuple(args)) if args.len() <= 1 => {
+                let items = match args.first().map(|items| self.object(items)) {
+                    None => Vec::new(),
+                    Some(Pickle::List(items) | Pickle::Tuple(items)) => items.clone(),
+                    Some(_) => return Pickle::Class(module.class(args.clone().into())),
+                };
+                return if module.name == "set" { Pickle::Set(items) } else { Pickle::FrozenSet(items) };
+            },
+            // Python 3 bytes pickled with protocol 2 or lower.
+            ("_codecs", "encode", Pickle::Tuple(args)) if args.len() == 2 => {
+                if let (Pickle::String(str), Pickle::String(encoding)) = (&args[0], &args[1]) {
+                    if encoding == "latin1" || encoding == "latin-1" {
+                        return Pickle::Binary(latin1_bytes(str));
+                    }
+                }
+            },
+            // Empty bytes pickled with protocol 2 or lower, and bytearray pickled with protocol 4 or lower.
+            ("builtins", "bytes" | "bytearray", Pickle::Tuple(args)) => match args.as_slice() {
+                [] => return Pickle::Binary(Vec::new()),
+                [Pickle::Binary(bytes)] => return Pickle::Binary(bytes.clone()),
+                [Pickle::String(str), Pickle::String(encoding)] if encoding == "latin1" || encoding == "latin-1" => return Pickle::Binary(latin1_bytes(str)),
+                _ => { },
             },
+            _ => { },
+        }
+        Pickle::Class(module.class(args))
+    }
+
+    /// Number of items & marks on the stack.
+    pub(super) fn stack_depth(&self) -> usize {
+        self.stack.stack.len()
+    }
+
+    pub(super) fn stack_top(&self) -> Option<&Pickle> {
+        match self.stack.stack.last() {
+            Some(PickleStackItem::Pickle(item, _)) => Some(item),
+            _ => None,
+        }
+    }
+
+    pub(super) fn next_memo_index(&self) -> usize {
+        self.memo.next_index()
+    }
+
+    /// Clear the state of the previous pickle.
+    pub(super) fn reset(&mut self) {
+        self.protocol = PickleProtocol::Unknown;
+        self.stack = PickleStack::new();
+        self.memo = PickleMemo::new();
+        self.objects = HashMap::new();
+        self.allocated = 0;
+    }
+
+    pub(super) fn read_operation(&mut self, data: &mut impl Read) -> Result<PickleOpcode, Box<dyn Error>> {
+        let opcode: PickleOpcode = PickleOpcode::new(data.read_primitive()?)?;
+
+        // Protocol 0 & 1 do not start with PROTO.
+        if self.protocol == PickleProtocol::Unknown && opcode != PickleOpcode::PROTO {
+            self.protocol = PickleProtocol::Protocol0;
+        }
+
+        match opcode {
+            PickleOpcode::MARK => { self.stack.push_mark(); },
+            PickleOpcode::STOP => { },
+            PickleOpcode::POP => { self.stack.discard()?; },
+            PickleOpcode::POP_MARK => { self.stack.pop_mark()?; },
+            PickleOpcode::DUP => {
+                let (item, depth) = self.stack.last_nested()?;
+                let item = item.clone();
+                self.push_copy(item, depth)?;
+            },
+            PickleOpcode::FLOAT => {
+                let line = self.read_line(data)?;
+                self.stack.push(Pickle::Number(PickleNumber::Float(parse_float(&line)?)));
+            },
+            PickleOpcode::INT => {
+                let line = self.read_line(data)?;
+                // Protocol 0 True & False.
+                match line.as_slice() {
+                    b"00" => self.stack.push(Pickle::Bool(false)),
+                    b"01" => self.stack.push(Pickle::Bool(true)),
+                    line => self.stack.push(Pickle::Number(parse_int(line)?)),
+                }
+            },
+            PickleOpcode::BININT => { self.stack.push(Pickle::Number(PickleNumber::Int(data.read_primitive::<i32>()?.into()))); },
             PickleOpcode::BININT1 => { self.stack.push(Pickle::Number(PickleNumber::Uint(data.read_primitive::<u8>()?.into()))); },
-            PickleOpcode::TUPLE => {
-                let mut items = self.stack.pop_mark()?;
-                items.reverse();
-                self.stack.push(Pickle::Tuple(items));
+            PickleOpcode::LONG => {
+                let line = self.read_line(data)?;
+                self.stack.push(Pickle::Number(parse_int(&line)?));
+            },
+            PickleOpcode::BININT2 => { self.stack.push(Pickle::Number(PickleNumber::Int(data.read_primitive::<u16>()?.into()))); },
+            PickleOpcode::NONE => { self.stack.push(Pickle::None); },
+            PickleOpcode::PERSID => {
+                let pid = Pickle::String(String::from_utf8(self.read_line(data)?)?);
+                let item = self.persistent(pid)?;
+                self.push_copy(item.clone(), nested_references(&item, 0, &mut Vec::new()))?;
+            },
+            PickleOpcode::BINPERSID => {
+                let pid = self.stack.pop()?;
+                let item = self.persistent(pid)?;
+                self.push_copy(item.clone(), nested_references(&item, 0, &mut Vec::new()))?;
+            },
+            PickleOpcode::REDUCE => {
+                let (args, depth) = self.stack.pop_nested()?;
+                let module = TryInto::<PickleModule>::try_into(self.stack.pop()?)?;
+                let item = self.reduce(module, args);
+                self.stack.push_nested(item, depth + 1);
+            },
+            // Python 2 str.
+            PickleOpcode::STRING => {
+                let bytes = unescape_string(&self.read_line(data)?)?;
+                self.stack.push(self.encoding.decode(bytes));
+            },
+            PickleOpcode::BINSTRING => {
+                let length: u32 = data.read_primitive()?;
+                let bytes = self.read_bytes(data, length.into())?;
+                self.stack.push(self.encoding.decode(bytes));
+            },
+            PickleOpcode::SHORT_BINSTRING => {
+                let length: u8 = data.read_primitive()?;
+                let bytes = self.read_bytes(data, length.into())?;
+                self.stack.push(self.encoding.decode(bytes));
+            },
+            PickleOpcode::UNICODE => {
+                let line = self.read_line(data)?;
+                self.stack.push(Pickle::String(unescape_raw_unicode(&line)?));
+            },
+            PickleOpcode::BINUNICODE => {
+                let length: u32 = data.read_primitive()?;
+                let str = self.read_string(data, length.into())?;
+                self.stack.push(Pickle::String(str));
+            },
+            PickleOpcode::APPEND => {
+                let (item, depth) = self.stack.pop_nested()?;
+                self.modify(depth, |list| Self::extend(list, vec![item]))?;
             },
             PickleOpcode::BUILD => {
-                let state = self.stack.pop()?;
-                let mut class = TryInto::<PickleClass>::try_into(self.stack.pop()?)?;
-                class.state = Some(Box::new(state));
-                self.stack.push(Pickle::Class(class));
-            },
-            PickleOpcode::NEWFALSE => { self.stack.push(Pickle::Bool(false)); }
-            PickleOpcode::NEWTRUE => { self.stack.push(Pickle::Bool(true)); }
-            // TODO: Don't clone, Refactor PickleStack & PickleMemo to use pointers to pickle.
-            PickleOpcode::LONG_BINPUT => { self.memo.set(data.read_primitive::<u32>()? as usize, self.stack.last()?.clone())?; },
-            // TODO: Don't clone, Refactor PickleStack & PickleMemo to use pointers to pickle.
-            PickleOpcode::LONG_BINGET => { self.stack.push(self.memo.get(data.read_primitive::<u32>()? as usize)?.clone()); }
+                let (state, depth) = self.stack.pop_nested()?;
+                self.modify(depth, |class| match class {
+                    Pickle::Class(mut class) => {
+                        class.state = Some(Box::new(state));
+                        Ok(Pickle::Class(class))
+                    },
+                    _ => Err(Box::new(PickleError::CannotTryInto)),
+                })?;
+            },
+            PickleOpcode::GLOBAL => {
+                let (module, name) = (self.read_line_string(data)?, self.read_line_string(data)?);
+                let module = self.global(module, name)?;
+                self.stack.push(Pickle::Module(module));
+            },
+            PickleOpcode::DICT => {
+                let (items, depth) = self.stack.pop_mark_nested()?;
+                self.stack.push_nested(Self::set_items(Pickle::Dict(PickleDict::new()), items)?, depth + 1);
+            },
+            PickleOpcode::EMPTY_DICT => { self.stack.push_nested(Pickle::Dict(PickleDict::new()), 1); },
             PickleOpcode::APPENDS => {
-                let mut items = self.stack.pop_mark()?;
-                let mut list = TryInto::<Vec<Pickle>>::try_into(self.stack.pop()?)?;
-                list.append(&mut items);
-                self.stack.push(Pickle::List(list));
+                let (items, depth) = self.stack.pop_mark_nested()?;
+                self.modify(depth, |list| Self::extend(list, items))?;
             },
-            PickleOpcode::BININT2 => { self.stack.push(Pickle::Number(PickleNumber::Int(data.read_primitive::<u16>()?.into()))); },
+            // Objects are shared by reference, other values are immutable so they are cloned.
+            PickleOpcode::GET => {
+                let index = parse_index(&self.read_line(data)?)?;
+                self.get(index)?;
+            },
+            PickleOpcode::BINGET => { self.get(data.read_primitive::<u8>()? as usize)?; },
+            // Python 2 old-style classes.
+            PickleOpcode::INST => {
+                let (module, name) = (self.read_line_string(data)?, self.read_line_string(data)?);
+                let mut module = self.global(module, name)?;
+                let (args, depth) = self.stack.pop_mark_nested()?;
+                self.stack.push_nested(Pickle::Class(module.class(Pickle::Tuple(args))), depth + 2);
+            },
+            PickleOpcode::LONG_BINGET => { self.get(data.read_primitive::<u32>()? as usize)?; },
+            PickleOpcode::LIST => {
+                let (items, depth) = self.stack.pop_mark_nested()?;
+                self.stack.push_nested(Pickle::List(items), depth + 1);
+            },
+            PickleOpcode::EMPTY_LIST => { self.stack.push_nested(Pickle::List(Vec::new()), 1); },
+            PickleOpcode::OBJ => {
+                let (mut args, depth) = self.stack.pop_mark_nested()?;
+                if args.is_empty() {
+                    return Err(Box::new(PickleError::StackEmpty));
+                }
+                let mut module = TryInto::<PickleModule>::try_into(args.remove(0))?;
+                self.stack.push_nested(Pickle::Class(module.class(Pickle::Tuple(args))), depth + 2);
+            },
+            PickleOpcode::PUT => {
+                let index = parse_index(&self.read_line(data)?)?;
+                self.put(index)?;
+            },
+            PickleOpcode::BINPUT => { self.put(data.read_primitive::<u8>()? as usize)?; },
+            PickleOpcode::LONG_BINPUT => { self.put(data.read_primitive::<u32>()? as usize)?; },
+            PickleOpcode::SETITEM => {
+                let (value, value_depth) = self.stack.pop_nested()?;
+                let (key, key_depth) = self.stack.pop_nested()?;
+                self.modify(value_depth.max(key_depth), |dict| Self::set_items(dict, vec![key, value]))?;
+            },
+            PickleOpcode::TUPLE => {
+                let (items, depth) = self.stack.pop_mark_nested()?;
+                self.stack.push_nested(Pickle::Tuple(items), depth + 1);
+            },
+            PickleOpcode::EMPTY_TUPLE => { self.stack.push_nested(Pickle::Tuple(Vec::new()), 1); },
+            PickleOpcode::SETITEMS => {
+                let (items, depth) = self.stack.pop_mark_nested()?;
+                self.modify(depth, |dict| Self::set_items(dict, items))?;
+            },
+            PickleOpcode::BINFLOAT => {
+                let mut bytes = [0; 8];
+                data.read_exact(&mut bytes)?;
+                self.stack.push(Pickle::Number(PickleNumber::Float(f64::from_be_bytes(bytes))));
+            },
+            // Protocol 2
+            PickleOpcode::PROTO => { self.protocol = PickleProtocol::from(data.read_primitive()?)?; },
+            PickleOpcode::NEWOBJ => {
+                let (args, depth) = self.stack.pop_nested()?;
+                let module = TryInto::<PickleModule>::try_into(self.stack.pop()?)?;
+                self.stack.push_nested(Self::new_class(module, args, None), depth + 1);
+            },
+            PickleOpcode::EXT1 => { self.stack.push(Pickle::Module(self.extension_module(data.read_primitive::<u8>()?.into())?)); },
+            PickleOpcode::EXT2 => { self.stack.push(Pickle::Module(self.extension_module(data.read_primitive::<u16>()?.into())?)); },
+            PickleOpcode::EXT4 => { self.stack.push(Pickle::Module(self.extension_module(data.read_primitive::<u32>()?)?)); },
+            PickleOpcode::TUPLE1 => {
+                let (item, depth) = self.stack.pop_nested()?;
+                self.stack.push_nested(Pickle::Tuple(vec![item]), depth + 1);
+            },
+            PickleOpcode::TUPLE2 => {
+                let items = (self.stack.pop_nested()?, self.stack.pop_nested()?);
+                self.stack.push_nested(Pickle::Tuple(vec![items.1.0, items.0.0]), items.0.1.max(items.1.1) + 1);
+            },
+            PickleOpcode::TUPLE3 => {
+                let items = (self.stack.pop_nested()?, self.stack.pop_nested()?, self.stack.pop_nested()?);
+                self.stack.push_nested(Pickle::Tuple(vec![items.2.0, items.1.0, items.0.0]), items.0.1.max(items.1.1).max(items.2.1) + 1);
+            },
+            PickleOpcode::NEWTRUE => { self.stack.push(Pickle::Bool(true)); },
+            PickleOpcode::NEWFALSE => { self.stack.push(Pickle::Bool(false)); },
             PickleOpcode::LONG1 => {
                 let length: u8 = data.read_primitive()?;
-                let bytes = data.read_to_vec(length as usize)?;
+                let bytes = self.read_bytes(data, length.into())?;
+                self.stack.push(Pickle::Number(PickleNumber::BigInt(bytes)));
+            },
+            PickleOpcode::LONG4 => {
+                let length: i32 = data.read_primitive()?;
+                let length = u64::try_from(length).map_err(|_| PickleError::InvalidNumber(length.to_string()))?;
+                let bytes = self.read_bytes(data, length)?;
                 self.stack.push(Pickle::Number(PickleNumber::BigInt(bytes)));
             },
-            opcode => return Err(Box::new(PickleError::UnsupportedOperation(opcode))),
+            // Protocol 3
+            PickleOpcode::BINBYTES => {
+                let length: u32 = data.read_primitive()?;
+                let bytes = self.read_bytes(data, length.into())?;
+                self.stack.push(Pickle::Binary(bytes));
+            },
+            PickleOpcode::SHORT_BINBYTES => {
+                let length: u8 = data.read_primitive()?;
+                let bytes = self.read_bytes(data, length.into())?;
+                self.stack.push(Pickle::Binary(bytes));
+            },
+            // Protocol 4
+            PickleOpcode::SHORT_BINUNICODE => {
+                let length: u8 = data.read_primitive()?;
+                let str = self.read_string(data, length.into())?;
+                self.stack.push(Pickle::String(str));
+            },
+            PickleOpcode::BINUNICODE8 => {
+                let length: u64 = data.read_primitive()?;
+                let str = self.read_string(data, length)?;
+                self.stack.push(Pickle::String(str));
+            },
+            PickleOpcode::BINBYTES8 => {
+                let length: u64 = data.read_primitive()?;
+                let bytes = self.read_bytes(data, length)?;
+                self.stack.push(Pickle::Binary(bytes));
+            },
+            PickleOpcode::EMPTY_SET => { self.stack.push_nested(Pickle::Set(Vec::new()), 1); },
+            PickleOpcode::ADDITEMS => {
+                let (items, depth) = self.stack.pop_mark_nested()?;
+                self.modify(depth, |set| Self::add_items(set, items))?;
+            },
+            PickleOpcode::FROZENSET => {
+                let (items, depth) = self.stack.pop_mark_nested()?;
+                self.stack.push_nested(Pickle::FrozenSet(items), depth + 1);
+            },
+            PickleOpcode::NEWOBJ_EX => {
+                let (kwargs, kwargs_depth) = self.stack.pop_nested()?;
+                let (args, args_depth) = self.stack.pop_nested()?;
+                let module = TryInto::<PickleModule>::try_into(self.stack.pop()?)?;
+                self.stack.push_nested(Self::new_class(module, args, Some(kwargs)), args_depth.max(kwargs_depth) + 1);
+            },
+            PickleOpcode::STACK_GLOBAL => {
+                let name = TryInto::<String>::try_into(self.stack.pop()?)?;
+                let module = TryInto::<String>::try_into(self.stack.pop()?)?;
+                let module = self.global(module, name)?;
+                self.stack.push(Pickle::Module(module));
+            },
+            PickleOpcode::MEMOIZE => { self.put(self.memo.next_index())?; },
+            PickleOpcode::FRAME => { data.read_primitive::<u64>()?; }, // Used to indicate how many bytes to preload.
+            // Protocol 5
+            PickleOpcode::BYTEARRAY8 => {
+                let length: u64 = data.read_primitive()?;
+                let bytes = self.read_bytes(data, length)?;
+                self.stack.push(Pickle::Binary(bytes));
+            },
+            PickleOpcode::NEXT_BUFFER => {
+                let buffer = self.buffers.as_mut().and_then(|buffers| buffers.pop_front()).ok_or(PickleError::MissingBuffer)?;
+                self.stack.push(Pickle::Binary(buffer));
+            },
+            PickleOpcode::READONLY_BUFFER => { self.stack.last()?; },
+        }
+
+        // Only the stack top can be new after an operation.
+        if self.stack.pushed > self.limits.max_objects {
+            return Err(Self::limit(PickleLimit::Objects(self.limits.max_objects)));
+        }
+        if let Ok((_, depth)) = self.stack.last_nested() {
+            if depth > self.limits.max_depth {
+                return Err(Self::limit(PickleLimit::Depth(self.limits.max_depth)));
+            }
         }
 
         Ok(opcode)
     }
 
     pub fn read(&mut self, data: &mut impl Read) -> Result<(), Box<dyn Error>> {
-        // TODO: Move protocol version read to here.
         while self.read_operation(data)? != PickleOpcode::STOP { }
         Ok(())
     }
 
+    /// Read a pickle, the parser may be reused to read the next pickle in the stream.
+    pub fn load(&mut self, data: &mut impl Read) -> Result<Pickle, Box<dyn Error>> {
+        self.reset();
+        self.read(data)?;
+        let pickle = self.stack.pop()?;
+        self.check_object_depth(&pickle)?;
+
+        // Only the pickle may reference objects, so references used once can be found.
+        self.stack = PickleStack::new();
+        self.memo = PickleMemo::new();
+        Ok(self.resolve_objects(pickle))
+    }
+
     pub fn parse(data: &mut impl Read) -> Result<Pickle, Box<dyn Error>> {
-        let mut reader = PickleParser::new();
-        reader.read(data)?;
-        Ok(reader.stack.pop()?)
+        Self::parse_with_encoding(data, PickleStringEncoding::Utf8)
+    }
+
+    pub fn parse_with_encoding(data: &mut impl Read, encoding: PickleStringEncoding) -> Result<Pickle, Box<dyn Error>> {
+        PickleParser::with_encoding(encoding).load(data)
     }
 
 }
 
 
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, error::Error, fs::File, io::{BufReader, Cursor}};
+    use crate::util::pickle::{error::PickleError, pickle::{Pickle, PickleClass, PickleDict, PickleModule, PickleRef}};
+    use super::{PickleLimit, PickleLimits, PickleParser};
+
+    // Generated by `tests/data/pickle/generate.py`.
+    fn load_with(mut parser: PickleParser, name: &str) -> Result<Pickle, Box<dyn Error>> {
+        let path = format!("{}/tests/data/pickle/{}.pickle", env!("CARGO_MANIFEST_DIR"), name);
+        parser.load(&mut BufReader::new(File::open(path)?))
+    }
+
+    fn load(name: &str) -> Result<Pickle, Box<dyn Error>> {
+        load_with(PickleParser::new(), name)
+    }
+
+    fn items(pickle: Pickle) -> Result<Vec<Pickle>, Box<dyn Error>> {
+        Ok(pickle.try_into()?)
+    }
+
+    fn class(pickle: &Pickle) -> Result<PickleClass, Box<dyn Error>> {
+        Ok(pickle.clone().try_into()?)
+    }
+
+    #[test]
+    fn scalars() -> Result<(), Box<dyn Error>> {
+        for protocol in 0..=5 {
+            assert_eq!(
+                load(&format!("scalars_{}", protocol))?.to_string(),
+                "(None, True, False, 0, 1, -1, 255, 256, 65535, 65536, -2147483648, 2147483647, 1.5, -0.25, '', 'héllo ☃', 'a\\nb\\\\c')",
+            );
+
+            let large = items(load(&format!("large_int_{}", protocol))?)?;
+            assert_eq!(TryInto::<i64>::try_into(large[0].clone())?, 1 << 40);
+            assert_eq!(TryInto::<i64>::try_into(large[1].clone())?, -(1 << 40));
+            assert!(TryInto::<i32>::try_into(large[0].clone()).is_err());
+
+            let big = load(&format!("big_int_{}", protocol))?;
+            assert_eq!(big.to_string(), "(18446744073709551616, -1267650600228229401496703205376, 340282366920938463463374607431768211455, -100000000000000000000000000000000000000000000000000)");
+            let big = items(big)?;
+            assert_eq!(TryInto::<u128>::try_into(big[0].clone())?, 1 << 64);
+            assert!(TryInto::<u64>::try_into(big[0].clone()).is_err());
+            assert_eq!(TryInto::<i128>::try_into(big[1].clone())?, -(1 << 100));
+            assert_eq!(TryInto::<u128>::try_into(big[2].clone())?, u128::MAX);
+            assert!(TryInto::<i128>::try_into(big[3].clone()).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn binary() -> Result<(), Box<dyn Error>> {
+        for protocol in 0..=5 {
+            let binary = items(load(&format!("binary_{}", protocol))?)?
+                .into_iter()
+                .map(TryInto::<Vec<u8>>::try_into)
+                .collect::<Result<Vec<_>, _>>()?;
+            assert_eq!(binary, vec![b"".to_vec(), b"abc".to_vec(), (0..=255).collect(), b"xyz".to_vec(), b"".to_vec()]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn containers() -> Result<(), Box<dyn Error>> {
+        for protocol in 0..=5 {
+            let dict: HashMap<String, Pickle> = load(&format!("containers_{}", protocol))?.try_into()?;
+            assert_eq!(dict["list"].to_string(), "[1, 2, 3]");
+            assert_eq!(dict["tuples"].to_string(), "((), (1,), (1, 2), (1, 2, 3), (1, 2, 3, 4))");
+            assert_eq!(dict["dict"].to_string(), "{'a': 1, 'b': []}");
+            assert_eq!(dict["set"].to_string(), "{1, 2, 3}");
+            assert_eq!(dict["frozenset"].to_string(), "frozenset({4, 5})");
+
+            let long_list = items(dict["long_list"].clone())?;
+            assert_eq!(long_list.len(), 1200);
+            assert_eq!(TryInto::<u64>::try_into(long_list[1199].clone())?, 1199);
+            let long_dict: HashMap<String, Pickle> = dict["long_dict"].clone().try_into()?;
+            assert_eq!(long_dict.len(), 1100);
+            assert_eq!(TryInto::<u64>::try_into(long_dict["1099"].clone())?, 1099);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn keys() -> Result<(), Box<dyn Error>> {
+        for protocol in 0..=5 {
+            let dict: PickleDict = load(&format!("keys_{}", protocol))?.try_into()?;
+            assert_eq!(dict.get_key(&Pickle::from(1)), Some(&Pickle::from("int")));
+            assert_eq!(dict.get_key(&Pickle::from(1.5)), Some(&Pickle::from("float")));
+            assert_eq!(dict.get_key(&Pickle::None), Some(&Pickle::from("none")));
+            assert_eq!(dict.get_key(&Pickle::Tuple(vec![Pickle::from(1), Pickle::from("a")])), Some(&Pickle::from("tuple")));
+            assert_eq!(dict.get_key(&Pickle::FrozenSet(vec![Pickle::from(2)])), Some(&Pickle::from("frozenset")));
+
+            // Objects are keys by identity.
+            let plain = dict.get("plain").cloned().ok_or("missing plain")?;
+            assert_eq!(dict.get_key(&plain), Some(&Pickle::from("object")));
+            assert_eq!(dict.keys().nth(5), Some(&plain));
+            assert_eq!(dict.get_key(&Pickle::Ref(PickleRef::new(plain.resolve().clone()))), None);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn objects() -> Result<(), Box<dyn Error>> {
+        for protocol in 0..=5 {
+            let objects = items(load(&format!("objects_{}", protocol))?)?;
+
+            let plain = class(&objects[0])?;
+            assert_eq!(plain.module.name, "Plain");
+            assert_eq!(plain.state.map(|state| state.to_string()).as_deref(), Some("{'x': 1, 'y': 'two'}"));
+
+            assert_eq!(objects[1].to_string(), "__main__.Reduced(1, 'a')");
+
+            let new_args = class(&objects[2])?;
+            assert_eq!(new_args.args.to_string(), if protocol < 2 { "()" } else { "(5,)" });
+            assert_eq!(new_args.state.map(|state| state.to_string()).as_deref(), Some("{'value': 5}"));
+
+            assert_eq!(objects[3].to_string(), "(__main__.Plain, builtins.len)");
+        }
+
+        for protocol in 2..=5 {
+            let slots = class(&load(&format!("slots_{}", protocol))?)?;
+            assert_eq!(slots.state.map(|state| state.to_string()).as_deref(), Some("(None, {'a': 1, 'b': 2})"));
+        }
+
+        for protocol in 4..=5 {
+            let new_args_ex = class(&load(&format!("newargs_ex_{}", protocol))?)?;
+            assert_eq!(new_args_ex.args.to_string(), "(1,)");
+            assert_eq!(new_args_ex.kwargs.map(|kwargs| kwargs.to_string()).as_deref(), Some("{'key': 'value'}"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn memo() -> Result<(), Box<dyn Error>> {
+        for protocol in 0..=5 {
+            let shared = items(load(&format!("shared_{}", protocol))?)?;
+            assert_eq!(shared[0].to_string(), "[1]");
+            assert_eq!(shared[4].to_string(), "'299'");
+            let dict: PickleDict = shared[2].clone().try_into()?;
+            match (&shared[0], &shared[1], dict.get("items")) {
+                (Pickle::Ref(a), Pickle::Ref(b), Some(Pickle::Ref(c))) => assert!(a.ptr_eq(b) && a.ptr_eq(c)),
+                items => panic!("{:?} is not shared", items),
+            }
+            // Used once, so not a reference.
+            assert!(matches!(shared[3], Pickle::List(_)));
+
+            let recursive = load(&format!("recursive_{}", protocol))?;
+            assert_eq!(recursive.to_string(), "[([...],)]");
+            let Pickle::Ref(list) = &recursive else { panic!("{:?} is not a reference", recursive) };
+            match list.get() {
+                Pickle::List(items) => match &items[..] {
+                    [Pickle::Tuple(tuple)] => assert!(matches!(&tuple[0], Pickle::Ref(item) if item.ptr_eq(list))),
+                    items => panic!("{:?}", items),
+                },
+                pickle => panic!("{:?}", pickle),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn persistent_load() -> Result<(), Box<dyn Error>> {
+        for protocol in 0..=5 {
+            assert!(load(&format!("persistent_{}", protocol)).is_err());
+
+            let parser = PickleParser::new().persistent_load(|pid| {
+                let pid: String = pid.try_into()?;
+                Ok(Pickle::String(format!("resolved {}", pid)))
+            });
+            let persistent = items(load_with(parser, &format!("persistent_{}", protocol))?)?;
+            assert_eq!(persistent[0].to_string(), "'resolved external-1'");
+            assert_eq!(class(&persistent[1])?.module.name, "Plain");
+            assert_eq!(persistent[2].to_string(), "'resolved external-2'");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn extensions() -> Result<(), Box<dyn Error>> {
+        for protocol in 2..=5 {
+            assert!(load(&format!("extensions_{}", protocol)).is_err());
+
+            let parser = PickleParser::new()
+                .extension(1, PickleModule::new("__main__".to_owned(), "Plain".to_owned()))
+                .extension(300, PickleModule::new("__main__".to_owned(), "Reduced".to_owned()))
+                .extension(70000, PickleModule::new("__main__".to_owned(), "NewArgs".to_owned()));
+            assert_eq!(load_with(parser, &format!("extensions_{}", protocol))?.to_string(), "(__main__.Plain, __main__.Reduced, __main__.NewArgs)");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn buffers() -> Result<(), Box<dyn Error>> {
+        assert!(load("buffers_5").is_err());
+
+        let parser = PickleParser::new().buffers(vec![b"abc".to_vec(), b"def".to_vec()]);
+        assert_eq!(load_with(parser, "buffers_5")?.to_string(), "[b'abc', b'def']");
+        assert_eq!(load("buffers_inband_5")?.to_string(), "[b'abc', b'def']");
+        Ok(())
+    }
+
+    #[test]
+    fn python2() -> Result<(), Box<dyn Error>> {
+        let inst = class(&load("python2_inst")?)?;
+        assert_eq!(inst.module.name, "Old");
+        assert_eq!(inst.state.map(|state| state.to_string()).as_deref(), Some("{'x': 1}"));
+
+        let obj = class(&load("python2_obj")?)?;
+        assert_eq!(obj.args.to_string(), "(1, 2)");
+        assert_eq!(obj.state.map(|state| state.to_string()).as_deref(), Some("{'y': 2}"));
+
+        assert_eq!(load("python2_strings")?.to_string(), "('abc', 'tab\there\u{0}\\'', 'def', 'ghi', 'snow ☃')");
+        assert_eq!(load("python2_containers")?.to_string(), "{'list': [1, 12345678901, 1.5], 'tuple': (False, True), 'dict': {'a': 1}}");
+        assert_eq!(load("python2_memo")?.to_string(), "['shared', 'shared', [...]]");
+        assert_eq!(load("stack")?.to_string(), "(1, 1)");
+        assert_eq!(load("large_4")?.to_string(), "(18446744073709551616, b'abc', 'def')");
+        Ok(())
+    }
+
+    fn load_limited(limits: PickleLimits, data: &[u8]) -> Result<Pickle, Box<dyn Error>> {
+        PickleParser::new().limits(limits).load(&mut Cursor::new(data))
+    }
+
+    fn exceeded(result: Result<Pickle, Box<dyn Error>>) -> Option<PickleLimit> {
+        match result.err()?.downcast::<PickleError>().ok().map(|err| *err) {
+            Some(PickleError::LimitExceeded(limit)) => Some(limit),
+            _ => None,
+        }
+    }
+
+    /// `count` lists that are each appended to the one before, made with the memo.
+    fn nested_lists(count: u32) -> Vec<u8> {
+        let mut data = b"\x80\x02".to_vec();
+        for index in 0..count {
+            data.extend(b"]r");
+            data.extend(index.to_le_bytes());
+            data.push(b'0');
+        }
+        for index in 1..count {
+            data.push(b'j');
+            data.extend((index - 1).to_le_bytes());
+            data.push(b'j');
+            data.extend(index.to_le_bytes());
+            data.extend(b"a0");
+        }
+        data.extend(b"j\x00\x00\x00\x00.");
+        data
+    }
+
+    #[test]
+    fn limits() -> Result<(), Box<dyn Error>> {
+        // Lengths are checked before they are allocated.
+        let huge = b"\x80\x04\x8e\xff\xff\xff\xff\xff\xff\xff\x7f.";
+        assert_eq!(exceeded(load_limited(PickleLimits::default(), huge)), Some(PickleLimit::Allocation(1 << 30)));
+        let truncated = b"\x80\x04\x8e\x00\x00\x00\x10\x00\x00\x00\x00abc.";
+        assert!(load_limited(PickleLimits::default(), truncated).is_err());
+
+        let mut deep = b"\x80\x02N".to_vec();
+        deep.extend([0x85; 2000]);
+        deep.push(b'.');
+        assert_eq!(exceeded(load_limited(PickleLimits::default(), &deep)), Some(PickleLimit::Depth(1000)));
+
+        // Shared objects only nest once they are resolved.
+        let limits = PickleLimits { max_depth: 100, ..Default::default() };
+        assert_eq!(exceeded(load_limited(limits.clone(), &nested_lists(200))), Some(PickleLimit::Depth(100)));
+        assert_eq!(load_limited(limits, &nested_lists(50))?.to_string(), format!("{}{}", "[".repeat(50), "]".repeat(50)));
+
+        // Memo indexes are not allocated up to.
+        assert_eq!(load_limited(PickleLimits::default(), b"\x80\x02Nr\xff\xff\xff\xff0j\xff\xff\xff\xff.")?, Pickle::None);
+        let memo = b"\x80\x02Nq\x000Nq\x010Nq\x02.";
+        assert_eq!(exceeded(load_limited(PickleLimits { max_memo: 2, ..Default::default() }, memo)), Some(PickleLimit::Memo(2)));
+
+        // Copies count everything they contain.
+        let mut copies = b"\x80\x02(NNNNNNNNNNt".to_vec();
+        copies.extend([b'2'; 20]);
+        copies.push(b'.');
+        assert_eq!(exceeded(load_limited(PickleLimits { max_objects: 100, ..Default::default() }, &copies)), Some(PickleLimit::Objects(100)));
+
+        let limits = PickleLimits::default().allow_global("builtins").allow_global("__main__.Plain");
+        assert_eq!(load_limited(limits.clone(), b"\x80\x02c__builtin__\nset\n]\x85R.")?.to_string(), "set()");
+        assert_eq!(load_limited(limits.clone(), b"\x80\x02c__main__\nPlain\n.")?.to_string(), "__main__.Plain");
+        assert_eq!(exceeded(load_limited(limits.clone(), b"\x80\x02cos\nsystem\n.")), Some(PickleLimit::Global("os.system".to_owned())));
+        assert_eq!(exceeded(load_limited(limits, b"\x80\x02cbuiltins_extra\nx\n.")), Some(PickleLimit::Global("builtins_extra.x".to_owned())));
+
+        // Lines without a newline are limited too.
+        let limits = PickleLimits { max_allocation: 64, ..Default::default() };
+        for opcode in [b'c', b'i', b'S', b'V', b'L'] {
+            let mut line = vec![b'\x80', b'\x02', opcode];
+            line.extend([b'a'; 100]);
+            assert_eq!(exceeded(load_limited(limits.clone(), &line)), Some(PickleLimit::Allocation(64)));
+        }
+        Ok(())
+    }
+}
@@ -1,16 +1,14 @@
 
 // https://github.com/python/cpython/blob/main/Lib/pickle.py
 
-use std::collections::HashMap;
+use std::{cell::{OnceCell, RefCell}, collections::{hash_map::DefaultHasher, HashMap, HashSet}, fmt, hash::{Hash, Hasher}, rc::Rc};
+use serde::de::DeserializeOwned;
 
-use super::error::PickleError;
+use super::{de::from_pickle, error::PickleError};
 
 
 
-// TODO: Maybe some way to convert pickle to type if possible.
-// eg: let a: Vec<(String, Vec<(u64, u64)>)> = pickle.parse()?;
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Pickle {
     None,
     Bool(bool),
@@ -18,10 +16,297 @@ pub enum Pickle {
     String(String),
     Binary(Vec<u8>),
     List(Vec<Pickle>),
-    Dict(HashMap<String, Pickle>),
+    Dict(PickleDict),
     Tuple(Vec<Pickle>),
+    /// Items in the order they were pickled.
+    Set(Vec<Pickle>),
+    FrozenSet(Vec<Pickle>),
     Module(PickleModule),
     Class(PickleClass),
+    /// Object that is referenced from more than one place, see [`PickleRef`].
+    Ref(PickleRef),
+}
+
+impl Pickle {
+
+    /// Convert to a type with serde.
+    ///
+    /// eg: `let a: Vec<(String, Vec<(u64, u64)>)> = pickle.parse()?;`
+    pub fn parse<T: DeserializeOwned>(self) -> Result<T, PickleError> {
+        from_pickle(self)
+    }
+
+    /// Follow references to the object they point to.
+    pub fn resolve(&self) -> &Pickle {
+        let mut pickle = self;
+        while let Pickle::Ref(reference) = pickle {
+            pickle = reference.get();
+        }
+        pickle
+    }
+
+    /// Same as [`Pickle::resolve`], shared objects are cloned.
+    pub fn into_resolved(self) -> Pickle {
+        match self {
+            Pickle::Ref(reference) => reference.get().resolve().clone(),
+            pickle => pickle,
+        }
+    }
+
+    /// Dict key as a string, keys that are not strings are their `repr()`.
+    pub fn key_string(&self) -> String {
+        match self.resolve() {
+            Pickle::String(str) => str.clone(),
+            key => key.to_string(),
+        }
+    }
+
+}
+
+
+
+thread_local! {
+    /// References that are being visited, to stop at cycles.
+    static VISITING: RefCell<HashSet<(usize, usize)>> = RefCell::new(HashSet::new());
+}
+
+/// Call `f` with true, or false if `key` is already being visited.
+fn visit<T>(key: (usize, usize), f: impl FnOnce(bool) -> T) -> T {
+    if !VISITING.with(|visiting| visiting.borrow_mut().insert(key)) {
+        return f(false);
+    }
+    let result = f(true);
+    VISITING.with(|visiting| visiting.borrow_mut().remove(&key));
+    result
+}
+
+/// Shared object, such as an object in two lists or a list that contains itself.
+///
+/// Objects are equal by value, use [`PickleRef::ptr_eq`] to check if they are the same object.
+#[derive(Clone)]
+pub struct PickleRef(Rc<OnceCell<Pickle>>);
+
+impl PickleRef {
+
+    pub fn new(pickle: Pickle) -> Self {
+        Self(Rc::new(OnceCell::from(pickle)))
+    }
+
+    /// Reference to an object that is not complete, the parser sets it once it is.
+    pub(super) fn empty() -> Self {
+        Self(Rc::new(OnceCell::new()))
+    }
+
+    pub(super) fn set(&self, pickle: Pickle) {
+        let _ = self.0.set(pickle);
+    }
+
+    /// Number of references to the object.
+    pub(super) fn count(&self) -> usize {
+        Rc::strong_count(&self.0)
+    }
+
+    pub fn get(&self) -> &Pickle {
+        const NONE: &Pickle = &Pickle::None;
+        self.0.get().unwrap_or(NONE)
+    }
+
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Address of the object, the same for all references to it.
+    pub fn id(&self) -> usize {
+        Rc::as_ptr(&self.0) as usize
+    }
+
+    /// Call `f` with the object, or `None` if it is already being visited, such as when printing a list that contains itself.
+    pub fn visit<T>(&self, f: impl FnOnce(Option<&Pickle>) -> T) -> T {
+        visit((self.id(), 0), |visit| f(visit.then(|| self.get())))
+    }
+
+}
+
+impl fmt::Debug for PickleRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.visit(|pickle| match pickle {
+            Some(pickle) => f.debug_tuple("PickleRef").field(pickle).finish(),
+            None => write!(f, "PickleRef(...)"),
+        })
+    }
+}
+
+/// Objects that contain themselves are equal if their cycles are.
+impl PartialEq for PickleRef {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other) || visit((self.id(), other.id()), |visit| !visit || self.get() == other.get())
+    }
+}
+
+
+
+fn hash_str(str: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    3u8.hash(&mut hasher);
+    str.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn hash_key(key: &Pickle) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    match key {
+        Pickle::None => 0u8.hash(&mut hasher),
+        Pickle::Bool(bool) => (1u8, bool).hash(&mut hasher),
+        Pickle::Number(PickleNumber::Float(v)) => (2u8, v.to_bits()).hash(&mut hasher),
+        Pickle::Number(number) => (2u8, number.long_bytes()).hash(&mut hasher),
+        Pickle::String(str) => return hash_str(str),
+        Pickle::Binary(bytes) => (4u8, bytes).hash(&mut hasher),
+        Pickle::Tuple(items) => (5u8, items.iter().map(hash_key).collect::<Vec<_>>()).hash(&mut hasher),
+        // Order of items does not matter.
+        Pickle::FrozenSet(items) => (6u8, items.iter().map(hash_key).fold(0, |a, b| a ^ b)).hash(&mut hasher),
+        Pickle::Module(module) => (7u8, &module.module, &module.name).hash(&mut hasher),
+        Pickle::Ref(reference) => (8u8, reference.id()).hash(&mut hasher),
+        Pickle::Class(class) => (9u8, &class.module.module, &class.module.name).hash(&mut hasher),
+        // Not hashable in python.
+        Pickle::List(_) | Pickle::Dict(_) | Pickle::Set(_) => 10u8.hash(&mut hasher),
+    }
+    hasher.finish()
+}
+
+/// Python dict key equality, objects are only equal to themselves.
+fn key_eq(a: &Pickle, b: &Pickle) -> bool {
+    match (a, b) {
+        (Pickle::Ref(a), Pickle::Ref(b)) => a.ptr_eq(b),
+        (Pickle::Tuple(a), Pickle::Tuple(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| key_eq(a, b)),
+        (Pickle::FrozenSet(a), Pickle::FrozenSet(b)) => a.len() == b.len() && a.iter().all(|a| b.iter().any(|b| key_eq(a, b))),
+        (Pickle::Class(_) | Pickle::List(_) | Pickle::Dict(_) | Pickle::Set(_), _) => false,
+        (a, b) => a == b,
+    }
+}
+
+/// Python dict, keys are any hashable pickle and items are kept in insertion order.
+#[derive(Clone, Default)]
+pub struct PickleDict {
+    items: Vec<(Pickle, Pickle)>,
+    /// Item positions by key hash.
+    index: HashMap<u64, Vec<usize>>,
+}
+
+impl PickleDict {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    fn position(&self, hash: u64, eq: impl Fn(&Pickle) -> bool) -> Option<usize> {
+        self.index.get(&hash)?.iter().copied().find(|position| eq(&self.items[*position].0))
+    }
+
+    /// Get the value of a string key.
+    pub fn get(&self, key: &str) -> Option<&Pickle> {
+        let position = self.position(hash_str(key), |item| matches!(item, Pickle::String(str) if str == key))?;
+        Some(&self.items[position].1)
+    }
+
+    pub fn get_key(&self, key: &Pickle) -> Option<&Pickle> {
+        let position = self.position(hash_key(key), |item| key_eq(item, key))?;
+        Some(&self.items[position].1)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Insert an item, an existing key keeps its position.
+    pub fn insert(&mut self, key: impl Into<Pickle>, value: Pickle) -> Option<Pickle> {
+        let key = key.into();
+        let hash = hash_key(&key);
+        match self.position(hash, |item| key_eq(item, &key)) {
+            Some(position) => Some(std::mem::replace(&mut self.items[position].1, value)),
+            None => {
+                self.index.entry(hash).or_default().push(self.items.len());
+                self.items.push((key, value));
+                None
+            },
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Pickle, &Pickle)> {
+        self.items.iter().map(|(key, value)| (key, value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Pickle> {
+        self.items.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Pickle> {
+        self.items.iter().map(|(_, value)| value)
+    }
+
+    /// Keys & values may be changed, the index is rebuilt after.
+    pub(super) fn modify(&mut self, f: impl FnOnce(&mut [(Pickle, Pickle)])) {
+        f(&mut self.items);
+        self.index.clear();
+        for (position, (key, _)) in self.items.iter().enumerate() {
+            self.index.entry(hash_key(key)).or_default().push(position);
+        }
+    }
+
+}
+
+impl fmt::Debug for PickleDict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// Dicts are equal when they have the same items in the same order.
+impl PartialEq for PickleDict {
+    fn eq(&self, other: &Self) -> bool {
+        self.items == other.items
+    }
+}
+
+impl<K: Into<Pickle>> FromIterator<(K, Pickle)> for PickleDict {
+    fn from_iter<T: IntoIterator<Item = (K, Pickle)>>(iter: T) -> Self {
+        let mut dict = Self::new();
+        dict.extend(iter);
+        dict
+    }
+}
+
+impl<K: Into<Pickle>> Extend<(K, Pickle)> for PickleDict {
+    fn extend<T: IntoIterator<Item = (K, Pickle)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl IntoIterator for PickleDict {
+    type Item = (Pickle, Pickle);
+    type IntoIter = std::vec::IntoIter<(Pickle, Pickle)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a PickleDict {
+    type Item = (&'a Pickle, &'a Pickle);
+    type IntoIter = std::iter::Map<std::slice::Iter<'a, (Pickle, Pickle)>, fn(&'a (Pickle, Pickle)) -> (&'a Pickle, &'a Pickle)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.iter().map(|(key, value)| (key, value))
+    }
 }
 
 
@@ -34,9 +319,158 @@ pub enum PickleNumber {
     BigInt(Vec<u8>),
 }
 
+impl PickleNumber {
+
+    /// Integer as the smallest little-endian two's complement bytes, same as `pickle.encode_long`.
+    pub fn long_bytes(&self) -> Option<Vec<u8>> {
+        let mut bytes = match self {
+            Self::Int(v) => i128::from(*v).to_le_bytes().to_vec(),
+            Self::Uint(v) => i128::from(*v).to_le_bytes().to_vec(),
+            Self::BigInt(bytes) => bytes.clone(),
+            Self::Float(_) => return None,
+        };
+        while let [.., second, last] = bytes[..] {
+            if (last == 0x00 && second & 0x80 == 0) || (last == 0xFF && second & 0x80 != 0) {
+                bytes.pop();
+            } else {
+                break;
+            }
+        }
+        if bytes == [0x00] {
+            bytes.clear();
+        }
+        Some(bytes)
+    }
 
+    pub fn to_i128(&self) -> Option<i128> {
+        match self {
+            Self::Int(v) => Some(i128::from(*v)),
+            Self::Uint(v) => Some(i128::from(*v)),
+            Self::Float(_) => None,
+            Self::BigInt(_) => {
+                let bytes = self.long_bytes()?;
+                if bytes.len() > 16 {
+                    return None;
+                }
+                let fill = if bytes.last().is_some_and(|byte| byte & 0x80 != 0) { 0xFF } else { 0x00 };
+                let mut buf = [fill; 16];
+                buf[..bytes.len()].copy_from_slice(&bytes);
+                Some(i128::from_le_bytes(buf))
+            },
+        }
+    }
 
-#[derive(Debug, Clone)]
+    pub fn to_u128(&self) -> Option<u128> {
+        match self {
+            Self::Int(v) => u128::try_from(*v).ok(),
+            Self::Uint(v) => Some(u128::from(*v)),
+            Self::Float(_) => None,
+            Self::BigInt(_) => {
+                let mut bytes = self.long_bytes()?;
+                if bytes.last().is_some_and(|byte| byte & 0x80 != 0) {
+                    return None;
+                }
+                // Positive longs above `i128::MAX` have a zero sign byte.
+                if bytes.len() == 17 {
+                    bytes.pop();
+                }
+                if bytes.len() > 16 {
+                    return None;
+                }
+                let mut buf = [0x00; 16];
+                buf[..bytes.len()].copy_from_slice(&bytes);
+                Some(u128::from_le_bytes(buf))
+            },
+        }
+    }
+
+    /// Integer in decimal, same as Python's `repr`.
+    ///
+    /// Integers longer than Python's `sys.int_max_str_digits` are not converted, as it takes quadratic time.
+    pub fn to_decimal(&self) -> Option<String> {
+        let bytes = match self {
+            Self::Int(v) => return Some(v.to_string()),
+            Self::Uint(v) => return Some(v.to_string()),
+            Self::Float(_) => return None,
+            Self::BigInt(_) => self.long_bytes()?,
+        };
+        // Each byte is at most 2.41 digits.
+        if bytes.len() * 241 / 100 > MAX_DIGITS {
+            return None;
+        }
+
+        let negative = bytes.last().is_some_and(|byte| byte & 0x80 != 0);
+        let mut magnitude = bytes;
+        if negative {
+            negate_long(&mut magnitude);
+        }
+
+        // Divide by 10^9 until nothing is left, the remainders are the digits from the end.
+        let mut chunks = Vec::new();
+        while !magnitude.is_empty() {
+            let mut remainder = 0u64;
+            for byte in magnitude.iter_mut().rev() {
+                let value = (remainder << 8) | *byte as u64;
+                *byte = (value / 1_000_000_000) as u8;
+                remainder = value % 1_000_000_000;
+            }
+            chunks.push(remainder);
+            while magnitude.last() == Some(&0x00) {
+                magnitude.pop();
+            }
+        }
+
+        let mut decimal = if negative { "-".to_owned() } else { String::new() };
+        let mut chunks = chunks.into_iter().rev();
+        decimal.push_str(&chunks.next().unwrap_or(0).to_string());
+        chunks.for_each(|chunk| decimal.push_str(&format!("{:09}", chunk)));
+        if decimal.len() - negative as usize > MAX_DIGITS {
+            return None;
+        }
+        Some(decimal)
+    }
+
+}
+
+/// Python's default `sys.int_max_str_digits`.
+pub(super) const MAX_DIGITS: usize = 4300;
+
+/// Hex integer, for integers too long to be decimal. Python reads it with `int(value, 0)`.
+pub fn long_hex(bytes: &[u8]) -> String {
+    let mut bytes = bytes.to_vec();
+    let negative = bytes.last().is_some_and(|byte| byte & 0x80 != 0);
+    if negative {
+        negate_long(&mut bytes);
+    }
+    let hex = bytes.iter().rev().map(|byte| format!("{:02x}", byte)).collect::<String>();
+    let hex = hex.trim_start_matches('0');
+    format!("{}0x{}", if negative { "-" } else { "" }, if hex.is_empty() { "0" } else { hex })
+}
+
+/// Integers are equal by value, no matter how they were stored.
+impl PartialEq for PickleNumber {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Float(a), Self::Float(b)) => a == b,
+            (Self::Float(_), _) | (_, Self::Float(_)) => false,
+            (a, b) => a.long_bytes() == b.long_bytes(),
+        }
+    }
+}
+
+/// Negate little-endian two's complement bytes.
+pub fn negate_long(bytes: &mut [u8]) {
+    let mut carry = true;
+    for byte in bytes.iter_mut() {
+        let (value, overflow) = (!*byte).overflowing_add(carry as u8);
+        *byte = value;
+        carry = overflow;
+    }
+}
+
+
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct PickleModule {
     pub module: String,
     pub name: String,
@@ -54,17 +488,120 @@ impl PickleModule {
 
 
 
-#[derive(Debug, Clone)]
+/// How a class instance is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PickleConstructor {
+    /// `cls(*args)`, from `REDUCE`, `INST` & `OBJ`.
+    #[default]
+    Call,
+    /// `cls.__new__(cls, *args)`, from `NEWOBJ` & `NEWOBJ_EX`.
+    New,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct PickleClass {
     pub module: PickleModule,
+    pub constructor: PickleConstructor,
     pub args: Box<Pickle>,
+    /// Keyword arguments, only from `NEWOBJ_EX`.
+    pub kwargs: Option<Box<Pickle>>,
     pub state: Option<Box<Pickle>>,
-    pub data: HashMap<String, Pickle>,
+    /// Items set on dict subclasses.
+    pub data: PickleDict,
+    /// Items appended to list subclasses.
+    pub items: Vec<Pickle>,
 }
 
 impl PickleClass {
     pub fn new(module: PickleModule, args: Pickle) -> Self {
-        Self { module, args: Box::new(args), state: None, data: HashMap::new() }
+        Self { module, constructor: PickleConstructor::Call, args: Box::new(args), kwargs: None, state: None, data: PickleDict::new(), items: Vec::new() }
+    }
+}
+
+
+
+/// Python `repr()` like formatting.
+impl fmt::Display for Pickle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn join(f: &mut fmt::Formatter<'_>, items: &[Pickle]) -> fmt::Result {
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", item)?;
+            }
+            Ok(())
+        }
+
+        match self {
+            Pickle::None => write!(f, "None"),
+            Pickle::Bool(true) => write!(f, "True"),
+            Pickle::Bool(false) => write!(f, "False"),
+            Pickle::Number(PickleNumber::Int(v)) => write!(f, "{}", v),
+            Pickle::Number(PickleNumber::Uint(v)) => write!(f, "{}", v),
+            Pickle::Number(PickleNumber::Float(v)) => write!(f, "{:?}", v),
+            Pickle::Number(number @ PickleNumber::BigInt(bytes)) => match number.to_decimal() {
+                Some(decimal) => write!(f, "{}", decimal),
+                None => write!(f, "{}", long_hex(bytes)),
+            },
+            Pickle::String(str) => write!(f, "'{}'", str.replace('\\', "\\\\").replace('\'', "\\'").replace('\n', "\\n")),
+            Pickle::Binary(bytes) => {
+                write!(f, "b'")?;
+                for byte in bytes {
+                    match byte {
+                        b'\\' | b'\'' => write!(f, "\\{}", *byte as char)?,
+                        0x20..=0x7E => write!(f, "{}", *byte as char)?,
+                        byte => write!(f, "\\x{:02x}", byte)?,
+                    }
+                }
+                write!(f, "'")
+            },
+            Pickle::List(items) => {
+                write!(f, "[")?;
+                join(f, items)?;
+                write!(f, "]")
+            },
+            Pickle::Tuple(items) => {
+                write!(f, "(")?;
+                join(f, items)?;
+                if items.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            },
+            Pickle::Dict(dict) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in dict.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, value)?;
+                }
+                write!(f, "}}")
+            },
+            Pickle::Set(items) if items.is_empty() => write!(f, "set()"),
+            Pickle::Set(items) => {
+                write!(f, "{{")?;
+                join(f, items)?;
+                write!(f, "}}")
+            },
+            Pickle::FrozenSet(items) if items.is_empty() => write!(f, "frozenset()"),
+            Pickle::FrozenSet(items) => {
+                write!(f, "frozenset({{")?;
+                join(f, items)?;
+                write!(f, "}})")
+            },
+            Pickle::Module(module) => write!(f, "{}.{}", module.module, module.name),
+            Pickle::Class(class) => write!(f, "{}.{}{}", class.module.module, class.module.name, class.args),
+            Pickle::Ref(reference) => reference.visit(|pickle| match pickle {
+                Some(pickle) => write!(f, "{}", pickle),
+                None => match reference.get() {
+                    Pickle::List(_) => write!(f, "[...]"),
+                    Pickle::Dict(_) | Pickle::Set(_) => write!(f, "{{...}}"),
+                    _ => write!(f, "..."),
+                },
+            }),
+        }
     }
 }
 
@@ -78,7 +615,7 @@ macro_rules! pickle_try_into {
             type Error = PickleError;
 
             fn try_into(self) -> Result<$type, Self::Error> {
-                match self {
+                match self.into_resolved() {
                     $match_head => $match_body,
                     _ => Err(PickleError::CannotTryInto),
                 }
@@ -89,7 +626,7 @@ macro_rules! pickle_try_into {
             type Error = PickleError;
 
             fn try_into(self) -> Result<Option<$type>, Self::Error> {
-                match self {
+                match self.into_resolved() {
                     $match_head => {
                         match $match_body {
                             Ok(v) => Ok(Some(v)),
@@ -117,6 +654,10 @@ macro_rules! pickle_try_into_int {
                 match(num) {
                     PickleNumber::Uint(v) => v.try_into().map_err(|_| PickleError::CannotTryInto),
                     PickleNumber::Int(v) => v.try_into().map_err(|_| PickleError::CannotTryInto),
+                    // Longs that fit, such as Python 2 longs.
+                    num @ PickleNumber::BigInt(_) => num.to_i128().and_then(|v| v.try_into().ok())
+                        .or_else(|| num.to_u128().and_then(|v| v.try_into().ok()))
+                        .ok_or(PickleError::CannotTryInto),
                     _ => Err(PickleError::CannotTryInto)
                 }
             }
@@ -131,6 +672,8 @@ pickle_try_into_int!(i8);
 pickle_try_into_int!(i16);
 pickle_try_into_int!(i32);
 pickle_try_into_int!(i64);
+pickle_try_into_int!(u128);
+pickle_try_into_int!(i128);
 pickle_try_into!(f32, Pickle::Number(PickleNumber::Float(v)), { Ok(v as f32) }); // Precision loss.
 pickle_try_into!(f64, Pickle::Number(PickleNumber::Float(v)), { v.try_into().map_err(|_| PickleError::CannotTryInto) });
 
@@ -138,9 +681,18 @@ pickle_try_into!(String, Pickle::String(str), { Ok(str) });
 
 pickle_try_into!(Vec<u8>, Pickle::Binary(bin), { Ok(bin) });
 
-pickle_try_into!(Vec<Pickle>, Pickle::List(vec) | Pickle::Tuple(vec), { Ok(vec) });
+pickle_try_into!(Vec<Pickle>, Pickle::List(vec) | Pickle::Tuple(vec) | Pickle::Set(vec) | Pickle::FrozenSet(vec), { Ok(vec) });
 
-pickle_try_into!(HashMap<String, Pickle>, Pickle::Dict(dict), { Ok(dict) });
+pickle_try_into!(PickleDict, Pickle::Dict(dict), { Ok(dict) });
+// Only when all keys are strings.
+pickle_try_into!(HashMap<String, Pickle>, Pickle::Dict(dict), {
+    dict.into_iter()
+        .map(|(key, value)| match key.into_resolved() {
+            Pickle::String(key) => Ok((key, value)),
+            _ => Err(PickleError::CannotTryInto),
+        })
+        .collect()
+});
 
 pickle_try_into!((), Pickle::Tuple(tuple), {
     if tuple.len() == 0 {
@@ -240,14 +792,17 @@ pickle_from!(&str, str, { Pickle::String(str.to_owned()) });
 
 pickle_from!(Vec<Pickle>, vec, { Pickle::List(vec) });
 
-pickle_from!(HashMap<String, Pickle>, dict, { Pickle::Dict(dict) });
+pickle_from!(PickleDict, dict, { Pickle::Dict(dict) });
+// Sorted by key, as hash maps have no order.
+pickle_from!(HashMap<String, Pickle>, dict, {
+    let mut items = dict.into_iter().collect::<Vec<_>>();
+    items.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Pickle::Dict(items.into_iter().collect())
+});
 pickle_from!(HashMap<&str, Pickle>, dict, {
-    let mut new = HashMap::new();
-    dict.into_iter()
-        .for_each(|(key, value)| {
-            new.insert(key.to_owned(), value);
-        });
-    Pickle::Dict(new)
+    let mut items = dict.into_iter().collect::<Vec<_>>();
+    items.sort_by_key(|(key, _)| *key);
+    Pickle::Dict(items.into_iter().collect())
 });
 
 
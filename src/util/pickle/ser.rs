@@ -0,0 +1,225 @@
+// https://serde.rs/impl-serializer.html
+
+use serde::{ser::{self, Serialize}, Serializer};
+use super::{error::PickleError, pickle::{Pickle, PickleDict, PickleNumber}};
+
+
+
+/// Serialize a type to a pickle.
+///
+/// Structs & maps become dicts, enum variants with a value become single entry dicts.
+pub fn to_pickle<T: Serialize + ?Sized>(value: &T) -> Result<Pickle, PickleError> {
+    value.serialize(PickleSerializer)
+}
+
+fn variant(variant: &str, value: Pickle) -> Pickle {
+    Pickle::Dict(PickleDict::from_iter([(variant, value)]))
+}
+
+fn integer(value: i128) -> Pickle {
+    if let Ok(v) = i64::try_from(value) {
+        Pickle::Number(PickleNumber::Int(v))
+    } else if let Ok(v) = u64::try_from(value) {
+        Pickle::Number(PickleNumber::Uint(v))
+    } else {
+        Pickle::Number(PickleNumber::BigInt(PickleNumber::BigInt(value.to_le_bytes().to_vec()).long_bytes().unwrap_or_default()))
+    }
+}
+
+
+
+pub struct PickleSerializer;
+
+impl Serializer for PickleSerializer {
+    type Ok = Pickle;
+    type Error = PickleError;
+
+    type SerializeSeq = PickleSeqSerializer;
+    type SerializeTuple = PickleSeqSerializer;
+    type SerializeTupleStruct = PickleSeqSerializer;
+    type SerializeTupleVariant = PickleSeqSerializer;
+    type SerializeMap = PickleMapSerializer;
+    type SerializeStruct = PickleMapSerializer;
+    type SerializeStructVariant = PickleMapSerializer;
+
+    fn serialize_bool(self, v: bool) -> Result<Pickle, PickleError> { Ok(Pickle::Bool(v)) }
+    fn serialize_i8(self, v: i8) -> Result<Pickle, PickleError> { Ok(Pickle::from(v)) }
+    fn serialize_i16(self, v: i16) -> Result<Pickle, PickleError> { Ok(Pickle::from(v)) }
+    fn serialize_i32(self, v: i32) -> Result<Pickle, PickleError> { Ok(Pickle::from(v)) }
+    fn serialize_i64(self, v: i64) -> Result<Pickle, PickleError> { Ok(Pickle::from(v)) }
+    fn serialize_i128(self, v: i128) -> Result<Pickle, PickleError> { Ok(integer(v)) }
+    fn serialize_u8(self, v: u8) -> Result<Pickle, PickleError> { Ok(Pickle::from(v)) }
+    fn serialize_u16(self, v: u16) -> Result<Pickle, PickleError> { Ok(Pickle::from(v)) }
+    fn serialize_u32(self, v: u32) -> Result<Pickle, PickleError> { Ok(Pickle::from(v)) }
+    fn serialize_u64(self, v: u64) -> Result<Pickle, PickleError> { Ok(Pickle::from(v)) }
+    fn serialize_u128(self, v: u128) -> Result<Pickle, PickleError> {
+        match i128::try_from(v) {
+            Ok(v) => Ok(integer(v)),
+            // Needs a zero sign byte.
+            Err(_) => Ok(Pickle::Number(PickleNumber::BigInt(v.to_le_bytes().into_iter().chain([0x00]).collect()))),
+        }
+    }
+    fn serialize_f32(self, v: f32) -> Result<Pickle, PickleError> { Ok(Pickle::from(v)) }
+    fn serialize_f64(self, v: f64) -> Result<Pickle, PickleError> { Ok(Pickle::from(v)) }
+    fn serialize_char(self, v: char) -> Result<Pickle, PickleError> { Ok(Pickle::String(v.to_string())) }
+    fn serialize_str(self, v: &str) -> Result<Pickle, PickleError> { Ok(Pickle::from(v)) }
+    fn serialize_bytes(self, v: &[u8]) -> Result<Pickle, PickleError> { Ok(Pickle::Binary(v.to_vec())) }
+    fn serialize_none(self) -> Result<Pickle, PickleError> { Ok(Pickle::None) }
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Pickle, PickleError> { value.serialize(self) }
+    fn serialize_unit(self) -> Result<Pickle, PickleError> { Ok(Pickle::None) }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Pickle, PickleError> { Ok(Pickle::None) }
+
+    fn serialize_unit_variant(self, _name: &'static str, _variant_index: u32, variant: &'static str) -> Result<Pickle, PickleError> {
+        Ok(Pickle::from(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<Pickle, PickleError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _variant_index: u32, name: &'static str, value: &T) -> Result<Pickle, PickleError> {
+        Ok(variant(name, value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, PickleError> {
+        Ok(PickleSeqSerializer { items: Vec::with_capacity(len.unwrap_or(0)), tuple: false, variant: None })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, PickleError> {
+        Ok(PickleSeqSerializer { items: Vec::with_capacity(len), tuple: true, variant: None })
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeTupleStruct, PickleError> {
+        self.serialize_tuple(len)
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _variant_index: u32, variant: &'static str, len: usize) -> Result<Self::SerializeTupleVariant, PickleError> {
+        Ok(PickleSeqSerializer { items: Vec::with_capacity(len), tuple: true, variant: Some(variant) })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, PickleError> {
+        Ok(PickleMapSerializer { dict: PickleDict::new(), key: None, variant: None })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct, PickleError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _variant_index: u32, variant: &'static str, _len: usize) -> Result<Self::SerializeStructVariant, PickleError> {
+        Ok(PickleMapSerializer { dict: PickleDict::new(), key: None, variant: Some(variant) })
+    }
+}
+
+
+
+pub struct PickleSeqSerializer {
+    items: Vec<Pickle>,
+    tuple: bool,
+    variant: Option<&'static str>,
+}
+
+impl PickleSeqSerializer {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PickleError> {
+        let index = self.items.len();
+        self.items.push(value.serialize(PickleSerializer).map_err(|err| err.at(&format!("[{}]", index)))?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Pickle, PickleError> {
+        let pickle = if self.tuple { Pickle::Tuple(self.items) } else { Pickle::List(self.items) };
+        Ok(match self.variant {
+            Some(name) => variant(name, pickle),
+            None => pickle,
+        })
+    }
+}
+
+impl ser::SerializeSeq for PickleSeqSerializer {
+    type Ok = Pickle;
+    type Error = PickleError;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PickleError> { self.push(value) }
+    fn end(self) -> Result<Pickle, PickleError> { self.finish() }
+}
+
+impl ser::SerializeTuple for PickleSeqSerializer {
+    type Ok = Pickle;
+    type Error = PickleError;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PickleError> { self.push(value) }
+    fn end(self) -> Result<Pickle, PickleError> { self.finish() }
+}
+
+impl ser::SerializeTupleStruct for PickleSeqSerializer {
+    type Ok = Pickle;
+    type Error = PickleError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PickleError> { self.push(value) }
+    fn end(self) -> Result<Pickle, PickleError> { self.finish() }
+}
+
+impl ser::SerializeTupleVariant for PickleSeqSerializer {
+    type Ok = Pickle;
+    type Error = PickleError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PickleError> { self.push(value) }
+    fn end(self) -> Result<Pickle, PickleError> { self.finish() }
+}
+
+
+
+pub struct PickleMapSerializer {
+    dict: PickleDict,
+    key: Option<Pickle>,
+    variant: Option<&'static str>,
+}
+
+impl PickleMapSerializer {
+    fn insert<T: Serialize + ?Sized>(&mut self, key: Pickle, value: &T) -> Result<(), PickleError> {
+        let segment = match &key {
+            Pickle::String(key) => format!(".{}", key),
+            key => format!("[{}]", key),
+        };
+        let value = value.serialize(PickleSerializer).map_err(|err| err.at(&segment))?;
+        self.dict.insert(key, value);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Pickle, PickleError> {
+        Ok(match self.variant {
+            Some(name) => variant(name, Pickle::Dict(self.dict)),
+            None => Pickle::Dict(self.dict),
+        })
+    }
+}
+
+impl ser::SerializeMap for PickleMapSerializer {
+    type Ok = Pickle;
+    type Error = PickleError;
+
+    /// Dict keys must be hashable, so they cannot be lists or dicts.
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), PickleError> {
+        self.key = Some(match key.serialize(PickleSerializer)? {
+            Pickle::List(_) | Pickle::Dict(_) | Pickle::Set(_) => return Err(ser::Error::custom("dict key must be hashable")),
+            key => key,
+        });
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PickleError> {
+        let key = self.key.take().ok_or_else(|| <PickleError as ser::Error>::custom("dict value without key"))?;
+        self.insert(key, value)
+    }
+
+    fn end(self) -> Result<Pickle, PickleError> { self.finish() }
+}
+
+impl ser::SerializeStruct for PickleMapSerializer {
+    type Ok = Pickle;
+    type Error = PickleError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), PickleError> { self.insert(Pickle::from(key), value) }
+    fn end(self) -> Result<Pickle, PickleError> { self.finish() }
+}
+
+impl ser::SerializeStructVariant for PickleMapSerializer {
+    type Ok = Pickle;
+    type Error = PickleError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), PickleError> { self.insert(Pickle::from(key), value) }
+    fn end(self) -> Result<Pickle, PickleError> { self.finish() }
+}
@@ -0,0 +1,608 @@
+// https://github.com/python/cpython/blob/main/Lib/pickle.py
+
+use std::{collections::HashMap, error::Error, io::Write};
+use super::{error::PickleError, parser::{PickleOpcode, PickleProtocol}, pickle::{long_hex, Pickle, PickleClass, PickleConstructor, PickleDict, PickleModule, PickleNumber, PickleRef}};
+
+
+
+/// Items per `APPENDS` & `SETITEMS`, same as CPython.
+const BATCH_SIZE: usize = 1000;
+
+/// Python 3 module names that were renamed from Python 2, the reverse of what the parser does.
+fn python2_module(module: &str) -> &str {
+    match module {
+        "builtins" => "__builtin__",
+        "copyreg" => "copy_reg",
+        module => module,
+    }
+}
+
+/// Python 3 `raw-unicode-escape`, with the characters that would break the line escaped.
+fn escape_raw_unicode(str: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for char in str.chars() {
+        match char {
+            '\\' | '\0' | '\n' | '\r' | '\x1a' => bytes.extend(format!("\\u{:04x}", char as u32).bytes()),
+            char if (char as u32) < 0x100 => bytes.push(char as u8),
+            char if (char as u32) < 0x10000 => bytes.extend(format!("\\u{:04x}", char as u32).bytes()),
+            char => bytes.extend(format!("\\U{:08x}", char as u32).bytes()),
+        }
+    }
+    bytes
+}
+
+
+
+/// Pickle serializer, the opposite of [`PickleParser`](super::parser::PickleParser).
+///
+/// Strings, bytes & globals are memoized, so repeated values are only written once.
+/// Shared objects are memoized before their items, so objects that contain themselves are written as they were read.
+#[derive(Debug)]
+pub struct PickleWriter {
+    protocol: PickleProtocol,
+    data: Vec<u8>,
+    memo: HashMap<String, u32>,
+}
+
+impl PickleWriter {
+
+    pub fn new(protocol: PickleProtocol) -> Result<Self, Box<dyn Error>> {
+        match protocol {
+            PickleProtocol::Unknown => Err(Box::new(PickleError::UnsupportedProtocol(protocol))),
+            protocol => Ok(Self { protocol, data: Vec::new(), memo: HashMap::new() }),
+        }
+    }
+
+    fn op(&mut self, opcode: PickleOpcode) {
+        self.data.push(opcode as u8);
+    }
+
+    fn line(&mut self, opcode: PickleOpcode, line: &[u8]) {
+        self.op(opcode);
+        self.data.extend_from_slice(line);
+        self.data.push(0x0A);
+    }
+
+    /// Write a previously memoized value, returns false if it was not memoized.
+    fn get(&mut self, key: &str) -> bool {
+        let index = match self.memo.get(key) {
+            Some(index) => *index,
+            None => return false,
+        };
+        if self.protocol == PickleProtocol::Protocol0 {
+            self.line(PickleOpcode::GET, index.to_string().as_bytes());
+        } else if let Ok(index) = u8::try_from(index) {
+            self.op(PickleOpcode::BINGET);
+            self.data.push(index);
+        } else {
+            self.op(PickleOpcode::LONG_BINGET);
+            self.data.extend_from_slice(&index.to_le_bytes());
+        }
+        true
+    }
+
+    /// Memoize the value on the top of the stack.
+    fn put(&mut self, key: String) {
+        let index = self.memo.len() as u32;
+        self.memo.insert(key, index);
+        if self.protocol >= PickleProtocol::Protocol4 {
+            self.op(PickleOpcode::MEMOIZE);
+        } else if self.protocol == PickleProtocol::Protocol0 {
+            self.line(PickleOpcode::PUT, index.to_string().as_bytes());
+        } else if let Ok(index) = u8::try_from(index) {
+            self.op(PickleOpcode::BINPUT);
+            self.data.push(index);
+        } else {
+            self.op(PickleOpcode::LONG_BINPUT);
+            self.data.extend_from_slice(&index.to_le_bytes());
+        }
+    }
+
+    fn save_number(&mut self, number: &PickleNumber) {
+        let value = match number {
+            PickleNumber::Float(v) => {
+                if self.protocol >= PickleProtocol::Protocol1 {
+                    self.op(PickleOpcode::BINFLOAT);
+                    self.data.extend_from_slice(&v.to_be_bytes());
+                } else {
+                    self.line(PickleOpcode::FLOAT, format!("{:?}", v).as_bytes());
+                }
+                return;
+            },
+            number => number.to_i128(),
+        };
+
+        if let Some(value) = value {
+            if self.protocol >= PickleProtocol::Protocol1 {
+                if let Ok(value) = u8::try_from(value) {
+                    self.op(PickleOpcode::BININT1);
+                    self.data.push(value);
+                    return;
+                } else if let Ok(value) = u16::try_from(value) {
+                    self.op(PickleOpcode::BININT2);
+                    self.data.extend_from_slice(&value.to_le_bytes());
+                    return;
+                } else if let Ok(value) = i32::try_from(value) {
+                    self.op(PickleOpcode::BININT);
+                    self.data.extend_from_slice(&value.to_le_bytes());
+                    return;
+                }
+            } else if i32::try_from(value).is_ok() {
+                self.line(PickleOpcode::INT, value.to_string().as_bytes());
+                return;
+            }
+        }
+
+        let bytes = number.long_bytes().unwrap_or_default();
+        if self.protocol >= PickleProtocol::Protocol2 {
+            if let Ok(length) = u8::try_from(bytes.len()) {
+                self.op(PickleOpcode::LONG1);
+                self.data.push(length);
+            } else {
+                self.op(PickleOpcode::LONG4);
+                self.data.extend_from_slice(&(bytes.len() as i32).to_le_bytes());
+            }
+            self.data.extend_from_slice(&bytes);
+        } else {
+            let long = number.to_decimal().unwrap_or_else(|| long_hex(&bytes));
+            self.line(PickleOpcode::LONG, format!("{}L", long).as_bytes());
+        }
+    }
+
+    fn save_string(&mut self, str: &str) -> Result<(), Box<dyn Error>> {
+        let key = Pickle::String(str.to_owned()).to_string();
+        if self.get(&key) {
+            return Ok(());
+        }
+
+        if self.protocol == PickleProtocol::Protocol0 {
+            self.line(PickleOpcode::UNICODE, &escape_raw_unicode(str));
+        } else if self.protocol >= PickleProtocol::Protocol4 && str.len() < 256 {
+            self.op(PickleOpcode::SHORT_BINUNICODE);
+            self.data.push(str.len() as u8);
+            self.data.extend_from_slice(str.as_bytes());
+        } else if let Ok(length) = u32::try_from(str.len()) {
+            self.op(PickleOpcode::BINUNICODE);
+            self.data.extend_from_slice(&length.to_le_bytes());
+            self.data.extend_from_slice(str.as_bytes());
+        } else if self.protocol >= PickleProtocol::Protocol4 {
+            self.op(PickleOpcode::BINUNICODE8);
+            self.data.extend_from_slice(&(str.len() as u64).to_le_bytes());
+            self.data.extend_from_slice(str.as_bytes());
+        } else {
+            return Err(Box::new(PickleError::TooLarge));
+        }
+
+        self.put(key);
+        Ok(())
+    }
+
+    fn save_binary(&mut self, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+        // Python 2 has no bytes type, it is pickled as `_codecs.encode(str, "latin1")`.
+        if self.protocol < PickleProtocol::Protocol3 {
+            let reduce = if bytes.is_empty() {
+                PickleModule::new("builtins".to_owned(), "bytes".to_owned()).class(Pickle::Tuple(Vec::new()))
+            } else {
+                let str = bytes.iter().map(|byte| *byte as char).collect::<String>();
+                PickleModule::new("_codecs".to_owned(), "encode".to_owned()).class(Pickle::Tuple(vec![Pickle::String(str), Pickle::String("latin1".to_owned())]))
+            };
+            return self.save_class(&reduce, None);
+        }
+
+        let key = Pickle::Binary(bytes.to_vec()).to_string();
+        if self.get(&key) {
+            return Ok(());
+        }
+
+        if let Ok(length) = u8::try_from(bytes.len()) {
+            self.op(PickleOpcode::SHORT_BINBYTES);
+            self.data.push(length);
+        } else if let Ok(length) = u32::try_from(bytes.len()) {
+            self.op(PickleOpcode::BINBYTES);
+            self.data.extend_from_slice(&length.to_le_bytes());
+        } else if self.protocol >= PickleProtocol::Protocol4 {
+            self.op(PickleOpcode::BINBYTES8);
+            self.data.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+        } else {
+            return Err(Box::new(PickleError::TooLarge));
+        }
+        self.data.extend_from_slice(bytes);
+
+        self.put(key);
+        Ok(())
+    }
+
+    fn save_module(&mut self, module: &PickleModule) -> Result<(), Box<dyn Error>> {
+        // Module & name may both contain dots, a line break can't be in either.
+        let key = format!("{}\n{}", module.module, module.name);
+        if self.get(&key) {
+            return Ok(());
+        }
+
+        if self.protocol >= PickleProtocol::Protocol4 {
+            self.save_string(&module.module)?;
+            self.save_string(&module.name)?;
+            self.op(PickleOpcode::STACK_GLOBAL);
+        } else {
+            let module_name = if self.protocol < PickleProtocol::Protocol3 { python2_module(&module.module) } else { &module.module };
+            self.op(PickleOpcode::GLOBAL);
+            self.data.extend_from_slice(format!("{}\n{}\n", module_name, module.name).as_bytes());
+        }
+
+        self.put(key);
+        Ok(())
+    }
+
+    /// Write items in batches, protocol 0 writes them one by one.
+    fn save_batched(&mut self, items: &[Pickle], step: usize, single: PickleOpcode, batch: PickleOpcode) -> Result<(), Box<dyn Error>> {
+        if self.protocol == PickleProtocol::Protocol0 {
+            for chunk in items.chunks(step) {
+                chunk.iter().try_for_each(|item| self.save(item))?;
+                self.op(single);
+            }
+            return Ok(());
+        }
+
+        for chunk in items.chunks(BATCH_SIZE * step) {
+            if chunk.len() == step {
+                chunk.iter().try_for_each(|item| self.save(item))?;
+                self.op(single);
+            } else {
+                self.op(PickleOpcode::MARK);
+                chunk.iter().try_for_each(|item| self.save(item))?;
+                self.op(batch);
+            }
+        }
+        Ok(())
+    }
+
+    fn save_dict_items(&mut self, dict: &PickleDict) -> Result<(), Box<dyn Error>> {
+        let items = dict.iter().flat_map(|(key, value)| [key.clone(), value.clone()]).collect::<Vec<_>>();
+        self.save_batched(&items, 2, PickleOpcode::SETITEM, PickleOpcode::SETITEMS)
+    }
+
+    fn save_tuple(&mut self, items: &[Pickle]) -> Result<(), Box<dyn Error>> {
+        if items.is_empty() && self.protocol >= PickleProtocol::Protocol1 {
+            self.op(PickleOpcode::EMPTY_TUPLE);
+            return Ok(());
+        }
+        if self.protocol >= PickleProtocol::Protocol2 && items.len() <= 3 {
+            items.iter().try_for_each(|item| self.save(item))?;
+            self.op([PickleOpcode::TUPLE1, PickleOpcode::TUPLE2, PickleOpcode::TUPLE3][items.len() - 1]);
+            return Ok(());
+        }
+        self.op(PickleOpcode::MARK);
+        items.iter().try_for_each(|item| self.save(item))?;
+        self.op(PickleOpcode::TUPLE);
+        Ok(())
+    }
+
+    /// Memoize a shared object.
+    fn memoize(&mut self, memo: Option<String>) {
+        if let Some(key) = memo {
+            self.put(key);
+        }
+    }
+
+    fn save_class(&mut self, class: &PickleClass, memo: Option<String>) -> Result<(), Box<dyn Error>> {
+        match (class.constructor, &class.kwargs) {
+            (PickleConstructor::Call, _) => {
+                self.save_module(&class.module)?;
+                self.save(&class.args)?;
+                self.op(PickleOpcode::REDUCE);
+            },
+            (PickleConstructor::New, None) if self.protocol >= PickleProtocol::Protocol2 => {
+                self.save_module(&class.module)?;
+                self.save(&class.args)?;
+                self.op(PickleOpcode::NEWOBJ);
+            },
+            (PickleConstructor::New, Some(kwargs)) if self.protocol >= PickleProtocol::Protocol4 => {
+                self.save_module(&class.module)?;
+                self.save(&class.args)?;
+                self.save(kwargs)?;
+                self.op(PickleOpcode::NEWOBJ_EX);
+            },
+            // Older protocols call the copyreg helpers that do the same.
+            (PickleConstructor::New, kwargs) => {
+                let mut args = vec![Pickle::Module(class.module.clone())];
+                match kwargs {
+                    Some(kwargs) => {
+                        self.save_module(&PickleModule::new("copyreg".to_owned(), "__newobj_ex__".to_owned()))?;
+                        args.extend([class.args.as_ref().clone(), kwargs.as_ref().clone()]);
+                    },
+                    None => {
+                        self.save_module(&PickleModule::new("copyreg".to_owned(), "__newobj__".to_owned()))?;
+                        match class.args.as_ref() {
+                            Pickle::Tuple(items) => args.extend(items.iter().cloned()),
+                            item => args.push(item.clone()),
+                        }
+                    },
+                }
+                self.save_tuple(&args)?;
+                self.op(PickleOpcode::REDUCE);
+            },
+        }
+        self.memoize(memo);
+
+        if !class.items.is_empty() {
+            self.save_batched(&class.items, 1, PickleOpcode::APPEND, PickleOpcode::APPENDS)?;
+        }
+        if !class.data.is_empty() {
+            self.save_dict_items(&class.data)?;
+        }
+        if let Some(state) = &class.state {
+            self.save(state)?;
+            self.op(PickleOpcode::BUILD);
+        }
+        Ok(())
+    }
+
+    fn save(&mut self, pickle: &Pickle) -> Result<(), Box<dyn Error>> {
+        match pickle {
+            Pickle::None => self.op(PickleOpcode::NONE),
+            Pickle::Bool(bool) => {
+                if self.protocol >= PickleProtocol::Protocol2 {
+                    self.op(if *bool { PickleOpcode::NEWTRUE } else { PickleOpcode::NEWFALSE });
+                } else {
+                    self.line(PickleOpcode::INT, if *bool { b"01" } else { b"00" });
+                }
+            },
+            Pickle::Number(number) => self.save_number(number),
+            Pickle::String(str) => self.save_string(str)?,
+            Pickle::Binary(bytes) => self.save_binary(bytes)?,
+            Pickle::Tuple(items) => self.save_tuple(items)?,
+            Pickle::Module(module) => self.save_module(module)?,
+            Pickle::Ref(reference) => self.save_ref(reference)?,
+            Pickle::List(_) | Pickle::Dict(_) | Pickle::Set(_) | Pickle::FrozenSet(_) | Pickle::Class(_) => self.save_object(pickle, None)?,
+        }
+        Ok(())
+    }
+
+    /// Write a mutable object, `memo` is the memo key when it is shared.
+    fn save_object(&mut self, pickle: &Pickle, memo: Option<String>) -> Result<(), Box<dyn Error>> {
+        match pickle {
+            Pickle::List(items) => {
+                if self.protocol >= PickleProtocol::Protocol1 {
+                    self.op(PickleOpcode::EMPTY_LIST);
+                } else {
+                    self.op(PickleOpcode::MARK);
+                    self.op(PickleOpcode::LIST);
+                }
+                self.memoize(memo);
+                self.save_batched(items, 1, PickleOpcode::APPEND, PickleOpcode::APPENDS)?;
+            },
+            Pickle::Dict(dict) => {
+                if self.protocol >= PickleProtocol::Protocol1 {
+                    self.op(PickleOpcode::EMPTY_DICT);
+                } else {
+                    self.op(PickleOpcode::MARK);
+                    self.op(PickleOpcode::DICT);
+                }
+                self.memoize(memo);
+                self.save_dict_items(dict)?;
+            },
+            Pickle::Set(items) if self.protocol >= PickleProtocol::Protocol4 => {
+                self.op(PickleOpcode::EMPTY_SET);
+                self.memoize(memo);
+                for chunk in items.chunks(BATCH_SIZE) {
+                    self.op(PickleOpcode::MARK);
+                    chunk.iter().try_for_each(|item| self.save(item))?;
+                    self.op(PickleOpcode::ADDITEMS);
+                }
+            },
+            Pickle::FrozenSet(items) if self.protocol >= PickleProtocol::Protocol4 => {
+                self.op(PickleOpcode::MARK);
+                items.iter().try_for_each(|item| self.save(item))?;
+                self.op(PickleOpcode::FROZENSET);
+                self.memoize(memo);
+            },
+            // Older protocols have no set opcodes, sets are `set([items])`.
+            Pickle::Set(items) | Pickle::FrozenSet(items) => {
+                let name = if matches!(pickle, Pickle::Set(_)) { "set" } else { "frozenset" };
+                let reduce = PickleModule::new("builtins".to_owned(), name.to_owned()).class(Pickle::Tuple(vec![Pickle::List(items.clone())]));
+                self.save_class(&reduce, memo)?;
+            },
+            Pickle::Class(class) => self.save_class(class, memo)?,
+            pickle => {
+                self.save(pickle)?;
+                self.memoize(memo);
+            },
+        }
+        Ok(())
+    }
+
+    /// Shared objects are written once, then read from the memo.
+    fn save_ref(&mut self, reference: &PickleRef) -> Result<(), Box<dyn Error>> {
+        let key = format!("<object {:#x}>", reference.id());
+        if self.get(&key) {
+            return Ok(());
+        }
+        self.save_object(reference.get(), Some(key))
+    }
+
+    /// Serialize a pickle, the writer may be reused to serialize the next pickle.
+    pub fn dump(&mut self, pickle: &Pickle) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.data = Vec::new();
+        self.memo = HashMap::new();
+
+        if self.protocol >= PickleProtocol::Protocol2 {
+            self.op(PickleOpcode::PROTO);
+            self.data.push(self.protocol.version().unwrap_or_default());
+        }
+        self.save(pickle)?;
+        self.op(PickleOpcode::STOP);
+
+        Ok(std::mem::take(&mut self.data))
+    }
+
+    pub fn write(data: &mut impl Write, pickle: &Pickle, protocol: PickleProtocol) -> Result<(), Box<dyn Error>> {
+        data.write_all(&PickleWriter::new(protocol)?.dump(pickle)?)?;
+        Ok(())
+    }
+
+}
+
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::{error::Error, io::Cursor};
+    use crate::hashmap;
+    use crate::util::pickle::{parser::{PickleParser, PickleProtocol}, pickle::{Pickle, PickleConstructor, PickleDict, PickleModule, PickleNumber, PickleRef}};
+    use super::PickleWriter;
+
+    const PROTOCOLS: [PickleProtocol; 6] = [
+        PickleProtocol::Protocol0,
+        PickleProtocol::Protocol1,
+        PickleProtocol::Protocol2,
+        PickleProtocol::Protocol3,
+        PickleProtocol::Protocol4,
+        PickleProtocol::Protocol5,
+    ];
+
+    fn round_trip(pickle: &Pickle) -> Result<(), Box<dyn Error>> {
+        for protocol in PROTOCOLS {
+            let data = PickleWriter::new(protocol)?.dump(pickle)?;
+            assert_eq!(&PickleParser::parse(&mut Cursor::new(data))?, pickle, "{:?}", protocol);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn scalars() -> Result<(), Box<dyn Error>> {
+        round_trip(&Pickle::Tuple(vec![
+            Pickle::None,
+            Pickle::Bool(true),
+            Pickle::Bool(false),
+            Pickle::from(0),
+            Pickle::from(255u8),
+            Pickle::from(256),
+            Pickle::from(65536),
+            Pickle::from(-1),
+            Pickle::from(i32::MIN),
+            Pickle::from(i32::MAX),
+            Pickle::from(1i64 << 40),
+            Pickle::from(i64::MIN),
+            Pickle::from(u64::MAX),
+            // 2 ** 72 & -(2 ** 72)
+            Pickle::Number(PickleNumber::BigInt(vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 1])),
+            Pickle::Number(PickleNumber::BigInt(vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF])),
+            Pickle::from(1.5),
+            Pickle::from(-1e100),
+            Pickle::from(f64::INFINITY),
+        ]))
+    }
+
+    #[test]
+    fn strings() -> Result<(), Box<dyn Error>> {
+        let repeated = Pickle::from("repeated");
+        round_trip(&Pickle::List(vec![
+            Pickle::from(""),
+            Pickle::from("héllo ☃ 😀"),
+            Pickle::from("line\nbreak\r\\ \0 \x1a"),
+            Pickle::from("long ".repeat(100)),
+            repeated.clone(),
+            repeated,
+            Pickle::Binary(Vec::new()),
+            Pickle::Binary(b"abc".to_vec()),
+            Pickle::Binary((0..=255).cycle().take(300).collect()),
+        ]))
+    }
+
+    #[test]
+    fn containers() -> Result<(), Box<dyn Error>> {
+        let mut dict = PickleDict::new();
+        for i in 0..1500 {
+            dict.insert(i.to_string(), Pickle::from(i));
+        }
+        let keys = PickleDict::from_iter([
+            (Pickle::from(1), Pickle::from("int")),
+            (Pickle::Tuple(vec![Pickle::from("a"), Pickle::from(2)]), Pickle::from("tuple")),
+            (Pickle::FrozenSet(vec![Pickle::from(3)]), Pickle::from("frozenset")),
+            (Pickle::None, Pickle::from("none")),
+        ]);
+        round_trip(&Pickle::Tuple(vec![
+            Pickle::Tuple(Vec::new()),
+            Pickle::Tuple(vec![Pickle::from(1)]),
+            Pickle::Tuple(vec![Pickle::from(1), Pickle::from(2)]),
+            Pickle::Tuple(vec![Pickle::from(1), Pickle::from(2), Pickle::from(3)]),
+            Pickle::Tuple(vec![Pickle::from(1), Pickle::from(2), Pickle::from(3), Pickle::from(4)]),
+            Pickle::List(Vec::new()),
+            Pickle::List(vec![Pickle::from(1)]),
+            Pickle::List((0..2500).map(Pickle::from).collect()),
+            Pickle::Dict(PickleDict::new()),
+            Pickle::Dict(dict),
+            Pickle::Dict(keys),
+            Pickle::Set(Vec::new()),
+            Pickle::Set((0..1200).map(Pickle::from).collect()),
+            Pickle::FrozenSet(Vec::new()),
+            Pickle::FrozenSet(vec![Pickle::from("a"), Pickle::from("b")]),
+        ]))
+    }
+
+    #[test]
+    fn shared() -> Result<(), Box<dyn Error>> {
+        let shared = PickleRef::new(Pickle::List(vec![Pickle::from(1)]));
+        let pickle = Pickle::Tuple(vec![Pickle::Ref(shared.clone()), Pickle::Ref(shared)]);
+        for protocol in PROTOCOLS {
+            let data = PickleWriter::new(protocol)?.dump(&pickle)?;
+            let items: Vec<Pickle> = PickleParser::parse(&mut Cursor::new(data))?.try_into()?;
+            match (&items[0], &items[1]) {
+                (Pickle::Ref(a), Pickle::Ref(b)) => assert!(a.ptr_eq(b), "{:?}", protocol),
+                items => panic!("{:?} is not shared, {:?}", items, protocol),
+            }
+        }
+
+        // `l = []; l.append(l)`
+        let recursive = PickleParser::parse(&mut Cursor::new(b"\x80\x02]q\x00h\x00a.".to_vec()))?;
+        for protocol in PROTOCOLS {
+            let data = PickleWriter::new(protocol)?.dump(&recursive)?;
+            let pickle = PickleParser::parse(&mut Cursor::new(data))?;
+            assert_eq!(pickle.to_string(), "[[...]]", "{:?}", protocol);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn classes() -> Result<(), Box<dyn Error>> {
+        let module = PickleModule::new("renpy.python".to_owned(), "RevertableDict".to_owned());
+
+        let mut reduced = module.clone().class(Pickle::Tuple(vec![Pickle::from(1), Pickle::from("a")]));
+        reduced.state = Some(Box::new(Pickle::from(hashmap![("x", Pickle::from(1))])));
+        reduced.data.insert("key".to_owned(), Pickle::from("value"));
+        reduced.items.push(Pickle::from(2));
+
+        let mut new = module.clone().class(Pickle::Tuple(Vec::new()));
+        new.constructor = PickleConstructor::New;
+        new.state = Some(Box::new(Pickle::Tuple(vec![Pickle::None, Pickle::from(hashmap![("slot", Pickle::from(2))])])));
+
+        let mut new_args = module.clone().class(Pickle::Tuple(vec![Pickle::from(5)]));
+        new_args.constructor = PickleConstructor::New;
+
+        let mut new_kwargs = module.clone().class(Pickle::Tuple(vec![Pickle::from(5)]));
+        new_kwargs.constructor = PickleConstructor::New;
+        new_kwargs.kwargs = Some(Box::new(Pickle::from(hashmap![("key", Pickle::from("value"))])));
+
+        round_trip(&Pickle::List(vec![
+            Pickle::Module(module.clone()),
+            Pickle::Module(PickleModule::new("builtins".to_owned(), "len".to_owned())),
+            Pickle::Class(reduced),
+            Pickle::Class(new),
+            Pickle::Class(new_args),
+            Pickle::Class(new_kwargs),
+            Pickle::Module(module),
+        ]))
+    }
+
+    #[test]
+    fn globals_with_dots() -> Result<(), Box<dyn Error>> {
+        // Both are `a.b.c` when joined with a dot, but are different globals.
+        round_trip(&Pickle::List(vec![
+            Pickle::Module(PickleModule::new("a.b".to_owned(), "c".to_owned())),
+            Pickle::Module(PickleModule::new("a".to_owned(), "b.c".to_owned())),
+            Pickle::Module(PickleModule::new("a.b".to_owned(), "c".to_owned())),
+        ]))
+    }
+}
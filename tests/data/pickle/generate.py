@@ -0,0 +1,178 @@
+#!/usr/bin/env python3
+# Generates the pickles used by the tests in `src/util/pickle/parser.rs`.
+#
+#     python3 tests/data/pickle/generate.py
+
+import copyreg
+import io
+import os
+import pickle
+import pickletools
+
+OUTPUT = os.path.dirname(os.path.abspath(__file__))
+
+
+
+class Plain:
+    def __init__(self):
+        self.x = 1
+        self.y = "two"
+
+class Slots:
+    __slots__ = ("a", "b")
+    def __init__(self):
+        self.a = 1
+        self.b = 2
+
+class Reduced:
+    def __init__(self, number, string):
+        self.number = number
+        self.string = string
+    def __reduce__(self):
+        return (Reduced, (self.number, self.string))
+
+class NewArgs:
+    def __new__(cls, value):
+        return super().__new__(cls)
+    def __init__(self, value):
+        self.value = value
+    def __getnewargs__(self):
+        return (self.value,)
+
+class NewArgsEx:
+    def __new__(cls, value, *, key):
+        return super().__new__(cls)
+    def __init__(self, value, *, key):
+        self.value = value
+        self.key = key
+    def __getnewargs_ex__(self):
+        return ((self.value,), { "key": self.key })
+
+class External:
+    def __init__(self, id):
+        self.id = id
+
+class ExternalPickler(pickle.Pickler):
+    def persistent_id(self, obj):
+        if isinstance(obj, External):
+            return "external-" + str(obj.id)
+        return None
+
+# Python 2 old-style class.
+class Old:
+    def __init__(self, *args):
+        self.args = args
+
+
+
+def scalars():
+    return (None, True, False, 0, 1, -1, 255, 256, 65535, 65536, -2 ** 31, 2 ** 31 - 1, 1.5, -0.25, "", "héllo ☃", "a\nb\\c")
+
+def binary():
+    return (b"", b"abc", bytes(range(256)), bytearray(b"xyz"), bytearray())
+
+def containers():
+    return {
+        "list": [1, 2, 3],
+        "tuples": ((), (1,), (1, 2), (1, 2, 3), (1, 2, 3, 4)),
+        "dict": { "a": 1, "b": [] },
+        # Batches of APPENDS & SETITEMS are 1000 items.
+        "long_list": list(range(1200)),
+        "long_dict": { str(i): i for i in range(1100) },
+        "set": { 1, 2, 3 },
+        "frozenset": frozenset({ 4, 5 }),
+    }
+
+def objects():
+    return (Plain(), Reduced(1, "a"), NewArgs(5), (Plain, len))
+
+def shared():
+    items = [1]
+    # More than 256 memo items.
+    strings = [str(i) for i in range(300)]
+    return (items, items, { "items": items }, strings, strings[-1])
+
+def keys():
+    plain = Plain()
+    return { 1: "int", 1.5: "float", None: "none", (1, "a"): "tuple", frozenset({ 2 }): "frozenset", plain: "object", "plain": plain }
+
+def recursive():
+    items = []
+    nested = (items,)
+    items.append(nested)
+    return items
+
+
+
+class Unpickler(pickle.Unpickler):
+    def persistent_load(self, pid):
+        return "resolved " + pid
+
+def write(name, data):
+    # Make sure CPython can read what it wrote.
+    if not name.startswith("buffers"):
+        Unpickler(io.BytesIO(data), encoding="latin1").load()
+    with open(os.path.join(OUTPUT, name + ".pickle"), "wb") as file:
+        file.write(data)
+    return data
+
+def main():
+    opcodes = set()
+    def track(data):
+        for opcode, _, _ in pickletools.genops(data):
+            opcodes.add(opcode.name)
+
+    for protocol in range(6):
+        for name, value in [("scalars", scalars), ("binary", binary), ("containers", containers), ("objects", objects), ("shared", shared), ("keys", keys), ("recursive", recursive)]:
+            track(write(f"{name}_{protocol}", pickle.dumps(value(), protocol=protocol)))
+
+        track(write(f"large_int_{protocol}", pickle.dumps((2 ** 40, -2 ** 40), protocol=protocol)))
+        track(write(f"big_int_{protocol}", pickle.dumps((2 ** 64, -2 ** 100, 2 ** 128 - 1, -10 ** 50), protocol=protocol)))
+
+        stream = io.BytesIO()
+        ExternalPickler(stream, protocol=protocol).dump([External(1), Plain(), External(2)])
+        track(write(f"persistent_{protocol}", stream.getvalue()))
+
+        if protocol >= 2:
+            track(write(f"slots_{protocol}", pickle.dumps(Slots(), protocol=protocol)))
+
+            copyreg.add_extension(__name__, "Plain", 1)
+            copyreg.add_extension(__name__, "Reduced", 300)
+            copyreg.add_extension(__name__, "NewArgs", 70000)
+            track(write(f"extensions_{protocol}", pickle.dumps((Plain, Reduced, NewArgs), protocol=protocol)))
+            copyreg.remove_extension(__name__, "Plain", 1)
+            copyreg.remove_extension(__name__, "Reduced", 300)
+            copyreg.remove_extension(__name__, "NewArgs", 70000)
+
+        if protocol >= 4:
+            track(write(f"newargs_ex_{protocol}", pickle.dumps(NewArgsEx(1, key="value"), protocol=protocol)))
+
+    # Protocol 5 out-of-band buffers, the buffers are supplied by the test.
+    buffers = []
+    data = pickle.dumps([pickle.PickleBuffer(b"abc"), pickle.PickleBuffer(bytearray(b"def"))], protocol=5, buffer_callback=buffers.append)
+    assert pickle.loads(data, buffers=[bytes(buffer) for buffer in buffers]) == [b"abc", bytearray(b"def")]
+    track(write("buffers_5", data))
+    # In-band buffers.
+    track(write("buffers_inband_5", pickle.dumps([pickle.PickleBuffer(b"abc"), pickle.PickleBuffer(bytearray(b"def"))], protocol=5)))
+
+    # Opcodes CPython 3 no longer writes, from Python 2.
+    handwritten = {
+        "python2_inst": b"(i__main__\nOld\n(dp0\nS'x'\np1\nI1\nsb.",
+        "python2_obj": b"(c__main__\nOld\nI1\nI2\no}(S'y'\nI2\nub.",
+        "python2_strings": b"(S'abc'\nS'tab\\there\\x00\\''\nU\x03defT\x03\x00\x00\x00ghiVsnow \\u2603\nt.",
+        "python2_containers": b"(dp0\nS'list'\np1\n(lp2\nI1\naL12345678901L\naF1.5\nasS'tuple'\n(I00\nI01\ntp3\nsS'dict'\n(S'a'\nI1\nds.",
+        "python2_memo": b"(lp0\nS'shared'\np1\nag1\nag0\na.",
+        # Opcodes CPython only uses for 4 GiB and larger.
+        "large_4": b"\x80\x04(\x8b\x09\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x8e\x03\x00\x00\x00\x00\x00\x00\x00abc\x8d\x03\x00\x00\x00\x00\x00\x00\x00deft.",
+        "stack": b"(I1\nI2\n0(I3\nI4\n12t.",
+    }
+    for name, data in handwritten.items():
+        track(write(name, data))
+
+    unused = [opcode.name for opcode in pickletools.opcodes if opcode.name not in opcodes]
+    print("Opcodes not covered:", ", ".join(unused) or "none")
+
+
+
+if __name__ == "__main__":
+    main()